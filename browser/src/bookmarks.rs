@@ -0,0 +1,714 @@
+//! Bookmark tree and the Netscape bookmark file format desktop browsers use
+//! for import and export.
+
+use std::collections::HashSet;
+
+use serde::{Deserialize, Serialize};
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Bookmark {
+    pub id: u32,
+    pub title: String,
+    pub url: String,
+    /// Milliseconds since the epoch
+    #[serde(default)]
+    pub added_at: f64,
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct BookmarkFolder {
+    pub id: u32,
+    pub title: String,
+    #[serde(default)]
+    pub children: Vec<BookmarkNode>,
+    #[serde(default)]
+    pub added_at: f64,
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub enum BookmarkNode {
+    Bookmark(Bookmark),
+    Folder(BookmarkFolder),
+}
+
+impl BookmarkNode {
+    pub fn id(&self) -> u32 {
+        match self {
+            BookmarkNode::Bookmark(b) => b.id,
+            BookmarkNode::Folder(f) => f.id,
+        }
+    }
+}
+
+impl BookmarkFolder {
+    fn new(id: u32, title: &str, added_at: f64) -> Self {
+        Self { id, title: title.to_string(), children: Vec::new(), added_at }
+    }
+
+    fn find_folder_mut(&mut self, id: u32) -> Option<&mut BookmarkFolder> {
+        if self.id == id {
+            return Some(self);
+        }
+        self.children.iter_mut().find_map(|child| match child {
+            BookmarkNode::Folder(folder) => folder.find_folder_mut(id),
+            BookmarkNode::Bookmark(_) => None,
+        })
+    }
+
+    fn remove(&mut self, id: u32) -> Option<BookmarkNode> {
+        if let Some(idx) = self.children.iter().position(|c| c.id() == id) {
+            return Some(self.children.remove(idx));
+        }
+        self.children.iter_mut().find_map(|child| match child {
+            BookmarkNode::Folder(folder) => folder.remove(id),
+            BookmarkNode::Bookmark(_) => None,
+        })
+    }
+
+    /// Every bookmark in this folder and its subfolders, depth first
+    pub fn bookmarks(&self) -> Vec<&Bookmark> {
+        let mut found = Vec::new();
+        for child in &self.children {
+            match child {
+                BookmarkNode::Bookmark(b) => found.push(b),
+                BookmarkNode::Folder(f) => found.extend(f.bookmarks()),
+            }
+        }
+        found
+    }
+
+    /// Every folder below this one with its nesting depth, depth first
+    pub fn folders(&self) -> Vec<(usize, &BookmarkFolder)> {
+        let mut found = Vec::new();
+        for child in &self.children {
+            if let BookmarkNode::Folder(f) = child {
+                found.push((0, f));
+                found.extend(f.folders().into_iter().map(|(depth, f)| (depth + 1, f)));
+            }
+        }
+        found
+    }
+
+    /// Drops bookmarks whose address is in `known` and adds the rest to it.
+    /// Folders with nothing left in them go too. Returns how many bookmarks
+    /// remain.
+    fn retain_new(&mut self, known: &mut HashSet<String>) -> usize {
+        let mut count = 0;
+        self.children.retain_mut(|child| match child {
+            BookmarkNode::Bookmark(b) => {
+                let new = known.insert(b.url.clone());
+                count += new as usize;
+                new
+            }
+            BookmarkNode::Folder(f) => {
+                let remaining = f.retain_new(known);
+                count += remaining;
+                remaining > 0
+            }
+        });
+        count
+    }
+
+    fn renumber(&mut self, next_id: &mut u32) {
+        self.id = *next_id;
+        *next_id += 1;
+        for child in self.children.iter_mut() {
+            match child {
+                BookmarkNode::Bookmark(b) => {
+                    b.id = *next_id;
+                    *next_id += 1;
+                }
+                BookmarkNode::Folder(f) => f.renumber(next_id),
+            }
+        }
+    }
+}
+
+pub const BOOKMARK_BAR_ID: u32 = 0;
+pub const OTHER_BOOKMARKS_ID: u32 = 1;
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Bookmarks {
+    /// Shown in the bookmark bar under the navigation bar
+    pub bar: BookmarkFolder,
+    pub other: BookmarkFolder,
+    pub next_id: u32,
+}
+
+impl Default for Bookmarks {
+    fn default() -> Self {
+        Self {
+            bar: BookmarkFolder::new(BOOKMARK_BAR_ID, "Bookmarks bar", 0.0),
+            other: BookmarkFolder::new(OTHER_BOOKMARKS_ID, "Other bookmarks", 0.0),
+            next_id: 2,
+        }
+    }
+}
+
+impl Bookmarks {
+    pub fn find_by_url(&self, url: &str) -> Option<&Bookmark> {
+        self.all().into_iter().find(|b| b.url == url)
+    }
+
+    pub fn all(&self) -> Vec<&Bookmark> {
+        let mut all = self.bar.bookmarks();
+        all.extend(self.other.bookmarks());
+        all
+    }
+
+    fn find_folder_mut(&mut self, id: u32) -> Option<&mut BookmarkFolder> {
+        self.bar.find_folder_mut(id).or_else(|| self.other.find_folder_mut(id))
+    }
+
+    fn take_id(&mut self) -> u32 {
+        let id = self.next_id;
+        self.next_id += 1;
+        id
+    }
+
+    /// Adds a bookmark to a folder, falling back to the bookmark bar
+    pub fn add_bookmark(&mut self, folder_id: u32, title: &str, url: &str, now: f64) -> u32 {
+        let id = self.take_id();
+        let bookmark = Bookmark { id, title: title.to_string(), url: url.to_string(), added_at: now };
+        let folder = match self.find_folder_mut(folder_id) {
+            Some(folder) => folder,
+            None => &mut self.bar,
+        };
+        folder.children.push(BookmarkNode::Bookmark(bookmark));
+        id
+    }
+
+    pub fn add_folder(&mut self, parent_id: u32, title: &str, now: f64) -> u32 {
+        let id = self.take_id();
+        let folder = BookmarkFolder::new(id, title, now);
+        let parent = match self.find_folder_mut(parent_id) {
+            Some(parent) => parent,
+            None => &mut self.bar,
+        };
+        parent.children.push(BookmarkNode::Folder(folder));
+        id
+    }
+
+    /// Removes a bookmark or folder. The two root folders can't be removed.
+    pub fn remove(&mut self, id: u32) -> Option<BookmarkNode> {
+        self.bar.remove(id).or_else(|| self.other.remove(id))
+    }
+
+    /// Moves a node into another folder, refusing to move a folder into itself
+    pub fn move_to(&mut self, id: u32, folder_id: u32) -> bool {
+        if id == folder_id || self.find_folder_mut(folder_id).is_none() {
+            return false;
+        }
+        if let Some(moving) = self.find_folder_mut(id) {
+            if moving.find_folder_mut(folder_id).is_some() {
+                return false;
+            }
+        }
+        let Some(node) = self.remove(id) else {
+            return false;
+        };
+        if let Some(folder) = self.find_folder_mut(folder_id) {
+            folder.children.push(node);
+        }
+        true
+    }
+
+    /// Merges a parsed bookmark file. The file's toolbar folder lands in the
+    /// bookmark bar; everything else goes into a new folder under "Other".
+    pub fn import(&mut self, imported: ImportedBookmarks, now: f64) -> usize {
+        let count = imported.toolbar.bookmarks().len() + imported.other.bookmarks().len();
+
+        let mut next_id = self.next_id;
+        let mut toolbar = imported.toolbar;
+        toolbar.renumber(&mut next_id);
+        self.bar.children.extend(toolbar.children);
+
+        if !imported.other.children.is_empty() {
+            let mut folder = imported.other;
+            folder.title = String::from("Imported");
+            folder.added_at = now;
+            folder.renumber(&mut next_id);
+            self.other.children.push(BookmarkNode::Folder(folder));
+        }
+        self.next_id = next_id;
+        count
+    }
+
+    /// Adds the bookmarks of another profile that aren't bookmarked here
+    /// yet, each root's contents going to the same root
+    pub fn merge(&mut self, other: Bookmarks) -> usize {
+        let mut known: HashSet<String> = self.all().into_iter().map(|b| b.url.clone()).collect();
+        let mut next_id = self.next_id;
+        let mut count = 0;
+        for (mut folder, root) in [(other.bar, &mut self.bar), (other.other, &mut self.other)] {
+            count += folder.retain_new(&mut known);
+            folder.renumber(&mut next_id);
+            root.children.extend(folder.children);
+        }
+        self.next_id = next_id;
+        count
+    }
+
+    /// The largest id in the tree
+    pub fn max_id(&self) -> u32 {
+        let folders = self.bar.folders().into_iter().chain(self.other.folders()).map(|(_, f)| f.id);
+        let bookmarks = self.all().into_iter().map(|b| b.id);
+        folders.chain(bookmarks).chain([self.bar.id, self.other.id]).max().unwrap_or(0)
+    }
+
+    /// Serialises to the Netscape bookmark file format
+    pub fn to_netscape_html(&self) -> String {
+        let mut html = String::from(
+            "<!DOCTYPE NETSCAPE-Bookmark-file-1>\n\
+             <!-- This is an automatically generated file.\n     \
+             It will be read and overwritten.\n     \
+             DO NOT EDIT! -->\n\
+             <META HTTP-EQUIV=\"Content-Type\" CONTENT=\"text/html; charset=UTF-8\">\n\
+             <TITLE>Bookmarks</TITLE>\n\
+             <H1>Bookmarks</H1>\n\
+             <DL><p>\n",
+        );
+        write_folder(&mut html, &self.bar, 1, true);
+        for child in &self.other.children {
+            write_node(&mut html, child, 1);
+        }
+        html.push_str("</DL><p>\n");
+        html
+    }
+}
+
+fn write_folder(html: &mut String, folder: &BookmarkFolder, depth: usize, toolbar: bool) {
+    let indent = "    ".repeat(depth);
+    html.push_str(&format!(
+        "{}<DT><H3 ADD_DATE=\"{}\"{}>{}</H3>\n{}<DL><p>\n",
+        indent,
+        (folder.added_at / 1000.0) as i64,
+        if toolbar { " PERSONAL_TOOLBAR_FOLDER=\"true\"" } else { "" },
+        escape(&folder.title),
+        indent,
+    ));
+    for child in &folder.children {
+        write_node(html, child, depth + 1);
+    }
+    html.push_str(&format!("{}</DL><p>\n", indent));
+}
+
+fn write_node(html: &mut String, node: &BookmarkNode, depth: usize) {
+    match node {
+        BookmarkNode::Bookmark(b) => html.push_str(&format!(
+            "{}<DT><A HREF=\"{}\" ADD_DATE=\"{}\">{}</A>\n",
+            "    ".repeat(depth),
+            escape(&b.url),
+            (b.added_at / 1000.0) as i64,
+            escape(&b.title),
+        )),
+        BookmarkNode::Folder(f) => write_folder(html, f, depth, false),
+    }
+}
+
+/// Bookmarks read from a Netscape bookmark file, not yet merged. Ids are
+/// assigned when merging.
+#[derive(Clone, Debug, PartialEq)]
+pub struct ImportedBookmarks {
+    pub toolbar: BookmarkFolder,
+    pub other: BookmarkFolder,
+}
+
+/// Parses a Netscape bookmark file. The format is loose HTML, so this reads
+/// the `<H3>`, `<A>` and `<DL>` tags it cares about and skips everything else.
+pub fn parse_netscape_html(html: &str) -> Result<ImportedBookmarks, String> {
+    if !html.to_ascii_uppercase().contains("<DL") {
+        return Err(String::from("Not a bookmark file"));
+    }
+
+    let mut toolbar = BookmarkFolder::new(0, "Bookmarks bar", 0.0);
+    let mut toolbar_path: Option<usize> = None;
+    // Folders being filled, outermost first; the root collects loose entries
+    let mut stack = vec![BookmarkFolder::new(0, "Imported", 0.0)];
+    // A folder heading whose <DL> hasn't opened yet
+    let mut pending: Option<(BookmarkFolder, bool)> = None;
+
+    let mut rest = html;
+    while let Some(start) = rest.find('<') {
+        rest = &rest[start..];
+        let Some(end) = rest.find('>') else {
+            break;
+        };
+        let tag = &rest[1..end];
+        let name = tag.split_whitespace().next().unwrap_or("").to_ascii_uppercase();
+        let after = &rest[end + 1..];
+
+        match name.as_str() {
+            "H3" => {
+                let (text, remaining) = read_text(after, "</H3");
+                let title = unescape(text.trim());
+                let title = if title.is_empty() { "Untitled" } else { title.as_str() };
+                let folder = BookmarkFolder::new(0, title, attribute_date(tag, "ADD_DATE"));
+                let is_toolbar = attribute(tag, "PERSONAL_TOOLBAR_FOLDER").is_some_and(|v| v.eq_ignore_ascii_case("true"));
+                pending = Some((folder, is_toolbar));
+                rest = remaining;
+                continue;
+            }
+            "A" => {
+                let (text, remaining) = read_text(after, "</A");
+                if let Some(href) = attribute(tag, "HREF") {
+                    let title = unescape(text.trim());
+                    let url = unescape(&href);
+                    let bookmark = Bookmark {
+                        id: 0,
+                        title: if title.is_empty() { url.clone() } else { title },
+                        url,
+                        added_at: attribute_date(tag, "ADD_DATE"),
+                    };
+                    if let Some(folder) = stack.last_mut() {
+                        folder.children.push(BookmarkNode::Bookmark(bookmark));
+                    }
+                }
+                rest = remaining;
+                continue;
+            }
+            "DL" => {
+                let (folder, is_toolbar) = pending.take().unwrap_or_else(|| (BookmarkFolder::new(0, "", 0.0), false));
+                if is_toolbar && toolbar_path.is_none() {
+                    toolbar_path = Some(stack.len());
+                }
+                stack.push(folder);
+            }
+            // The root collecting loose entries is never closed
+            "/DL" if stack.len() > 1 => {
+                let folder = stack.pop().unwrap_or_else(|| BookmarkFolder::new(0, "", 0.0));
+                if toolbar_path == Some(stack.len()) {
+                    toolbar = folder;
+                    toolbar_path = None;
+                } else if folder.title.is_empty() {
+                    // A list without a heading, such as the file's own top level
+                    if let Some(parent) = stack.last_mut() {
+                        parent.children.extend(folder.children);
+                    }
+                } else if let Some(parent) = stack.last_mut() {
+                    parent.children.push(BookmarkNode::Folder(folder));
+                }
+            }
+            _ => {}
+        }
+        rest = after;
+    }
+
+    // Unclosed folders in a truncated file still keep their contents
+    while stack.len() > 1 {
+        let folder = stack.pop().unwrap_or_else(|| BookmarkFolder::new(0, "", 0.0));
+        if let Some(parent) = stack.last_mut() {
+            if folder.title.is_empty() {
+                parent.children.extend(folder.children);
+            } else {
+                parent.children.push(BookmarkNode::Folder(folder));
+            }
+        }
+    }
+    let other = stack.pop().unwrap_or_else(|| BookmarkFolder::new(0, "Imported", 0.0));
+    Ok(ImportedBookmarks { toolbar, other })
+}
+
+/// Splits `html` at the closing tag `close`, matched case-insensitively
+fn read_text<'a>(html: &'a str, close: &str) -> (&'a str, &'a str) {
+    let mut offset = 0;
+    while let Some(pos) = html[offset..].find("</") {
+        let start = offset + pos;
+        if html[start..].get(..close.len()).is_some_and(|t| t.eq_ignore_ascii_case(close)) {
+            return (&html[..start], &html[start..]);
+        }
+        offset = start + 2;
+    }
+    (html, "")
+}
+
+/// Reads an attribute value from inside a tag, quoted or bare
+fn attribute(tag: &str, name: &str) -> Option<String> {
+    let upper = tag.to_ascii_uppercase();
+    let mut search = 0;
+    while let Some(found) = upper[search..].find(name) {
+        let start = search + found;
+        search = start + name.len();
+        // Must be a whole attribute name
+        if start > 0 && !tag.as_bytes()[start - 1].is_ascii_whitespace() {
+            continue;
+        }
+        let rest = tag[search..].trim_start();
+        let Some(rest) = rest.strip_prefix('=') else {
+            continue;
+        };
+        let rest = rest.trim_start();
+        let value = match rest.chars().next() {
+            Some(quote @ ('"' | '\'')) => rest[1..].split(quote).next().unwrap_or(""),
+            _ => rest.split_whitespace().next().unwrap_or(""),
+        };
+        return Some(value.to_string());
+    }
+    None
+}
+
+/// Reads a seconds-since-epoch attribute as milliseconds
+fn attribute_date(tag: &str, name: &str) -> f64 {
+    attribute(tag, name).and_then(|v| v.parse::<f64>().ok()).map(|s| s * 1000.0).unwrap_or(0.0)
+}
+
+fn escape(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+fn unescape(text: &str) -> String {
+    let mut result = String::with_capacity(text.len());
+    let mut rest = text;
+    while let Some(amp) = rest.find('&') {
+        result.push_str(&rest[..amp]);
+        rest = &rest[amp..];
+        let entity_end = rest.find(';').filter(|&end| end <= 10);
+        let decoded = entity_end.and_then(|end| {
+            let entity = &rest[1..end];
+            let ch = match entity {
+                "amp" => Some('&'),
+                "lt" => Some('<'),
+                "gt" => Some('>'),
+                "quot" => Some('"'),
+                "apos" => Some('\''),
+                _ if entity.starts_with("#x") || entity.starts_with("#X") => {
+                    u32::from_str_radix(&entity[2..], 16).ok().and_then(char::from_u32)
+                }
+                _ if entity.starts_with('#') => entity[1..].parse().ok().and_then(char::from_u32),
+                _ => None,
+            };
+            ch.map(|c| (c, end + 1))
+        });
+        match decoded {
+            Some((ch, len)) => {
+                result.push(ch);
+                rest = &rest[len..];
+            }
+            None => {
+                result.push('&');
+                rest = &rest[1..];
+            }
+        }
+    }
+    result.push_str(rest);
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Exported by Chrome: the bar is a flagged folder, and "Other bookmarks"
+    /// are loose entries after it
+    const CHROME_EXPORT: &str = r#"<!DOCTYPE NETSCAPE-Bookmark-file-1>
+<!-- This is an automatically generated file.
+     It will be read and overwritten.
+     DO NOT EDIT! -->
+<META HTTP-EQUIV="Content-Type" CONTENT="text/html; charset=UTF-8">
+<TITLE>Bookmarks</TITLE>
+<H1>Bookmarks</H1>
+<DL><p>
+    <DT><H3 ADD_DATE="1700000000" LAST_MODIFIED="1700000500" PERSONAL_TOOLBAR_FOLDER="true">Bookmarks bar</H3>
+    <DL><p>
+        <DT><A HREF="https://www.rust-lang.org/" ADD_DATE="1700000100" ICON="data:image/png;base64,iVBORw0KGgo=">Rust Programming Language</A>
+        <DT><H3 ADD_DATE="1700000200" LAST_MODIFIED="1700000300">Docs</H3>
+        <DL><p>
+            <DT><A HREF="https://doc.rust-lang.org/std/?search=Vec&amp;filter=fn" ADD_DATE="1700000250">std - Rust</A>
+        </DL><p>
+    </DL><p>
+    <DT><A HREF="https://example.com/" ADD_DATE="1700000400">Example Domain</A>
+</DL><p>
+"#;
+
+    /// Exported by Firefox: the menu's contents sit at the top level, the
+    /// toolbar and "Other Bookmarks" are folders in it, and there are
+    /// descriptions, separators and a stray closing `</meta>`
+    const FIREFOX_EXPORT: &str = r#"<!DOCTYPE NETSCAPE-Bookmark-file-1>
+<!-- This is an automatically generated file.
+     It will be read and overwritten.
+     DO NOT EDIT! -->
+<META HTTP-EQUIV="Content-Type" CONTENT="text/html; charset=UTF-8">
+<meta http-equiv="Content-Security-Policy"
+      content="default-src 'self'; script-src 'none'; img-src data: *; object-src 'none'"></meta>
+<TITLE>Bookmarks</TITLE>
+<H1>Bookmarks Menu</H1>
+
+<DL><p>
+    <DT><H3 ADD_DATE="1690000000" LAST_MODIFIED="1690000100">Mozilla Firefox</H3>
+    <DL><p>
+        <DT><A HREF="https://support.mozilla.org/products/firefox" ADD_DATE="1690000010" LAST_MODIFIED="1690000010" ICON_URI="fake-favicon-uri:https://support.mozilla.org/products/firefox">Get Help</A>
+    </DL><p>
+    <HR>    <DT><H3 ADD_DATE="1690000000" LAST_MODIFIED="1690000200" PERSONAL_TOOLBAR_FOLDER="true">Bookmarks Toolbar</H3>
+    <DL><p>
+        <DT><A HREF="https://www.mozilla.org/about/" ADD_DATE="1690000020" LAST_MODIFIED="1690000020" TAGS="mozilla">About Us &amp; Mission</A>
+        <DD>Who we are &#8212; and why
+        <DT><A HREF="https://developer.mozilla.org/" ADD_DATE="1690000030"></A>
+    </DL><p>
+    <DT><H3 ADD_DATE="1690000000" LAST_MODIFIED="1690000300" UNFILED_BOOKMARKS_FOLDER="true">Other Bookmarks</H3>
+    <DL><p>
+        <DT><A HREF="https://www.example.org/?a=1&amp;b=2" ADD_DATE="1690000040">Caf&#xE9; &lt;Example&gt;</A>
+    </DL><p>
+</DL>
+"#;
+
+    fn bookmark(title: &str, url: &str, added_seconds: u32) -> BookmarkNode {
+        BookmarkNode::Bookmark(Bookmark {
+            id: 0,
+            title: title.to_string(),
+            url: url.to_string(),
+            added_at: added_seconds as f64 * 1000.0,
+        })
+    }
+
+    fn folder(title: &str, added_seconds: u32, children: Vec<BookmarkNode>) -> BookmarkFolder {
+        BookmarkFolder { id: 0, title: title.to_string(), children, added_at: added_seconds as f64 * 1000.0 }
+    }
+
+    /// Parsed bookmarks have no ids until they are imported
+    fn without_ids(mut folder: BookmarkFolder) -> BookmarkFolder {
+        folder.id = 0;
+        folder.children = folder
+            .children
+            .into_iter()
+            .map(|child| match child {
+                BookmarkNode::Bookmark(b) => BookmarkNode::Bookmark(Bookmark { id: 0, ..b }),
+                BookmarkNode::Folder(f) => BookmarkNode::Folder(without_ids(f)),
+            })
+            .collect();
+        folder
+    }
+
+    /// Untitled bookmarks are read back titled with their address
+    fn titles_from_urls(folder: &mut BookmarkFolder) {
+        for child in folder.children.iter_mut() {
+            match child {
+                BookmarkNode::Bookmark(b) if b.title.is_empty() => b.title = b.url.clone(),
+                BookmarkNode::Bookmark(_) => {}
+                BookmarkNode::Folder(f) => titles_from_urls(f),
+            }
+        }
+    }
+
+    #[test]
+    fn export_round_trips_nested_folders_and_escapes() {
+        let mut bookmarks = Bookmarks::default();
+        bookmarks.bar.added_at = 1_000_000.0;
+        let work = bookmarks.add_folder(BOOKMARK_BAR_ID, "Work & <Play>", 2_000_000.0);
+        let deep = bookmarks.add_folder(work, "\"Deep\" folder", 3_000_000.0);
+        bookmarks.add_bookmark(BOOKMARK_BAR_ID, "Search \"quoted\" & <tagged>", "https://example.com/?q=a&b=<c>", 4_000_000.0);
+        bookmarks.add_bookmark(work, "Café ünïcode", "https://例え.jp/パス", 5_000_000.0);
+        bookmarks.add_bookmark(deep, "", "https://example.com/empty-title", 6_000_000.0);
+        bookmarks.add_bookmark(OTHER_BOOKMARKS_ID, "Elsewhere", "https://example.org/", 7_000_000.0);
+        let archive = bookmarks.add_folder(OTHER_BOOKMARKS_ID, "Archive", 8_000_000.0);
+        bookmarks.add_bookmark(archive, "Old & gold", "https://example.net/old", 9_000_000.0);
+
+        let html = bookmarks.to_netscape_html();
+        assert!(html.contains("Work &amp; &lt;Play&gt;"));
+        assert!(html.contains("HREF=\"https://example.com/?q=a&amp;b=&lt;c&gt;\""));
+        assert!(!html.contains("<Play>"));
+
+        let parsed = parse_netscape_html(&html).unwrap();
+        let mut expected_bar = without_ids(bookmarks.bar.clone());
+        titles_from_urls(&mut expected_bar);
+        assert_eq!(parsed.toolbar, expected_bar);
+        assert_eq!(parsed.other, folder("Imported", 0, without_ids(bookmarks.other.clone()).children));
+    }
+
+    #[test]
+    fn parses_a_chrome_export() {
+        let parsed = parse_netscape_html(CHROME_EXPORT).unwrap();
+        assert_eq!(
+            parsed.toolbar,
+            folder(
+                "Bookmarks bar",
+                1700000000,
+                vec![
+                    bookmark("Rust Programming Language", "https://www.rust-lang.org/", 1700000100),
+                    BookmarkNode::Folder(folder(
+                        "Docs",
+                        1700000200,
+                        vec![bookmark("std - Rust", "https://doc.rust-lang.org/std/?search=Vec&filter=fn", 1700000250)],
+                    )),
+                ],
+            )
+        );
+        assert_eq!(parsed.other, folder("Imported", 0, vec![bookmark("Example Domain", "https://example.com/", 1700000400)]));
+    }
+
+    #[test]
+    fn parses_a_firefox_export() {
+        let parsed = parse_netscape_html(FIREFOX_EXPORT).unwrap();
+        assert_eq!(
+            parsed.toolbar,
+            folder(
+                "Bookmarks Toolbar",
+                1690000000,
+                vec![
+                    bookmark("About Us & Mission", "https://www.mozilla.org/about/", 1690000020),
+                    bookmark("https://developer.mozilla.org/", "https://developer.mozilla.org/", 1690000030),
+                ],
+            )
+        );
+        assert_eq!(
+            parsed.other,
+            folder(
+                "Imported",
+                0,
+                vec![
+                    BookmarkNode::Folder(folder(
+                        "Mozilla Firefox",
+                        1690000000,
+                        vec![bookmark("Get Help", "https://support.mozilla.org/products/firefox", 1690000010)],
+                    )),
+                    BookmarkNode::Folder(folder(
+                        "Other Bookmarks",
+                        1690000000,
+                        vec![bookmark("Café <Example>", "https://www.example.org/?a=1&b=2", 1690000040)],
+                    )),
+                ],
+            )
+        );
+    }
+
+    #[test]
+    fn importing_assigns_fresh_ids() {
+        let mut bookmarks = Bookmarks::default();
+        bookmarks.add_bookmark(BOOKMARK_BAR_ID, "Existing", "https://example.com/", 0.0);
+        let count = bookmarks.import(parse_netscape_html(CHROME_EXPORT).unwrap(), 42.0);
+        assert_eq!(count, 3);
+        assert_eq!(bookmarks.bar.children.len(), 3);
+        let Some(BookmarkNode::Folder(imported)) = bookmarks.other.children.last() else {
+            panic!("no Imported folder");
+        };
+        assert_eq!(imported.title, "Imported");
+        assert_eq!(imported.added_at, 42.0);
+
+        let mut ids: Vec<u32> = bookmarks.all().iter().map(|b| b.id).collect();
+        ids.extend(bookmarks.bar.folders().iter().chain(bookmarks.other.folders().iter()).map(|(_, f)| f.id));
+        ids.extend([bookmarks.bar.id, bookmarks.other.id]);
+        let count = ids.len();
+        ids.sort_unstable();
+        ids.dedup();
+        assert_eq!(ids.len(), count, "duplicate ids");
+        assert_eq!(bookmarks.next_id, bookmarks.max_id() + 1);
+    }
+
+    #[test]
+    fn truncated_and_foreign_files() {
+        let truncated = "<DL><p><DT><H3>Unfinished</H3><DL><p><DT><A HREF=\"https://example.com/\">Kept</A>";
+        let parsed = parse_netscape_html(truncated).unwrap();
+        assert_eq!(
+            parsed.other,
+            folder("Imported", 0, vec![BookmarkNode::Folder(folder("Unfinished", 0, vec![bookmark("Kept", "https://example.com/", 0)]))])
+        );
+
+        assert!(parse_netscape_html("<html><body>Not bookmarks</body></html>").is_err());
+        assert!(parse_netscape_html("").is_err());
+        // Unterminated tags and entities don't panic
+        assert!(parse_netscape_html("<DL><DT><A HREF=\"https://example.com/\">&#xZZ; &amp").is_ok());
+        assert!(parse_netscape_html("<DL><DT><A HREF=").is_ok());
+    }
+}
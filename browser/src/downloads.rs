@@ -0,0 +1,411 @@
+//! Downloads: fetching files with progress, pausing through HTTP range
+//! requests, and keeping finished files in IndexedDB so they can be saved to
+//! disk again later.
+
+use std::cell::{Cell, RefCell};
+use std::rc::Rc;
+
+use serde::{Deserialize, Serialize};
+use wasm_bindgen::prelude::*;
+use wasm_bindgen_futures::JsFuture;
+use web_sys::{AbortController, Blob, BlobPropertyBag, HtmlAnchorElement, ReadableStreamDefaultReader, Url};
+use yew::Callback;
+
+use crate::idb::{self, js_error};
+use crate::proxy::ProxyClient;
+
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub enum DownloadStatus {
+    #[default]
+    InProgress,
+    Paused,
+    Completed,
+    Cancelled,
+    Failed(String),
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Download {
+    pub id: u32,
+    pub filename: String,
+    #[serde(default)]
+    pub url: String,
+    #[serde(default)]
+    pub mime_type: Option<String>,
+    #[serde(default)]
+    pub received_bytes: u64,
+    #[serde(default)]
+    pub total_bytes: Option<u64>,
+    #[serde(default)]
+    pub status: DownloadStatus,
+    /// Milliseconds since the epoch
+    #[serde(default)]
+    pub started_at: f64,
+    #[serde(default)]
+    pub finished_at: Option<f64>,
+    /// Started from a private tab: never saved, and the file isn't kept
+    #[serde(default)]
+    pub private: bool,
+}
+
+impl Download {
+    pub fn new(id: u32, url: &str) -> Self {
+        Self {
+            id,
+            filename: filename_from_url(url),
+            url: url.to_string(),
+            mime_type: None,
+            received_bytes: 0,
+            total_bytes: None,
+            status: DownloadStatus::InProgress,
+            started_at: js_sys::Date::now(),
+            finished_at: None,
+            private: false,
+        }
+    }
+
+    /// Short human readable progress line for the downloads panel
+    pub fn status_text(&self) -> String {
+        let received = format_bytes(self.received_bytes);
+        let progress = match self.total_bytes {
+            Some(total) => format!("{} of {}", received, format_bytes(total)),
+            None => received,
+        };
+        match &self.status {
+            DownloadStatus::InProgress => progress,
+            DownloadStatus::Paused => format!("Paused, {}", progress),
+            DownloadStatus::Completed => format_bytes(self.received_bytes),
+            DownloadStatus::Cancelled => String::from("Cancelled"),
+            DownloadStatus::Failed(reason) => format!("Failed: {}", reason),
+        }
+    }
+
+    /// Fraction complete, when the total size is known
+    pub fn progress(&self) -> Option<f64> {
+        self.total_bytes
+            .filter(|total| *total > 0)
+            .map(|total| (self.received_bytes as f64 / total as f64).min(1.0))
+    }
+}
+
+/// Where a download's bytes come from
+#[derive(Clone)]
+pub enum Transport {
+    /// A plain `fetch`, possibly of an address rewritten by an HTTP proxy
+    Fetch(String),
+    /// The WebSocket proxy
+    WebSocket(ProxyClient),
+}
+
+pub enum DownloadEvent {
+    /// Response headers arrived. `restarted` is set when the server ignored
+    /// the range request and the file is being fetched from the start.
+    Started {
+        total_bytes: Option<u64>,
+        mime_type: Option<String>,
+        filename: Option<String>,
+        restarted: bool,
+    },
+    Progress(u64),
+    /// All bytes received. The file is stored in IndexedDB, or handed over
+    /// here when it wasn't to be kept.
+    Completed(Option<Blob>),
+    Failed(String),
+}
+
+/// A running or paused download. Received bytes stay in memory until the
+/// download completes, so a paused download can resume where it stopped.
+pub struct DownloadJob {
+    buffer: Rc<RefCell<Vec<u8>>>,
+    abort: Option<AbortController>,
+    /// Bumped on every (re)start and pause. Shared with the running attempt,
+    /// which stops touching the buffer and storage once it's been disowned.
+    generation: Rc<Cell<u32>>,
+    /// The running attempt streams, so pausing keeps what it received
+    pausable: bool,
+}
+
+/// One (re)start of a download, until a later pause or restart disowns it
+struct Attempt {
+    generation: u32,
+    current: Rc<Cell<u32>>,
+}
+
+impl Attempt {
+    fn is_current(&self) -> bool {
+        self.current.get() == self.generation
+    }
+
+    fn check(&self) -> Result<(), String> {
+        if self.is_current() {
+            Ok(())
+        } else {
+            Err(String::from("Download was stopped"))
+        }
+    }
+}
+
+impl DownloadJob {
+    pub fn new() -> Self {
+        Self { buffer: Rc::new(RefCell::new(Vec::new())), abort: None, generation: Rc::new(Cell::new(0)), pausable: false }
+    }
+
+    /// Tags the events of the current attempt
+    pub fn generation(&self) -> u32 {
+        self.generation.get()
+    }
+
+    /// Whether pausing keeps the bytes received so far. The WebSocket proxy
+    /// hands over a body all at once, so pausing it would only start over.
+    pub fn can_pause(&self) -> bool {
+        self.pausable
+    }
+
+    /// Starts fetching, continuing from any bytes already received. Events are
+    /// tagged with the job generation they belong to. Unless `keep` is set the
+    /// finished file is only passed on in the completion event.
+    pub fn start(&mut self, id: u32, url: &str, transport: Transport, keep: bool, events: Callback<(u32, DownloadEvent)>) {
+        self.stop();
+        self.generation.set(self.generation.get() + 1);
+        let attempt = Attempt { generation: self.generation.get(), current: Rc::clone(&self.generation) };
+        let generation = attempt.generation;
+        let events = events.reform(move |event| (generation, event));
+        self.pausable = matches!(transport, Transport::Fetch(_));
+        let abort = AbortController::new().ok();
+        let signal = abort.as_ref().map(|a| a.signal());
+        self.abort = abort;
+
+        let buffer = Rc::clone(&self.buffer);
+        let url = url.to_string();
+        wasm_bindgen_futures::spawn_local(async move {
+            let result = match transport {
+                Transport::Fetch(request_url) => fetch_stream(&request_url, signal.clone(), &attempt, &buffer, &events).await,
+                Transport::WebSocket(client) => fetch_websocket(&client, &url, &attempt, &buffer, &events).await,
+            };
+            let blob = result.and_then(|mime_type| create_blob(&buffer.borrow(), &mime_type));
+            let result = match blob.and_then(|blob| attempt.check().map(|_| blob)) {
+                Ok(blob) if keep => store_file(id, &blob, &attempt).await.map(|_| None),
+                Ok(blob) => Ok(Some(blob)),
+                Err(err) => Err(err),
+            };
+            match result {
+                Ok(file) => events.emit(DownloadEvent::Completed(file)),
+                // Aborting on pause or cancel is not a failure
+                Err(_) if signal_aborted(&signal) || !attempt.is_current() => {}
+                Err(err) => events.emit(DownloadEvent::Failed(err)),
+            }
+        });
+    }
+
+    /// Aborts the request in flight, keeping the bytes received so far
+    pub fn stop(&mut self) {
+        if let Some(abort) = self.abort.take() {
+            abort.abort();
+        }
+    }
+
+    /// Stops and disowns the current attempt, whose transport may not be abortable
+    pub fn pause(&mut self) {
+        self.stop();
+        self.generation.set(self.generation.get() + 1);
+    }
+}
+
+impl Default for DownloadJob {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+fn signal_aborted(signal: &Option<web_sys::AbortSignal>) -> bool {
+    signal.as_ref().map(|s| s.aborted()).unwrap_or(false)
+}
+
+/// Keeps a finished file, unless the download was cancelled or deleted
+/// meanwhile, in which case nothing would ever remove it
+async fn store_file(id: u32, blob: &Blob, attempt: &Attempt) -> Result<(), String> {
+    idb::put(idb::DOWNLOAD_FILES, &JsValue::from(id), blob).await?;
+    if !attempt.is_current() {
+        delete_file(id).await?;
+    }
+    attempt.check()
+}
+
+async fn fetch_stream(
+    request_url: &str,
+    signal: Option<web_sys::AbortSignal>,
+    attempt: &Attempt,
+    buffer: &Rc<RefCell<Vec<u8>>>,
+    events: &Callback<DownloadEvent>,
+) -> Result<String, String> {
+    let offset = buffer.borrow().len() as u64;
+    let mut request = gloo_net::http::Request::get(request_url).abort_signal(signal.as_ref());
+    if offset > 0 {
+        request = request.header("Range", &format!("bytes={}-", offset));
+    }
+    let response = request.send().await.map_err(|e| e.to_string())?;
+    attempt.check()?;
+    if !response.ok() {
+        return Err(format!("{} {}", response.status(), response.status_text()));
+    }
+
+    let headers = response.headers();
+    let partial = response.status() == 206;
+    if !partial {
+        buffer.borrow_mut().clear();
+    }
+    let total_bytes = if partial {
+        headers.get("content-range").and_then(|r| r.rsplit('/').next().and_then(|t| t.parse().ok()))
+    } else {
+        headers.get("content-length").and_then(|l| l.parse().ok())
+    };
+    let mime_type = headers.get("content-type");
+    events.emit(DownloadEvent::Started {
+        total_bytes,
+        mime_type: mime_type.clone(),
+        filename: headers.get("content-disposition").and_then(|d| filename_from_disposition(&d)),
+        restarted: offset > 0 && !partial,
+    });
+
+    let reader = response
+        .body()
+        .ok_or("Response has no body")?
+        .get_reader()
+        .dyn_into::<ReadableStreamDefaultReader>()
+        .map_err(|_| "Response body can't be read")?;
+    loop {
+        let chunk = JsFuture::from(reader.read()).await.map_err(js_error)?;
+        let done = js_sys::Reflect::get(&chunk, &"done".into()).map(|d| d.is_truthy()).unwrap_or(true);
+        if done {
+            break;
+        }
+        attempt.check()?;
+        if let Ok(value) = js_sys::Reflect::get(&chunk, &"value".into()) {
+            let bytes = js_sys::Uint8Array::new(&value).to_vec();
+            buffer.borrow_mut().extend_from_slice(&bytes);
+        }
+        events.emit(DownloadEvent::Progress(buffer.borrow().len() as u64));
+    }
+    Ok(mime_type.unwrap_or_else(|| String::from("application/octet-stream")))
+}
+
+async fn fetch_websocket(
+    client: &ProxyClient,
+    url: &str,
+    attempt: &Attempt,
+    buffer: &Rc<RefCell<Vec<u8>>>,
+    events: &Callback<DownloadEvent>,
+) -> Result<String, String> {
+    let offset = buffer.borrow().len();
+    let headers = if offset > 0 {
+        vec![(String::from("range"), format!("bytes={}-", offset))]
+    } else {
+        Vec::new()
+    };
+    let response = client.request("GET", url, headers, None).await?;
+    // The request can't be aborted, so it may outlive a pause or cancel
+    attempt.check()?;
+    if !(200..300).contains(&response.status) {
+        return Err(format!("{} {}", response.status, response.status_text));
+    }
+
+    let partial = response.status == 206;
+    let mime_type = response.header("content-type").map(String::from);
+    events.emit(DownloadEvent::Started {
+        total_bytes: Some(response.body.len() as u64 + if partial { offset as u64 } else { 0 }),
+        mime_type: mime_type.clone(),
+        filename: response.header("content-disposition").and_then(filename_from_disposition),
+        restarted: offset > 0 && !partial,
+    });
+    {
+        let mut buffer = buffer.borrow_mut();
+        if !partial {
+            buffer.clear();
+        }
+        buffer.extend_from_slice(&response.body);
+    }
+    events.emit(DownloadEvent::Progress(buffer.borrow().len() as u64));
+    Ok(mime_type.unwrap_or_else(|| String::from("application/octet-stream")))
+}
+
+pub async fn delete_file(id: u32) -> Result<(), String> {
+    idb::delete(idb::DOWNLOAD_FILES, &JsValue::from(id)).await
+}
+
+/// Saves a stored download to disk through an `<a download>` click
+pub async fn save_to_disk(id: u32, filename: &str) -> Result<(), String> {
+    let blob = idb::get(idb::DOWNLOAD_FILES, &JsValue::from(id))
+        .await?
+        .ok_or("The file is no longer stored")?
+        .dyn_into::<Blob>()
+        .map_err(|_| "The stored file is unreadable")?;
+    save_blob(&blob, filename)
+}
+
+/// Offers in-memory data to the user as a file download
+pub fn save_bytes(bytes: &[u8], mime_type: &str, filename: &str) -> Result<(), String> {
+    save_blob(&create_blob(bytes, mime_type)?, filename)
+}
+
+/// Offers a blob to the user as a file download
+pub fn save_blob(blob: &Blob, filename: &str) -> Result<(), String> {
+    let object_url = Url::create_object_url_with_blob(blob).map_err(js_error)?;
+
+    let document = web_sys::window().and_then(|w| w.document()).ok_or("No document")?;
+    let anchor = document
+        .create_element("a")
+        .map_err(js_error)?
+        .dyn_into::<HtmlAnchorElement>()
+        .map_err(|_| "Can't create link")?;
+    anchor.set_href(&object_url);
+    anchor.set_download(filename);
+    anchor.click();
+
+    // Revoke once the browser has picked up the click
+    gloo::timers::callback::Timeout::new(10_000, move || {
+        let _ = Url::revoke_object_url(&object_url);
+    })
+    .forget();
+    Ok(())
+}
+
+fn create_blob(bytes: &[u8], mime_type: &str) -> Result<Blob, String> {
+    let parts = js_sys::Array::of1(&js_sys::Uint8Array::from(bytes));
+    let options = BlobPropertyBag::new();
+    options.set_type(mime_type);
+    Blob::new_with_u8_array_sequence_and_options(&parts, &options).map_err(js_error)
+}
+
+/// Last path segment of the URL, or "download" when there isn't one
+pub fn filename_from_url(url: &str) -> String {
+    let path = url.split(['?', '#']).next().unwrap_or(url);
+    let path = path.split_once("://").map(|(_, rest)| rest).unwrap_or(path);
+    path.split_once('/')
+        .and_then(|(_, path)| path.rsplit('/').find(|s| !s.is_empty()))
+        .map(crate::proxy_config::decode_component)
+        .unwrap_or_else(|| String::from("download"))
+}
+
+fn filename_from_disposition(disposition: &str) -> Option<String> {
+    disposition
+        .split(';')
+        .map(str::trim)
+        .find_map(|part| part.strip_prefix("filename="))
+        .map(|name| name.trim_matches('"').to_string())
+        .filter(|name| !name.is_empty())
+}
+
+pub fn format_bytes(bytes: u64) -> String {
+    const UNITS: [&str; 4] = ["KB", "MB", "GB", "TB"];
+    if bytes < 1024 {
+        return format!("{} B", bytes);
+    }
+    let mut value = bytes as f64 / 1024.0;
+    let mut unit = 0;
+    while value >= 1024.0 && unit < UNITS.len() - 1 {
+        value /= 1024.0;
+        unit += 1;
+    }
+    format!("{:.1} {}", value, UNITS[unit])
+}
+
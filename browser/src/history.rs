@@ -0,0 +1,245 @@
+//! Global browsing history shown on `graphite://history`.
+
+use std::collections::HashMap;
+
+use serde::{Deserialize, Deserializer, Serialize};
+
+const DAY_MS: f64 = 24.0 * 60.0 * 60.0 * 1000.0;
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct HistoryEntry {
+    pub id: u32,
+    pub url: String,
+    pub title: String,
+    /// Milliseconds since the epoch of the most recent visit
+    pub last_visit: f64,
+    pub visit_count: u32,
+    /// Tab the most recent visit happened in
+    pub tab_id: u32,
+}
+
+/// One entry per URL, most recently visited first
+#[derive(Clone, Debug, Default, PartialEq, Serialize)]
+pub struct BrowsingHistory {
+    pub entries: Vec<HistoryEntry>,
+    pub next_id: u32,
+}
+
+impl BrowsingHistory {
+    pub fn record_visit(&mut self, url: &str, title: &str, tab_id: u32, now: f64) {
+        let entry = match self.entries.iter().position(|e| e.url == url) {
+            Some(idx) => {
+                let mut entry = self.entries.remove(idx);
+                entry.visit_count += 1;
+                entry
+            }
+            None => {
+                let id = self.next_id;
+                self.next_id += 1;
+                HistoryEntry { id, url: url.to_string(), title: String::new(), last_visit: now, visit_count: 1, tab_id }
+            }
+        };
+        self.entries.insert(0, HistoryEntry { title: title.to_string(), last_visit: now, tab_id, ..entry });
+    }
+
+    /// Adds another profile's history. Addresses visited in both keep the
+    /// later visit and count the visits from both. Returns how many
+    /// addresses were new.
+    pub fn merge(&mut self, other: BrowsingHistory) -> usize {
+        let mut positions: HashMap<String, usize> =
+            self.entries.iter().enumerate().map(|(i, e)| (e.url.clone(), i)).collect();
+        let mut added = 0;
+        for entry in other.entries {
+            match positions.get(&entry.url) {
+                Some(&i) => {
+                    let existing = &mut self.entries[i];
+                    existing.visit_count += entry.visit_count;
+                    if entry.last_visit > existing.last_visit {
+                        existing.last_visit = entry.last_visit;
+                        existing.title = entry.title;
+                    }
+                }
+                None => {
+                    positions.insert(entry.url.clone(), self.entries.len());
+                    self.entries.push(HistoryEntry { id: self.next_id, ..entry });
+                    self.next_id += 1;
+                    added += 1;
+                }
+            }
+        }
+        self.entries.sort_by(|a, b| b.last_visit.total_cmp(&a.last_visit));
+        added
+    }
+
+    /// Updates the title once the page has loaded and reported one
+    pub fn update_title(&mut self, url: &str, title: &str) {
+        if let Some(entry) = self.entries.iter_mut().find(|e| e.url == url) {
+            entry.title = title.to_string();
+        }
+    }
+
+    pub fn remove(&mut self, id: u32) -> bool {
+        let len = self.entries.len();
+        self.entries.retain(|e| e.id != id);
+        self.entries.len() != len
+    }
+
+    /// Removes entries last visited at or after `since`
+    pub fn clear_since(&mut self, since: f64) {
+        self.entries.retain(|e| e.last_visit < since);
+    }
+
+    /// Drops entries not visited within `retention_days`; zero keeps everything
+    pub fn prune(&mut self, retention_days: u32, now: f64) -> bool {
+        if retention_days == 0 {
+            return false;
+        }
+        let cutoff = now - retention_days as f64 * DAY_MS;
+        let len = self.entries.len();
+        self.entries.retain(|e| e.last_visit >= cutoff);
+        self.entries.len() != len
+    }
+
+    /// Entries whose title or URL contains every word of `query`
+    pub fn search(&self, query: &str) -> Vec<&HistoryEntry> {
+        let words: Vec<String> = query.split_whitespace().map(str::to_lowercase).collect();
+        self.entries
+            .iter()
+            .filter(|e| {
+                let title = e.title.to_lowercase();
+                let url = e.url.to_lowercase();
+                words.iter().all(|w| title.contains(w.as_str()) || url.contains(w.as_str()))
+            })
+            .collect()
+    }
+}
+
+impl<'de> Deserialize<'de> for BrowsingHistory {
+    /// Older saved states had a never-used list of URL strings here
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        #[derive(Deserialize)]
+        struct Log {
+            entries: Vec<HistoryEntry>,
+            #[serde(default)]
+            next_id: u32,
+        }
+
+        #[derive(Deserialize)]
+        #[serde(untagged)]
+        enum Stored {
+            Log(Log),
+            Legacy(#[serde(deserialize_with = "legacy_urls")] ()),
+        }
+
+        /// Only the shape matters; those URLs carry no visit times to keep
+        fn legacy_urls<'de, D: Deserializer<'de>>(deserializer: D) -> Result<(), D::Error> {
+            Vec::<String>::deserialize(deserializer).map(drop)
+        }
+
+        Ok(match Stored::deserialize(deserializer)? {
+            Stored::Log(log) => {
+                let next_id = log.entries.iter().map(|e| e.id + 1).max().unwrap_or(0).max(log.next_id);
+                Self { entries: log.entries, next_id }
+            }
+            Stored::Legacy(()) => Self::default(),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn entry(id: u32, url: &str, title: &str, last_visit: f64, visit_count: u32) -> HistoryEntry {
+        HistoryEntry { id, url: url.to_string(), title: title.to_string(), last_visit, visit_count, tab_id: 0 }
+    }
+
+    fn urls(history: &BrowsingHistory) -> Vec<&str> {
+        history.entries.iter().map(|e| e.url.as_str()).collect()
+    }
+
+    #[test]
+    fn revisits_move_to_the_front() {
+        let mut history = BrowsingHistory::default();
+        history.record_visit("https://a.test/", "A", 1, 100.0);
+        history.record_visit("https://b.test/", "B", 1, 200.0);
+        history.record_visit("https://a.test/", "A again", 2, 300.0);
+        assert_eq!(urls(&history), ["https://a.test/", "https://b.test/"]);
+        assert_eq!(history.entries[0], HistoryEntry { tab_id: 2, ..entry(0, "https://a.test/", "A again", 300.0, 2) });
+        assert_eq!(history.next_id, 2);
+    }
+
+    #[test]
+    fn merging_sums_visits_and_keeps_the_newer_title() {
+        let mut history = BrowsingHistory {
+            entries: vec![entry(0, "https://a.test/", "Old A", 300.0, 2), entry(1, "https://b.test/", "B", 100.0, 1)],
+            next_id: 2,
+        };
+        let other = BrowsingHistory {
+            entries: vec![
+                entry(0, "https://c.test/", "C", 400.0, 1),
+                entry(1, "https://a.test/", "New A", 350.0, 3),
+                entry(2, "https://b.test/", "Older B", 50.0, 4),
+            ],
+            next_id: 3,
+        };
+        assert_eq!(history.merge(other), 1);
+        assert_eq!(urls(&history), ["https://c.test/", "https://a.test/", "https://b.test/"]);
+        assert_eq!(history.entries[0], entry(2, "https://c.test/", "C", 400.0, 1));
+        assert_eq!(history.entries[1], entry(0, "https://a.test/", "New A", 350.0, 5));
+        assert_eq!(history.entries[2], entry(1, "https://b.test/", "B", 100.0, 5));
+        assert_eq!(history.next_id, 3);
+    }
+
+    #[test]
+    fn clearing_and_pruning() {
+        let day = DAY_MS;
+        let mut history = BrowsingHistory {
+            entries: vec![entry(2, "https://c.test/", "C", 10.0 * day, 1), entry(1, "https://b.test/", "B", 5.0 * day, 1), entry(0, "https://a.test/", "A", day, 1)],
+            next_id: 3,
+        };
+        // Zero keeps everything
+        let mut kept = history.clone();
+        assert!(!kept.prune(0, 100.0 * day));
+        assert_eq!(kept, history);
+        let mut pruned = history.clone();
+        assert!(pruned.prune(7, 10.0 * day));
+        assert_eq!(urls(&pruned), ["https://c.test/", "https://b.test/"]);
+        assert!(!pruned.prune(7, 10.0 * day));
+
+        history.clear_since(5.0 * day);
+        assert_eq!(urls(&history), ["https://a.test/"]);
+        assert!(history.remove(0));
+        assert!(!history.remove(0));
+    }
+
+    #[test]
+    fn search_needs_every_word() {
+        let history = BrowsingHistory {
+            entries: vec![entry(1, "https://doc.rust-lang.org/book/", "The Rust Book", 2.0, 1), entry(0, "https://example.com/", "Example", 1.0, 1)],
+            next_id: 2,
+        };
+        let found = |query: &str| -> Vec<u32> { history.search(query).iter().map(|e| e.id).collect() };
+        assert_eq!(found("rust BOOK"), [1]);
+        assert_eq!(found("rust example"), [] as [u32; 0]);
+        assert_eq!(found(""), [1, 0]);
+    }
+
+    #[test]
+    fn saved_histories_decode() {
+        // The never-used list of URLs older states kept here
+        let legacy: BrowsingHistory = serde_json::from_str(r#"["https://example.com/"]"#).unwrap();
+        assert_eq!(legacy, BrowsingHistory::default());
+        assert!(serde_json::from_str::<BrowsingHistory>("[1]").is_err());
+        assert!(serde_json::from_str::<BrowsingHistory>(r#"{"next_id": 1}"#).is_err());
+
+        // A counter behind the ids in use is moved past them
+        let json = r#"{"entries": [{"id": 4, "url": "https://a.test/", "title": "A", "last_visit": 1.0, "visit_count": 1, "tab_id": 0}], "next_id": 2}"#;
+        let history: BrowsingHistory = serde_json::from_str(json).unwrap();
+        assert_eq!(history.next_id, 5);
+        let history: BrowsingHistory = serde_json::from_str(r#"{"entries": []}"#).unwrap();
+        assert_eq!(history.next_id, 0);
+        let round_trip: BrowsingHistory = serde_json::from_str(&serde_json::to_string(&history).unwrap()).unwrap();
+        assert_eq!(round_trip, history);
+    }
+}
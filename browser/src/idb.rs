@@ -0,0 +1,180 @@
+//! Minimal promise-style helpers over the IndexedDB callback API.
+
+use std::future::Future;
+
+use wasm_bindgen::prelude::*;
+use wasm_bindgen_futures::JsFuture;
+use web_sys::{IdbDatabase, IdbFactory, IdbRequest, IdbTransaction, IdbTransactionMode};
+
+use crate::{profiles, storage};
+
+/// Name of the default profile's database; other profiles add their id
+pub const DATABASE_NAME: &str = "graphite";
+pub const DATABASE_VERSION: u32 = 2;
+
+/// Finished downloads, keyed by download id
+pub const DOWNLOAD_FILES: &str = "download_files";
+
+fn factory() -> Result<IdbFactory, String> {
+    web_sys::window()
+        .ok_or("No window")?
+        .indexed_db()
+        .map_err(js_error)?
+        .ok_or_else(|| String::from("IndexedDB is not available"))
+}
+
+/// Opens the current profile's database, creating any missing object stores
+pub async fn open() -> Result<IdbDatabase, String> {
+    let name = profiles::namespaced(DATABASE_NAME);
+    let request = factory()?.open_with_u32(&name, DATABASE_VERSION).map_err(js_error)?;
+
+    let upgrade_request = request.clone();
+    let on_upgrade = Closure::once_into_js(move |_: web_sys::Event| {
+        if let Ok(db) = upgrade_request.result().and_then(|r| r.dyn_into::<IdbDatabase>()) {
+            let existing = db.object_store_names();
+            for store in std::iter::once(DOWNLOAD_FILES).chain(storage::STORES) {
+                if !existing.contains(store) {
+                    let _ = db.create_object_store(store);
+                }
+            }
+        }
+    });
+    request.set_onupgradeneeded(Some(on_upgrade.unchecked_ref()));
+
+    let result = request_future(&request).await?;
+    result.dyn_into::<IdbDatabase>().map_err(js_error)
+}
+
+/// Deletes a whole database. Pages still holding it open delay the deletion
+/// until they close.
+pub fn delete_database(name: &str) -> Result<(), String> {
+    factory()?.delete_database(name).map(|_| ()).map_err(js_error)
+}
+
+pub async fn put(store: &str, key: &JsValue, value: &JsValue) -> Result<(), String> {
+    let db = open().await?;
+    let request = db
+        .transaction_with_str_and_mode(store, IdbTransactionMode::Readwrite)
+        .and_then(|tx| tx.object_store(store))
+        .and_then(|s| s.put_with_key(value, key))
+        .map_err(js_error)?;
+    request_future(&request).await.map(|_| ())
+}
+
+pub async fn get(store: &str, key: &JsValue) -> Result<Option<JsValue>, String> {
+    let db = open().await?;
+    let request = db
+        .transaction_with_str(store)
+        .and_then(|tx| tx.object_store(store))
+        .and_then(|s| s.get(key))
+        .map_err(js_error)?;
+    let value = request_future(&request).await?;
+    Ok((!value.is_undefined()).then_some(value))
+}
+
+pub async fn delete(store: &str, key: &JsValue) -> Result<(), String> {
+    let db = open().await?;
+    let request = db
+        .transaction_with_str_and_mode(store, IdbTransactionMode::Readwrite)
+        .and_then(|tx| tx.object_store(store))
+        .and_then(|s| s.delete(key))
+        .map_err(js_error)?;
+    request_future(&request).await.map(|_| ())
+}
+
+/// Every key and value in `store`, in key order
+pub async fn entries(store: &str) -> Result<Vec<(JsValue, JsValue)>, String> {
+    let db = open().await?;
+    let object_store = db
+        .transaction_with_str(store)
+        .and_then(|tx| tx.object_store(store))
+        .map_err(js_error)?;
+    // Both requests are made before waiting so the transaction stays open for them
+    let keys_request = object_store.get_all_keys().map_err(js_error)?;
+    let values_request = object_store.get_all().map_err(js_error)?;
+    let keys = js_sys::Array::from(&request_future(&keys_request).await?);
+    let values = js_sys::Array::from(&request_future(&values_request).await?);
+    Ok(keys.iter().zip(values.iter()).collect())
+}
+
+/// A change made by [`apply`]
+pub enum Op {
+    Put { store: &'static str, key: JsValue, value: JsValue },
+    Delete { store: &'static str, key: JsValue },
+}
+
+/// Makes every change in one transaction, so either all of them are saved or
+/// none are. The transaction starts before this returns, so it is already
+/// queued if the page is closing. Errors are returned as thrown, for the
+/// caller to inspect.
+pub fn apply(db: &IdbDatabase, ops: Vec<Op>) -> impl Future<Output = Result<(), JsValue>> {
+    let tx = start_transaction(db, ops);
+    async move { transaction_future(&tx?).await }
+}
+
+fn start_transaction(db: &IdbDatabase, ops: Vec<Op>) -> Result<IdbTransaction, JsValue> {
+    let mut stores: Vec<&str> = ops
+        .iter()
+        .map(|op| match op {
+            Op::Put { store, .. } | Op::Delete { store, .. } => *store,
+        })
+        .collect();
+    stores.sort_unstable();
+    stores.dedup();
+    let names: js_sys::Array = stores.iter().map(|s| JsValue::from_str(s)).collect();
+    let tx = db.transaction_with_str_sequence_and_mode(&names, IdbTransactionMode::Readwrite)?;
+    for op in ops {
+        match op {
+            Op::Put { store, key, value } => tx.object_store(store)?.put_with_key(&value, &key)?,
+            Op::Delete { store, key } => tx.object_store(store)?.delete(&key)?,
+        };
+    }
+    Ok(tx)
+}
+
+/// Resolves once the transaction commits, or fails with the error that aborted it
+async fn transaction_future(tx: &IdbTransaction) -> Result<(), JsValue> {
+    let promise = js_sys::Promise::new(&mut |resolve, reject| {
+        let on_complete = Closure::once_into_js(move |_: web_sys::Event| {
+            let _ = resolve.call0(&JsValue::NULL);
+        });
+        let abort_tx = tx.clone();
+        let on_abort = Closure::once_into_js(move |_: web_sys::Event| {
+            let error = abort_tx.error().map(JsValue::from).unwrap_or(JsValue::NULL);
+            let _ = reject.call1(&JsValue::NULL, &error);
+        });
+        tx.set_oncomplete(Some(on_complete.unchecked_ref()));
+        tx.set_onabort(Some(on_abort.unchecked_ref()));
+    });
+    JsFuture::from(promise).await.map(|_| ())
+}
+
+/// Resolves with the request's result once it succeeds
+async fn request_future(request: &IdbRequest) -> Result<JsValue, String> {
+    let promise = js_sys::Promise::new(&mut |resolve, reject| {
+        let success_request = request.clone();
+        let on_success = Closure::once_into_js(move |_: web_sys::Event| {
+            let result = success_request.result().unwrap_or(JsValue::UNDEFINED);
+            let _ = resolve.call1(&JsValue::NULL, &result);
+        });
+        let error_request = request.clone();
+        let on_error = Closure::once_into_js(move |_: web_sys::Event| {
+            let error = error_request.error().ok().flatten().map(JsValue::from).unwrap_or(JsValue::NULL);
+            let _ = reject.call1(&JsValue::NULL, &error);
+        });
+        request.set_onsuccess(Some(on_success.unchecked_ref()));
+        request.set_onerror(Some(on_error.unchecked_ref()));
+    });
+    JsFuture::from(promise).await.map_err(js_error)
+}
+
+/// A readable message for an error thrown by a browser API
+pub fn js_error(err: JsValue) -> String {
+    if let Some(error) = err.dyn_ref::<js_sys::Error>() {
+        return String::from(error.message());
+    }
+    if let Some(error) = err.dyn_ref::<web_sys::DomException>() {
+        return error.message();
+    }
+    err.as_string().unwrap_or_else(|| format!("{:?}", err))
+}
@@ -0,0 +1,505 @@
+//! Keyboard shortcuts.
+//!
+//! Every action that can be bound is registered in [`ACTIONS`] under a
+//! stable id, with its label, icon, default keys and the message it sends.
+//! The command palette lists the same actions. The
+//! user's changes are kept in [`Keymap`] as a list of keys per action id, so
+//! actions added later start out with their defaults. Keys are written like
+//! `Ctrl+Shift+T`, and Ctrl also stands for the Command key.
+//!
+//! The browser Graphite runs in keeps a few combinations, such as Ctrl+T and
+//! Ctrl+W, for itself and never passes them on. Actions bound to those have
+//! an Alt default as well, and the shortcuts page points out bindings that
+//! may never arrive.
+
+use std::cell::RefCell;
+use std::collections::{BTreeMap, HashMap};
+use std::fmt;
+use std::rc::Rc;
+
+use gloo::events::{EventListener, EventListenerOptions, EventListenerPhase};
+use serde::{Deserialize, Serialize};
+use wasm_bindgen::{JsCast, JsValue};
+use web_sys::{EventTarget, KeyboardEvent};
+use yew::html::Scope;
+
+use crate::{App, Msg};
+
+pub struct Action {
+    pub id: &'static str,
+    pub label: &'static str,
+    pub icon: &'static str,
+    /// Default bindings, which may be none
+    pub keys: &'static [&'static str],
+    msg: fn(&App) -> Msg,
+}
+
+impl Action {
+    pub fn msg(&self, app: &App) -> Msg {
+        (self.msg)(app)
+    }
+}
+
+pub static ACTIONS: &[Action] = &[
+    Action { id: "new-tab", label: "New tab", icon: "icon-add", keys: &["Ctrl+T", "Alt+T"], msg: |_| Msg::NewTab },
+    Action { id: "new-private-tab", label: "New private tab", icon: "icon-private", keys: &["Ctrl+Shift+N", "Alt+Shift+N"], msg: |_| Msg::NewPrivateTab },
+    Action { id: "close-tab", label: "Close tab", icon: "icon-close", keys: &["Ctrl+W", "Alt+W"], msg: |app| Msg::CloseTab(app.state.active_tab_id) },
+    Action { id: "duplicate-tab", label: "Duplicate tab", icon: "icon-tab", keys: &[], msg: |app| Msg::DuplicateTab(app.state.active_tab_id) },
+    Action { id: "pin-tab", label: "Pin or unpin tab", icon: "icon-pin", keys: &[], msg: |app| Msg::TogglePinTab(app.state.active_tab_id) },
+    Action { id: "close-other-tabs", label: "Close other tabs", icon: "icon-close", keys: &[], msg: close_others },
+    Action { id: "copy-url", label: "Copy the page address", icon: "icon-copy", keys: &[], msg: |app| Msg::CopyTabUrl(app.state.active_tab_id) },
+    Action { id: "toggle-vertical-tabs", label: "Switch between top and side tabs", icon: "icon-tab", keys: &[], msg: |app| Msg::SetTabLayout(app.state.tab_layout.other()) },
+    Action { id: "toggle-tab-sidebar", label: "Collapse or expand the tab sidebar", icon: "icon-tab", keys: &[], msg: |_| Msg::ToggleSidebar },
+    Action { id: "reopen-closed-tab", label: "Reopen closed tab", icon: "icon-undo", keys: &["Ctrl+Shift+T", "Alt+Shift+T"], msg: |_| Msg::ReopenClosed(0) },
+    Action { id: "next-tab", label: "Next tab", icon: "icon-tab", keys: &["Ctrl+Tab", "Alt+PageDown"], msg: |app| select_relative(app, 1) },
+    Action { id: "previous-tab", label: "Previous tab", icon: "icon-tab", keys: &["Ctrl+Shift+Tab", "Alt+PageUp"], msg: |app| select_relative(app, -1) },
+    Action { id: "select-tab-1", label: "First tab", icon: "icon-tab", keys: &["Ctrl+1"], msg: |app| select_at(app, 0) },
+    Action { id: "select-tab-2", label: "Second tab", icon: "icon-tab", keys: &["Ctrl+2"], msg: |app| select_at(app, 1) },
+    Action { id: "select-tab-3", label: "Third tab", icon: "icon-tab", keys: &["Ctrl+3"], msg: |app| select_at(app, 2) },
+    Action { id: "select-tab-4", label: "Fourth tab", icon: "icon-tab", keys: &["Ctrl+4"], msg: |app| select_at(app, 3) },
+    Action { id: "select-tab-5", label: "Fifth tab", icon: "icon-tab", keys: &["Ctrl+5"], msg: |app| select_at(app, 4) },
+    Action { id: "select-tab-6", label: "Sixth tab", icon: "icon-tab", keys: &["Ctrl+6"], msg: |app| select_at(app, 5) },
+    Action { id: "select-tab-7", label: "Seventh tab", icon: "icon-tab", keys: &["Ctrl+7"], msg: |app| select_at(app, 6) },
+    Action { id: "select-tab-8", label: "Eighth tab", icon: "icon-tab", keys: &["Ctrl+8"], msg: |app| select_at(app, 7) },
+    Action { id: "select-last-tab", label: "Last tab", icon: "icon-tab", keys: &["Ctrl+9"], msg: |app| select_at(app, usize::MAX) },
+    Action { id: "focus-address-bar", label: "Focus the address bar", icon: "icon-search", keys: &["Ctrl+L", "Alt+D"], msg: |_| Msg::FocusUrlBar },
+    Action { id: "back", label: "Back", icon: "icon-arrow-back", keys: &["Alt+Left"], msg: |_| Msg::GoBack },
+    Action { id: "forward", label: "Forward", icon: "icon-arrow-forward", keys: &["Alt+Right"], msg: |_| Msg::GoForward },
+    Action { id: "reload", label: "Reload", icon: "icon-refresh", keys: &["F5", "Ctrl+R"], msg: |_| Msg::Reload },
+    Action { id: "close-panels", label: "Close panels and menus", icon: "icon-close", keys: &["Escape"], msg: |_| Msg::CloseAllPanels },
+    Action { id: "command-palette", label: "Command palette", icon: "icon-search", keys: &["Ctrl+K", "Ctrl+Shift+P"], msg: |_| Msg::TogglePalette },
+    Action { id: "go-home", label: "Go home", icon: "icon-home", keys: &["Alt+Home"], msg: |_| Msg::GoHome },
+    Action { id: "bookmark-page", label: "Bookmark this page", icon: "icon-star", keys: &["Ctrl+D"], msg: |_| Msg::ToggleBookmark },
+    Action { id: "toggle-bookmarks", label: "Show bookmarks", icon: "icon-bookmarks", keys: &["Ctrl+Shift+O"], msg: |_| Msg::ToggleBookmarksPanel },
+    Action { id: "toggle-downloads", label: "Show downloads", icon: "icon-download", keys: &["Ctrl+J"], msg: |_| Msg::ToggleDownloadsPanel },
+    Action { id: "toggle-settings", label: "Show settings", icon: "icon-settings", keys: &[], msg: |_| Msg::ToggleSettingsPanel },
+    Action { id: "toggle-profiles", label: "Show profiles", icon: "icon-person", keys: &[], msg: |_| Msg::ToggleProfilesPanel },
+    Action { id: "recently-closed", label: "Show recently closed tabs", icon: "icon-undo", keys: &[], msg: |_| Msg::ToggleRecentlyClosed },
+    Action { id: "cycle-theme", label: "Switch to the next theme", icon: "icon-brightness-auto", keys: &[], msg: |_| Msg::CycleTheme },
+    Action { id: "export-bookmarks", label: "Export bookmarks", icon: "icon-bookmarks", keys: &[], msg: |_| Msg::ExportBookmarks },
+    Action { id: "export-profile", label: "Export profile", icon: "icon-person", keys: &[], msg: |_| Msg::ExportProfile },
+];
+
+/// Combinations the surrounding browser handles before the page sees them
+const HOST_RESERVED: &[&str] = &[
+    "Ctrl+N",
+    "Ctrl+Shift+N",
+    "Ctrl+T",
+    "Ctrl+Shift+T",
+    "Ctrl+W",
+    "Ctrl+Shift+W",
+    "Ctrl+Tab",
+    "Ctrl+Shift+Tab",
+    "Ctrl+Q",
+];
+
+pub fn find_action(id: &str) -> Option<&'static Action> {
+    ACTIONS.iter().find(|a| a.id == id)
+}
+
+/// Closes every unpinned tab but the active one
+fn close_others(app: &App) -> Msg {
+    let active_tab_id = app.state.active_tab_id;
+    Msg::CloseTabs(app.state.tabs.iter().filter(|t| t.id != active_tab_id && !t.pinned).map(|t| t.id).collect())
+}
+
+/// Selects the tab at `index`, or the last one when there are fewer
+fn select_at(app: &App, index: usize) -> Msg {
+    match app.state.tabs.get(index).or(app.state.tabs.last()) {
+        Some(tab) => Msg::SelectTab(tab.id),
+        None => Msg::NoOp,
+    }
+}
+
+/// Selects the tab `offset` places from the active one, wrapping around
+fn select_relative(app: &App, offset: isize) -> Msg {
+    let tabs = &app.state.tabs;
+    let Some(index) = tabs.iter().position(|t| t.id == app.state.active_tab_id) else {
+        return Msg::NoOp;
+    };
+    let index = (index as isize + offset).rem_euclid(tabs.len() as isize) as usize;
+    Msg::SelectTab(tabs[index].id)
+}
+
+/// A key together with the modifiers held down with it
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct KeyCombo {
+    /// Control, or Command on a Mac
+    pub ctrl: bool,
+    pub alt: bool,
+    pub shift: bool,
+    /// An uppercase letter, a digit, or a key name such as `ArrowLeft`
+    pub key: String,
+}
+
+impl KeyCombo {
+    pub fn parse(text: &str) -> Option<Self> {
+        let mut combo = Self { ctrl: false, alt: false, shift: false, key: String::new() };
+        let mut parts: Vec<&str> = text.split('+').map(str::trim).collect();
+        // A plus key leaves two empty parts behind; one means the key is missing
+        if parts.len() > 1 && parts[parts.len() - 2..].iter().all(|p| p.is_empty()) {
+            parts.truncate(parts.len() - 2);
+            parts.push("+");
+        }
+        let key = parts.pop()?;
+        for modifier in parts {
+            match modifier.to_ascii_lowercase().as_str() {
+                "ctrl" | "control" | "cmd" | "command" | "meta" => combo.ctrl = true,
+                "alt" | "option" => combo.alt = true,
+                "shift" => combo.shift = true,
+                _ => return None,
+            }
+        }
+        combo.key = normalize_key(key)?;
+        Some(combo)
+    }
+
+    /// The combination pressed, unless only a modifier went down
+    pub fn from_event(e: &KeyboardEvent) -> Option<Self> {
+        Self::from_parts(&e.key(), &e.code(), e.ctrl_key() || e.meta_key(), e.alt_key(), e.shift_key())
+    }
+
+    /// The combination for a key press described by its `key` and `code`,
+    /// as a proxied page's bridge reports it
+    pub fn from_parts(key: &str, code: &str, ctrl: bool, alt: bool, shift: bool) -> Option<Self> {
+        if matches!(key, "Control" | "Alt" | "AltGraph" | "Shift" | "Meta" | "CapsLock" | "Dead" | "Unidentified") {
+            return None;
+        }
+        // Letters and digits go by their position, so Shift and the layout's
+        // symbols don't change which shortcut they are
+        let key = match (code.strip_prefix("Key"), code.strip_prefix("Digit")) {
+            (Some(letter), _) if letter.len() == 1 => letter.to_string(),
+            (_, Some(digit)) if digit.len() == 1 => digit.to_string(),
+            _ => normalize_key(key)?,
+        };
+        Some(Self { ctrl, alt, shift, key })
+    }
+
+    /// Whether the combination may be taken while text is being typed. Plain
+    /// and Shift keys type characters, so only Escape and function keys are.
+    pub fn works_while_typing(&self) -> bool {
+        self.ctrl || self.alt || self.key == "Escape" || is_function_key(&self.key)
+    }
+
+    /// Whether the surrounding browser is likely to keep this for itself
+    pub fn is_host_reserved(&self) -> bool {
+        HOST_RESERVED.iter().any(|reserved| KeyCombo::parse(reserved).as_ref() == Some(self))
+    }
+}
+
+impl fmt::Display for KeyCombo {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.ctrl {
+            f.write_str("Ctrl+")?;
+        }
+        if self.alt {
+            f.write_str("Alt+")?;
+        }
+        if self.shift {
+            f.write_str("Shift+")?;
+        }
+        f.write_str(self.key.strip_prefix("Arrow").unwrap_or(&self.key))
+    }
+}
+
+fn normalize_key(key: &str) -> Option<String> {
+    if key.is_empty() {
+        return None;
+    }
+    let key = match key.to_ascii_lowercase().as_str() {
+        "left" | "right" | "up" | "down" => format!("Arrow{}{}", key[..1].to_ascii_uppercase(), key[1..].to_ascii_lowercase()),
+        "esc" => String::from("Escape"),
+        " " | "space" => String::from("Space"),
+        _ if key.chars().count() == 1 => key.to_uppercase(),
+        _ if is_function_key(&key.to_ascii_uppercase()) => key.to_ascii_uppercase(),
+        _ => {
+            let mut chars = key.chars();
+            let first = chars.next()?;
+            first.to_uppercase().chain(chars).collect()
+        }
+    };
+    Some(key)
+}
+
+fn is_function_key(key: &str) -> bool {
+    key.strip_prefix('F').is_some_and(|n| !n.is_empty() && n.chars().all(|c| c.is_ascii_digit()))
+}
+
+/// The user's bindings, by action id. Actions that aren't listed use their
+/// defaults; an empty list unbinds one.
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+#[serde(transparent)]
+pub struct Keymap {
+    overrides: BTreeMap<String, Vec<String>>,
+}
+
+impl Keymap {
+    pub fn keys(&self, action: &Action) -> Vec<KeyCombo> {
+        match self.overrides.get(action.id) {
+            Some(keys) => keys.iter().filter_map(|k| KeyCombo::parse(k)).collect(),
+            None => action.keys.iter().filter_map(|k| KeyCombo::parse(k)).collect(),
+        }
+    }
+
+    pub fn is_customized(&self, action: &Action) -> bool {
+        self.overrides.contains_key(action.id)
+    }
+
+    /// The action a combination runs; the first registered wins a conflict
+    pub fn action_for(&self, combo: &KeyCombo) -> Option<&'static Action> {
+        ACTIONS.iter().find(|a| self.keys(a).contains(combo))
+    }
+
+    /// Combinations bound to more than one action, as can happen when
+    /// defaults change or a profile is imported
+    pub fn conflicts(&self) -> Vec<(KeyCombo, Vec<&'static Action>)> {
+        let mut conflicts: Vec<(KeyCombo, Vec<&'static Action>)> = Vec::new();
+        for action in ACTIONS {
+            for combo in self.keys(action) {
+                match conflicts.iter_mut().find(|(c, _)| *c == combo) {
+                    Some((_, actions)) => actions.push(action),
+                    None => conflicts.push((combo, vec![action])),
+                }
+            }
+        }
+        conflicts.retain(|(_, actions)| actions.len() > 1);
+        conflicts
+    }
+
+    /// Binds another combination to an action, unless a different action
+    /// already has it or it would type a character
+    pub fn add(&mut self, action: &Action, combo: KeyCombo) -> Result<(), String> {
+        if !combo.works_while_typing() {
+            return Err(format!("{} would get in the way of typing. Add Ctrl or Alt to it.", combo));
+        }
+        if let Some(other) = self.action_for(&combo) {
+            if other.id == action.id {
+                return Ok(());
+            }
+            return Err(format!("{} is already the shortcut for {}", combo, other.label));
+        }
+        let mut keys = self.keys(action);
+        keys.push(combo);
+        self.set(action, keys);
+        Ok(())
+    }
+
+    pub fn remove(&mut self, action: &Action, combo: &KeyCombo) {
+        let mut keys = self.keys(action);
+        keys.retain(|k| k != combo);
+        self.set(action, keys);
+    }
+
+    pub fn reset(&mut self, action: &Action) {
+        self.overrides.remove(action.id);
+    }
+
+    pub fn reset_all(&mut self) {
+        self.overrides.clear();
+    }
+
+    /// What the key listener looks combinations up in
+    pub fn table(&self) -> HashMap<KeyCombo, &'static Action> {
+        let mut table = HashMap::new();
+        for action in ACTIONS {
+            for combo in self.keys(action) {
+                table.entry(combo).or_insert(action);
+            }
+        }
+        table
+    }
+
+    fn set(&mut self, action: &Action, keys: Vec<KeyCombo>) {
+        let defaults: Vec<KeyCombo> = action.keys.iter().filter_map(|k| KeyCombo::parse(k)).collect();
+        if keys == defaults {
+            self.overrides.remove(action.id);
+        } else {
+            self.overrides.insert(action.id.to_string(), keys.iter().map(KeyCombo::to_string).collect());
+        }
+    }
+}
+
+/// What the key listeners need from the app, kept up to date by it
+#[derive(Default)]
+pub struct Dispatch {
+    pub table: HashMap<KeyCombo, &'static Action>,
+    /// The shortcuts page is waiting for a combination to bind
+    pub recording: bool,
+}
+
+impl Dispatch {
+    /// What a key press means, if anything. `typing` is whether it went to
+    /// a text field.
+    pub fn msg_for(&self, combo: KeyCombo, typing: bool) -> Option<Msg> {
+        if self.recording {
+            return Some(Msg::ShortcutPressed(combo));
+        }
+        match self.table.get(&combo) {
+            Some(action) if combo.works_while_typing() || !typing => Some(Msg::RunAction(action.id)),
+            _ => None,
+        }
+    }
+}
+
+/// Runs shortcuts for key presses on `target`, the window or the document of
+/// a same-origin frame. Keys that aren't bound are left to the page.
+pub fn listen(target: &EventTarget, dispatch: Rc<RefCell<Dispatch>>, link: Scope<App>) -> EventListener {
+    // Capturing, so pages and text fields can't swallow shortcuts first
+    let options = EventListenerOptions { phase: EventListenerPhase::Capture, passive: false };
+    EventListener::new_with_options(target, "keydown", options, move |e| {
+        // Only keydowns arrive here, and a frame's events would fail an
+        // instanceof check against this window's KeyboardEvent
+        let e = e.unchecked_ref::<KeyboardEvent>();
+        let Some(combo) = KeyCombo::from_event(e) else {
+            return;
+        };
+        // The borrow ends before the message is handled, which may update it
+        let Some(msg) = dispatch.borrow().msg_for(combo, is_typing(e)) else {
+            return;
+        };
+        if matches!(msg, Msg::ShortcutPressed(_)) {
+            e.stop_propagation();
+        }
+        e.prevent_default();
+        link.send_message(msg);
+    })
+}
+
+/// Whether the key went to a text field. Read through plain properties,
+/// since elements in a frame aren't instances of this window's classes.
+fn is_typing(e: &KeyboardEvent) -> bool {
+    let Some(target) = e.target() else {
+        return false;
+    };
+    let property = |name: &str| js_sys::Reflect::get(&target, &JsValue::from_str(name)).ok();
+    property("isContentEditable").and_then(|v| v.as_bool()).unwrap_or(false)
+        || property("tagName")
+            .and_then(|v| v.as_string())
+            .is_some_and(|tag| matches!(tag.to_ascii_lowercase().as_str(), "input" | "textarea" | "select"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn combo(text: &str) -> KeyCombo {
+        KeyCombo::parse(text).unwrap_or_else(|| panic!("{text} doesn't parse"))
+    }
+
+    fn action(id: &str) -> &'static Action {
+        find_action(id).unwrap()
+    }
+
+    #[test]
+    fn combos_parse_and_print() {
+        for (text, printed) in [
+            ("Ctrl+Shift+T", "Ctrl+Shift+T"),
+            ("ctrl+t", "Ctrl+T"),
+            ("Cmd+Alt+k", "Ctrl+Alt+K"),
+            ("Ctrl++", "Ctrl++"),
+            ("+", "+"),
+            ("Alt+Left", "Alt+Left"),
+            ("Alt + ArrowRight", "Alt+Right"),
+            ("esc", "Escape"),
+            ("f12", "F12"),
+            ("Ctrl+space", "Ctrl+Space"),
+            ("Alt+pageDown", "Alt+PageDown"),
+        ] {
+            let parsed = combo(text);
+            assert_eq!(parsed.to_string(), printed);
+            assert_eq!(combo(printed), parsed);
+        }
+        assert_eq!(combo("Ctrl++"), KeyCombo { ctrl: true, alt: false, shift: false, key: String::from("+") });
+        assert_eq!(combo("Alt+Left").key, "ArrowLeft");
+        assert_eq!(KeyCombo::parse(""), None);
+        assert_eq!(KeyCombo::parse("Ctrl+"), None);
+        assert_eq!(KeyCombo::parse("Hyper+T"), None);
+    }
+
+    #[test]
+    fn presses_go_by_key_position() {
+        // Shift turns 1 into ! on most layouts
+        assert_eq!(KeyCombo::from_parts("!", "Digit1", true, false, true), Some(combo("Ctrl+Shift+1")));
+        // and other layouts put other letters there
+        assert_eq!(KeyCombo::from_parts("ф", "KeyA", true, false, false), Some(combo("Ctrl+A")));
+        assert_eq!(KeyCombo::from_parts("T", "KeyT", false, true, true), Some(combo("Alt+Shift+T")));
+        assert_eq!(KeyCombo::from_parts("ArrowLeft", "ArrowLeft", false, true, false), Some(combo("Alt+Left")));
+        assert_eq!(KeyCombo::from_parts("+", "Equal", true, false, true), Some(combo("Ctrl+Shift++")));
+        assert_eq!(KeyCombo::from_parts("Escape", "Escape", false, false, false), Some(combo("Escape")));
+        assert_eq!(KeyCombo::from_parts("Shift", "ShiftLeft", false, false, true), None);
+        assert_eq!(KeyCombo::from_parts("Dead", "Quote", false, false, false), None);
+    }
+
+    #[test]
+    fn typing_and_host_keys() {
+        assert!(combo("Ctrl+T").works_while_typing());
+        assert!(combo("Escape").works_while_typing());
+        assert!(combo("F5").works_while_typing());
+        assert!(!combo("Shift+A").works_while_typing());
+        assert!(!combo("F").works_while_typing());
+        assert!(combo("Cmd+t").is_host_reserved());
+        assert!(!combo("Alt+T").is_host_reserved());
+    }
+
+    #[test]
+    fn adding_refuses_taken_and_typing_keys() {
+        let mut keymap = Keymap::default();
+        let reload = action("reload");
+        assert_eq!(keymap.add(reload, combo("Ctrl+T")), Err(String::from("Ctrl+T is already the shortcut for New tab")));
+        assert!(keymap.add(reload, combo("Shift+R")).unwrap_err().contains("in the way of typing"));
+        assert!(!keymap.is_customized(reload));
+        // Its own keys are already there
+        assert_eq!(keymap.add(reload, combo("F5")), Ok(()));
+        assert!(!keymap.is_customized(reload));
+
+        assert_eq!(keymap.add(reload, combo("Ctrl+Shift+Y")), Ok(()));
+        assert_eq!(keymap.keys(reload), [combo("F5"), combo("Ctrl+R"), combo("Ctrl+Shift+Y")]);
+        assert_eq!(keymap.action_for(&combo("Ctrl+Shift+Y")).map(|a| a.id), Some("reload"));
+        assert_eq!(keymap.table().get(&combo("Ctrl+Shift+Y")).map(|a| a.id), Some("reload"));
+    }
+
+    #[test]
+    fn matching_the_defaults_drops_the_override() {
+        let mut keymap = Keymap::default();
+        let reload = action("reload");
+        keymap.remove(reload, &combo("Ctrl+R"));
+        assert!(keymap.is_customized(reload));
+        assert_eq!(keymap.keys(reload), [combo("F5")]);
+        keymap.add(reload, combo("Ctrl+R")).unwrap();
+        assert!(!keymap.is_customized(reload));
+        assert_eq!(keymap, Keymap::default());
+
+        // Unbinding everything is kept as an empty list
+        let bookmark = action("bookmark-page");
+        keymap.remove(bookmark, &combo("Ctrl+D"));
+        assert!(keymap.keys(bookmark).is_empty());
+        assert_eq!(serde_json::to_string(&keymap).unwrap(), r#"{"bookmark-page":[]}"#);
+        keymap.reset(bookmark);
+        assert_eq!(keymap.keys(bookmark), [combo("Ctrl+D")]);
+    }
+
+    #[test]
+    fn combos_bound_twice_are_conflicts() {
+        assert!(Keymap::default().conflicts().is_empty());
+        // Only saved or imported bindings can clash
+        let keymap: Keymap = serde_json::from_str(r#"{"reload": ["F5", "Ctrl+T"], "go-home": ["ctrl+t"]}"#).unwrap();
+        let conflicts: Vec<(String, Vec<&str>)> = keymap
+            .conflicts()
+            .into_iter()
+            .map(|(combo, actions)| (combo.to_string(), actions.iter().map(|a| a.id).collect()))
+            .collect();
+        assert_eq!(conflicts, [(String::from("Ctrl+T"), vec!["new-tab", "reload", "go-home"])]);
+        // The first registered wins
+        assert_eq!(keymap.action_for(&combo("Ctrl+T")).map(|a| a.id), Some("new-tab"));
+    }
+
+    #[test]
+    fn dispatch_leaves_typing_alone() {
+        let mut dispatch = Dispatch { table: Keymap::default().table(), recording: false };
+        assert!(matches!(dispatch.msg_for(combo("Ctrl+K"), true), Some(Msg::RunAction("command-palette"))));
+        assert!(matches!(dispatch.msg_for(combo("Escape"), true), Some(Msg::RunAction("close-panels"))));
+        assert!(dispatch.msg_for(combo("Ctrl+Shift+Y"), false).is_none());
+        dispatch.recording = true;
+        assert!(matches!(dispatch.msg_for(combo("Shift+A"), true), Some(Msg::ShortcutPressed(c)) if c == combo("Shift+A")));
+    }
+}
@@ -1,10 +1,57 @@
-use gloo::storage::{LocalStorage, Storage};
+use std::cell::{Cell, RefCell};
+use std::collections::{HashMap, HashSet};
+use std::rc::Rc;
+
+use gloo::events::{EventListener, EventListenerOptions, EventListenerPhase};
+use futures::future::{FutureExt, LocalBoxFuture};
 use serde::{Deserialize, Serialize};
 use wasm_bindgen::prelude::*;
-use web_sys::{HtmlInputElement, MouseEvent};
+use web_sys::{HtmlIFrameElement, HtmlInputElement, HtmlLinkElement, HtmlSelectElement, MouseEvent};
 use yew::prelude::*;
 
+mod address;
+mod bookmarks;
 mod components;
+mod downloads;
+mod history;
+mod idb;
+mod keymap;
+mod omnibox;
+mod opensearch;
+mod pages;
+mod palette;
+mod proxy;
+mod profile;
+mod profiles;
+mod proxy_config;
+mod schema;
+mod search_engines;
+mod session;
+mod storage;
+mod tab_groups;
+mod tab_strip;
+mod theme;
+mod xml;
+
+pub use bookmarks::{Bookmark, BookmarkFolder, BookmarkNode, Bookmarks};
+use bookmarks::BOOKMARK_BAR_ID;
+pub use downloads::{Download, DownloadStatus};
+use downloads::{DownloadEvent, DownloadJob, Transport};
+pub use history::{BrowsingHistory, HistoryEntry};
+use keymap::{KeyCombo, Keymap};
+use address::InputKind;
+use omnibox::{Candidate, Suggestion, SuggestionKind};
+use palette::Target;
+use profile::ImportMode;
+use proxy::{BridgeMessage, ProxyClient};
+pub use search_engines::SearchEngine;
+use search_engines::EngineField;
+use session::ClosedEntry;
+use storage::{StorageBackend, StorageError};
+use tab_groups::{GroupColor, SavedGroup, TabGroup};
+use tab_strip::TabLayout;
+pub use theme::{CustomTheme, Theme};
+use proxy_config::{ProxyConfig, ProxyKind, UrlCodec};
 
 #[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
 pub struct Tab {
@@ -13,62 +60,152 @@ pub struct Tab {
     pub url: String,
     pub favicon: Option<String>,
     pub is_loading: bool,
+    /// Back/forward stack for this tab, oldest entry first
+    #[serde(default)]
+    pub history: Vec<String>,
+    /// Position of the current page within `history`
+    #[serde(default)]
+    pub history_index: usize,
+    /// Whether the tab's frame has been unloaded to save memory; it is
+    /// reloaded lazily the next time the tab is selected
+    #[serde(skip)]
+    pub discarded: bool,
+    /// Private tabs leave no trace: they are never saved and their visits
+    /// aren't recorded
+    #[serde(default)]
+    pub private: bool,
+    /// Pinned tabs sit at the left, show only their icon and are only
+    /// closed when asked to explicitly
+    #[serde(default)]
+    pub pinned: bool,
+    /// The [`TabGroup`] the tab belongs to
+    #[serde(default)]
+    pub group: Option<u32>,
+    /// The tab it was opened from, which it sits under in the vertical
+    /// layout's tree
+    #[serde(default)]
+    pub opener: Option<u32>,
+    /// The page's audio and video are kept muted
+    #[serde(default)]
+    pub muted: bool,
 }
 
 impl Default for Tab {
     fn default() -> Self {
         Self {
             id: 0,
-            title: String::from("Home"),
-            url: String::from("graphite://home"),
+            title: pages::find(pages::HOME_URL).title.to_string(),
+            url: String::from(pages::HOME_URL),
             favicon: None,
             is_loading: false,
+            history: vec![String::from(pages::HOME_URL)],
+            history_index: 0,
+            discarded: false,
+            private: false,
+            pinned: false,
+            group: None,
+            opener: None,
+            muted: false,
         }
     }
 }
 
-#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
-pub struct Download {
-    pub id: u32,
-    pub filename: String,
-    pub completed: bool,
-}
+impl Tab {
+    /// Records a navigation to `url`, dropping any forward entries
+    fn push_history(&mut self, url: &str) {
+        // Tabs restored from older saved state have no stack yet
+        if self.history.is_empty() {
+            self.history.push(self.url.clone());
+            self.history_index = 0;
+        }
+        self.history.truncate(self.history_index + 1);
+        if self.history.last().map(|u| u != url).unwrap_or(true) {
+            self.history.push(url.to_string());
+        }
+        self.history_index = self.history.len() - 1;
+    }
 
-#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
-pub enum SearchEngine {
-    Yahoo,
-    Google,
-    Bing,
-    DuckDuckGo,
-    Brave,
-}
+    /// A new tab that was never navigated anywhere
+    fn is_blank(&self) -> bool {
+        self.url == pages::HOME_URL && self.history.len() <= 1
+    }
 
-impl SearchEngine {
-    fn get_search_url(&self, query: &str) -> String {
-        let encoded = js_sys::encode_uri_component(query);
-        match self {
-            SearchEngine::Yahoo => format!("https://search.yahoo.com/search?p={}", encoded),
-            SearchEngine::Google => format!("https://www.google.com/search?q={}", encoded),
-            SearchEngine::Bing => format!("https://www.bing.com/search?q={}", encoded),
-            SearchEngine::DuckDuckGo => format!("https://duckduckgo.com/?q={}", encoded),
-            SearchEngine::Brave => format!("https://search.brave.com/search?q={}", encoded),
-        }
+    /// Whether the tab currently needs an iframe in the content area
+    fn has_live_frame(&self) -> bool {
+        !self.discarded && !pages::is_internal(&self.url)
+    }
+
+    fn can_go_back(&self) -> bool {
+        self.history_index > 0 && self.history_index < self.history.len()
+    }
+
+    fn can_go_forward(&self) -> bool {
+        self.history_index + 1 < self.history.len()
     }
 
-    fn get_icon(&self) -> &'static str {
-        match self {
-            SearchEngine::Yahoo => "https://www.yahoo.com/favicon.ico",
-            SearchEngine::Google => "https://www.google.com/favicon.ico",
-            SearchEngine::Bing => "https://www.bing.com/favicon.ico",
-            SearchEngine::DuckDuckGo => "https://duckduckgo.com/favicon.ico",
-            SearchEngine::Brave => "https://brave.com/static-assets/images/brave-favicon.png",
+    /// Moves `offset` entries through the history stack and loads that page
+    fn step_history(&mut self, offset: isize) -> bool {
+        let target = self.history_index as isize + offset;
+        if target < 0 || target as usize >= self.history.len() {
+            return false;
         }
+        self.history_index = target as usize;
+        self.url = self.history[self.history_index].clone();
+        self.title = App::get_title_from_url(&self.url);
+        self.favicon = None;
+        self.is_loading = !pages::is_internal(&self.url);
+        true
     }
 }
 
-impl Default for SearchEngine {
-    fn default() -> Self {
-        SearchEngine::Google
+/// Title, icon and search engine link read out of a loaded frame's document
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct FrameMetadata {
+    /// Address the document was loaded from, which may be a proxied URL
+    pub url: Option<String>,
+    pub title: Option<String>,
+    pub favicon: Option<String>,
+    /// `href` of the page's OpenSearch description link, as written
+    pub search_description: Option<String>,
+}
+
+impl FrameMetadata {
+    /// Reads the document title and icon link, which is only possible when the
+    /// frame is same-origin with the browser. WebSocket-proxied pages never
+    /// are, and report through their bridge script instead.
+    fn read(iframe: &HtmlIFrameElement) -> Self {
+        let Some(document) = iframe.content_document() else {
+            return Self::default();
+        };
+        let url = document.url().ok();
+        let title = Some(document.title().trim().to_string()).filter(|t| !t.is_empty());
+        let favicon = document
+            .query_selector("link[rel~='icon']")
+            .ok()
+            .flatten()
+            .and_then(|el| el.dyn_into::<HtmlLinkElement>().ok())
+            .map(|link| link.href())
+            .filter(|href| !href.is_empty());
+        let search_description = document
+            .query_selector(&format!("link[rel~='search'][type='{}']", opensearch::DESCRIPTION_TYPE))
+            .ok()
+            .flatten()
+            .and_then(|el| el.get_attribute("href"))
+            .map(|href| href.trim().to_string())
+            .filter(|href| !href.is_empty());
+        Self { url, title, favicon, search_description }
+    }
+}
+
+/// Mutes a media element and marks it as muted by the browser, or unmutes
+/// one the browser muted
+fn set_media_muted(media: &web_sys::HtmlMediaElement, muted: bool) {
+    if !muted {
+        media.set_muted(false);
+        let _ = media.remove_attribute(proxy::MUTED_ATTRIBUTE);
+    } else if !media.muted() {
+        media.set_muted(true);
+        let _ = media.set_attribute(proxy::MUTED_ATTRIBUTE, "");
     }
 }
 
@@ -77,11 +214,69 @@ pub struct BrowserState {
     pub tabs: Vec<Tab>,
     pub active_tab_id: u32,
     pub next_tab_id: u32,
-    pub search_engine: SearchEngine,
-    pub proxy_server: String,
+    /// Id of the selected engine. Older states saved the name of a built-in
+    /// engine here, which [`search_engines::find`] still accepts.
+    #[serde(default = "default_search_engine")]
+    pub search_engine: String,
+    /// Engines the user added, shown after the built-in ones
+    #[serde(default)]
+    pub custom_search_engines: Vec<SearchEngine>,
+    #[serde(default, alias = "proxy_server")]
+    pub proxy: ProxyConfig,
+    #[serde(default)]
     pub downloads: Vec<Download>,
-    pub history: Vec<String>,
-    pub history_index: usize,
+    #[serde(default)]
+    pub next_download_id: u32,
+    /// Global log of visited pages, shared by all tabs
+    #[serde(default)]
+    pub history: BrowsingHistory,
+    /// Days before unvisited history entries are pruned; zero keeps them forever
+    #[serde(default = "default_history_retention_days")]
+    pub history_retention_days: u32,
+    /// Maximum number of tabs that keep a loaded iframe in the background
+    #[serde(default = "default_max_live_frames")]
+    pub max_live_frames: usize,
+    #[serde(default)]
+    pub theme: Theme,
+    #[serde(default)]
+    pub custom_theme: Option<CustomTheme>,
+    #[serde(default)]
+    pub bookmarks: Bookmarks,
+    /// Closed tabs and set-aside sessions, most recent first
+    #[serde(default)]
+    pub recently_closed: Vec<ClosedEntry>,
+    /// Keyboard shortcuts the user changed from their defaults
+    #[serde(default)]
+    pub keymap: Keymap,
+    #[serde(default)]
+    pub tab_groups: Vec<TabGroup>,
+    #[serde(default)]
+    pub next_group_id: u32,
+    /// Groups closed to be restored later, most recent first
+    #[serde(default)]
+    pub saved_groups: Vec<SavedGroup>,
+    #[serde(default)]
+    pub tab_layout: TabLayout,
+    /// The vertical layout's sidebar shows only tab icons
+    #[serde(default)]
+    pub sidebar_collapsed: bool,
+}
+
+/// Typing pause before the search engine is asked for suggestions
+const SUGGEST_DELAY_MS: u32 = 150;
+/// How long changes are collected before they are written to storage
+const SAVE_DELAY_MS: u32 = 500;
+
+fn default_search_engine() -> String {
+    String::from(search_engines::DEFAULT_ENGINE_ID)
+}
+
+fn default_max_live_frames() -> usize {
+    6
+}
+
+fn default_history_retention_days() -> u32 {
+    90
 }
 
 impl Default for BrowserState {
@@ -90,39 +285,171 @@ impl Default for BrowserState {
             tabs: vec![Tab::default()],
             active_tab_id: 0,
             next_tab_id: 1,
-            search_engine: SearchEngine::default(),
-            proxy_server: String::new(),
-            downloads: vec![
-                Download { id: 0, filename: "google.png".into(), completed: true },
-                Download { id: 1, filename: "graphiteiscool.txt".into(), completed: true },
-                Download { id: 2, filename: "vscode.exe".into(), completed: true },
-            ],
-            history: vec![],
-            history_index: 0,
+            search_engine: default_search_engine(),
+            custom_search_engines: Vec::new(),
+            proxy: ProxyConfig::default(),
+            downloads: vec![],
+            next_download_id: 0,
+            history: BrowsingHistory::default(),
+            history_retention_days: default_history_retention_days(),
+            max_live_frames: default_max_live_frames(),
+            theme: Theme::default(),
+            custom_theme: None,
+            bookmarks: Bookmarks::default(),
+            recently_closed: Vec::new(),
+            keymap: Keymap::default(),
+            tab_groups: Vec::new(),
+            next_group_id: 0,
+            saved_groups: Vec::new(),
+            tab_layout: TabLayout::default(),
+            sidebar_collapsed: false,
         }
     }
 }
 
 pub enum Msg {
     NewTab,
+    NewPrivateTab,
+    /// Closes a tab unless it is pinned
     CloseTab(u32),
+    /// Closes tabs whether or not they are pinned
+    CloseTabs(Vec<u32>),
     SelectTab(u32),
+    /// Opens a link from a tab's page in a new tab under it, in the
+    /// background or not
+    OpenInNewTab(String, u32, bool),
+    SetTabLayout(TabLayout),
+    ToggleSidebar,
+    /// Scrolls the horizontal strip left (negative) or right by a page
+    ScrollTabs(i32),
+    /// Checks whether the horizontal strip has more tabs than fit
+    MeasureTabStrip,
+    ToggleAllTabs,
+    /// Opens the tab context menu at the pointer's viewport coordinates, since the menu is fixed
+    ShowTabMenu(u32, i32, i32),
+    DuplicateTab(u32),
+    TogglePinTab(u32),
+    ToggleMuteTab(u32),
+    ReloadTab(u32),
+    CopyTabUrl(u32),
+    /// Bookmarks a tab's page, or removes the bookmark it already has
+    BookmarkTab(u32),
+    /// Moves a tab into a group, or into a new one
+    AddToGroup(u32, Option<u32>),
+    RemoveFromGroup(u32),
+    ToggleGroupCollapsed(u32),
+    /// Opens a group's menu at the pointer's viewport coordinates, like the tab menu
+    ShowGroupMenu(u32, i32, i32),
+    RenameGroup(u32, String),
+    SetGroupColor(u32, GroupColor),
+    /// Dissolves a group, leaving its tabs open
+    Ungroup(u32),
+    CloseGroup(u32),
+    /// Closes a group's tabs, keeping them to be restored together
+    SaveGroup(u32),
+    RestoreGroup(usize),
+    DeleteSavedGroup(usize),
+    ToggleSavedGroups,
     Navigate(String),
     GoBack,
     GoForward,
     Reload,
+    FrameLoaded(u32, FrameMetadata),
+    /// A message posted to the window, with the window that sent it
+    FrameMessage(js_sys::Object, BridgeMessage),
+    FrameError(u32),
+    ProxyLoaded(u32, String, Result<String, String>),
     GoHome,
     UpdateUrlBar(String),
-    SetSearchEngine(SearchEngine),
-    SetProxyServer(String),
+    /// Asks the search engine for suggestions, unless typing has moved on
+    FetchSuggestions(u32),
+    SearchSuggestionsLoaded(u32, Vec<String>),
+    MoveSuggestion(isize),
+    AcceptSuggestion(usize),
+    DismissSuggestions,
+    SetSearchEngine(String),
+    /// Opens the engine form for an existing custom engine, or a new one
+    EditSearchEngine(Option<String>),
+    UpdateEngineDraft(EngineField, String),
+    SaveSearchEngine,
+    CancelEngineEdit,
+    RemoveSearchEngine(String),
+    SearchDescriptionLoaded(String, Result<String, String>),
+    /// Adds an engine found on a visited page, by its place in the offers
+    AddDiscoveredEngine(usize),
+    SetProxyConfig(ProxyConfig),
+    SetMaxLiveFrames(usize),
     ToggleSettingsPanel,
     ToggleDownloadsPanel,
+    StartDownload(String),
+    DownloadProgress(u32, u32, DownloadEvent),
+    PauseDownload(u32),
+    ResumeDownload(u32),
+    CancelDownload(u32),
     DeleteDownload(u32),
     OpenDownloadFolder(u32),
     DragStart(u32),
     DragOver(u32),
     DragEnd,
+    CycleTheme,
+    SetTheme(Theme),
+    SystemThemeChanged(bool),
+    ImportTheme(String),
+    ExportTheme,
+    ResetTheme,
+    ToggleBookmarksPanel,
+    ToggleBookmark,
+    ToggleBookmarkFolder(u32),
+    AddBookmarkFolder(String),
+    MoveBookmark(u32, u32),
+    DeleteBookmark(u32),
+    ImportBookmarks(String),
+    ExportBookmarks,
+    ExportProfile,
+    /// Contents of a profile file the user picked
+    ImportProfile(String),
+    ApplyProfileImport(ImportMode),
+    CancelProfileImport,
+    ToggleProfilesPanel,
+    AddProfile(String),
+    /// Saves the state and reopens the browser in another profile
+    SwitchProfile(String),
+    /// Deletes a profile other than the current one, with all its data
+    RemoveProfile(String),
+    SetHistoryQuery(String),
+    DeleteHistoryEntry(u32),
+    /// Clears history visited within the given number of milliseconds, or all of it
+    ClearHistory(Option<f64>),
+    SetHistoryRetention(u32),
+    /// Reopens an entry of the recently closed list
+    ReopenClosed(usize),
+    ToggleRecentlyClosed,
+    /// Brings back the tabs set aside after an unclean shutdown
+    RestoreSession,
+    DismissRestore,
+    DismissStateNotice,
+    StateLoaded(Box<storage::Loaded>),
+    /// Writes whatever changed since the last save
+    FlushState,
+    /// The page is being hidden and may not come back
+    PageHidden,
+    /// A write finished; carries the records it deleted, in case it failed
+    StateSaved(Result<(), StorageError>, Vec<(&'static str, String)>),
     CloseAllPanels,
+    /// Runs a keyboard shortcut's action, by its id
+    RunAction(&'static str),
+    FocusUrlBar,
+    /// Waits for the next key combination to bind to an action, or stops waiting
+    RecordShortcut(Option<&'static str>),
+    ShortcutPressed(KeyCombo),
+    RemoveShortcut(&'static str, KeyCombo),
+    /// Restores an action's default keys, or every action's
+    ResetShortcuts(Option<&'static str>),
+    TogglePalette,
+    SetPaletteQuery(String),
+    MovePaletteSelection(isize),
+    /// Runs a row of the command palette and closes it
+    RunPaletteEntry(usize),
     NoOp,
 }
 
@@ -131,451 +458,3897 @@ pub struct App {
     url_input: String,
     show_settings: bool,
     show_downloads: bool,
+    show_bookmarks: bool,
+    /// Bookmark bar folder whose dropdown is open
+    open_bookmark_folder: Option<u32>,
+    bookmark_message: Option<String>,
+    show_recently_closed: bool,
+    /// Tab whose context menu is open, and where
+    tab_menu: Option<(u32, i32, i32)>,
+    /// Group whose menu is open, and where
+    group_menu: Option<(u32, i32, i32)>,
+    show_saved_groups: bool,
+    tab_strip_ref: NodeRef,
+    /// The horizontal strip's tabs don't fit, so it scrolls
+    tab_overflow: bool,
+    /// The dropdown listing every tab
+    show_all_tabs: bool,
+    /// The tab last scrolled into view, so it only happens on a change
+    revealed_tab: Option<u32>,
+    /// The last session didn't shut down, so its tabs wait on the user
+    offer_restore: bool,
+    /// Why the saved state couldn't be loaded or saved
+    state_notice: Option<String>,
+    /// Where the state is saved, `None` until it has been loaded
+    storage: Option<Rc<dyn StorageBackend>>,
+    /// The records the storage holds, so saves only write what changed
+    written: storage::Written,
+    save_scheduled: Cell<bool>,
+    /// Writes handed to the storage that haven't finished
+    writes_in_flight: usize,
+    /// The state was read from the old single-string format, which is
+    /// removed once it has been saved the new way
+    remove_legacy: bool,
+    link: yew::html::Scope<Self>,
+    history_query: String,
+    /// Omnibox dropdown rows for the current `url_input`
+    suggestions: Vec<Suggestion>,
+    selected_suggestion: Option<usize>,
+    search_suggestions: Vec<String>,
+    /// Bumped on every keystroke so slow suggestion requests can be discarded
+    suggest_generation: u32,
     dragging_tab: Option<u32>,
+    frame_refs: HashMap<u32, NodeRef>,
+    /// Tab ids ordered from least to most recently selected
+    recent_tabs: Vec<u32>,
+    /// The src last assigned to each tab's iframe
+    frame_srcs: HashMap<u32, String>,
+    proxy_client: Option<ProxyClient>,
+    proxy_frames: HashMap<u32, ProxyFrame>,
+    /// Fields a proxied page's form posted, by tab, with the URL they go to
+    proxy_posts: HashMap<u32, (String, String)>,
+    download_jobs: HashMap<u32, DownloadJob>,
+    system_prefers_dark: bool,
+    theme_error: Option<String>,
+    /// Custom engine being added or edited in settings
+    engine_draft: Option<SearchEngine>,
+    engine_error: Option<String>,
+    /// OpenSearch descriptions already fetched, so each is only asked for once
+    seen_search_descriptions: HashSet<String>,
+    /// A profile file that was read, waiting to be merged or swapped in
+    profile_import: Option<profile::Imported>,
+    /// What was wrong with the last profile file, one line per field
+    profile_errors: Vec<String>,
+    profile_message: Option<String>,
+    profiles: profiles::Registry,
+    show_profiles: bool,
+    profiles_error: Option<String>,
+    /// The state is being saved before the page reloads into another profile
+    switching_profile: bool,
+    /// Cookie jar the WebSocket proxy keeps for private tabs, renamed
+    /// whenever the last private tab closes
+    private_jar: String,
+    /// Engines described by visited pages, offered in settings
+    discovered_engines: Vec<SearchEngine>,
+    url_bar_ref: NodeRef,
+    /// The bindings the key listeners act on
+    key_dispatch: Rc<RefCell<keymap::Dispatch>>,
+    /// Action on the shortcuts page waiting for a key combination
+    recording_shortcut: Option<&'static str>,
+    shortcut_error: Option<String>,
+    show_palette: bool,
+    palette_query: String,
+    palette_selected: usize,
+    palette_ref: NodeRef,
+    /// The palette has just opened and its input wants the focus
+    focus_palette: bool,
+    /// Shortcuts for key presses inside same-origin frames, which the window
+    /// never sees
+    frame_key_listeners: HashMap<u32, EventListener>,
+    /// Keeps media in muted same-origin pages muted as it starts playing
+    frame_media_listeners: HashMap<u32, EventListener>,
+    _color_scheme_listener: Option<EventListener>,
+    /// Shutdown tracking and keyboard shortcuts
+    _window_listeners: Vec<EventListener>,
+}
+
+/// A page fetched through the WebSocket proxy for one tab
+struct ProxyFrame {
+    url: String,
+    /// Blob URL serving the page, `None` while the fetch is in flight
+    object_url: Option<String>,
 }
 
 impl Component for App {
     type Message = Msg;
     type Properties = ();
 
-    fn create(_ctx: &Context<Self>) -> Self {
-        let state = LocalStorage::get::<BrowserState>("graphite_state")
-            .unwrap_or_default();
-        
-        // Don't show graphite://home in URL bar - show empty string
-        let url_input = state.tabs
-            .iter()
-            .find(|t| t.id == state.active_tab_id)
-            .map(|t| Self::get_display_url(&t.url))
+    fn create(ctx: &Context<Self>) -> Self {
+        // Nothing is shown until the saved state has been read, which needs
+        // the profile settled first
+        let profiles = profiles::start();
+        ctx.link().send_future(async { Msg::StateLoaded(Box::new(storage::load().await)) });
+
+        // Keep System theme in step with the OS setting
+        let color_scheme = theme::color_scheme_query();
+        let system_prefers_dark = color_scheme.as_ref().map(|q| q.matches()).unwrap_or(false);
+        let color_scheme_listener = color_scheme.map(|query| {
+            let link = ctx.link().clone();
+            EventListener::new(&query, "change", move |e| {
+                let matches = e.dyn_ref::<web_sys::MediaQueryListEvent>().map(|e| e.matches()).unwrap_or(false);
+                link.send_message(Msg::SystemThemeChanged(matches));
+            })
+        });
+
+        let key_dispatch = Rc::new(RefCell::new(keymap::Dispatch::default()));
+        let window_listeners = web_sys::window()
+            .map(|window| {
+                let link = ctx.link().clone();
+                vec![
+                    EventListener::new(&window, "pagehide", {
+                        let link = link.clone();
+                        // Messages are handled in a microtask, which still
+                        // runs before the page is unloaded
+                        move |_| {
+                            link.send_message(Msg::PageHidden);
+                            session::end();
+                        }
+                    }),
+                    // Pages kept in the back/forward cache come back to life
+                    EventListener::new(&window, "pageshow", |e| {
+                        if e.dyn_ref::<web_sys::PageTransitionEvent>().is_some_and(|e| e.persisted()) {
+                            session::begin();
+                        }
+                    }),
+                    // Proxied pages report through their bridge script
+                    EventListener::new(&window, "message", {
+                        let link = link.clone();
+                        move |e| {
+                            let Some(e) = e.dyn_ref::<web_sys::MessageEvent>() else {
+                                return;
+                            };
+                            let message = e.data().as_string().and_then(|data| BridgeMessage::parse(&data));
+                            if let (Some(source), Some(message)) = (e.source(), message) {
+                                link.send_message(Msg::FrameMessage(source, message));
+                            }
+                        }
+                    }),
+                    EventListener::new(&window, "resize", {
+                        let link = link.clone();
+                        move |_| link.send_message(Msg::MeasureTabStrip)
+                    }),
+                    keymap::listen(&window, Rc::clone(&key_dispatch), link),
+                ]
+            })
             .unwrap_or_default();
 
         Self {
-            state,
-            url_input,
+            state: BrowserState::default(),
+            url_input: String::new(),
             show_settings: false,
             show_downloads: false,
+            show_bookmarks: false,
+            open_bookmark_folder: None,
+            bookmark_message: None,
+            show_recently_closed: false,
+            tab_menu: None,
+            group_menu: None,
+            show_saved_groups: false,
+            tab_strip_ref: NodeRef::default(),
+            tab_overflow: false,
+            show_all_tabs: false,
+            revealed_tab: None,
+            offer_restore: false,
+            state_notice: None,
+            storage: None,
+            written: storage::Written::new(),
+            save_scheduled: Cell::new(false),
+            writes_in_flight: 0,
+            remove_legacy: false,
+            link: ctx.link().clone(),
+            history_query: String::new(),
+            suggestions: Vec::new(),
+            selected_suggestion: None,
+            search_suggestions: Vec::new(),
+            suggest_generation: 0,
             dragging_tab: None,
+            frame_refs: HashMap::new(),
+            recent_tabs: Vec::new(),
+            frame_srcs: HashMap::new(),
+            proxy_client: None,
+            proxy_frames: HashMap::new(),
+            proxy_posts: HashMap::new(),
+            download_jobs: HashMap::new(),
+            system_prefers_dark,
+            theme_error: None,
+            engine_draft: None,
+            engine_error: None,
+            seen_search_descriptions: HashSet::new(),
+            profile_import: None,
+            profile_errors: Vec::new(),
+            profile_message: None,
+            profiles,
+            show_profiles: false,
+            profiles_error: None,
+            switching_profile: false,
+            private_jar: Self::new_private_jar(),
+            discovered_engines: Vec::new(),
+            url_bar_ref: NodeRef::default(),
+            key_dispatch,
+            recording_shortcut: None,
+            shortcut_error: None,
+            show_palette: false,
+            palette_query: String::new(),
+            palette_selected: 0,
+            palette_ref: NodeRef::default(),
+            focus_palette: false,
+            frame_key_listeners: HashMap::new(),
+            frame_media_listeners: HashMap::new(),
+            _color_scheme_listener: color_scheme_listener,
+            _window_listeners: window_listeners,
         }
     }
 
-    fn update(&mut self, _ctx: &Context<Self>, msg: Self::Message) -> bool {
+
+    fn update(&mut self, ctx: &Context<Self>, msg: Self::Message) -> bool {
         match msg {
-            Msg::NewTab => {
+            Msg::NewTab | Msg::NewPrivateTab => {
                 let new_tab = Tab {
                     id: self.state.next_tab_id,
+                    private: matches!(msg, Msg::NewPrivateTab),
                     ..Tab::default()
                 };
+                let id = new_tab.id;
                 self.state.tabs.push(new_tab);
-                self.state.active_tab_id = self.state.next_tab_id;
+                self.frame_refs.insert(id, NodeRef::default());
                 self.state.next_tab_id += 1;
-                self.url_input = String::new(); // Don't show graphite://home
+                self.activate_tab(id);
                 self.save_state();
                 true
             }
             Msg::CloseTab(id) => {
-                if self.state.tabs.len() > 1 {
-                    let idx = self.state.tabs.iter().position(|t| t.id == id);
-                    if let Some(idx) = idx {
-                        self.state.tabs.remove(idx);
-                        if self.state.active_tab_id == id {
-                            let new_idx = idx.saturating_sub(1).min(self.state.tabs.len() - 1);
-                            self.state.active_tab_id = self.state.tabs[new_idx].id;
-                            self.url_input = Self::get_display_url(&self.state.tabs[new_idx].url);
+                if self.state.tabs.iter().any(|t| t.id == id && t.pinned) {
+                    return false;
+                }
+                self.close_tab(id);
+                self.save_state();
+                true
+            }
+            Msg::CloseTabs(ids) => {
+                for id in ids {
+                    self.close_tab(id);
+                }
+                self.save_state();
+                true
+            }
+            Msg::SelectTab(id) => {
+                self.activate_tab(id);
+                self.save_state();
+                true
+            }
+            Msg::OpenInNewTab(href, opener, background) => {
+                let Some(url) = self.state.proxy.decode(&href).filter(|u| u.starts_with("http://") || u.starts_with("https://")) else {
+                    return false;
+                };
+                let Some(parent) = self.state.tabs.iter().find(|t| t.id == opener) else {
+                    return false;
+                };
+                let tab = Tab {
+                    id: self.state.next_tab_id,
+                    title: Self::get_title_from_url(&url),
+                    url: url.clone(),
+                    is_loading: true,
+                    history: vec![url.clone()],
+                    private: parent.private,
+                    group: parent.group,
+                    opener: Some(opener),
+                    ..Tab::default()
+                };
+                let (id, title) = (tab.id, tab.title.clone());
+                let index = tab_strip::insert_index(&self.state.tabs, opener).unwrap_or(self.state.tabs.len());
+                self.state.next_tab_id += 1;
+                self.state.tabs.insert(index, tab);
+                self.frame_refs.insert(id, NodeRef::default());
+                self.arrange_tabs();
+                self.record_visit(&url, &title, id);
+                if background {
+                    self.enforce_frame_limit();
+                } else {
+                    self.activate_tab(id);
+                }
+                self.save_state();
+                true
+            }
+            Msg::SetTabLayout(layout) => {
+                self.state.tab_layout = layout;
+                self.show_all_tabs = false;
+                self.revealed_tab = None;
+                self.save_state();
+                true
+            }
+            Msg::ToggleSidebar => {
+                self.state.sidebar_collapsed = !self.state.sidebar_collapsed;
+                self.save_state();
+                true
+            }
+            Msg::ScrollTabs(direction) => {
+                if let Some(strip) = self.tab_strip_ref.cast::<web_sys::Element>() {
+                    let page = (strip.client_width() * 3 / 4).max(1);
+                    strip.set_scroll_left(strip.scroll_left() + direction.signum() * page);
+                }
+                false
+            }
+            Msg::MeasureTabStrip => {
+                let overflow = self.tab_strip_overflows();
+                let changed = overflow != self.tab_overflow;
+                self.tab_overflow = overflow;
+                if !overflow {
+                    self.show_all_tabs = false;
+                }
+                changed
+            }
+            Msg::ToggleAllTabs => {
+                self.show_all_tabs = !self.show_all_tabs;
+                self.show_recently_closed = false;
+                self.show_saved_groups = false;
+                true
+            }
+            Msg::ShowTabMenu(id, x, y) => {
+                self.tab_menu = Some((id, x, y));
+                self.group_menu = None;
+                self.show_recently_closed = false;
+                true
+            }
+            Msg::DuplicateTab(id) => {
+                let Some(index) = self.state.tabs.iter().position(|t| t.id == id) else {
+                    return false;
+                };
+                let tab = Tab {
+                    id: self.state.next_tab_id,
+                    is_loading: false,
+                    discarded: true,
+                    opener: Some(id),
+                    ..self.state.tabs[index].clone()
+                };
+                let new_id = tab.id;
+                self.state.next_tab_id += 1;
+                self.state.tabs.insert(index + 1, tab);
+                self.frame_refs.insert(new_id, NodeRef::default());
+                self.activate_tab(new_id);
+                self.save_state();
+                true
+            }
+            Msg::TogglePinTab(id) => {
+                let Some(tab) = self.state.tabs.iter_mut().find(|t| t.id == id) else {
+                    return false;
+                };
+                tab.pinned = !tab.pinned;
+                self.arrange_tabs();
+                self.save_state();
+                true
+            }
+            Msg::ToggleMuteTab(id) => {
+                let Some(tab) = self.state.tabs.iter_mut().find(|t| t.id == id) else {
+                    return false;
+                };
+                tab.muted = !tab.muted;
+                self.apply_mute(id);
+                self.save_state();
+                true
+            }
+            Msg::ReloadTab(id) => {
+                if self.state.proxy.is_websocket() {
+                    // Dropping the cached page makes the next render fetch it again
+                    if self.proxy_frames.contains_key(&id) {
+                        self.drop_proxy_frame(id);
+                        if let Some(tab) = self.state.tabs.iter_mut().find(|t| t.id == id) {
+                            tab.is_loading = true;
                         }
                     }
+                } else if let Some(tab) = self.state.tabs.iter_mut().find(|t| t.id == id) {
+                    let frame = self.frame_refs.get(&tab.id).and_then(|r| r.cast::<HtmlIFrameElement>());
+                    if let (Some(iframe), Some(src)) = (frame, self.frame_srcs.get(&tab.id)) {
+                        // Re-assigning src reloads even cross-origin frames
+                        iframe.set_src(src);
+                        tab.is_loading = true;
+                    }
+                }
+                true
+            }
+            Msg::AddToGroup(id, group) => {
+                // Pinned tabs stay out of groups, and a new group shouldn't be left empty
+                if !self.state.tabs.iter().any(|t| t.id == id && !t.pinned) {
+                    return false;
+                }
+                let group = match group {
+                    Some(group) => group,
+                    None => {
+                        let color = self.state.tab_groups.last().map(|g| g.color.next()).unwrap_or_default();
+                        let group = TabGroup { id: self.state.next_group_id, name: String::new(), color, collapsed: false };
+                        self.state.next_group_id += 1;
+                        self.state.tab_groups.push(group);
+                        self.state.next_group_id - 1
+                    }
+                };
+                if let Some(tab) = self.state.tabs.iter_mut().find(|t| t.id == id) {
+                    tab.group = Some(group);
+                }
+                self.arrange_tabs();
+                self.save_state();
+                true
+            }
+            Msg::RemoveFromGroup(id) => {
+                let Some(index) = self.state.tabs.iter().position(|t| t.id == id) else {
+                    return false;
+                };
+                let mut tab = self.state.tabs.remove(index);
+                let group = tab.group.take();
+                // Out past the end of the group it left
+                let after = self.state.tabs.iter().rposition(|t| group.is_some() && t.group == group).map_or(index, |i| i + 1);
+                self.state.tabs.insert(after, tab);
+                self.arrange_tabs();
+                self.save_state();
+                true
+            }
+            Msg::ToggleGroupCollapsed(group) => {
+                if let Some(group) = self.state.tab_groups.iter_mut().find(|g| g.id == group) {
+                    group.collapsed = !group.collapsed;
+                }
+                self.save_state();
+                true
+            }
+            Msg::ShowGroupMenu(group, x, y) => {
+                self.group_menu = Some((group, x, y));
+                self.tab_menu = None;
+                true
+            }
+            Msg::RenameGroup(group, name) => {
+                if let Some(group) = self.state.tab_groups.iter_mut().find(|g| g.id == group) {
+                    group.name = name.trim().to_string();
+                }
+                self.save_state();
+                true
+            }
+            Msg::SetGroupColor(group, color) => {
+                if let Some(group) = self.state.tab_groups.iter_mut().find(|g| g.id == group) {
+                    group.color = color;
+                }
+                self.save_state();
+                true
+            }
+            Msg::Ungroup(group) => {
+                self.group_menu = None;
+                self.state.tab_groups.retain(|g| g.id != group);
+                self.arrange_tabs();
+                self.save_state();
+                true
+            }
+            Msg::CloseGroup(group) => {
+                self.group_menu = None;
+                self.close_group(group);
+                self.save_state();
+                true
+            }
+            Msg::SaveGroup(group) => {
+                self.group_menu = None;
+                let Some(info) = self.state.tab_groups.iter().find(|g| g.id == group).cloned() else {
+                    return false;
+                };
+                // Private tabs just close, they are never kept
+                let tabs: Vec<Tab> = self.close_group(group)
+                    .into_iter()
+                    .filter(|t| !t.private)
+                    .map(|t| Tab { group: None, ..t })
+                    .collect();
+                if !tabs.is_empty() {
+                    let saved = SavedGroup { name: info.name, color: info.color, tabs, saved_at: js_sys::Date::now() };
+                    self.state.saved_groups.insert(0, saved);
+                }
+                self.save_state();
+                true
+            }
+            Msg::RestoreGroup(index) => {
+                if index >= self.state.saved_groups.len() {
+                    return false;
+                }
+                self.show_saved_groups = false;
+                let saved = self.state.saved_groups.remove(index);
+                let group = TabGroup { id: self.state.next_group_id, name: saved.name, color: saved.color, collapsed: false };
+                self.state.next_group_id += 1;
+                self.state.tab_groups.push(group.clone());
+                let mut first = None;
+                for tab in saved.tabs {
+                    let id = self.insert_reopened_tab(Tab { group: Some(group.id), pinned: false, ..tab }, usize::MAX);
+                    first.get_or_insert(id);
+                }
+                if let Some(id) = first {
+                    self.activate_tab(id);
+                }
+                self.save_state();
+                true
+            }
+            Msg::DeleteSavedGroup(index) => {
+                if index < self.state.saved_groups.len() {
+                    self.state.saved_groups.remove(index);
                     self.save_state();
                 }
                 true
             }
-            Msg::SelectTab(id) => {
-                self.state.active_tab_id = id;
-                if let Some(tab) = self.state.tabs.iter().find(|t| t.id == id) {
-                    self.url_input = Self::get_display_url(&tab.url);
+            Msg::ToggleSavedGroups => {
+                self.show_saved_groups = !self.show_saved_groups;
+                self.show_recently_closed = false;
+                true
+            }
+            Msg::CopyTabUrl(id) => {
+                let Some(url) = self.state.tabs.iter().find(|t| t.id == id).map(|t| t.url.clone()) else {
+                    return false;
+                };
+                if let Some(window) = web_sys::window() {
+                    let promise = window.navigator().clipboard().write_text(&url);
+                    wasm_bindgen_futures::spawn_local(async move {
+                        if let Err(err) = wasm_bindgen_futures::JsFuture::from(promise).await {
+                            log::error!("Failed to copy {}: {:?}", url, err);
+                        }
+                    });
+                }
+                false
+            }
+            Msg::BookmarkTab(id) => {
+                let Some(tab) = self.state.tabs.iter().find(|t| t.id == id) else {
+                    return false;
+                };
+                if pages::is_internal(&tab.url) {
+                    return false;
+                }
+                match self.state.bookmarks.find_by_url(&tab.url).map(|b| b.id) {
+                    Some(id) => {
+                        self.state.bookmarks.remove(id);
+                    }
+                    None => {
+                        let (title, url) = (tab.title.clone(), tab.url.clone());
+                        self.state.bookmarks.add_bookmark(BOOKMARK_BAR_ID, &title, &url, js_sys::Date::now());
+                    }
                 }
                 self.save_state();
                 true
             }
             Msg::Navigate(url) => {
+                self.clear_suggestions();
                 let final_url = self.process_url(&url);
-                let title = Self::get_title_from_url(&final_url);
-                if let Some(tab) = self.state.tabs.iter_mut().find(|t| t.id == self.state.active_tab_id) {
-                    tab.url = final_url.clone();
-                    tab.title = title;
-                    tab.is_loading = true;
-                }
+                self.navigate_tab(self.state.active_tab_id, &final_url);
                 self.url_input = final_url;
-                self.save_state();
                 true
             }
-            Msg::GoBack => {
-                // Go back in iframe history
+            Msg::GoBack => self.step_active_history(-1),
+            Msg::GoForward => self.step_active_history(1),
+            Msg::Reload => {
+                let active_tab_id = self.state.active_tab_id;
+                Component::update(self, ctx, Msg::ReloadTab(active_tab_id))
+            }
+            Msg::ProxyLoaded(id, url, result) => {
+                match self.proxy_frames.get_mut(&id) {
+                    Some(frame) if frame.url == url => match result {
+                        Ok(object_url) => frame.object_url = Some(object_url),
+                        Err(err) => {
+                            log::error!("Failed to serve proxied page {}: {}", url, err);
+                            if let Some(tab) = self.state.tabs.iter_mut().find(|t| t.id == id) {
+                                tab.is_loading = false;
+                            }
+                        }
+                    },
+                    // The tab moved on while the page was being fetched
+                    _ => {
+                        if let Ok(object_url) = result {
+                            proxy::revoke_object_url(&object_url);
+                        }
+                        return false;
+                    }
+                }
                 true
             }
-            Msg::GoForward => {
-                // Go forward in iframe history
+            Msg::FrameLoaded(id, metadata) => {
+                // Frames fire a load for the blank document they start out with
+                let wants_blank = self.state.tabs.iter().any(|t| t.id == id && t.url == "about:blank");
+                if metadata.url.as_deref() == Some("about:blank") && !wants_blank {
+                    return false;
+                }
+                self.listen_for_frame_keys(id);
+                self.apply_mute(id);
+                // Same-origin frames reveal where they ended up, through redirects or links
+                let location = metadata.url
+                    .as_deref()
+                    .filter(|_| !self.state.proxy.is_websocket())
+                    .and_then(|u| self.state.proxy.decode(u))
+                    .filter(|u| u.starts_with("http://") || u.starts_with("https://"));
+                let mut visited = None;
+                let mut loaded = None;
+                if let Some(tab) = self.state.tabs.iter_mut().find(|t| t.id == id) {
+                    if let Some(location) = location.filter(|l| l.trim_end_matches('/') != tab.url.trim_end_matches('/')) {
+                        if tab.is_loading {
+                            // A redirect replaces the entry we navigated to
+                            if let Some(entry) = tab.history.get_mut(tab.history_index) {
+                                *entry = location.clone();
+                            }
+                        } else {
+                            tab.push_history(&location);
+                        }
+                        tab.title = Self::get_title_from_url(&location);
+                        visited = Some(location.clone());
+                        // The frame is already showing this page, so don't assign it again
+                        self.frame_srcs.insert(id, self.state.proxy.encode(&location));
+                        if id == self.state.active_tab_id {
+                            self.url_input = Self::get_display_url(&location);
+                        }
+                        tab.url = location;
+                    }
+                    tab.is_loading = false;
+                    if let Some(title) = metadata.title {
+                        tab.title = title;
+                    }
+                    tab.favicon = metadata.favicon.or_else(|| Self::get_fallback_favicon(&tab.url));
+                    loaded = Some((tab.url.clone(), tab.title.clone()));
+                }
+                if let Some((url, title)) = loaded.filter(|_| !self.is_private_tab(id)) {
+                    if visited.is_some() {
+                        self.record_visit(&url, &title, id);
+                    } else {
+                        self.state.history.update_title(&url, &title);
+                    }
+                    if let Some(href) = metadata.search_description {
+                        self.discover_search_engine(ctx, &url, &href);
+                    }
+                }
+                self.save_state();
                 true
             }
-            Msg::Reload => {
-                if let Some(tab) = self.state.tabs.iter_mut().find(|t| t.id == self.state.active_tab_id) {
-                    tab.is_loading = true;
+            Msg::FrameMessage(source, message) => {
+                // Each frame only speaks for its own tab
+                let sender = self.frame_refs.iter().find(|(_, frame)| {
+                    frame
+                        .cast::<HtmlIFrameElement>()
+                        .and_then(|iframe| iframe.content_window())
+                        .is_some_and(|window| js_sys::Object::is(&window, &source))
+                });
+                let Some(id) = sender.map(|(id, _)| *id) else {
+                    return false;
+                };
+                match message {
+                    // Media can start playing before the page has loaded
+                    BridgeMessage::Ready => {
+                        self.apply_mute(id);
+                        false
+                    }
+                    BridgeMessage::Loaded { title, favicon, search_description } => {
+                        let mut loaded = None;
+                        if let Some(tab) = self.state.tabs.iter_mut().find(|t| t.id == id) {
+                            if let Some(title) = title {
+                                tab.title = title;
+                            }
+                            if favicon.is_some() {
+                                tab.favicon = favicon;
+                            }
+                            loaded = Some((tab.url.clone(), tab.title.clone()));
+                        }
+                        if let Some((url, title)) = loaded.filter(|_| !self.is_private_tab(id)) {
+                            self.state.history.update_title(&url, &title);
+                            if let Some(href) = search_description {
+                                self.discover_search_engine(ctx, &url, &href);
+                            }
+                        }
+                        self.save_state();
+                        true
+                    }
+                    BridgeMessage::Open { url, background } => {
+                        Component::update(self, ctx, Msg::OpenInNewTab(url, id, background))
+                    }
+                    BridgeMessage::Navigate { url, body } => {
+                        // Fetched again even when it's the page already showing
+                        self.drop_proxy_frame(id);
+                        match body {
+                            Some(body) => self.proxy_posts.insert(id, (url.clone(), body)),
+                            None => self.proxy_posts.remove(&id),
+                        };
+                        if id == self.state.active_tab_id {
+                            Component::update(self, ctx, Msg::Navigate(url))
+                        } else {
+                            self.navigate_tab(id, &url);
+                            true
+                        }
+                    }
+                    BridgeMessage::Key { key, code, ctrl, alt, shift, typing } => {
+                        let msg = KeyCombo::from_parts(&key, &code, ctrl, alt, shift)
+                            .and_then(|combo| self.key_dispatch.borrow().msg_for(combo, typing));
+                        match msg {
+                            Some(msg) => Component::update(self, ctx, msg),
+                            None => false,
+                        }
+                    }
                 }
+            }
+            Msg::FrameError(id) => {
+                if let Some(tab) = self.state.tabs.iter_mut().find(|t| t.id == id) {
+                    tab.is_loading = false;
+                    tab.favicon = Self::get_fallback_favicon(&tab.url);
+                }
+                self.save_state();
                 true
             }
             Msg::GoHome => {
                 if let Some(tab) = self.state.tabs.iter_mut().find(|t| t.id == self.state.active_tab_id) {
-                    tab.url = String::from("graphite://home");
-                    tab.title = String::from("Home");
+                    tab.push_history(pages::HOME_URL);
+                    tab.url = String::from(pages::HOME_URL);
+                    tab.title = Self::get_title_from_url(pages::HOME_URL);
+                    tab.favicon = None;
                     tab.is_loading = false;
                 }
-                self.url_input = String::new(); // Don't show graphite://home
+                self.url_input = Self::get_display_url(pages::HOME_URL);
                 self.save_state();
                 true
             }
             Msg::UpdateUrlBar(value) => {
                 self.url_input = value;
+                self.suggest_generation = self.suggest_generation.wrapping_add(1);
+                if self.url_input.trim().is_empty() {
+                    self.search_suggestions.clear();
+                } else {
+                    // Wait for a pause in typing before asking the search engine
+                    let generation = self.suggest_generation;
+                    ctx.link().send_future(async move {
+                        gloo::timers::future::TimeoutFuture::new(SUGGEST_DELAY_MS).await;
+                        Msg::FetchSuggestions(generation)
+                    });
+                }
+                self.update_suggestions();
                 true
             }
-            Msg::SetSearchEngine(engine) => {
-                self.state.search_engine = engine;
-                self.save_state();
-                true
+            Msg::FetchSuggestions(generation) => {
+                if generation != self.suggest_generation {
+                    return false;
+                }
+                let (engine, query) = self.search_target(&self.url_input);
+                let Some(url) = engine.suggest_url(&query) else {
+                    return false;
+                };
+                let request = self.fetch_through_proxy(&url);
+                ctx.link().send_future(async move {
+                    let suggestions = match request.await {
+                        Ok(body) => omnibox::parse_suggestions(&body),
+                        Err(err) => {
+                            log::warn!("Failed to fetch search suggestions: {}", err);
+                            Vec::new()
+                        }
+                    };
+                    Msg::SearchSuggestionsLoaded(generation, suggestions)
+                });
+                false
             }
-            Msg::SetProxyServer(proxy) => {
-                self.state.proxy_server = proxy;
-                self.save_state();
+            Msg::SearchSuggestionsLoaded(generation, suggestions) => {
+                if generation != self.suggest_generation {
+                    return false;
+                }
+                self.search_suggestions = suggestions;
+                self.update_suggestions();
                 true
             }
-            Msg::ToggleSettingsPanel => {
-                self.show_settings = !self.show_settings;
-                self.show_downloads = false;
+            Msg::MoveSuggestion(offset) => {
+                let count = self.suggestions.len() as isize;
+                if count == 0 {
+                    return false;
+                }
+                // Stepping past either end returns to what was typed
+                let current = self.selected_suggestion.map(|i| i as isize).unwrap_or(-1);
+                let next = (current + offset + count + 1).rem_euclid(count + 1) - 1;
+                self.selected_suggestion = (next >= 0).then_some(next as usize);
                 true
             }
-            Msg::ToggleDownloadsPanel => {
-                self.show_downloads = !self.show_downloads;
-                self.show_settings = false;
+            Msg::AcceptSuggestion(index) => {
+                let Some(suggestion) = self.suggestions.get(index).cloned() else {
+                    return false;
+                };
+                // Suggested queries came from the engine the typed text targets
+                let (engine, _) = self.search_target(&self.url_input);
+                self.clear_suggestions();
+                match suggestion.kind {
+                    SuggestionKind::OpenTab(id) if self.state.tabs.iter().any(|t| t.id == id) => {
+                        self.activate_tab(id);
+                        self.save_state();
+                        true
+                    }
+                    SuggestionKind::Search => {
+                        let url = engine.search_url(&suggestion.url);
+                        Component::update(self, ctx, Msg::Navigate(url))
+                    }
+                    _ => Component::update(self, ctx, Msg::Navigate(suggestion.url)),
+                }
+            }
+            Msg::DismissSuggestions => {
+                if self.suggestions.is_empty() {
+                    return false;
+                }
+                self.clear_suggestions();
                 true
             }
-            Msg::DeleteDownload(id) => {
-                self.state.downloads.retain(|d| d.id != id);
+            Msg::SetSearchEngine(id) => {
+                self.state.search_engine = id;
                 self.save_state();
                 true
             }
-            Msg::OpenDownloadFolder(_id) => {
-                // In WASM, we can't open file explorer
+            Msg::EditSearchEngine(id) => {
+                self.engine_draft = match id {
+                    Some(id) => search_engines::find(&self.state.custom_search_engines, &id).cloned(),
+                    None => Some(SearchEngine::default()),
+                };
+                self.engine_error = None;
                 true
             }
-            Msg::DragStart(id) => {
-                self.dragging_tab = Some(id);
-                true
+            Msg::UpdateEngineDraft(field, value) => {
+                if let Some(draft) = self.engine_draft.as_mut() {
+                    draft.set(field, value);
+                }
+                false
             }
-            Msg::DragOver(target_id) => {
-                if let Some(drag_id) = self.dragging_tab {
-                    if drag_id != target_id {
-                        let drag_idx = self.state.tabs.iter().position(|t| t.id == drag_id);
-                        let target_idx = self.state.tabs.iter().position(|t| t.id == target_id);
-                        if let (Some(from), Some(to)) = (drag_idx, target_idx) {
-                            let tab = self.state.tabs.remove(from);
-                            self.state.tabs.insert(to, tab);
+            Msg::SaveSearchEngine => {
+                let Some(draft) = self.engine_draft.clone() else {
+                    return false;
+                };
+                let others: Vec<SearchEngine> = self.search_engines().into_iter().filter(|e| e.id != draft.id).collect();
+                match draft.validated(&others) {
+                    Ok(mut engine) => {
+                        match self.state.custom_search_engines.iter_mut().find(|e| e.id == engine.id) {
+                            Some(existing) => *existing = engine,
+                            None => {
+                                engine.id = search_engines::new_id(&self.state.custom_search_engines);
+                                self.state.custom_search_engines.push(engine);
+                            }
                         }
+                        self.engine_draft = None;
+                        self.engine_error = None;
+                        self.save_state();
                     }
+                    Err(err) => self.engine_error = Some(err),
                 }
                 true
             }
-            Msg::DragEnd => {
-                self.dragging_tab = None;
-                self.save_state();
+            Msg::CancelEngineEdit => {
+                self.engine_draft = None;
+                self.engine_error = None;
                 true
             }
-            Msg::CloseAllPanels => {
-                self.show_settings = false;
-                self.show_downloads = false;
+            Msg::RemoveSearchEngine(id) => {
+                self.state.custom_search_engines.retain(|e| e.id != id);
+                if self.state.search_engine.eq_ignore_ascii_case(&id) {
+                    self.state.search_engine = default_search_engine();
+                }
+                if self.engine_draft.as_ref().is_some_and(|d| d.id == id) {
+                    self.engine_draft = None;
+                    self.engine_error = None;
+                }
+                self.save_state();
                 true
             }
-            Msg::NoOp => false,
-        }
-    }
-
-    fn view(&self, ctx: &Context<Self>) -> Html {
-        let link = ctx.link();
-        let active_tab = self.state.tabs.iter().find(|t| t.id == self.state.active_tab_id);
-        let is_home = active_tab.map(|t| t.url == "graphite://home").unwrap_or(true);
-
-        html! {
-            <div class="browser-container" onclick={link.callback(|_| Msg::CloseAllPanels)}>
-                // Tab Bar
-                <div class="tab-bar">
-                    { for self.state.tabs.iter().map(|tab| {
-                        let is_active = tab.id == self.state.active_tab_id;
-                        let tab_id = tab.id;
-                        let close_id = tab.id;
-                        let drag_id = tab.id;
-                        let drop_id = tab.id;
-                        
-                        html! {
-                            <div 
-                                class={classes!("tab", is_active.then_some("active"))}
-                                onclick={link.callback(move |_| Msg::SelectTab(tab_id))}
-                                draggable="true"
-                                ondragstart={link.callback(move |_| Msg::DragStart(drag_id))}
-                                ondragover={link.callback(move |e: DragEvent| {
-                                    e.prevent_default();
-                                    Msg::DragOver(drop_id)
-                                })}
-                                ondragend={link.callback(|_| Msg::DragEnd)}
-                            >
-                                <span class="tab-favicon icon icon-home"></span>
-                                <span class="tab-title">{&tab.title}</span>
-                                <button 
-                                    class="tab-close"
-                                    onclick={link.callback(move |e: MouseEvent| {
-                                        e.stop_propagation();
-                                        Msg::CloseTab(close_id)
-                                    })}
-                                ><span class="icon icon-close"></span></button>
-                            </div>
+            Msg::SearchDescriptionLoaded(url, result) => {
+                let engine = result.and_then(|xml| opensearch::parse_description(&xml, &url));
+                match engine {
+                    Ok(engine) => {
+                        let known = self.search_engines().iter().chain(&self.discovered_engines).any(|e| e.search_url == engine.search_url);
+                        if known {
+                            return false;
                         }
+                        self.discovered_engines.push(engine);
+                        true
+                    }
+                    Err(err) => {
+                        log::warn!("Failed to read search engine description {}: {}", url, err);
+                        false
+                    }
+                }
+            }
+            Msg::AddDiscoveredEngine(index) => {
+                if index >= self.discovered_engines.len() {
+                    return false;
+                }
+                let mut engine = self.discovered_engines.remove(index);
+                let engines = self.search_engines();
+                // The host name is only a suggested keyword; drop it rather than refuse the engine
+                if engines.iter().any(|e| e.keyword.eq_ignore_ascii_case(&engine.keyword)) {
+                    engine.keyword.clear();
+                }
+                match engine.validated(&engines) {
+                    Ok(mut engine) => {
+                        engine.id = search_engines::new_id(&self.state.custom_search_engines);
+                        self.state.custom_search_engines.push(engine);
+                        self.engine_error = None;
+                        self.save_state();
+                    }
+                    Err(err) => self.engine_error = Some(format!("{} can't be added. {}", engine.name, err)),
+                }
+                true
+            }
+            Msg::SetProxyConfig(proxy) => {
+                if self.state.proxy == proxy {
+                    return false;
+                }
+                self.state.proxy = proxy;
+                self.proxy_client = None;
+                for id in self.proxy_frames.keys().copied().collect::<Vec<_>>() {
+                    self.drop_proxy_frame(id);
+                }
+                // Every live frame reloads through the new proxy
+                for tab in self.state.tabs.iter_mut().filter(|t| t.has_live_frame()) {
+                    tab.is_loading = true;
+                }
+                self.save_state();
+                true
+            }
+            Msg::SetMaxLiveFrames(max) => {
+                self.state.max_live_frames = max.max(1);
+                self.enforce_frame_limit();
+                self.save_state();
+                true
+            }
+            Msg::ToggleSettingsPanel => {
+                self.show_settings = !self.show_settings;
+                self.show_downloads = false;
+                self.show_bookmarks = false;
+                self.show_profiles = false;
+                true
+            }
+            Msg::ToggleDownloadsPanel => {
+                self.show_downloads = !self.show_downloads;
+                self.show_settings = false;
+                self.show_bookmarks = false;
+                self.show_profiles = false;
+                true
+            }
+            Msg::StartDownload(url) => {
+                let url = url.trim();
+                if url.is_empty() {
+                    return false;
+                }
+                let url = self.process_url(url);
+                if pages::is_internal(&url) {
+                    return false;
+                }
+                let id = self.state.next_download_id;
+                self.state.next_download_id += 1;
+                let private = self.is_private_tab(self.state.active_tab_id);
+                self.state.downloads.insert(0, Download { private, ..Download::new(id, &url) });
+                self.download_jobs.insert(id, DownloadJob::new());
+                self.start_download_job(ctx, id);
+                self.save_state();
+                true
+            }
+            Msg::DownloadProgress(id, generation, event) => {
+                // Ignore stragglers from attempts that were paused or cancelled
+                if self.download_jobs.get(&id).map(DownloadJob::generation) != Some(generation) {
+                    return false;
+                }
+                let Some(download) = self.state.downloads.iter_mut().find(|d| d.id == id) else {
+                    return false;
+                };
+                match event {
+                    DownloadEvent::Started { total_bytes, mime_type, filename, restarted } => {
+                        download.total_bytes = total_bytes;
+                        download.mime_type = mime_type;
+                        if let Some(filename) = filename {
+                            download.filename = filename;
+                        }
+                        if restarted {
+                            download.received_bytes = 0;
+                        }
+                    }
+                    DownloadEvent::Progress(received) => {
+                        // Progress is only persisted with the next state change
+                        download.received_bytes = received;
+                        return true;
+                    }
+                    DownloadEvent::Completed(file) => {
+                        download.status = DownloadStatus::Completed;
+                        download.finished_at = Some(js_sys::Date::now());
+                        download.total_bytes = Some(download.received_bytes);
+                        self.download_jobs.remove(&id);
+                        let filename = download.filename.clone();
+                        match file {
+                            // Private files go straight to disk without being stored
+                            Some(blob) => {
+                                if let Err(err) = downloads::save_blob(&blob, &filename) {
+                                    log::error!("Failed to save download {}: {}", filename, err);
+                                }
+                            }
+                            None => wasm_bindgen_futures::spawn_local(async move {
+                                if let Err(err) = downloads::save_to_disk(id, &filename).await {
+                                    log::error!("Failed to save download {}: {}", filename, err);
+                                }
+                            }),
+                        }
+                    }
+                    DownloadEvent::Failed(reason) => {
+                        download.status = DownloadStatus::Failed(reason);
+                        download.finished_at = Some(js_sys::Date::now());
+                        self.download_jobs.remove(&id);
+                    }
+                }
+                self.save_state();
+                true
+            }
+            Msg::PauseDownload(id) => {
+                let Some(job) = self.download_jobs.get_mut(&id).filter(|j| j.can_pause()) else {
+                    return false;
+                };
+                job.pause();
+                if let Some(download) = self.state.downloads.iter_mut().find(|d| d.id == id) {
+                    download.status = DownloadStatus::Paused;
+                }
+                self.save_state();
+                true
+            }
+            Msg::ResumeDownload(id) => {
+                if !self.download_jobs.contains_key(&id) {
+                    return false;
+                }
+                if let Some(download) = self.state.downloads.iter_mut().find(|d| d.id == id) {
+                    download.status = DownloadStatus::InProgress;
+                }
+                self.start_download_job(ctx, id);
+                self.save_state();
+                true
+            }
+            Msg::CancelDownload(id) => {
+                if let Some(mut job) = self.download_jobs.remove(&id) {
+                    job.pause();
+                }
+                if let Some(download) = self.state.downloads.iter_mut().find(|d| d.id == id) {
+                    download.status = DownloadStatus::Cancelled;
+                    download.finished_at = Some(js_sys::Date::now());
+                }
+                self.save_state();
+                true
+            }
+            Msg::DeleteDownload(id) => {
+                if let Some(mut job) = self.download_jobs.remove(&id) {
+                    job.pause();
+                }
+                self.state.downloads.retain(|d| d.id != id);
+                wasm_bindgen_futures::spawn_local(async move {
+                    if let Err(err) = downloads::delete_file(id).await {
+                        log::warn!("Failed to delete stored download {}: {}", id, err);
+                    }
+                });
+                self.save_state();
+                true
+            }
+            Msg::OpenDownloadFolder(id) => {
+                // In WASM we can't open a file explorer, so save the stored file again instead
+                if let Some(download) = self.state.downloads.iter().find(|d| d.id == id && d.status == DownloadStatus::Completed) {
+                    let filename = download.filename.clone();
+                    wasm_bindgen_futures::spawn_local(async move {
+                        if let Err(err) = downloads::save_to_disk(id, &filename).await {
+                            log::error!("Failed to save download {}: {}", filename, err);
+                        }
+                    });
+                }
+                false
+            }
+            Msg::DragStart(id) => {
+                self.dragging_tab = Some(id);
+                true
+            }
+            Msg::DragOver(target_id) => {
+                if let Some(drag_id) = self.dragging_tab {
+                    if drag_id != target_id {
+                        let drag_idx = self.state.tabs.iter().position(|t| t.id == drag_id);
+                        let target_idx = self.state.tabs.iter().position(|t| t.id == target_id);
+                        if let (Some(from), Some(to)) = (drag_idx, target_idx) {
+                            // Pinned tabs only trade places with each other, and so do the rest
+                            if self.state.tabs[from].pinned == self.state.tabs[to].pinned {
+                                // A tab dragged over another joins its group, or leaves its own
+                                let group = self.state.tabs[to].group;
+                                let opener = self.state.tabs[to].opener;
+                                let mut tab = self.state.tabs.remove(from);
+                                tab.group = group;
+                                // In the sidebar's tree it also becomes the other tab's sibling
+                                if self.state.tab_layout == TabLayout::Vertical {
+                                    tab.opener = opener;
+                                }
+                                self.state.tabs.insert(to, tab);
+                                self.arrange_tabs();
+                            }
+                        }
+                    }
+                }
+                true
+            }
+            Msg::DragEnd => {
+                self.dragging_tab = None;
+                self.save_state();
+                true
+            }
+            Msg::CycleTheme => {
+                self.state.theme = self.state.theme.next();
+                self.apply_theme();
+                self.save_state();
+                true
+            }
+            Msg::SetTheme(theme) => {
+                self.state.theme = theme;
+                self.apply_theme();
+                self.save_state();
+                true
+            }
+            Msg::SystemThemeChanged(prefers_dark) => {
+                self.system_prefers_dark = prefers_dark;
+                self.apply_theme();
+                self.state.theme == Theme::System
+            }
+            Msg::ImportTheme(json) => {
+                match CustomTheme::from_json(&json) {
+                    Ok(theme) => {
+                        self.state.custom_theme = Some(theme);
+                        self.theme_error = None;
+                        self.apply_theme();
+                        self.save_state();
+                    }
+                    Err(err) => self.theme_error = Some(err),
+                }
+                true
+            }
+            Msg::ExportTheme => {
+                let theme = self.state.custom_theme.clone().unwrap_or_else(CustomTheme::built_in);
+                let filename = format!("{}.theme.json", theme.name.to_lowercase().replace(' ', "-"));
+                if let Err(err) = downloads::save_bytes(theme.to_json().as_bytes(), "application/json", &filename) {
+                    self.theme_error = Some(err);
+                    return true;
+                }
+                false
+            }
+            Msg::ResetTheme => {
+                self.state.custom_theme = None;
+                self.theme_error = None;
+                self.apply_theme();
+                self.save_state();
+                true
+            }
+            Msg::ToggleBookmarksPanel => {
+                self.show_bookmarks = !self.show_bookmarks;
+                self.show_settings = false;
+                self.show_downloads = false;
+                self.show_profiles = false;
+                self.bookmark_message = None;
+                true
+            }
+            Msg::ToggleBookmark => {
+                let active_tab_id = self.state.active_tab_id;
+                Component::update(self, ctx, Msg::BookmarkTab(active_tab_id))
+            }
+            Msg::ToggleBookmarkFolder(id) => {
+                self.open_bookmark_folder = if self.open_bookmark_folder == Some(id) { None } else { Some(id) };
+                true
+            }
+            Msg::AddBookmarkFolder(name) => {
+                let name = name.trim();
+                if name.is_empty() {
+                    return false;
+                }
+                self.state.bookmarks.add_folder(BOOKMARK_BAR_ID, name, js_sys::Date::now());
+                self.save_state();
+                true
+            }
+            Msg::MoveBookmark(id, folder_id) => {
+                if !self.state.bookmarks.move_to(id, folder_id) {
+                    return false;
+                }
+                self.save_state();
+                true
+            }
+            Msg::DeleteBookmark(id) => {
+                if self.state.bookmarks.remove(id).is_none() {
+                    return false;
+                }
+                if self.open_bookmark_folder == Some(id) {
+                    self.open_bookmark_folder = None;
+                }
+                self.save_state();
+                true
+            }
+            Msg::ImportBookmarks(html) => {
+                self.bookmark_message = Some(match bookmarks::parse_netscape_html(&html) {
+                    Ok(imported) => {
+                        let count = self.state.bookmarks.import(imported, js_sys::Date::now());
+                        self.save_state();
+                        format!("Imported {} bookmark{}", count, if count == 1 { "" } else { "s" })
+                    }
+                    Err(err) => err,
+                });
+                true
+            }
+            Msg::ExportBookmarks => {
+                let html = self.state.bookmarks.to_netscape_html();
+                if let Err(err) = downloads::save_bytes(html.as_bytes(), "text/html", "bookmarks.html") {
+                    self.bookmark_message = Some(err);
+                    return true;
+                }
+                false
+            }
+            Msg::ExportProfile => {
+                let now = js_sys::Date::now();
+                let file = profile::export(&self.state, now).and_then(|json| {
+                    let date = js_sys::Date::new(&JsValue::from_f64(now));
+                    let filename = format!(
+                        "graphite-profile-{:04}-{:02}-{:02}.json",
+                        date.get_full_year(),
+                        date.get_month() + 1,
+                        date.get_date()
+                    );
+                    downloads::save_bytes(json.as_bytes(), "application/json", &filename)
+                });
+                match file {
+                    Ok(()) => false,
+                    Err(err) => {
+                        self.profile_message = Some(format!("The profile couldn't be exported: {}", err));
+                        true
+                    }
+                }
+            }
+            Msg::ImportProfile(json) => {
+                self.profile_message = None;
+                match profile::parse(&json) {
+                    Ok(imported) => {
+                        self.profile_import = Some(imported);
+                        self.profile_errors.clear();
+                    }
+                    Err(errors) => {
+                        self.profile_import = None;
+                        self.profile_errors = errors;
+                    }
+                }
+                true
+            }
+            Msg::ApplyProfileImport(mode) => {
+                let Some(imported) = self.profile_import.take() else {
+                    return false;
+                };
+                match mode {
+                    ImportMode::Merge => {
+                        let (tabs, summary) = profile::merge(&mut self.state, imported.state);
+                        for tab in tabs {
+                            self.insert_reopened_tab(tab, usize::MAX);
+                        }
+                        self.profile_message = Some(summary);
+                    }
+                    ImportMode::Replace => {
+                        self.replace_profile(imported.state);
+                        self.profile_message = Some(String::from("Replaced this profile with the imported one"));
+                    }
+                }
+                self.save_state();
+                true
+            }
+            Msg::CancelProfileImport => {
+                self.profile_import = None;
+                true
+            }
+            Msg::ToggleProfilesPanel => {
+                self.show_profiles = !self.show_profiles;
+                self.show_settings = false;
+                self.show_downloads = false;
+                self.show_bookmarks = false;
+                self.profiles_error = None;
+                true
+            }
+            Msg::AddProfile(name) => {
+                self.profiles_error = match self.profiles.add(&name) {
+                    Ok(profile) => self.profiles.save().err().map(|err| {
+                        self.profiles.remove(&profile.id);
+                        format!("Couldn't save the profile: {}", err)
+                    }),
+                    Err(err) => Some(err),
+                };
+                true
+            }
+            Msg::SwitchProfile(id) => {
+                if self.switching_profile || id == profiles::current().id || self.profiles.find(&id).is_none() {
+                    return false;
+                }
+                self.profiles.active = id;
+                if let Err(err) = self.profiles.save() {
+                    self.profiles.active = profiles::current().id;
+                    self.profiles_error = Some(format!("Couldn't switch profiles: {}", err));
+                    return true;
+                }
+                self.switching_profile = true;
+                if !self.flush_state() {
+                    Self::reload_page();
+                }
+                true
+            }
+            Msg::RemoveProfile(id) => {
+                if id == profiles::current().id {
+                    return false;
+                }
+                let Some(profile) = self.profiles.remove(&id) else {
+                    return false;
+                };
+                if let Err(err) = self.profiles.save() {
+                    self.profiles_error = Some(err);
+                    return true;
+                }
+                storage::erase(&profile);
+                true
+            }
+            Msg::SetHistoryQuery(query) => {
+                self.history_query = query;
+                true
+            }
+            Msg::DeleteHistoryEntry(id) => {
+                if !self.state.history.remove(id) {
+                    return false;
+                }
+                self.save_state();
+                true
+            }
+            Msg::ClearHistory(range) => {
+                match range {
+                    Some(range) => self.state.history.clear_since(js_sys::Date::now() - range),
+                    None => self.state.history = BrowsingHistory::default(),
+                }
+                self.save_state();
+                true
+            }
+            Msg::SetHistoryRetention(days) => {
+                self.state.history_retention_days = days;
+                self.state.history.prune(days, js_sys::Date::now());
+                self.save_state();
+                true
+            }
+            Msg::ReopenClosed(index) => {
+                self.show_recently_closed = false;
+                self.reopen_closed(index)
+            }
+            Msg::ToggleRecentlyClosed => {
+                self.show_recently_closed = !self.show_recently_closed;
+                true
+            }
+            Msg::RestoreSession => {
+                self.offer_restore = false;
+                match self.state.recently_closed.iter().position(|e| matches!(e, ClosedEntry::Window { .. })) {
+                    Some(index) => self.reopen_closed(index),
+                    None => true,
+                }
+            }
+            Msg::DismissRestore => {
+                self.offer_restore = false;
+                true
+            }
+            Msg::DismissStateNotice => {
+                self.state_notice = None;
+                true
+            }
+            Msg::StateLoaded(loaded) => {
+                self.restore(*loaded);
+                true
+            }
+            Msg::FlushState => {
+                self.save_scheduled.set(false);
+                self.flush_state();
+                false
+            }
+            Msg::PageHidden => {
+                self.save_scheduled.set(false);
+                // The write may not finish before the page goes, so the state
+                // is kept where it can be written straight away too
+                if self.storage.as_ref().is_some_and(|backend| backend.is_persistent()) {
+                    storage::write_snapshot(&self.state);
+                }
+                self.flush_state();
+                false
+            }
+            Msg::StateSaved(result, deleted) => {
+                self.writes_in_flight = self.writes_in_flight.saturating_sub(1);
+                match result {
+                    Ok(()) => {
+                        // Everything in the snapshot has reached the backend
+                        if self.writes_in_flight == 0 {
+                            storage::clear_snapshot();
+                        }
+                        if std::mem::take(&mut self.remove_legacy) {
+                            schema::remove_legacy();
+                        }
+                        if self.switching_profile {
+                            Self::reload_page();
+                        }
+                        false
+                    }
+                    Err(err) => {
+                        if std::mem::take(&mut self.switching_profile) {
+                            // Stay put rather than leave unsaved changes behind
+                            self.profiles.active = profiles::current().id;
+                            let _ = self.profiles.save();
+                        }
+                        log::error!("Failed to save the browser state: {}", err);
+                        // Nothing the write touched can be trusted, so the next
+                        // save rewrites every record and retries the deletions
+                        for json in self.written.values_mut() {
+                            json.clear();
+                        }
+                        self.written.extend(deleted.into_iter().map(|key| (key, String::new())));
+                        self.state_notice = Some(match err {
+                            StorageError::QuotaExceeded => String::from(
+                                "Graphite is out of storage space, so recent changes weren't saved. Clearing history or downloads frees some up.",
+                            ),
+                            StorageError::Failed(err) => format!("Recent changes couldn't be saved ({}).", err),
+                        });
+                        true
+                    }
+                }
+            }
+            Msg::CloseAllPanels => {
+                self.show_recently_closed = false;
+                self.show_settings = false;
+                self.show_downloads = false;
+                self.show_bookmarks = false;
+                self.show_profiles = false;
+                self.show_palette = false;
+                self.tab_menu = None;
+                self.group_menu = None;
+                self.show_saved_groups = false;
+                self.show_all_tabs = false;
+                self.open_bookmark_folder = None;
+                true
+            }
+            Msg::RunAction(id) => match keymap::find_action(id) {
+                Some(action) => {
+                    let msg = action.msg(self);
+                    Component::update(self, ctx, msg)
+                }
+                None => false,
+            },
+            Msg::FocusUrlBar => {
+                if let Some(input) = self.url_bar_ref.cast::<HtmlInputElement>() {
+                    let _ = input.focus();
+                    input.select();
+                }
+                false
+            }
+            Msg::RecordShortcut(id) => {
+                self.recording_shortcut = id;
+                self.shortcut_error = None;
+                true
+            }
+            Msg::ShortcutPressed(combo) => {
+                let Some(action) = self.recording_shortcut.and_then(keymap::find_action) else {
+                    return false;
+                };
+                // A bare Escape gives up rather than being bound
+                if combo == (KeyCombo { ctrl: false, alt: false, shift: false, key: String::from("Escape") }) {
+                    self.recording_shortcut = None;
+                    self.shortcut_error = None;
+                    return true;
+                }
+                match self.state.keymap.add(action, combo) {
+                    Ok(()) => {
+                        self.recording_shortcut = None;
+                        self.shortcut_error = None;
+                        self.sync_keymap();
+                        self.save_state();
+                    }
+                    // Keeps waiting, so another combination can be tried
+                    Err(err) => self.shortcut_error = Some(err),
+                }
+                true
+            }
+            Msg::RemoveShortcut(id, combo) => {
+                if let Some(action) = keymap::find_action(id) {
+                    self.state.keymap.remove(action, &combo);
+                    self.sync_keymap();
+                    self.save_state();
+                }
+                true
+            }
+            Msg::ResetShortcuts(id) => {
+                match id.and_then(keymap::find_action) {
+                    Some(action) => self.state.keymap.reset(action),
+                    None => self.state.keymap.reset_all(),
+                }
+                self.shortcut_error = None;
+                self.sync_keymap();
+                self.save_state();
+                true
+            }
+            Msg::TogglePalette => {
+                self.show_palette = !self.show_palette;
+                self.palette_query.clear();
+                self.palette_selected = 0;
+                self.focus_palette = self.show_palette;
+                true
+            }
+            Msg::SetPaletteQuery(query) => {
+                self.palette_query = query;
+                self.palette_selected = 0;
+                true
+            }
+            Msg::MovePaletteSelection(delta) => {
+                let count = self.palette_entries().len();
+                if count == 0 {
+                    return false;
+                }
+                self.palette_selected = (self.palette_selected as isize + delta).rem_euclid(count as isize) as usize;
+                true
+            }
+            Msg::RunPaletteEntry(index) => {
+                let Some(entry) = self.palette_entries().into_iter().nth(index) else {
+                    return false;
+                };
+                self.show_palette = false;
+                Component::update(self, ctx, entry.target.msg());
+                true
+            }
+            Msg::NoOp => false,
+        }
+    }
+
+    fn rendered(&mut self, ctx: &Context<Self>, _first_render: bool) {
+        if self.storage.is_none() {
+            return;
+        }
+        self.load_proxied_frames(ctx);
+        self.sync_frame_sources();
+        // Combinations are only taken for binding while the shortcuts page shows
+        let recording = self.recording_shortcut.is_some()
+            && self.state.tabs.iter().any(|t| t.id == self.state.active_tab_id && pages::find(&t.url).name == "shortcuts");
+        self.key_dispatch.borrow_mut().recording = recording;
+        if self.tab_strip_overflows() != self.tab_overflow {
+            ctx.link().send_message(Msg::MeasureTabStrip);
+        }
+        if self.revealed_tab != Some(self.state.active_tab_id) {
+            self.revealed_tab = Some(self.state.active_tab_id);
+            self.reveal_active_tab();
+        }
+        if std::mem::take(&mut self.focus_palette) {
+            if let Some(input) = self.palette_ref.cast::<HtmlInputElement>() {
+                let _ = input.focus();
+            }
+        }
+    }
+
+    fn view(&self, ctx: &Context<Self>) -> Html {
+        if self.storage.is_none() {
+            return html! { <div class="browser-container" /> };
+        }
+        let link = ctx.link();
+        let active_tab = self.state.tabs.iter().find(|t| t.id == self.state.active_tab_id);
+        let active_tab_id = self.state.active_tab_id;
+        let internal_url = active_tab.map(|t| t.url.as_str()).filter(|url| pages::is_internal(url));
+        let can_go_back = active_tab.map(|t| t.can_go_back()).unwrap_or(false);
+        let can_go_forward = active_tab.map(|t| t.can_go_forward()).unwrap_or(false);
+        let is_internal = active_tab.map(|t| pages::is_internal(&t.url)).unwrap_or(true);
+        let selected_suggestion = self.selected_suggestion;
+        let is_bookmarked = active_tab.map(|t| self.state.bookmarks.find_by_url(&t.url).is_some()).unwrap_or(false);
+
+        html! {
+            <div
+                class={classes!("browser-container", (self.state.tab_layout == TabLayout::Vertical).then_some("vertical-tabs"))}
+                onclick={link.callback(|_| Msg::CloseAllPanels)}
+            >
+                // Tab Bar
+                if self.state.tab_layout == TabLayout::Horizontal {
+                    { self.render_tab_bar(link) }
+                }
+                { self.render_tab_menu(link) }
+                { self.render_group_menu(link) }
+
+                // Navigation Bar
+                <div class="nav-bar">
+                    <div class="nav-controls">
+                        <button class="nav-btn" onclick={link.callback(|_| Msg::GoBack)} title={self.with_shortcut("Back", "back")} disabled={!can_go_back}>
+                            <span class="icon icon-arrow-back"></span>
+                        </button>
+                        <button class="nav-btn" onclick={link.callback(|_| Msg::GoForward)} title={self.with_shortcut("Forward", "forward")} disabled={!can_go_forward}>
+                            <span class="icon icon-arrow-forward"></span>
+                        </button>
+                        <button class="nav-btn" onclick={link.callback(|_| Msg::Reload)} title={self.with_shortcut("Reload", "reload")}>
+                            <span class="icon icon-refresh"></span>
+                        </button>
+                    </div>
+                    
+                    <div class="url-bar-container">
+                        <input 
+                            type="text" 
+                            class="url-bar"
+                            ref={self.url_bar_ref.clone()}
+                            placeholder="Search or enter a URL..."
+                            value={self.url_input.clone()}
+                            oninput={link.callback(|e: InputEvent| {
+                                let input: HtmlInputElement = e.target_unchecked_into();
+                                Msg::UpdateUrlBar(input.value())
+                            })}
+                            onkeydown={link.callback(move |e: KeyboardEvent| {
+                                match e.key().as_str() {
+                                    "Enter" => match selected_suggestion {
+                                        Some(index) => Msg::AcceptSuggestion(index),
+                                        None => {
+                                            let input: HtmlInputElement = e.target_unchecked_into();
+                                            Msg::Navigate(input.value())
+                                        }
+                                    },
+                                    "ArrowDown" => {
+                                        e.prevent_default();
+                                        Msg::MoveSuggestion(1)
+                                    }
+                                    "ArrowUp" => {
+                                        e.prevent_default();
+                                        Msg::MoveSuggestion(-1)
+                                    }
+                                    "Escape" => Msg::DismissSuggestions,
+                                    _ => Msg::NoOp,
+                                }
+                            })}
+                            onblur={link.callback(|_| Msg::DismissSuggestions)}
+                        />
+                        { self.render_suggestions(link) }
+                        <button
+                            class="url-bar-star-btn"
+                            onclick={link.callback(|_| Msg::ToggleBookmark)}
+                            title={if is_bookmarked { "Remove bookmark" } else { "Bookmark this page" }}
+                            disabled={is_internal}
+                        >
+                            <span class={classes!("icon", if is_bookmarked { "icon-star-filled" } else { "icon-star" })}></span>
+                        </button>
+                        <button class="url-bar-search-btn" title="Search">
+                            <span class="icon icon-search"></span>
+                        </button>
+                    </div>
+
+                    <div class="toolbar-icons">
+                        <button
+                            class="toolbar-btn"
+                            onclick={link.callback(|_| Msg::CycleTheme)}
+                            title={format!("Theme: {}", self.state.theme.name())}
+                        >
+                            <span class={classes!("icon", self.state.theme.icon())}></span>
+                        </button>
+                        <button class="toolbar-btn" onclick={link.callback(|_| Msg::GoHome)} title="Home">
+                            <span class="icon icon-home"></span>
+                        </button>
+                        <button
+                            class="toolbar-btn"
+                            onclick={link.callback(|_| Msg::Navigate(format!("{}history", pages::SCHEME)))}
+                            title="History"
+                        >
+                            <span class="icon icon-history"></span>
+                        </button>
+                        <button
+                            class="toolbar-btn"
+                            onclick={link.callback(|e: MouseEvent| {
+                                e.stop_propagation();
+                                Msg::ToggleBookmarksPanel
+                            })}
+                            title="Bookmarks"
+                        >
+                            <span class="icon icon-bookmarks"></span>
+                        </button>
+                        <button 
+                            class="toolbar-btn" 
+                            onclick={link.callback(|e: MouseEvent| {
+                                e.stop_propagation();
+                                Msg::ToggleDownloadsPanel
+                            })}
+                            title="Downloads"
+                        >
+                            <span class="icon icon-download"></span>
+                        </button>
+                        <button 
+                            class="toolbar-btn" 
+                            onclick={link.callback(|e: MouseEvent| {
+                                e.stop_propagation();
+                                Msg::ToggleSettingsPanel
+                            })}
+                            title="Settings"
+                        >
+                            <span class="icon icon-settings"></span>
+                        </button>
+                        { self.render_profile_button(link) }
+                    </div>
+                </div>
+
+                // Bookmark Bar
+                { self.render_bookmark_bar(link) }
+
+                if let Some(notice) = &self.state_notice {
+                    <div class="session-banner">
+                        <span class="session-banner-icon icon icon-error"></span>
+                        <span class="session-banner-text">{notice}</span>
+                        <button class="theme-btn" onclick={link.callback(|_| Msg::DismissStateNotice)}>{"Dismiss"}</button>
+                    </div>
+                }
+                if self.offer_restore {
+                    <div class="session-banner">
+                        <span class="session-banner-icon icon icon-undo"></span>
+                        <span class="session-banner-text">
+                            {"Graphite didn't shut down properly. Restore your tabs, or start fresh if a page was causing trouble."}
+                        </span>
+                        <button class="theme-btn" onclick={link.callback(|_| Msg::RestoreSession)}>{"Restore tabs"}</button>
+                        <button class="theme-btn" onclick={link.callback(|_| Msg::DismissRestore)}>{"Start fresh"}</button>
+                    </div>
+                }
+
+                // The content area keeps its place whichever layout is chosen,
+                // so switching doesn't reload its frames
+                <div class="browser-main">
+                    if self.state.tab_layout == TabLayout::Vertical {
+                        { self.render_tab_sidebar(link) }
+                    }
+                    // Content Area
+                    <div class="content-area">
+                        // One frame per live tab, in id order so that reordering tabs
+                        // never moves (and thereby reloads) an iframe in the DOM
+                        { for self.frame_tabs().into_iter().map(|(tab, _)| {
+                            // Blob URLs have the browser's origin, so pages served
+                            // through them must not keep it
+                            let sandbox = if self.state.proxy.is_websocket() {
+                                "allow-scripts allow-forms allow-popups"
+                            } else {
+                                "allow-scripts allow-same-origin allow-forms allow-popups"
+                            };
+                            let tab_id = tab.id;
+                            let is_active = tab_id == active_tab_id;
+                            html! {
+                                <iframe
+                                    key={tab_id}
+                                    ref={self.frame_refs.get(&tab_id).cloned().unwrap_or_default()}
+                                    class={classes!("browser-iframe", (!is_active).then_some("background"))}
+                                    sandbox={sandbox}
+                                    onload={link.callback(move |e: Event| {
+                                        let iframe: HtmlIFrameElement = e.target_unchecked_into();
+                                        Msg::FrameLoaded(tab_id, FrameMetadata::read(&iframe))
+                                    })}
+                                    onerror={link.callback(move |_| Msg::FrameError(tab_id))}
+                                />
+                            }
+                        })}
+                        if let Some(url) = internal_url {
+                            { pages::render(self, link, url) }
+                        }
+                    </div>
+                </div>
+
+                if self.show_palette {
+                    { self.render_palette(link) }
+                }
+
+                // Settings Panel
+                if self.show_settings {
+                    <div class="panel settings-panel" onclick={|e: MouseEvent| e.stop_propagation()}>
+                        { self.render_settings(link) }
+                    </div>
+                }
+
+                // Downloads Panel
+                if self.show_downloads {
+                    <div class="panel downloads-panel" onclick={|e: MouseEvent| e.stop_propagation()}>
+                        <div class="panel-header">
+                            <span class="panel-icon icon icon-download"></span>
+                            <span class="panel-title">{"Downloads"}</span>
+                        </div>
+                        { self.render_downloads(link) }
+                    </div>
+                }
+
+                // Bookmarks Panel
+                if self.show_bookmarks {
+                    { self.render_bookmarks_panel(link) }
+                }
+
+                // Profiles Panel
+                if self.show_profiles {
+                    { self.render_profiles_panel(link) }
+                }
+            </div>
+        }
+    }
+}
+
+impl App {
+    /// Takes over the state read from storage and readies it for this session
+    fn restore(&mut self, loaded: storage::Loaded) {
+        self.state = loaded.state;
+        self.state_notice = loaded.notice;
+        self.written = loaded.written;
+        self.remove_legacy = loaded.from_legacy;
+        self.storage = Some(loaded.backend);
+
+        // A session that never shut down may have been brought down by one of
+        // its pages, so its tabs are set aside until the user asks for them
+        self.offer_restore = session::begin() && self.state.tabs.iter().any(|t| !t.is_blank());
+        if self.offer_restore {
+            let tabs = std::mem::take(&mut self.state.tabs);
+            let closed = ClosedEntry::Window { tabs, active_tab_id: self.state.active_tab_id, closed_at: js_sys::Date::now() };
+            session::push_closed(&mut self.state.recently_closed, closed);
+            let tab = Tab { id: self.state.next_tab_id, ..Tab::default() };
+            self.state.active_tab_id = tab.id;
+            self.state.next_tab_id += 1;
+            self.state.tabs.push(tab);
+        }
+
+        self.start_tabs();
+
+        // Entries from before real downloads existed have no source, and
+        // anything still running when the page closed has lost its bytes
+        self.state.downloads.retain(|d| !d.url.is_empty());
+        for download in self.state.downloads.iter_mut() {
+            if matches!(download.status, DownloadStatus::InProgress | DownloadStatus::Paused) {
+                download.status = DownloadStatus::Failed(String::from("Interrupted"));
+            }
+        }
+        let next_download_id = self.state.downloads.iter().map(|d| d.id + 1).max().unwrap_or(0);
+        self.state.next_download_id = self.state.next_download_id.max(next_download_id);
+
+        self.state.history.prune(self.state.history_retention_days, js_sys::Date::now());
+
+        self.apply_theme();
+        self.sync_keymap();
+
+        // Saves the normalised state, and moves it out of the old format
+        self.save_state();
+    }
+
+    fn is_private_tab(&self, id: u32) -> bool {
+        self.state.tabs.iter().any(|t| t.id == id && t.private)
+    }
+
+    fn new_private_jar() -> String {
+        format!("private-{:08x}", (js_sys::Math::random() * f64::from(u32::MAX)) as u32)
+    }
+
+    /// Forgets what private tabs left behind once the last one is closed
+    fn end_private_session(&mut self) {
+        for id in self.state.downloads.iter().filter(|d| d.private).map(|d| d.id).collect::<Vec<_>>() {
+            if let Some(mut job) = self.download_jobs.remove(&id) {
+                job.pause();
+            }
+        }
+        self.state.downloads.retain(|d| !d.private);
+        self.private_jar = Self::new_private_jar();
+    }
+
+    /// Swaps the whole state for an imported profile
+    fn replace_profile(&mut self, imported: BrowserState) {
+        self.proxy_client = None;
+        for id in self.proxy_frames.keys().copied().collect::<Vec<_>>() {
+            self.drop_proxy_frame(id);
+        }
+        self.state = profile::replace(&self.state, imported);
+        self.state.history.prune(self.state.history_retention_days, js_sys::Date::now());
+        self.start_tabs();
+        self.apply_theme();
+        self.sync_keymap();
+    }
+
+    /// Readies a newly adopted set of tabs. Only the active tab's frame is
+    /// loaded, the rest load when selected.
+    fn start_tabs(&mut self) {
+        // Every saved tab may have been private
+        if self.state.tabs.is_empty() {
+            let tab = Tab { id: self.state.next_tab_id, ..Tab::default() };
+            self.state.active_tab_id = tab.id;
+            self.state.next_tab_id += 1;
+            self.state.tabs.push(tab);
+        }
+        if !self.state.tabs.iter().any(|t| t.id == self.state.active_tab_id) {
+            self.state.active_tab_id = self.state.tabs[0].id;
+        }
+        self.arrange_tabs();
+        let active_tab_id = self.state.active_tab_id;
+        for tab in self.state.tabs.iter_mut() {
+            tab.discarded = tab.id != active_tab_id;
+            tab.is_loading = tab.has_live_frame();
+        }
+        self.frame_refs = self.state.tabs.iter().map(|t| (t.id, NodeRef::default())).collect();
+        self.frame_srcs.clear();
+
+        // Don't show graphite://home in URL bar - show empty string
+        self.url_input = self.state.tabs
+            .iter()
+            .find(|t| t.id == active_tab_id)
+            .map(|t| Self::get_display_url(&t.url))
+            .unwrap_or_default();
+        self.recent_tabs = vec![active_tab_id];
+    }
+
+    /// Schedules a save of the state; changes made within [`SAVE_DELAY_MS`]
+    /// of each other are written together
+    fn save_state(&self) {
+        if self.storage.is_some() && !self.save_scheduled.replace(true) {
+            self.link.send_future(async {
+                gloo::timers::future::TimeoutFuture::new(SAVE_DELAY_MS).await;
+                Msg::FlushState
+            });
+        }
+    }
+
+    /// Writes the records that changed since the last save, and reports
+    /// whether there were any
+    fn flush_state(&mut self) -> bool {
+        let Some(backend) = self.storage.clone() else {
+            return false;
+        };
+        let records = match storage::split(&self.state) {
+            Ok(records) => records,
+            Err(err) => {
+                log::error!("Failed to encode the browser state: {}", err);
+                return false;
+            }
+        };
+        let changes = storage::diff(&mut self.written, records);
+        if changes.is_empty() {
+            return false;
+        }
+        let deleted = changes
+            .iter()
+            .filter_map(|change| match change {
+                storage::Change::Delete { store, key } => Some((*store, key.clone())),
+                storage::Change::Put(_) => None,
+            })
+            .collect();
+        let write = backend.write(changes);
+        self.writes_in_flight += 1;
+        self.link.send_future(async move { Msg::StateSaved(write.await, deleted) });
+        true
+    }
+
+    fn reload_page() {
+        if let Some(Err(err)) = web_sys::window().map(|w| w.location().reload()) {
+            log::error!("Failed to reload the page: {:?}", err);
+        }
+    }
+
+    /// Reopens a closed tab where it was, or a closed window's tabs after the
+    /// open ones
+    fn reopen_closed(&mut self, index: usize) -> bool {
+        if index >= self.state.recently_closed.len() {
+            return false;
+        }
+        match self.state.recently_closed.remove(index) {
+            ClosedEntry::Tab { tab, index, .. } => {
+                let id = self.insert_reopened_tab(tab, index);
+                self.activate_tab(id);
+            }
+            ClosedEntry::Window { tabs, active_tab_id, .. } => {
+                // A lone new tab is replaced rather than kept alongside
+                let blank = match self.state.tabs.as_slice() {
+                    [tab] if tab.is_blank() => Some(tab.id),
+                    _ => None,
+                };
+                let mut active = None;
+                for tab in tabs {
+                    let was_active = tab.id == active_tab_id;
+                    let id = self.insert_reopened_tab(tab, usize::MAX);
+                    if was_active || active.is_none() {
+                        active = Some(id);
+                    }
+                }
+                if let Some(id) = active {
+                    self.activate_tab(id);
+                }
+                if let Some(blank) = blank {
+                    self.state.tabs.retain(|t| t.id != blank);
+                    self.frame_refs.remove(&blank);
+                    self.recent_tabs.retain(|&t| t != blank);
+                }
+            }
+        }
+        self.save_state();
+        true
+    }
+
+    /// Adds a reopened tab under a fresh id, unloaded until it is selected
+    fn insert_reopened_tab(&mut self, mut tab: Tab, index: usize) -> u32 {
+        tab.id = self.state.next_tab_id;
+        self.state.next_tab_id += 1;
+        tab.discarded = true;
+        tab.is_loading = false;
+        let id = tab.id;
+        let index = index.min(self.state.tabs.len());
+        self.state.tabs.insert(index, tab);
+        self.arrange_tabs();
+        self.frame_refs.insert(id, NodeRef::default());
+        id
+    }
+
+    /// Removes a tab, remembering it so it can be reopened. The last tab
+    /// always stays.
+    fn close_tab(&mut self, id: u32) {
+        if self.state.tabs.len() <= 1 {
+            return;
+        }
+        let Some(idx) = self.state.tabs.iter().position(|t| t.id == id) else {
+            return;
+        };
+        let tab = self.state.tabs.remove(idx);
+        // Its children move up to take its place in the tree
+        for child in self.state.tabs.iter_mut().filter(|t| t.opener == Some(id)) {
+            child.opener = tab.opener;
+        }
+        if tab.private {
+            if !self.state.tabs.iter().any(|t| t.private) {
+                self.end_private_session();
+            }
+        } else if !tab.is_blank() {
+            let closed = ClosedEntry::Tab { tab, index: idx, closed_at: js_sys::Date::now() };
+            session::push_closed(&mut self.state.recently_closed, closed);
+        }
+        self.frame_refs.remove(&id);
+        self.frame_key_listeners.remove(&id);
+        self.frame_media_listeners.remove(&id);
+        self.drop_proxy_frame(id);
+        self.proxy_posts.remove(&id);
+        self.recent_tabs.retain(|&t| t != id);
+        if self.tab_menu.is_some_and(|(menu_id, _, _)| menu_id == id) {
+            self.tab_menu = None;
+        }
+        self.arrange_tabs();
+        if self.state.active_tab_id == id {
+            let new_idx = idx.saturating_sub(1).min(self.state.tabs.len() - 1);
+            self.activate_tab(self.state.tabs[new_idx].id);
+        }
+    }
+
+    /// Whether the horizontal strip holds more tabs than it can show
+    fn tab_strip_overflows(&self) -> bool {
+        self.state.tab_layout == TabLayout::Horizontal
+            && self.tab_strip_ref.cast::<web_sys::Element>().is_some_and(|strip| strip.scroll_width() > strip.client_width())
+    }
+
+    /// Scrolls the strip or sidebar just far enough to show the active tab
+    fn reveal_active_tab(&self) {
+        let tab = self.tab_strip_ref
+            .cast::<web_sys::Element>()
+            .and_then(|strip| strip.query_selector(".tab.active").ok().flatten());
+        if let Some(tab) = tab {
+            let options = web_sys::ScrollIntoViewOptions::new();
+            options.set_block(web_sys::ScrollLogicalPosition::Nearest);
+            options.set_inline(web_sys::ScrollLogicalPosition::Nearest);
+            tab.scroll_into_view_with_scroll_into_view_options(&options);
+        }
+    }
+
+    /// Keeps pinned tabs first and each group's tabs together
+    fn arrange_tabs(&mut self) {
+        tab_groups::arrange(&mut self.state.tabs, &mut self.state.tab_groups);
+    }
+
+    /// Closes a group's tabs, opening a new tab first if nothing else would
+    /// be left. Returns the tabs that were closed.
+    fn close_group(&mut self, group: u32) -> Vec<Tab> {
+        let tabs: Vec<Tab> = self.state.tabs.iter().filter(|t| t.group == Some(group)).cloned().collect();
+        if tabs.len() == self.state.tabs.len() {
+            let tab = Tab { id: self.state.next_tab_id, ..Tab::default() };
+            self.state.next_tab_id += 1;
+            self.frame_refs.insert(tab.id, NodeRef::default());
+            self.state.tabs.push(tab);
+        }
+        for tab in tabs.iter() {
+            self.close_tab(tab.id);
+        }
+        tabs
+    }
+
+    /// Selects a tab, waking its frame back up if it had been discarded
+    fn activate_tab(&mut self, id: u32) {
+        let Some(tab) = self.state.tabs.iter_mut().find(|t| t.id == id) else {
+            return;
+        };
+        if tab.discarded {
+            tab.discarded = false;
+            tab.is_loading = tab.has_live_frame();
+        }
+        self.url_input = Self::get_display_url(&tab.url);
+        self.state.active_tab_id = id;
+        self.recent_tabs.retain(|&t| t != id);
+        self.recent_tabs.push(id);
+        self.enforce_frame_limit();
+    }
+
+    /// Discards the least recently used frames beyond `max_live_frames`
+    fn enforce_frame_limit(&mut self) {
+        let active_tab_id = self.state.active_tab_id;
+        let mut live: Vec<u32> = self.state.tabs
+            .iter()
+            .filter(|t| t.has_live_frame() && t.id != active_tab_id)
+            .map(|t| t.id)
+            .collect();
+        // The active frame always stays live and counts against the limit
+        let budget = self.state.max_live_frames.saturating_sub(1);
+        if live.len() <= budget {
+            return;
+        }
+        // Tabs missing from `recent_tabs` were never selected this session
+        live.sort_by_key(|id| self.recent_tabs.iter().position(|t| t == id));
+        for id in live.iter().take(live.len() - budget) {
+            if let Some(tab) = self.state.tabs.iter_mut().find(|t| t.id == *id) {
+                tab.discarded = true;
+                tab.is_loading = false;
+            }
+            self.drop_proxy_frame(*id);
+        }
+    }
+
+    /// Tabs that currently own an iframe along with its source, in a stable order
+    fn frame_tabs(&self) -> Vec<(&Tab, String)> {
+        let websocket_proxy = self.state.proxy.is_websocket();
+        let mut tabs: Vec<(&Tab, String)> = self.state.tabs
+            .iter()
+            .filter(|t| t.has_live_frame())
+            .filter_map(|t| {
+                if websocket_proxy && t.url != "about:blank" {
+                    // Proxied frames only mount once their page has arrived
+                    let frame = self.proxy_frames.get(&t.id).filter(|f| f.url == t.url)?;
+                    Some((t, frame.object_url.clone()?))
+                } else {
+                    Some((t, self.get_proxied_url(&t.url)))
+                }
+            })
+            .collect();
+        tabs.sort_by_key(|(t, _)| t.id);
+        tabs
+    }
+
+    /// Starts WebSocket proxy fetches for live tabs whose page isn't loaded yet
+    fn load_proxied_frames(&mut self, ctx: &Context<Self>) {
+        if !self.state.proxy.is_websocket() {
+            return;
+        }
+        let stale: Vec<u32> = self.proxy_frames
+            .iter()
+            .filter(|(id, frame)| {
+                !self.state.tabs.iter().any(|t| t.id == **id && t.has_live_frame() && t.url == frame.url)
+            })
+            .map(|(id, _)| *id)
+            .collect();
+        for id in stale {
+            self.drop_proxy_frame(id);
+        }
+
+        let wanted: Vec<(u32, String)> = self.state.tabs
+            .iter()
+            .filter(|t| t.has_live_frame() && t.url != "about:blank" && !self.proxy_frames.contains_key(&t.id))
+            .map(|t| (t.id, t.url.clone()))
+            .collect();
+        if wanted.is_empty() {
+            return;
+        }
+
+        for (id, url) in wanted {
+            self.proxy_frames.insert(id, ProxyFrame { url: url.clone(), object_url: None });
+            let client = self.websocket_client_for(self.is_private_tab(id));
+            // Only the load straight after the form was submitted posts it
+            let post = self.proxy_posts.remove(&id).filter(|(to, _)| *to == url).map(|(_, body)| body);
+            ctx.link().send_future(async move {
+                let result = match (client, post) {
+                    (Ok(client), None) => client.fetch(&url).await.and_then(|r| r.into_object_url(&url)),
+                    (Ok(client), Some(body)) => {
+                        let headers = vec![(String::from("content-type"), String::from(proxy::FORM_CONTENT_TYPE))];
+                        let response = client.request("POST", &url, headers, Some(body.into_bytes())).await;
+                        response.and_then(|r| r.into_object_url(&url))
+                    }
+                    (Err(err), _) => Err(err),
+                };
+                let result = result.or_else(|err| proxy::error_page_url(&url, &err));
+                Msg::ProxyLoaded(id, url, result)
+            });
+        }
+    }
+
+    /// Points each iframe at its tab's page. Sources are assigned here rather
+    /// than through the `src` attribute so that a frame which navigated on its
+    /// own is not sent back to the page it started on.
+    fn sync_frame_sources(&mut self) {
+        let frames: Vec<(u32, String)> = self.frame_tabs().into_iter().map(|(t, src)| (t.id, src)).collect();
+        self.frame_srcs.retain(|id, _| frames.iter().any(|(f, _)| f == id));
+        for (id, src) in frames {
+            if self.frame_srcs.get(&id) == Some(&src) {
+                continue;
+            }
+            if let Some(iframe) = self.frame_refs.get(&id).and_then(|r| r.cast::<HtmlIFrameElement>()) {
+                iframe.set_src(&src);
+                self.frame_srcs.insert(id, src);
+            }
+        }
+    }
+
+    /// Returns the open WebSocket proxy connection, reconnecting if it dropped
+    fn websocket_client(&mut self) -> Result<ProxyClient, String> {
+        let reusable = self.proxy_client
+            .as_ref()
+            .filter(|c| !c.is_closed() && c.url() == self.state.proxy.endpoint());
+        let client = match reusable {
+            Some(client) => Ok(client.clone()),
+            None => ProxyClient::connect(self.state.proxy.endpoint()),
+        };
+        self.proxy_client = client.as_ref().ok().cloned();
+        client
+    }
+
+    /// The proxy connection for a tab; private tabs get their own cookie jar
+    fn websocket_client_for(&mut self, private: bool) -> Result<ProxyClient, String> {
+        let client = self.websocket_client()?;
+        Ok(if private { client.with_jar(&self.private_jar) } else { client })
+    }
+
+    /// Fetches a small text resource, such as search suggestions, the same
+    /// way pages are loaded: over the WebSocket proxy or through the
+    /// configured URL rewriting proxy
+    fn fetch_through_proxy(&mut self, url: &str) -> LocalBoxFuture<'static, Result<String, String>> {
+        let url = url.to_string();
+        if self.state.proxy.is_websocket() {
+            let client = self.websocket_client_for(self.is_private_tab(self.state.active_tab_id));
+            return async move {
+                let response = client?.fetch(&url).await?;
+                Ok(String::from_utf8_lossy(&response.body).into_owned())
+            }
+            .boxed_local();
+        }
+        let proxied = self.state.proxy.encode(&url);
+        async move {
+            let response = gloo_net::http::Request::get(&proxied).send().await.map_err(|e| e.to_string())?;
+            if !response.ok() {
+                return Err(format!("HTTP {}", response.status()));
+            }
+            response.text().await.map_err(|e| e.to_string())
+        }
+        .boxed_local()
+    }
+
+    /// Rebuilds the omnibox dropdown from open tabs, bookmarks and history
+    fn update_suggestions(&mut self) {
+        let active_tab_id = self.state.active_tab_id;
+        let tabs = self.state.tabs
+            .iter()
+            .filter(|t| t.id != active_tab_id && !t.private && !pages::is_internal(&t.url))
+            .map(|t| Candidate { kind: SuggestionKind::OpenTab(t.id), title: &t.title, url: &t.url, visit_count: 0, last_visit: 0.0 });
+        let bookmarks = self.state.bookmarks
+            .all()
+            .into_iter()
+            .map(|b| Candidate { kind: SuggestionKind::Bookmark, title: &b.title, url: &b.url, visit_count: 0, last_visit: b.added_at });
+        let history = self.state.history.entries.iter().map(|e| Candidate {
+            kind: SuggestionKind::History,
+            title: &e.title,
+            url: &e.url,
+            visit_count: e.visit_count,
+            last_visit: e.last_visit,
+        });
+        let candidates = tabs.chain(bookmarks).chain(history).collect();
+        self.suggestions = omnibox::rank(&self.url_input, candidates, &self.search_suggestions, js_sys::Date::now());
+        self.selected_suggestion = None;
+    }
+
+    fn clear_suggestions(&mut self) {
+        self.suggest_generation = self.suggest_generation.wrapping_add(1);
+        self.suggestions.clear();
+        self.search_suggestions.clear();
+        self.selected_suggestion = None;
+    }
+
+    /// Fetches the OpenSearch description a page links to, the first time
+    /// any page links to it
+    fn discover_search_engine(&mut self, ctx: &Context<Self>, page_url: &str, href: &str) {
+        let Some(address) = url::Url::parse(page_url).ok().and_then(|page| page.join(href).ok()) else {
+            return;
+        };
+        // Proxies that rewrite links leave a proxied address in the attribute
+        let address = address.to_string();
+        let address = self.state.proxy.decode(&address).unwrap_or(address);
+        if !self.seen_search_descriptions.insert(address.clone()) {
+            return;
+        }
+        let request = self.fetch_through_proxy(&address);
+        ctx.link().send_future(async move { Msg::SearchDescriptionLoaded(address, request.await) });
+    }
+
+    fn search_engines(&self) -> Vec<SearchEngine> {
+        search_engines::all(&self.state.custom_search_engines)
+    }
+
+    /// The selected engine, or the default one if it has been removed
+    fn search_engine(&self) -> SearchEngine {
+        let engines = self.search_engines();
+        search_engines::find(&engines, &self.state.search_engine)
+            .or_else(|| search_engines::find(&engines, search_engines::DEFAULT_ENGINE_ID))
+            .cloned()
+            .unwrap_or_default()
+    }
+
+    /// The engine URL bar text should be searched with, and the query for it
+    fn search_target(&self, input: &str) -> (SearchEngine, String) {
+        let engines = self.search_engines();
+        match search_engines::split_keyword(&engines, input) {
+            Some((engine, query)) => (engine.clone(), query.to_string()),
+            None => (self.search_engine(), input.trim().to_string()),
+        }
+    }
+
+    /// (Re)starts a download through the configured proxy, resuming from the
+    /// bytes its job already holds
+    fn start_download_job(&mut self, ctx: &Context<Self>, id: u32) {
+        let Some((url, private)) = self.state.downloads.iter().find(|d| d.id == id).map(|d| (d.url.clone(), d.private)) else {
+            return;
+        };
+        let transport = if self.state.proxy.is_websocket() {
+            match self.websocket_client_for(private) {
+                Ok(client) => Transport::WebSocket(client),
+                Err(err) => {
+                    ctx.link().send_message(Msg::DownloadProgress(
+                        id,
+                        self.download_jobs.get(&id).map(DownloadJob::generation).unwrap_or_default(),
+                        DownloadEvent::Failed(err),
+                    ));
+                    return;
+                }
+            }
+        } else {
+            Transport::Fetch(self.get_proxied_url(&url))
+        };
+        if let Some(job) = self.download_jobs.get_mut(&id) {
+            let events = ctx.link().callback(move |(generation, event)| Msg::DownloadProgress(id, generation, event));
+            job.start(id, &url, transport, !private, events);
+        }
+    }
+
+    fn drop_proxy_frame(&mut self, id: u32) {
+        if let Some(object_url) = self.proxy_frames.remove(&id).and_then(|f| f.object_url) {
+            proxy::revoke_object_url(&object_url);
+        }
+    }
+
+    /// Moves the active tab through its back/forward stack
+    fn step_active_history(&mut self, offset: isize) -> bool {
+        let Some(tab) = self.state.tabs.iter_mut().find(|t| t.id == self.state.active_tab_id) else {
+            return false;
+        };
+        if !tab.step_history(offset) {
+            return false;
+        }
+        self.url_input = Self::get_display_url(&tab.url);
+        let (url, title, id) = (tab.url.clone(), tab.title.clone(), tab.id);
+        self.record_visit(&url, &title, id);
+        self.save_state();
+        true
+    }
+
+    /// Loads `url` in a tab as a new entry in its history
+    fn navigate_tab(&mut self, id: u32, url: &str) {
+        let title = Self::get_title_from_url(url);
+        if let Some(tab) = self.state.tabs.iter_mut().find(|t| t.id == id) {
+            tab.push_history(url);
+            tab.url = url.to_string();
+            tab.title = title.clone();
+            tab.favicon = None;
+            tab.is_loading = !pages::is_internal(url);
+        }
+        self.record_visit(url, &title, id);
+        self.enforce_frame_limit();
+        self.save_state();
+    }
+
+    /// Adds a page visit to the global history; internal pages and private
+    /// tabs aren't recorded
+    fn record_visit(&mut self, url: &str, title: &str, tab_id: u32) {
+        if pages::is_internal(url) || self.is_private_tab(tab_id) {
+            return;
+        }
+        let now = js_sys::Date::now();
+        self.state.history.record_visit(url, title, tab_id, now);
+        self.state.history.prune(self.state.history_retention_days, now);
+    }
+
+    /// Returns the display URL for the URL bar (empty for home page)
+    fn get_display_url(url: &str) -> String {
+        if url == pages::HOME_URL {
+            String::new()
+        } else {
+            url.to_string()
+        }
+    }
+
+    fn process_url(&self, input: &str) -> String {
+        // `gh rust-lang` searches the engine with the keyword `gh`
+        let engines = self.search_engines();
+        if let Some((engine, query)) = search_engines::split_keyword(&engines, input) {
+            return engine.search_url(query);
+        }
+
+        match address::classify(input) {
+            // Unwrap addresses pasted from the proxy
+            InputKind::Url(url) => self.state.proxy.decode(&url).unwrap_or(url),
+            InputKind::Internal(url) => url,
+            InputKind::Search(query) => self.search_engine().search_url(&query),
+            InputKind::Unsupported { scheme } => pages::error_url(
+                input.trim(),
+                &format!("{}: addresses can't be opened in graphite.", scheme),
+            ),
+        }
+    }
+
+    fn get_proxied_url(&self, url: &str) -> String {
+        // Nothing to fetch for a blank page
+        if url == "about:blank" {
+            return url.to_string();
+        }
+        self.state.proxy.encode(url)
+    }
+
+    fn get_title_from_url(url: &str) -> String {
+        if pages::is_internal(url) {
+            return pages::find(url).title.to_string();
+        }
+        
+        // Extract domain from URL
+        url.replace("https://", "")
+            .replace("http://", "")
+            .split('/')
+            .next()
+            .unwrap_or("New Tab")
+            .to_string()
+    }
+
+    /// Guesses the site icon location when the document can't be inspected
+    fn get_fallback_favicon(url: &str) -> Option<String> {
+        if pages::is_internal(url) {
+            return None;
+        }
+        let host = Self::get_title_from_url(url);
+        (!host.is_empty()).then(|| format!("https://{}/favicon.ico", host))
+    }
+
+    fn apply_theme(&self) {
+        theme::apply(self.state.theme, self.state.custom_theme.as_ref(), self.system_prefers_dark);
+    }
+
+    /// Hands the current bindings to the key listeners
+    fn sync_keymap(&self) {
+        self.key_dispatch.borrow_mut().table = self.state.keymap.table();
+    }
+
+    /// Key presses inside same-origin pages never reach the window, so
+    /// shortcuts are listened for in their documents as well
+    fn listen_for_frame_keys(&mut self, id: u32) {
+        let document = self.frame_refs
+            .get(&id)
+            .and_then(|r| r.cast::<HtmlIFrameElement>())
+            .and_then(|iframe| iframe.content_document());
+        match document {
+            Some(document) => {
+                let listener = keymap::listen(&document, Rc::clone(&self.key_dispatch), self.link.clone());
+                self.frame_key_listeners.insert(id, listener);
+            }
+            None => {
+                self.frame_key_listeners.remove(&id);
+            }
+        }
+    }
+
+    /// Mutes or unmutes a tab's page as far as the browser can reach it:
+    /// through the bridge script of WebSocket-proxied pages, or directly in
+    /// same-origin ones. Other cross-origin pages are out of reach.
+    fn apply_mute(&mut self, id: u32) {
+        let muted = self.state.tabs.iter().any(|t| t.id == id && t.muted);
+        let Some(iframe) = self.frame_refs.get(&id).and_then(|r| r.cast::<HtmlIFrameElement>()) else {
+            return;
+        };
+        if self.state.proxy.is_websocket() {
+            if let Some(window) = iframe.content_window() {
+                // The page's origin is opaque, so it can't be named
+                let _ = window.post_message(&JsValue::from_str(&proxy::mute_message(muted)), "*");
+            }
+            return;
+        }
+        let Some(document) = iframe.content_document() else {
+            self.frame_media_listeners.remove(&id);
+            return;
+        };
+        let selector = if muted { String::from("audio, video") } else { format!("[{}]", proxy::MUTED_ATTRIBUTE) };
+        if let Ok(media) = document.query_selector_all(&selector) {
+            for i in 0..media.length() {
+                if let Some(node) = media.item(i) {
+                    // Elements of a frame fail instanceof checks against this window
+                    set_media_muted(node.unchecked_ref(), muted);
+                }
+            }
+        }
+        if muted {
+            // Media events don't bubble, but they do pass the document while capturing
+            let options = EventListenerOptions { phase: EventListenerPhase::Capture, passive: true };
+            let listener = EventListener::new_with_options(&document, "play", options, |e| {
+                if let Some(target) = e.target() {
+                    set_media_muted(target.unchecked_ref(), true);
+                }
+            });
+            self.frame_media_listeners.insert(id, listener);
+        } else {
+            self.frame_media_listeners.remove(&id);
+        }
+    }
+
+    /// What the command palette offers for the current query
+    fn palette_entries(&self) -> Vec<palette::Entry> {
+        let keymap = &self.state.keymap;
+        let mut entries: Vec<palette::Entry> = keymap::ACTIONS
+            .iter()
+            .filter(|action| action.id != "command-palette")
+            .map(|action| palette::Entry {
+                target: Target::Action(action.id),
+                title: action.label.to_string(),
+                detail: String::from("Command"),
+                icon: action.icon,
+                shortcut: keymap.keys(action).first().map(KeyCombo::to_string),
+            })
+            .collect();
+        entries.extend(self.state.tabs.iter().filter(|t| t.id != self.state.active_tab_id).map(|tab| palette::Entry {
+            target: Target::Tab(tab.id),
+            title: tab.title.clone(),
+            detail: format!("Switch to tab · {}", tab.url),
+            icon: if tab.private { "icon-private" } else { "icon-tab" },
+            shortcut: None,
+        }));
+        entries.extend(self.state.saved_groups.iter().enumerate().map(|(index, group)| palette::Entry {
+            target: Target::SavedGroup(index),
+            title: format!("Restore {}", group.title()),
+            detail: String::from("Saved group"),
+            icon: "icon-folder",
+            shortcut: None,
+        }));
+        entries.extend(search_engines::all(&self.state.custom_search_engines).into_iter().map(|engine| palette::Entry {
+            title: format!("Search with {}", engine.name),
+            detail: String::from("Search engine"),
+            target: Target::SearchEngine(engine.id),
+            icon: "icon-search",
+            shortcut: None,
+        }));
+        entries.extend([Theme::Light, Theme::Dark, Theme::System].into_iter().map(|theme| palette::Entry {
+            target: Target::Theme(theme),
+            title: format!("{} theme", theme.name()),
+            detail: String::from("Theme"),
+            icon: theme.icon(),
+            shortcut: None,
+        }));
+        entries.extend(pages::PAGES.iter().filter(|page| page.name != "error").map(|page| palette::Entry {
+            target: Target::Url(format!("{}{}", pages::SCHEME, page.name)),
+            title: format!("Open {}", page.title),
+            detail: format!("{}{}", pages::SCHEME, page.name),
+            icon: page.icon,
+            shortcut: None,
+        }));
+        // Pages only come up once something is typed, there are too many to browse
+        if !self.palette_query.trim().is_empty() {
+            entries.extend(self.state.bookmarks.all().into_iter().map(|bookmark| palette::Entry {
+                target: Target::Url(bookmark.url.clone()),
+                title: bookmark.title.clone(),
+                detail: bookmark.url.clone(),
+                icon: "icon-star-filled",
+                shortcut: None,
+            }));
+            entries.extend(self.state.history.entries.iter().map(|entry| palette::Entry {
+                target: Target::Url(entry.url.clone()),
+                title: if entry.title.is_empty() { entry.url.clone() } else { entry.title.clone() },
+                detail: entry.url.clone(),
+                icon: "icon-history",
+                shortcut: None,
+            }));
+        }
+        palette::search(&self.palette_query, entries)
+    }
+
+    /// A button tooltip naming the first key bound to the button's action
+    fn with_shortcut(&self, label: &str, id: &str) -> String {
+        let key = keymap::find_action(id).and_then(|action| self.state.keymap.keys(action).into_iter().next());
+        match key {
+            Some(key) => format!("{} ({})", label, key),
+            None => label.to_string(),
+        }
+    }
+
+    /// Settings controls, shared by the settings panel and `graphite://settings`
+    fn render_settings(&self, link: &yew::html::Scope<Self>) -> Html {
+        html! {
+            <>
+                <div class="panel-header">
+                    <span class="panel-icon icon icon-search"></span>
+                    <span class="panel-title">{"Search Engine"}</span>
+                </div>
+                <div class="search-engines">
+                    { for self.search_engines().iter().map(|engine| self.render_search_engine_option(link, engine, None)) }
+                    <button
+                        class="search-engine-btn search-engine-add"
+                        title="Add a search engine"
+                        onclick={link.callback(|_| Msg::EditSearchEngine(None))}
+                    >
+                        <span class="icon icon-add"></span>
+                    </button>
+                </div>
+                if !self.discovered_engines.is_empty() {
+                    <div class="settings-note">{"Found on sites you visited"}</div>
+                    <div class="search-engines">
+                        { for self.discovered_engines.iter().enumerate().map(|(i, engine)| self.render_search_engine_option(link, engine, Some(i))) }
+                    </div>
+                }
+                { self.render_custom_search_engines(link) }
+                <div class="panel-header proxy-header">
+                    <span class="panel-icon icon icon-cell-tower"></span>
+                    <span class="panel-title">{"Proxy Server"}</span>
+                </div>
+                { self.render_proxy_settings(link) }
+                <div class="panel-header frames-header">
+                    <span class="panel-icon icon icon-tab"></span>
+                    <span class="panel-title">{"Live Tabs"}</span>
+                </div>
+                <input
+                    type="number"
+                    class="proxy-input"
+                    min="1"
+                    title="Tabs beyond this limit are unloaded and reload when selected"
+                    value={self.state.max_live_frames.to_string()}
+                    onchange={link.callback(|e: Event| {
+                        let input: HtmlInputElement = e.target_unchecked_into();
+                        match input.value().parse() {
+                            Ok(max) => Msg::SetMaxLiveFrames(max),
+                            Err(_) => Msg::NoOp,
+                        }
+                    })}
+                />
+                <div class="panel-header tab-layout-header">
+                    <span class="panel-icon icon icon-tab"></span>
+                    <span class="panel-title">{"Tab Layout"}</span>
+                </div>
+                <div class="theme-options">
+                    { for TabLayout::ALL.into_iter().map(|layout| html! {
+                        <button
+                            class={classes!("theme-btn", (self.state.tab_layout == layout).then_some("selected"))}
+                            onclick={link.callback(move |_| Msg::SetTabLayout(layout))}
+                        >
+                            {layout.name()}
+                        </button>
+                    })}
+                </div>
+                <div class="panel-header history-header">
+                    <span class="panel-icon icon icon-history"></span>
+                    <span class="panel-title">{"History"}</span>
+                </div>
+                <label class="settings-label">
+                    {"Keep history for"}
+                    <input
+                        type="number"
+                        class="proxy-input settings-number"
+                        min="0"
+                        title="Zero keeps history forever"
+                        value={self.state.history_retention_days.to_string()}
+                        onchange={link.callback(|e: Event| {
+                            let input: HtmlInputElement = e.target_unchecked_into();
+                            match input.value().parse() {
+                                Ok(days) => Msg::SetHistoryRetention(days),
+                                Err(_) => Msg::NoOp,
+                            }
+                        })}
+                    />
+                    {"days"}
+                </label>
+                { self.render_theme_settings(link) }
+                { self.render_profile_settings(link) }
+            </>
+        }
+    }
+
+    /// Download list and link input, shared by the downloads panel and `graphite://downloads`
+    fn render_downloads(&self, link: &yew::html::Scope<Self>) -> Html {
+        html! {
+            <>
+                <input
+                    type="text"
+                    class="proxy-input download-url-input"
+                    placeholder="Paste a link to download"
+                    onkeypress={link.callback(|e: KeyboardEvent| {
+                        if e.key() == "Enter" {
+                            let input: HtmlInputElement = e.target_unchecked_into();
+                            let url = input.value();
+                            input.set_value("");
+                            Msg::StartDownload(url)
+                        } else {
+                            Msg::NoOp
+                        }
+                    })}
+                />
+                <div class="downloads-list">
+                    { for self.state.downloads.iter().map(|download| self.render_download(link, download)) }
+                </div>
+            </>
+        }
+    }
+
+    fn render_theme_settings(&self, link: &yew::html::Scope<Self>) -> Html {
+        let on_import = {
+            let link = link.clone();
+            Callback::from(move |e: Event| {
+                let input: HtmlInputElement = e.target_unchecked_into();
+                let Some(file) = input.files().and_then(|files| files.get(0)) else {
+                    return;
+                };
+                input.set_value("");
+                link.send_future(async move {
+                    match wasm_bindgen_futures::JsFuture::from(file.text()).await {
+                        Ok(text) => Msg::ImportTheme(text.as_string().unwrap_or_default()),
+                        Err(_) => Msg::ImportTheme(String::new()),
+                    }
+                });
+            })
+        };
+
+        html! {
+            <>
+                <div class="panel-header theme-header">
+                    <span class={classes!("panel-icon", "icon", self.state.theme.icon())}></span>
+                    <span class="panel-title">{"Theme"}</span>
+                </div>
+                <div class="theme-options">
+                    { for [Theme::Light, Theme::Dark, Theme::System].into_iter().map(|theme| html! {
+                        <button
+                            class={classes!("theme-btn", (self.state.theme == theme).then_some("selected"))}
+                            onclick={link.callback(move |_| Msg::SetTheme(theme))}
+                        >
+                            {theme.name()}
+                        </button>
+                    })}
+                </div>
+                <div class="theme-options">
+                    <label class="theme-btn" title="Load a theme from a JSON file">
+                        {"Import"}
+                        <input type="file" accept=".json,application/json" class="hidden-file-input" onchange={on_import} />
+                    </label>
+                    <button class="theme-btn" onclick={link.callback(|_| Msg::ExportTheme)} title="Save the current theme as JSON">
+                        {"Export"}
+                    </button>
+                    if let Some(custom) = &self.state.custom_theme {
+                        <button class="theme-btn" onclick={link.callback(|_| Msg::ResetTheme)} title={format!("Remove \"{}\"", custom.name)}>
+                            {"Reset"}
+                        </button>
+                    }
+                </div>
+                if let Some(err) = &self.theme_error {
+                    <div class="settings-error">{err}</div>
+                }
+            </>
+        }
+    }
+
+    fn render_profile_settings(&self, link: &yew::html::Scope<Self>) -> Html {
+        let on_import = {
+            let link = link.clone();
+            Callback::from(move |e: Event| {
+                let input: HtmlInputElement = e.target_unchecked_into();
+                let Some(file) = input.files().and_then(|files| files.get(0)) else {
+                    return;
+                };
+                input.set_value("");
+                link.send_future(async move {
+                    match wasm_bindgen_futures::JsFuture::from(file.text()).await {
+                        Ok(text) => Msg::ImportProfile(text.as_string().unwrap_or_default()),
+                        Err(_) => Msg::ImportProfile(String::new()),
+                    }
+                });
+            })
+        };
+
+        html! {
+            <>
+                <div class="panel-header profile-header">
+                    <span class="panel-icon icon icon-person"></span>
+                    <span class="panel-title">{"Profile"}</span>
+                </div>
+                <div class="settings-note">{"Tabs, bookmarks, history, search engines, proxy and theme, as one file"}</div>
+                <div class="theme-options">
+                    <label class="theme-btn" title="Load a profile exported from Graphite">
+                        {"Import"}
+                        <input type="file" accept=".json,application/json" class="hidden-file-input" onchange={on_import} />
+                    </label>
+                    <button class="theme-btn" onclick={link.callback(|_| Msg::ExportProfile)} title="Save this profile as JSON">
+                        {"Export"}
+                    </button>
+                </div>
+                if let Some(imported) = &self.profile_import {
+                    <div class="profile-import">
+                        <div class="settings-note">
+                            { imported.summary() }
+                            if let Some(exported_at) = imported.exported_at {
+                                { format!(", exported {}", String::from(js_sys::Date::new(&JsValue::from_f64(exported_at)).to_date_string())) }
+                            }
+                        </div>
+                        <div class="theme-options">
+                            <button
+                                class="theme-btn"
+                                onclick={link.callback(|_| Msg::ApplyProfileImport(ImportMode::Merge))}
+                                title="Add the tabs, bookmarks, history and search engines and keep these settings"
+                            >
+                                {"Merge"}
+                            </button>
+                            <button
+                                class="theme-btn"
+                                onclick={link.callback(|_| Msg::ApplyProfileImport(ImportMode::Replace))}
+                                title="Use everything from the file in place of this profile; downloads are kept"
+                            >
+                                {"Replace"}
+                            </button>
+                            <button class="theme-btn" onclick={link.callback(|_| Msg::CancelProfileImport)}>{"Cancel"}</button>
+                        </div>
+                    </div>
+                }
+                if !self.profile_errors.is_empty() {
+                    <div class="settings-error">{"The profile can't be imported:"}</div>
+                    <ul class="profile-errors">
+                        { for self.profile_errors.iter().map(|err| html! { <li class="settings-error">{err}</li> }) }
+                    </ul>
+                }
+                if let Some(message) = &self.profile_message {
+                    <div class="settings-note">{message}</div>
+                }
+            </>
+        }
+    }
+
+    fn render_proxy_settings(&self, link: &yew::html::Scope<Self>) -> Html {
+        let proxy = &self.state.proxy;
+        let kind = proxy.kind();
+        let on_kind = {
+            let proxy = proxy.clone();
+            link.callback(move |e: Event| {
+                let select: HtmlSelectElement = e.target_unchecked_into();
+                match select.value().parse::<usize>().ok().and_then(|i| ProxyKind::ALL.get(i)) {
+                    Some(kind) => Msg::SetProxyConfig(proxy.with_kind(*kind)),
+                    None => Msg::NoOp,
+                }
+            })
+        };
+        let on_endpoint = {
+            let proxy = proxy.clone();
+            link.callback(move |e: Event| {
+                let input: HtmlInputElement = e.target_unchecked_into();
+                Msg::SetProxyConfig(proxy.with_endpoint(&input.value()))
+            })
+        };
+
+        html! {
+            <>
+                <select class="proxy-input proxy-select" onchange={on_kind}>
+                    { for ProxyKind::ALL.iter().enumerate().map(|(i, k)| html! {
+                        <option value={i.to_string()} selected={*k == kind}>{k.name()}</option>
+                    })}
+                </select>
+                if kind != ProxyKind::Direct {
+                    <input 
+                        type="text" 
+                        class="proxy-input"
+                        placeholder={kind.placeholder()}
+                        value={proxy.endpoint().to_string()}
+                        onchange={on_endpoint}
+                    />
+                }
+                if let ProxyConfig::Prefix { codec, .. } = proxy {
+                    <select
+                        class="proxy-input proxy-select"
+                        title="How target URLs are encoded after the prefix"
+                        onchange={{
+                            let proxy = proxy.clone();
+                            link.callback(move |e: Event| {
+                                let select: HtmlSelectElement = e.target_unchecked_into();
+                                match select.value().parse::<usize>().ok().and_then(|i| UrlCodec::ALL.get(i)) {
+                                    Some(codec) => Msg::SetProxyConfig(proxy.with_codec(*codec)),
+                                    None => Msg::NoOp,
+                                }
+                            })
+                        }}
+                    >
+                        { for UrlCodec::ALL.iter().enumerate().map(|(i, c)| html! {
+                            <option value={i.to_string()} selected={c == codec}>{c.name()}</option>
+                        })}
+                    </select>
+                }
+            </>
+        }
+    }
+
+    fn render_suggestions(&self, link: &yew::html::Scope<Self>) -> Html {
+        if self.suggestions.is_empty() {
+            return Html::default();
+        }
+
+        html! {
+            <div class="omnibox-dropdown">
+                { for self.suggestions.iter().enumerate().map(|(index, suggestion)| {
+                    let detail = match suggestion.kind {
+                        SuggestionKind::OpenTab(_) => String::from("Switch to tab"),
+                        SuggestionKind::Search => String::from("Search"),
+                        _ => suggestion.url.clone(),
+                    };
+                    html! {
+                        <div
+                            class={classes!("omnibox-item", (self.selected_suggestion == Some(index)).then_some("selected"))}
+                            // Mouse down rather than click, which would come after the input's blur
+                            onmousedown={link.callback(move |e: MouseEvent| {
+                                e.prevent_default();
+                                Msg::AcceptSuggestion(index)
+                            })}
+                        >
+                            <span class={classes!("omnibox-icon", "icon", suggestion.kind.icon())}></span>
+                            <span class="omnibox-title">{&suggestion.title}</span>
+                            <span class="omnibox-detail">{detail}</span>
+                        </div>
+                    }
+                })}
+            </div>
+        }
+    }
+
+    fn render_palette(&self, link: &yew::html::Scope<Self>) -> Html {
+        let entries = self.palette_entries();
+        let selected = self.palette_selected.min(entries.len().saturating_sub(1));
+
+        html! {
+            <div class="palette" onclick={|e: MouseEvent| e.stop_propagation()}>
+                <input
+                    type="text"
+                    class="palette-input"
+                    placeholder="Type a command, tab, page or setting"
+                    ref={self.palette_ref.clone()}
+                    value={self.palette_query.clone()}
+                    oninput={link.callback(|e: InputEvent| {
+                        let input: HtmlInputElement = e.target_unchecked_into();
+                        Msg::SetPaletteQuery(input.value())
+                    })}
+                    onkeydown={link.callback(move |e: KeyboardEvent| {
+                        match e.key().as_str() {
+                            "Enter" => Msg::RunPaletteEntry(selected),
+                            "ArrowDown" => {
+                                e.prevent_default();
+                                Msg::MovePaletteSelection(1)
+                            }
+                            "ArrowUp" => {
+                                e.prevent_default();
+                                Msg::MovePaletteSelection(-1)
+                            }
+                            _ => Msg::NoOp,
+                        }
+                    })}
+                />
+                <div class="palette-list">
+                    if entries.is_empty() {
+                        <div class="palette-empty">{"Nothing matches"}</div>
+                    }
+                    { for entries.into_iter().enumerate().map(|(index, entry)| html! {
+                        <div
+                            class={classes!("omnibox-item", (index == selected).then_some("selected"))}
+                            onclick={link.callback(move |_| Msg::RunPaletteEntry(index))}
+                        >
+                            <span class={classes!("omnibox-icon", "icon", entry.icon)}></span>
+                            <span class="omnibox-title">{entry.title}</span>
+                            <span class="omnibox-detail">{entry.detail}</span>
+                            if let Some(shortcut) = entry.shortcut {
+                                <span class="shortcut-key">{shortcut}</span>
+                            }
+                        </div>
+                    })}
+                </div>
+            </div>
+        }
+    }
+
+    /// The horizontal layout's strip along the top, which scrolls once the
+    /// tabs are down to their minimum width
+    fn render_tab_bar(&self, link: &yew::html::Scope<Self>) -> Html {
+        let rows = self.state.tabs.iter().map(|tab| (tab, 0)).collect();
+
+        html! {
+            <div class="tab-bar">
+                if self.tab_overflow {
+                    <button class="new-tab-btn tab-scroll-btn" title="Scroll tabs left" onclick={link.callback(|_| Msg::ScrollTabs(-1))}>
+                        <span class="icon icon-arrow-back"></span>
+                    </button>
+                }
+                <div class="tab-strip" ref={self.tab_strip_ref.clone()}>
+                    { self.render_tab_rows(link, rows) }
+                </div>
+                if self.tab_overflow {
+                    <button class="new-tab-btn tab-scroll-btn" title="Scroll tabs right" onclick={link.callback(|_| Msg::ScrollTabs(1))}>
+                        <span class="icon icon-arrow-forward"></span>
+                    </button>
+                    { self.render_all_tabs(link) }
+                }
+                { self.render_tab_buttons(link) }
+            </div>
+        }
+    }
+
+    /// The vertical layout's sidebar, with tabs in a tree by opener
+    fn render_tab_sidebar(&self, link: &yew::html::Scope<Self>) -> Html {
+        let collapsed = self.state.sidebar_collapsed;
+
+        html! {
+            <div class={classes!("tab-sidebar", collapsed.then_some("collapsed"))}>
+                <div class="tab-sidebar-header">
+                    <button
+                        class="new-tab-btn"
+                        title={if collapsed { "Expand sidebar" } else { "Collapse sidebar" }}
+                        onclick={link.callback(|_| Msg::ToggleSidebar)}
+                    >
+                        <span class={classes!("icon", if collapsed { "icon-arrow-forward" } else { "icon-arrow-back" })}></span>
+                    </button>
+                    { self.render_tab_buttons(link) }
+                </div>
+                <div class="tab-sidebar-list" ref={self.tab_strip_ref.clone()}>
+                    { self.render_tab_rows(link, tab_strip::tree(&self.state.tabs)) }
+                </div>
+            </div>
+        }
+    }
+
+    /// Tabs and the chips heading their groups, in the order given with how
+    /// deep each tab is indented
+    fn render_tab_rows(&self, link: &yew::html::Scope<Self>, rows: Vec<(&Tab, usize)>) -> Html {
+        let active_tab_id = self.state.active_tab_id;
+
+        html! {
+            { for rows.iter().enumerate().map(|(index, &(tab, depth))| {
+                let group = tab.group.and_then(|id| self.state.tab_groups.iter().find(|g| g.id == id));
+                let starts_group = index == 0 || rows[index - 1].0.group != tab.group;
+                // A collapsed group still shows its tab while it is selected
+                let hidden = group.is_some_and(|g| g.collapsed) && tab.id != active_tab_id;
+                html! {
+                    <>
+                        if let Some(group) = group.filter(|_| starts_group) {
+                            { self.render_group_chip(link, group) }
+                        }
+                        if !hidden {
+                            { self.render_tab(link, tab, group, depth) }
+                        }
+                    </>
+                }
+            })}
+        }
+    }
+
+    /// New tab buttons and the lists of closed tabs and saved groups
+    fn render_tab_buttons(&self, link: &yew::html::Scope<Self>) -> Html {
+        html! {
+            <>
+                <button class="new-tab-btn" title={self.with_shortcut("New tab", "new-tab")} onclick={link.callback(|_| Msg::NewTab)}>
+                    <span class="icon icon-add"></span>
+                </button>
+                <button class="new-tab-btn" title={self.with_shortcut("New private tab", "new-private-tab")} onclick={link.callback(|_| Msg::NewPrivateTab)}>
+                    <span class="icon icon-private"></span>
+                </button>
+                { self.render_recently_closed(link) }
+                { self.render_saved_groups(link) }
+            </>
+        }
+    }
+
+    /// The dropdown listing every tab, for when they don't all fit in the strip
+    fn render_all_tabs(&self, link: &yew::html::Scope<Self>) -> Html {
+        html! {
+            <div class="recently-closed">
+                <button
+                    class={classes!("new-tab-btn", self.show_all_tabs.then_some("open"))}
+                    title="All tabs"
+                    onclick={link.callback(|e: MouseEvent| {
+                        e.stop_propagation();
+                        Msg::ToggleAllTabs
+                    })}
+                ><span class="icon icon-tab"></span></button>
+                if self.show_all_tabs {
+                    <div class="bookmark-menu recently-closed-menu all-tabs-menu">
+                        { for self.state.tabs.iter().map(|tab| {
+                            let id = tab.id;
+                            let icon = if pages::is_internal(&tab.url) {
+                                html! { <span class={classes!("bookmark-icon", "icon", pages::find(&tab.url).icon)}></span> }
+                            } else if let Some(favicon) = &tab.favicon {
+                                html! { <img class="bookmark-icon icon" src={favicon.clone()} alt="" /> }
+                            } else {
+                                html! { <span class="bookmark-icon icon icon-tab"></span> }
+                            };
+                            // The click goes on to close the list
+                            html! {
+                                <button
+                                    class={classes!("bookmark-menu-item", (id == self.state.active_tab_id).then_some("active"))}
+                                    title={tab.url.clone()}
+                                    onclick={link.callback(move |_| Msg::SelectTab(id))}
+                                >
+                                    {icon}
+                                    <span class="bookmark-title">{&tab.title}</span>
+                                </button>
+                            }
+                        })}
+                    </div>
+                }
+            </div>
+        }
+    }
+
+    fn render_tab(&self, link: &yew::html::Scope<Self>, tab: &Tab, group: Option<&TabGroup>, depth: usize) -> Html {
+        let is_active = tab.id == self.state.active_tab_id;
+        let tab_id = tab.id;
+        let close_id = tab.id;
+        let drag_id = tab.id;
+        let drop_id = tab.id;
+        
+        html! {
+            <div 
+                class={classes!(
+                    "tab",
+                    is_active.then_some("active"),
+                    tab.discarded.then_some("discarded"),
+                    tab.private.then_some("private"),
+                    tab.pinned.then_some("pinned"),
+                    group.map(|_| "grouped"),
+                )}
+                style={format!("--depth: {}; --group-color: {};", depth, group.map_or("transparent", |g| g.color.css()))}
+                title={tab.pinned.then(|| tab.title.clone())}
+                onclick={link.callback(move |_| Msg::SelectTab(tab_id))}
+                oncontextmenu={link.callback(move |e: MouseEvent| {
+                    e.prevent_default();
+                    Msg::ShowTabMenu(tab_id, e.client_x(), e.client_y())
+                })}
+                draggable="true"
+                ondragstart={link.callback(move |_| Msg::DragStart(drag_id))}
+                ondragover={link.callback(move |e: DragEvent| {
+                    e.prevent_default();
+                    Msg::DragOver(drop_id)
+                })}
+                ondragend={link.callback(|_| Msg::DragEnd)}
+            >
+                if tab.is_loading {
+                    <span class="tab-favicon icon tab-spinner"></span>
+                } else if pages::is_internal(&tab.url) {
+                    <span class={classes!("tab-favicon", "icon", pages::find(&tab.url).icon)}></span>
+                } else if let Some(favicon) = &tab.favicon {
+                    <img class="tab-favicon icon" src={favicon.clone()} alt="" />
+                } else {
+                    <span class="tab-favicon icon icon-home"></span>
+                }
+                if tab.private {
+                    <span class="tab-private icon icon-private" title="Private tab"></span>
+                }
+                if tab.muted {
+                    <button
+                        class="tab-mute"
+                        title="Unmute tab"
+                        onclick={link.callback(move |e: MouseEvent| {
+                            e.stop_propagation();
+                            Msg::ToggleMuteTab(tab_id)
+                        })}
+                    ><span class="icon icon-volume-off"></span></button>
+                }
+                if !tab.pinned {
+                    <span class="tab-title">{&tab.title}</span>
+                    <button 
+                        class="tab-close"
+                        onclick={link.callback(move |e: MouseEvent| {
+                            e.stop_propagation();
+                            Msg::CloseTab(close_id)
+                        })}
+                    ><span class="icon icon-close"></span></button>
+                }
+            </div>
+        }
+    }
+
+    /// The menu opened by right-clicking a tab
+    fn render_tab_menu(&self, link: &yew::html::Scope<Self>) -> Html {
+        let Some((id, x, y)) = self.tab_menu else {
+            return Html::default();
+        };
+        let Some(index) = self.state.tabs.iter().position(|t| t.id == id) else {
+            return Html::default();
+        };
+        let tab = &self.state.tabs[index];
+        // Closing others leaves pinned tabs alone
+        let others: Vec<u32> = self.state.tabs.iter().filter(|t| t.id != id && !t.pinned).map(|t| t.id).collect();
+        let to_the_right: Vec<u32> = self.state.tabs[index + 1..].iter().filter(|t| !t.pinned).map(|t| t.id).collect();
+        let is_bookmarked = self.state.bookmarks.find_by_url(&tab.url).is_some();
+        let is_internal = pages::is_internal(&tab.url);
+        // The click goes on to close the menu
+        let item = |label: &str, icon: &'static str, disabled: bool, onclick: Callback<MouseEvent>| {
+            html! {
+                <button class="bookmark-menu-item" disabled={disabled} onclick={onclick}>
+                    <span class={classes!("bookmark-icon", "icon", icon)}></span>
+                    <span class="bookmark-title">{label.to_string()}</span>
+                </button>
+            }
+        };
+
+        html! {
+            <div class="bookmark-menu tab-menu" style={format!("left: {}px; top: {}px;", x, y)}>
+                { item("Duplicate", "icon-tab", false, link.callback(move |_| Msg::DuplicateTab(id))) }
+                { item(if tab.pinned { "Unpin" } else { "Pin" }, "icon-pin", false, link.callback(move |_| Msg::TogglePinTab(id))) }
+                { item(if tab.muted { "Unmute" } else { "Mute" }, if tab.muted { "icon-volume-up" } else { "icon-volume-off" }, false, link.callback(move |_| Msg::ToggleMuteTab(id))) }
+                { item("Reload", "icon-refresh", is_internal, link.callback(move |_| Msg::ReloadTab(id))) }
+                { item("Copy URL", "icon-copy", false, link.callback(move |_| Msg::CopyTabUrl(id))) }
+                { item(
+                    if is_bookmarked { "Remove bookmark" } else { "Bookmark" },
+                    if is_bookmarked { "icon-star-filled" } else { "icon-star" },
+                    is_internal,
+                    link.callback(move |_| Msg::BookmarkTab(id)),
+                ) }
+                <div class="tab-menu-separator"></div>
+                { item("Add to new group", "icon-folder", tab.pinned, link.callback(move |_| Msg::AddToGroup(id, None))) }
+                { for self.state.tab_groups.iter().filter(|g| tab.group != Some(g.id)).map(|g| {
+                    let group = g.id;
+                    let label = format!("Add to group \"{}\"", self.group_name(g));
+                    item(&label, "icon-folder", tab.pinned, link.callback(move |_| Msg::AddToGroup(id, Some(group))))
+                })}
+                if tab.group.is_some() {
+                    { item("Remove from group", "icon-folder", false, link.callback(move |_| Msg::RemoveFromGroup(id))) }
+                }
+                <div class="tab-menu-separator"></div>
+                { item("Close", "icon-close", self.state.tabs.len() < 2, link.callback(move |_| Msg::CloseTabs(vec![id]))) }
+                { item("Close other tabs", "icon-close", others.is_empty(), link.callback(move |_| Msg::CloseTabs(others.clone()))) }
+                { item("Close tabs to the right", "icon-close", to_the_right.is_empty(), link.callback(move |_| Msg::CloseTabs(to_the_right.clone()))) }
+                { item("Reopen closed tab", "icon-undo", self.state.recently_closed.is_empty(), link.callback(|_| Msg::ReopenClosed(0))) }
+            </div>
+        }
+    }
+
+    /// A group's name, or how many tabs it holds when it has none
+    fn group_name(&self, group: &TabGroup) -> String {
+        if !group.name.is_empty() {
+            return group.name.clone();
+        }
+        match self.state.tabs.iter().filter(|t| t.group == Some(group.id)).count() {
+            1 => String::from("1 tab"),
+            n => format!("{} tabs", n),
+        }
+    }
+
+    /// The chip heading a group in the strip, which collapses it on click
+    fn render_group_chip(&self, link: &yew::html::Scope<Self>, group: &TabGroup) -> Html {
+        let id = group.id;
+        let first_tab = self.state.tabs.iter().find(|t| t.group == Some(id)).map(|t| t.id);
+
+        html! {
+            <div
+                class={classes!("tab-group-chip", group.collapsed.then_some("collapsed"))}
+                style={format!("--group-color: {};", group.color.css())}
+                title={if group.collapsed { "Expand group" } else { "Collapse group" }}
+                onclick={link.callback(move |e: MouseEvent| {
+                    e.stop_propagation();
+                    Msg::ToggleGroupCollapsed(id)
+                })}
+                oncontextmenu={link.callback(move |e: MouseEvent| {
+                    e.prevent_default();
+                    e.stop_propagation();
+                    Msg::ShowGroupMenu(id, e.client_x(), e.client_y())
+                })}
+                ondragover={link.batch_callback(move |e: DragEvent| {
+                    e.prevent_default();
+                    // Dropping on the chip puts a tab at the front of the group
+                    first_tab.map(Msg::DragOver)
+                })}
+            >
+                {self.group_name(group)}
+            </div>
+        }
+    }
+
+    /// The menu opened by right-clicking a group's chip
+    fn render_group_menu(&self, link: &yew::html::Scope<Self>) -> Html {
+        let Some((id, x, y)) = self.group_menu else {
+            return Html::default();
+        };
+        let Some(group) = self.state.tab_groups.iter().find(|g| g.id == id) else {
+            return Html::default();
+        };
+        let item = |label: &str, icon: &'static str, onclick: Callback<MouseEvent>| {
+            html! {
+                <button class="bookmark-menu-item" onclick={onclick}>
+                    <span class={classes!("bookmark-icon", "icon", icon)}></span>
+                    <span class="bookmark-title">{label.to_string()}</span>
+                </button>
+            }
+        };
+
+        html! {
+            <div
+                class="bookmark-menu tab-menu group-menu"
+                style={format!("left: {}px; top: {}px;", x, y)}
+                onclick={|e: MouseEvent| e.stop_propagation()}
+            >
+                <input
+                    type="text"
+                    class="group-name-input"
+                    placeholder="Name this group"
+                    value={group.name.clone()}
+                    onchange={link.callback(move |e: Event| {
+                        let input: HtmlInputElement = e.target_unchecked_into();
+                        Msg::RenameGroup(id, input.value())
+                    })}
+                />
+                <div class="group-colors">
+                    { for GroupColor::ALL.iter().map(|&color| html! {
+                        <button
+                            class={classes!("group-color", (color == group.color).then_some("selected"))}
+                            style={format!("background-color: {};", color.css())}
+                            title={color.name()}
+                            onclick={link.callback(move |_| Msg::SetGroupColor(id, color))}
+                        ></button>
                     })}
-                    <button class="new-tab-btn" onclick={link.callback(|_| Msg::NewTab)}><span class="icon icon-add"></span></button>
                 </div>
+                <div class="tab-menu-separator"></div>
+                { item(if group.collapsed { "Expand" } else { "Collapse" }, "icon-folder", link.callback(move |_| Msg::ToggleGroupCollapsed(id))) }
+                { item("Ungroup", "icon-tab", link.callback(move |_| Msg::Ungroup(id))) }
+                <div class="tab-menu-separator"></div>
+                { item("Save and close group", "icon-folder", link.callback(move |_| Msg::SaveGroup(id))) }
+                { item("Close group", "icon-close", link.callback(move |_| Msg::CloseGroup(id))) }
+            </div>
+        }
+    }
 
-                // Navigation Bar
-                <div class="nav-bar">
-                    <div class="nav-controls">
-                        <button class="nav-btn" onclick={link.callback(|_| Msg::GoBack)} title="Back">
-                            <span class="icon icon-arrow-back"></span>
-                        </button>
-                        <button class="nav-btn" onclick={link.callback(|_| Msg::GoForward)} title="Forward">
-                            <span class="icon icon-arrow-forward"></span>
-                        </button>
-                        <button class="nav-btn" onclick={link.callback(|_| Msg::Reload)} title="Reload">
-                            <span class="icon icon-refresh"></span>
-                        </button>
+    /// The tab strip's button for saved groups and its list of them
+    fn render_saved_groups(&self, link: &yew::html::Scope<Self>) -> Html {
+        let saved = &self.state.saved_groups;
+        if saved.is_empty() {
+            return Html::default();
+        }
+
+        html! {
+            <div class="recently-closed">
+                <button
+                    class={classes!("new-tab-btn", self.show_saved_groups.then_some("open"))}
+                    title="Saved groups"
+                    onclick={link.callback(|e: MouseEvent| {
+                        e.stop_propagation();
+                        Msg::ToggleSavedGroups
+                    })}
+                ><span class="icon icon-folder"></span></button>
+                if self.show_saved_groups {
+                    <div class="bookmark-menu recently-closed-menu">
+                        { for saved.iter().enumerate().map(|(index, group)| html! {
+                            <div class="bookmark-menu-item saved-group" title={group.tabs.iter().map(|t| t.title.as_str()).collect::<Vec<_>>().join("\n")}>
+                                <span class="saved-group-color" style={format!("background-color: {};", group.color.css())}></span>
+                                <button
+                                    class="bookmark-title saved-group-restore"
+                                    onclick={link.callback(move |e: MouseEvent| {
+                                        e.stop_propagation();
+                                        Msg::RestoreGroup(index)
+                                    })}
+                                >{group.title()}</button>
+                                <button
+                                    class="saved-group-delete"
+                                    title="Delete saved group"
+                                    onclick={link.callback(move |e: MouseEvent| {
+                                        e.stop_propagation();
+                                        Msg::DeleteSavedGroup(index)
+                                    })}
+                                ><span class="icon icon-delete"></span></button>
+                            </div>
+                        })}
                     </div>
-                    
-                    <div class="url-bar-container">
-                        <input 
-                            type="text" 
-                            class="url-bar"
-                            placeholder="Search or enter a URL..."
-                            value={self.url_input.clone()}
-                            oninput={link.callback(|e: InputEvent| {
-                                let input: HtmlInputElement = e.target_unchecked_into();
-                                Msg::UpdateUrlBar(input.value())
-                            })}
-                            onkeypress={link.callback(|e: KeyboardEvent| {
-                                if e.key() == "Enter" {
-                                    let input: HtmlInputElement = e.target_unchecked_into();
-                                    Msg::Navigate(input.value())
-                                } else {
-                                    Msg::NoOp
+                }
+            </div>
+        }
+    }
+
+    /// The tab strip's reopen button and its list of closed tabs and windows
+    fn render_recently_closed(&self, link: &yew::html::Scope<Self>) -> Html {
+        let closed = &self.state.recently_closed;
+
+        html! {
+            <div class="recently-closed">
+                <button
+                    class={classes!("new-tab-btn", self.show_recently_closed.then_some("open"))}
+                    title={self.with_shortcut("Recently closed", "reopen-closed-tab")}
+                    disabled={closed.is_empty()}
+                    onclick={link.callback(|e: MouseEvent| {
+                        e.stop_propagation();
+                        Msg::ToggleRecentlyClosed
+                    })}
+                ><span class="icon icon-undo"></span></button>
+                if self.show_recently_closed && !closed.is_empty() {
+                    <div class="bookmark-menu recently-closed-menu">
+                        { for closed.iter().enumerate().map(|(index, entry)| {
+                            let icon = match entry {
+                                ClosedEntry::Tab { tab, .. } if pages::is_internal(&tab.url) => {
+                                    html! { <span class={classes!("bookmark-icon", "icon", pages::find(&tab.url).icon)}></span> }
                                 }
-                            })}
-                        />
-                        <button class="url-bar-search-btn" title="Search">
-                            <span class="icon icon-search"></span>
-                        </button>
+                                ClosedEntry::Tab { tab, .. } => match tab.favicon.clone().or_else(|| Self::get_fallback_favicon(&tab.url)) {
+                                    Some(favicon) => html! { <img class="bookmark-icon icon" src={favicon} alt="" /> },
+                                    None => html! { <span class="bookmark-icon icon icon-tab"></span> },
+                                },
+                                ClosedEntry::Window { .. } => html! { <span class="bookmark-icon icon icon-undo"></span> },
+                            };
+                            html! {
+                                <button
+                                    class="bookmark-menu-item"
+                                    title={entry.detail()}
+                                    onclick={link.callback(move |e: MouseEvent| {
+                                        e.stop_propagation();
+                                        Msg::ReopenClosed(index)
+                                    })}
+                                >
+                                    {icon}
+                                    <span class="bookmark-title">{entry.title()}</span>
+                                </button>
+                            }
+                        })}
                     </div>
+                }
+            </div>
+        }
+    }
 
-                    <div class="toolbar-icons">
-                        <button class="toolbar-btn" title="Toggle Dark Mode">
-                            <span class="icon icon-light-mode"></span>
-                        </button>
-                        <button class="toolbar-btn" onclick={link.callback(|_| Msg::GoHome)} title="Home">
-                            <span class="icon icon-home"></span>
-                        </button>
-                        <button 
-                            class="toolbar-btn" 
-                            onclick={link.callback(|e: MouseEvent| {
-                                e.stop_propagation();
-                                Msg::ToggleDownloadsPanel
-                            })}
-                            title="Downloads"
-                        >
-                            <span class="icon icon-download"></span>
-                        </button>
-                        <button 
-                            class="toolbar-btn" 
-                            onclick={link.callback(|e: MouseEvent| {
-                                e.stop_propagation();
-                                Msg::ToggleSettingsPanel
-                            })}
-                            title="Settings"
-                        >
-                            <span class="icon icon-settings"></span>
-                        </button>
-                    </div>
-                </div>
+    fn render_profile_button(&self, link: &yew::html::Scope<Self>) -> Html {
+        let profile = profiles::current();
+        html! {
+            <button
+                class="toolbar-btn"
+                onclick={link.callback(|e: MouseEvent| {
+                    e.stop_propagation();
+                    Msg::ToggleProfilesPanel
+                })}
+                title={format!("Profile: {}", profile.name)}
+            >
+                <span class="profile-badge" style={format!("background-color: {}", profile.color)}>{profile.initial()}</span>
+            </button>
+        }
+    }
 
-                // Content Area
-                <div class="content-area">
-                    if is_home {
-                        <div class="home-page">
-                            <h1 class="browser-title">{"graphite"}</h1>
-                            <p class="browser-tagline">{"a simple, sleek, modern, minimalist web browser"}</p>
-                            <div class="home-search-container">
-                                <input 
-                                    type="text" 
-                                    class="home-search"
-                                    placeholder="Search or enter a URL"
-                                    onkeypress={link.callback(|e: KeyboardEvent| {
-                                        if e.key() == "Enter" {
-                                            let input: HtmlInputElement = e.target_unchecked_into();
-                                            Msg::Navigate(input.value())
-                                        } else {
-                                            Msg::NoOp
-                                        }
-                                    })}
-                                />
-                                <button class="home-search-btn">
-                                    <span class="icon icon-search"></span>
+    fn render_profiles_panel(&self, link: &yew::html::Scope<Self>) -> Html {
+        let current = profiles::current();
+        html! {
+            <div class="panel profiles-panel" onclick={|e: MouseEvent| e.stop_propagation()}>
+                <div class="panel-header">
+                    <span class="panel-icon icon icon-person"></span>
+                    <span class="panel-title">{"Profiles"}</span>
+                </div>
+                <div class="settings-note">{"Each profile keeps its own tabs, history, bookmarks, proxy and search engine"}</div>
+                <div class="profiles-list">
+                    { for self.profiles.profiles.iter().map(|profile| {
+                        let is_current = profile.id == current.id;
+                        let switch_id = profile.id.clone();
+                        let remove_id = profile.id.clone();
+                        html! {
+                            <div class={classes!("profile-row", is_current.then_some("current"))}>
+                                <button
+                                    class="profile-switch"
+                                    disabled={is_current || self.switching_profile}
+                                    title={if is_current { String::from("In use") } else { format!("Switch to {}", profile.name) }}
+                                    onclick={link.callback(move |_| Msg::SwitchProfile(switch_id.clone()))}
+                                >
+                                    <span class="profile-badge" style={format!("background-color: {}", profile.color)}>{profile.initial()}</span>
+                                    <span class="profile-name">{&profile.name}</span>
+                                    if is_current {
+                                        <span class="profile-current">{"In use"}</span>
+                                    }
                                 </button>
+                                if !is_current {
+                                    <button
+                                        class="download-btn"
+                                        title={format!("Delete {} and everything saved in it", profile.name)}
+                                        onclick={link.callback(move |_| Msg::RemoveProfile(remove_id.clone()))}
+                                    >
+                                        <span class="icon icon-delete"></span>
+                                    </button>
+                                }
                             </div>
-                        </div>
-                    } else {
-                        <iframe 
-                            class="browser-iframe"
-                            src={self.get_proxied_url(active_tab.map(|t| &t.url).unwrap_or(&String::new()))}
-                            sandbox="allow-scripts allow-same-origin allow-forms allow-popups"
-                        />
-                    }
+                        }
+                    })}
                 </div>
-
-                // Settings Panel
-                if self.show_settings {
-                    <div class="panel settings-panel" onclick={|e: MouseEvent| e.stop_propagation()}>
-                        <div class="panel-header">
-                            <span class="panel-icon icon icon-search"></span>
-                            <span class="panel-title">{"Search Engine"}</span>
-                        </div>
-                        <div class="search-engines">
-                            { self.render_search_engine_option(link, SearchEngine::Yahoo, "Y!", "#6001d2") }
-                            { self.render_search_engine_option(link, SearchEngine::Google, "G", "#4285f4") }
-                            { self.render_search_engine_option(link, SearchEngine::Bing, "b", "#00809d") }
-                            { self.render_search_engine_option(link, SearchEngine::DuckDuckGo, "🦆", "#de5833") }
-                            { self.render_search_engine_option(link, SearchEngine::Brave, "🦁", "#fb542b") }
-                        </div>
-                        <div class="panel-header proxy-header">
-                            <span class="panel-icon icon icon-cell-tower"></span>
-                            <span class="panel-title">{"Proxy Server"}</span>
-                        </div>
-                        <input 
-                            type="text" 
-                            class="proxy-input"
-                            placeholder="Enter a wss:// or ws:// proxy"
-                            value={self.state.proxy_server.clone()}
-                            oninput={link.callback(|e: InputEvent| {
-                                let input: HtmlInputElement = e.target_unchecked_into();
-                                Msg::SetProxyServer(input.value())
-                            })}
-                        />
-                    </div>
+                <input
+                    type="text"
+                    class="proxy-input"
+                    placeholder="New profile name"
+                    onkeypress={link.callback(|e: KeyboardEvent| {
+                        if e.key() == "Enter" {
+                            let input: HtmlInputElement = e.target_unchecked_into();
+                            let name = input.value();
+                            input.set_value("");
+                            Msg::AddProfile(name)
+                        } else {
+                            Msg::NoOp
+                        }
+                    })}
+                />
+                if let Some(err) = &self.profiles_error {
+                    <div class="settings-error">{err}</div>
                 }
+            </div>
+        }
+    }
 
-                // Downloads Panel
-                if self.show_downloads {
-                    <div class="panel downloads-panel" onclick={|e: MouseEvent| e.stop_propagation()}>
-                        <div class="panel-header">
-                            <span class="panel-icon icon icon-download"></span>
-                            <span class="panel-title">{"Downloads"}</span>
-                        </div>
-                        <div class="downloads-list">
-                            { for self.state.downloads.iter().map(|download| {
-                                let dl_id = download.id;
-                                let dl_id2 = download.id;
-                                html! {
-                                    <div class="download-item">
-                                        <span class="download-name">{&download.filename}</span>
-                                        <div class="download-actions">
-                                            <button 
-                                                class="download-btn"
-                                                onclick={link.callback(move |_| Msg::OpenDownloadFolder(dl_id))}
-                                                title="Open Folder"
-                                            ><span class="icon icon-folder"></span></button>
-                                            <button 
-                                                class="download-btn"
-                                                onclick={link.callback(move |_| Msg::DeleteDownload(dl_id2))}
-                                                title="Delete"
-                                            ><span class="icon icon-delete"></span></button>
-                                        </div>
+    fn render_bookmark_bar(&self, link: &yew::html::Scope<Self>) -> Html {
+        let bar = &self.state.bookmarks.bar;
+        if bar.children.is_empty() {
+            return Html::default();
+        }
+
+        html! {
+            <div class="bookmark-bar">
+                { for bar.children.iter().map(|node| match node {
+                    BookmarkNode::Bookmark(bookmark) => {
+                        let url = bookmark.url.clone();
+                        html! {
+                            <button
+                                class="bookmark-bar-item"
+                                title={bookmark.url.clone()}
+                                onclick={link.callback(move |_| Msg::Navigate(url.clone()))}
+                            >
+                                { Self::render_bookmark_icon(bookmark) }
+                                <span class="bookmark-title">{&bookmark.title}</span>
+                            </button>
+                        }
+                    }
+                    BookmarkNode::Folder(folder) => {
+                        let folder_id = folder.id;
+                        let is_open = self.open_bookmark_folder == Some(folder_id);
+                        html! {
+                            <div class="bookmark-bar-folder">
+                                <button
+                                    class={classes!("bookmark-bar-item", is_open.then_some("open"))}
+                                    onclick={link.callback(move |e: MouseEvent| {
+                                        e.stop_propagation();
+                                        Msg::ToggleBookmarkFolder(folder_id)
+                                    })}
+                                >
+                                    <span class="bookmark-icon icon icon-folder"></span>
+                                    <span class="bookmark-title">{&folder.title}</span>
+                                </button>
+                                if is_open {
+                                    <div class="bookmark-menu">
+                                        { self.render_bookmark_menu(link, folder) }
                                     </div>
                                 }
-                            })}
-                        </div>
-                    </div>
-                }
+                            </div>
+                        }
+                    }
+                })}
             </div>
         }
     }
-}
 
-impl App {
-    fn save_state(&self) {
-        let _ = LocalStorage::set("graphite_state", &self.state);
+    /// Dropdown contents for a bookmark bar folder, with subfolders inline
+    fn render_bookmark_menu(&self, link: &yew::html::Scope<Self>, folder: &BookmarkFolder) -> Html {
+        if folder.children.is_empty() {
+            return html! { <div class="bookmark-menu-empty">{"Empty folder"}</div> };
+        }
+
+        html! {
+            { for folder.children.iter().map(|node| match node {
+                BookmarkNode::Bookmark(bookmark) => {
+                    let url = bookmark.url.clone();
+                    html! {
+                        <button
+                            class="bookmark-menu-item"
+                            title={bookmark.url.clone()}
+                            onclick={link.callback(move |_| Msg::Navigate(url.clone()))}
+                        >
+                            { Self::render_bookmark_icon(bookmark) }
+                            <span class="bookmark-title">{&bookmark.title}</span>
+                        </button>
+                    }
+                }
+                BookmarkNode::Folder(subfolder) => html! {
+                    <div class="bookmark-menu-folder">
+                        <div class="bookmark-menu-heading">
+                            <span class="bookmark-icon icon icon-folder"></span>
+                            <span class="bookmark-title">{&subfolder.title}</span>
+                        </div>
+                        { self.render_bookmark_menu(link, subfolder) }
+                    </div>
+                },
+            })}
+        }
     }
 
-    /// Returns the display URL for the URL bar (empty for home page)
-    fn get_display_url(url: &str) -> String {
-        if url == "graphite://home" {
-            String::new()
-        } else {
-            url.to_string()
+    fn render_bookmark_icon(bookmark: &Bookmark) -> Html {
+        match Self::get_fallback_favicon(&bookmark.url) {
+            Some(favicon) => html! { <img class="bookmark-icon icon" src={favicon} alt="" /> },
+            None => html! { <span class="bookmark-icon icon icon-tab"></span> },
         }
     }
 
-    fn process_url(&self, input: &str) -> String {
-        let input = input.trim();
-        
-        // Check if it's already a URL
-        if input.starts_with("http://") || input.starts_with("https://") || input.starts_with("graphite://") {
-            return input.to_string();
+    fn render_bookmarks_panel(&self, link: &yew::html::Scope<Self>) -> Html {
+        let bookmarks = &self.state.bookmarks;
+        let folders = self.bookmark_folder_choices();
+        let on_import = {
+            let link = link.clone();
+            Callback::from(move |e: Event| {
+                let input: HtmlInputElement = e.target_unchecked_into();
+                let Some(file) = input.files().and_then(|files| files.get(0)) else {
+                    return;
+                };
+                input.set_value("");
+                link.send_future(async move {
+                    match wasm_bindgen_futures::JsFuture::from(file.text()).await {
+                        Ok(text) => Msg::ImportBookmarks(text.as_string().unwrap_or_default()),
+                        Err(_) => Msg::ImportBookmarks(String::new()),
+                    }
+                });
+            })
+        };
+
+        html! {
+            <div class="panel bookmarks-panel" onclick={|e: MouseEvent| e.stop_propagation()}>
+                <div class="panel-header">
+                    <span class="panel-icon icon icon-bookmarks"></span>
+                    <span class="panel-title">{"Bookmarks"}</span>
+                </div>
+                <input
+                    type="text"
+                    class="proxy-input bookmark-folder-input"
+                    placeholder="New folder name"
+                    onkeypress={link.callback(|e: KeyboardEvent| {
+                        if e.key() == "Enter" {
+                            let input: HtmlInputElement = e.target_unchecked_into();
+                            let name = input.value();
+                            input.set_value("");
+                            Msg::AddBookmarkFolder(name)
+                        } else {
+                            Msg::NoOp
+                        }
+                    })}
+                />
+                <div class="bookmarks-list">
+                    { for [&bookmarks.bar, &bookmarks.other].into_iter().map(|root| html! {
+                        <>
+                            <div class="bookmark-section">{&root.title}</div>
+                            if root.children.is_empty() {
+                                <div class="bookmark-menu-empty">{"No bookmarks"}</div>
+                            }
+                            { for root.children.iter().map(|node| self.render_bookmark_entry(link, node, 0, &folders)) }
+                        </>
+                    })}
+                </div>
+                <div class="theme-options">
+                    <label class="theme-btn" title="Load bookmarks exported from another browser">
+                        {"Import"}
+                        <input type="file" accept=".html,.htm,text/html" class="hidden-file-input" onchange={on_import} />
+                    </label>
+                    <button class="theme-btn" onclick={link.callback(|_| Msg::ExportBookmarks)} title="Save bookmarks as an HTML file">
+                        {"Export"}
+                    </button>
+                </div>
+                if let Some(message) = &self.bookmark_message {
+                    <div class="bookmark-message">{message}</div>
+                }
+            </div>
         }
-        
-        // Check if it looks like a domain
-        if input.contains('.') && !input.contains(' ') {
-            return format!("https://{}", input);
+    }
+
+    /// One row of the bookmarks panel, followed by a folder's contents
+    fn render_bookmark_entry(&self, link: &yew::html::Scope<Self>, node: &BookmarkNode, depth: usize, folders: &[(u32, String)]) -> Html {
+        let id = node.id();
+        let on_move = link.callback(move |e: Event| {
+            let select: HtmlSelectElement = e.target_unchecked_into();
+            match select.value().parse() {
+                Ok(folder_id) => Msg::MoveBookmark(id, folder_id),
+                Err(_) => Msg::NoOp,
+            }
+        });
+        let (icon, title, url) = match node {
+            BookmarkNode::Bookmark(b) => (Self::render_bookmark_icon(b), &b.title, Some(b.url.clone())),
+            BookmarkNode::Folder(f) => (html! { <span class="bookmark-icon icon icon-folder"></span> }, &f.title, None),
+        };
+
+        html! {
+            <>
+                <div class="bookmark-item" style={format!("padding-left: {}px;", 8 + depth * 16)} title={url.clone()}>
+                    { icon }
+                    if let Some(url) = url {
+                        <span class="bookmark-title bookmark-link" onclick={link.callback(move |_| Msg::Navigate(url.clone()))}>{title}</span>
+                    } else {
+                        <span class="bookmark-title">{title}</span>
+                    }
+                    <select class="bookmark-move" title="Move to folder" onchange={on_move}>
+                        <option value="" selected=true>{"Move to…"}</option>
+                        { for folders.iter().filter(|(folder_id, _)| *folder_id != id).map(|(folder_id, name)| html! {
+                            <option value={folder_id.to_string()}>{name}</option>
+                        })}
+                    </select>
+                    <button
+                        class="download-btn"
+                        onclick={link.callback(move |_| Msg::DeleteBookmark(id))}
+                        title="Delete"
+                    ><span class="icon icon-delete"></span></button>
+                </div>
+                if let BookmarkNode::Folder(folder) = node {
+                    { for folder.children.iter().map(|child| self.render_bookmark_entry(link, child, depth + 1, folders)) }
+                }
+            </>
         }
-        
-        // Otherwise, treat as a search query
-        self.state.search_engine.get_search_url(input)
     }
 
-    fn get_proxied_url(&self, url: &str) -> String {
-        if self.state.proxy_server.is_empty() {
-            url.to_string()
-        } else {
-            // Use proxy server if configured
-            format!("{}?url={}", self.state.proxy_server, js_sys::encode_uri_component(url))
+    /// Every folder a bookmark can be moved into, indented by depth
+    fn bookmark_folder_choices(&self) -> Vec<(u32, String)> {
+        let bookmarks = &self.state.bookmarks;
+        let mut choices = Vec::new();
+        for root in [&bookmarks.bar, &bookmarks.other] {
+            choices.push((root.id, root.title.clone()));
+            for (depth, folder) in root.folders() {
+                choices.push((folder.id, format!("{}{}", "\u{a0}\u{a0}".repeat(depth + 1), folder.title)));
+            }
         }
+        choices
     }
 
-    fn get_title_from_url(url: &str) -> String {
-        if url.starts_with("graphite://") {
-            return "Home".to_string();
+    fn render_download(&self, link: &yew::html::Scope<Self>, download: &Download) -> Html {
+        let dl_id = download.id;
+        let is_active = matches!(download.status, DownloadStatus::InProgress | DownloadStatus::Paused);
+
+        html! {
+            <div class="download-item" title={download.url.clone()}>
+                <div class="download-info">
+                    <span class="download-name">
+                        if download.private {
+                            <span class="tab-private icon icon-private" title="Private download, forgotten when the last private tab closes"></span>
+                        }
+                        {&download.filename}
+                    </span>
+                    <span class={classes!("download-status", matches!(download.status, DownloadStatus::Failed(_)).then_some("failed"))}>
+                        {download.status_text()}
+                    </span>
+                    if is_active {
+                        <div class="download-progress">
+                            <div
+                                class="download-progress-bar"
+                                style={format!("width: {:.1}%;", download.progress().unwrap_or(0.0) * 100.0)}
+                            ></div>
+                        </div>
+                    }
+                </div>
+                <div class="download-actions">
+                    if download.status == DownloadStatus::InProgress {
+                        if self.download_jobs.get(&dl_id).is_some_and(DownloadJob::can_pause) {
+                            <button 
+                                class="download-btn"
+                                onclick={link.callback(move |_| Msg::PauseDownload(dl_id))}
+                                title="Pause"
+                            ><span class="icon icon-pause"></span></button>
+                        }
+                    } else if download.status == DownloadStatus::Paused {
+                        <button 
+                            class="download-btn"
+                            onclick={link.callback(move |_| Msg::ResumeDownload(dl_id))}
+                            title="Resume"
+                        ><span class="icon icon-play"></span></button>
+                    }
+                    if is_active {
+                        <button 
+                            class="download-btn"
+                            onclick={link.callback(move |_| Msg::CancelDownload(dl_id))}
+                            title="Cancel"
+                        ><span class="icon icon-close"></span></button>
+                    }
+                    // Private files aren't kept, so there is nothing to save again
+                    if download.status == DownloadStatus::Completed && !download.private {
+                        <button 
+                            class="download-btn"
+                            onclick={link.callback(move |_| Msg::OpenDownloadFolder(dl_id))}
+                            title="Open Folder"
+                        ><span class="icon icon-folder"></span></button>
+                    }
+                    <button 
+                        class="download-btn"
+                        onclick={link.callback(move |_| Msg::DeleteDownload(dl_id))}
+                        title="Delete"
+                    ><span class="icon icon-delete"></span></button>
+                </div>
+            </div>
         }
-        
-        // Extract domain from URL
-        url.replace("https://", "")
-            .replace("http://", "")
-            .split('/')
-            .next()
-            .unwrap_or("New Tab")
-            .to_string()
     }
 
-    fn render_search_engine_option(&self, link: &yew::html::Scope<Self>, engine: SearchEngine, icon: &str, color: &str) -> Html {
-        let is_selected = self.state.search_engine == engine;
-        let engine_clone = engine.clone();
-        
+    /// A button selecting `engine`, or adding it when it is the `discovered`
+    /// offer at that index
+    fn render_search_engine_option(&self, link: &yew::html::Scope<Self>, engine: &SearchEngine, discovered: Option<usize>) -> Html {
+        let is_selected = discovered.is_none() && self.search_engine().id == engine.id;
+        let color = if engine.color.is_empty() { "var(--accent)" } else { engine.color.as_str() };
+        let title = match (discovered, engine.keyword.is_empty()) {
+            (Some(_), _) => format!("Add {}", engine.name),
+            (None, true) => engine.name.clone(),
+            (None, false) => format!("{} (keyword: {})", engine.name, engine.keyword),
+        };
+        let onclick = match discovered {
+            Some(index) => link.callback(move |_| Msg::AddDiscoveredEngine(index)),
+            None => {
+                let id = engine.id.clone();
+                link.callback(move |_| Msg::SetSearchEngine(id.clone()))
+            }
+        };
+
         html! {
-            <button 
-                class={classes!("search-engine-btn", is_selected.then_some("selected"))}
+            <button
+                class={classes!("search-engine-btn", is_selected.then_some("selected"), discovered.is_some().then_some("discovered"))}
                 style={format!("background-color: {};", color)}
-                onclick={link.callback(move |_| Msg::SetSearchEngine(engine_clone.clone()))}
+                {title}
+                {onclick}
             >
-                {icon}
+                if let Some(src) = engine.icon_image() {
+                    <img class="search-engine-img" src={src.to_string()} alt="" />
+                } else {
+                    {engine.badge()}
+                }
+                if discovered.is_some() {
+                    <span class="search-engine-badge icon icon-add"></span>
+                }
             </button>
         }
     }
+
+    /// The user's engines with edit and remove buttons, and the form for
+    /// the one being edited
+    fn render_custom_search_engines(&self, link: &yew::html::Scope<Self>) -> Html {
+        let field = |field: EngineField, label: &str, placeholder: &str, value: String| {
+            html! {
+                <input
+                    type="text"
+                    class="proxy-input"
+                    title={label.to_string()}
+                    placeholder={placeholder.to_string()}
+                    {value}
+                    oninput={link.callback(move |e: InputEvent| {
+                        let input: HtmlInputElement = e.target_unchecked_into();
+                        Msg::UpdateEngineDraft(field, input.value())
+                    })}
+                />
+            }
+        };
+
+        html! {
+            <>
+                { for self.state.custom_search_engines.iter().map(|engine| {
+                    let edit_id = engine.id.clone();
+                    let remove_id = engine.id.clone();
+                    html! {
+                        <div class="engine-row">
+                            <span class="engine-name">{&engine.name}</span>
+                            if !engine.keyword.is_empty() {
+                                <span class="engine-keyword">{&engine.keyword}</span>
+                            }
+                            <button
+                                class="download-btn"
+                                title="Edit"
+                                onclick={link.callback(move |_| Msg::EditSearchEngine(Some(edit_id.clone())))}
+                            ><span class="icon icon-edit"></span></button>
+                            <button
+                                class="download-btn"
+                                title="Remove"
+                                onclick={link.callback(move |_| Msg::RemoveSearchEngine(remove_id.clone()))}
+                            ><span class="icon icon-delete"></span></button>
+                        </div>
+                    }
+                })}
+                if let Some(draft) = &self.engine_draft {
+                    <form
+                        class="engine-form"
+                        onsubmit={link.callback(|e: SubmitEvent| {
+                            e.prevent_default();
+                            Msg::SaveSearchEngine
+                        })}
+                    >
+                        { field(EngineField::Name, "Name", "Name", draft.name.clone()) }
+                        { field(EngineField::SearchUrl, "Search URL", "https://example.com/search?q=%s", draft.search_url.clone()) }
+                        { field(
+                            EngineField::SuggestUrl,
+                            "Suggestion URL (optional)",
+                            "Suggestion URL with %s (optional)",
+                            draft.suggest_url.clone().unwrap_or_default(),
+                        ) }
+                        { field(EngineField::Icon, "Image URL or a short label", "Icon (optional)", draft.icon.clone()) }
+                        { field(EngineField::Keyword, "Type the keyword and a space before a query to search this engine", "Keyword, e.g. gh", draft.keyword.clone()) }
+                        if let Some(err) = &self.engine_error {
+                            <div class="settings-error">{err}</div>
+                        }
+                        <div class="theme-options engine-actions">
+                            <button type="submit" class="theme-btn">{"Save"}</button>
+                            <button type="button" class="theme-btn" onclick={link.callback(|_| Msg::CancelEngineEdit)}>{"Cancel"}</button>
+                        </div>
+                    </form>
+                }
+            </>
+        }
+    }
 }
 
 #[wasm_bindgen(start)]
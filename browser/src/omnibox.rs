@@ -0,0 +1,310 @@
+//! Ranking for the URL bar's suggestion dropdown.
+//!
+//! Everything here is plain data in, plain data out: the app gathers open
+//! tabs, bookmarks and history into [`Candidate`]s, and [`rank`] scores them
+//! against what was typed and merges in the search engine's suggestions.
+
+const DAY_MS: f64 = 24.0 * 60.0 * 60.0 * 1000.0;
+
+/// Most rows shown in the dropdown
+pub const MAX_SUGGESTIONS: usize = 8;
+/// Rows kept for page matches when search suggestions are available
+const MAX_PAGE_SUGGESTIONS: usize = 5;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum SuggestionKind {
+    /// An already open tab, identified by its id
+    OpenTab(u32),
+    Bookmark,
+    History,
+    /// A query suggested by the search engine
+    Search,
+}
+
+impl SuggestionKind {
+    pub fn icon(&self) -> &'static str {
+        match self {
+            SuggestionKind::OpenTab(_) => "icon-tab",
+            SuggestionKind::Bookmark => "icon-star-filled",
+            SuggestionKind::History => "icon-history",
+            SuggestionKind::Search => "icon-search",
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Suggestion {
+    pub kind: SuggestionKind,
+    pub title: String,
+    /// The page address, or the query text for search suggestions
+    pub url: String,
+    pub score: f64,
+}
+
+/// A page that may be suggested
+#[derive(Clone, Debug, PartialEq)]
+pub struct Candidate<'a> {
+    pub kind: SuggestionKind,
+    pub title: &'a str,
+    pub url: &'a str,
+    pub visit_count: u32,
+    /// Milliseconds since the epoch, zero when unknown
+    pub last_visit: f64,
+}
+
+/// Scores `candidates` against `query` and returns the rows to show, best
+/// page matches first followed by the search engine's suggestions
+pub fn rank(query: &str, candidates: Vec<Candidate>, search_suggestions: &[String], now: f64) -> Vec<Suggestion> {
+    let query = query.trim().to_lowercase();
+    if query.is_empty() {
+        return Vec::new();
+    }
+    let words: Vec<&str> = query.split_whitespace().collect();
+
+    let mut scored: Vec<Suggestion> = candidates
+        .into_iter()
+        .filter_map(|c| {
+            let score = match_score(&query, &words, c.title, c.url)? + kind_score(&c, now);
+            Some(Suggestion { kind: c.kind, title: c.title.to_string(), url: c.url.to_string(), score })
+        })
+        .collect();
+    scored.sort_by(|a, b| b.score.total_cmp(&a.score));
+
+    // The same page can be open, bookmarked and in history; keep one row at
+    // its best score, offering the open tab or bookmark over a history entry
+    let mut seen: Vec<String> = Vec::new();
+    let mut unique: Vec<Suggestion> = Vec::new();
+    for page in scored {
+        let key = strip_scheme(&page.url.to_lowercase()).trim_end_matches('/').to_string();
+        match seen.iter().position(|k| *k == key) {
+            Some(i) if precedence(page.kind) < precedence(unique[i].kind) => {
+                unique[i] = Suggestion { score: unique[i].score, ..page };
+            }
+            Some(_) => {}
+            None => {
+                seen.push(key);
+                unique.push(page);
+            }
+        }
+    }
+    let mut pages = unique;
+
+    let mut searches: Vec<Suggestion> = Vec::new();
+    for text in search_suggestions {
+        let lower = text.trim().to_lowercase();
+        if lower.is_empty() || searches.iter().any(|s| s.url.to_lowercase() == lower) {
+            continue;
+        }
+        searches.push(Suggestion { kind: SuggestionKind::Search, title: text.trim().to_string(), url: text.trim().to_string(), score: 0.0 });
+    }
+
+    let page_slots = if searches.is_empty() { MAX_SUGGESTIONS } else { MAX_PAGE_SUGGESTIONS };
+    pages.truncate(page_slots);
+    searches.truncate(MAX_SUGGESTIONS - pages.len());
+    pages.extend(searches);
+    pages
+}
+
+/// How well the title and address match, or `None` when some word is missing
+fn match_score(query: &str, words: &[&str], title: &str, url: &str) -> Option<f64> {
+    let title = title.to_lowercase();
+    let url = url.to_lowercase();
+    let address = strip_scheme(&url);
+
+    let mut score = 0.0;
+    if address.starts_with(query) {
+        // Typing the start of an address is the strongest signal; prefer the
+        // shortest completion so "git" suggests github.com over a deep link
+        score += 100.0 + 20.0 / (1.0 + (address.len() - query.len()) as f64 / 10.0);
+    }
+    for word in words {
+        if title.split(|c: char| !c.is_alphanumeric()).any(|w| w.starts_with(word)) {
+            score += 15.0;
+        } else if title.contains(word) {
+            score += 8.0;
+        } else if address.contains(word) {
+            score += 6.0;
+        } else {
+            return None;
+        }
+    }
+    Some(score)
+}
+
+/// Bonus for where a candidate came from and how often it was visited
+fn kind_score(candidate: &Candidate, now: f64) -> f64 {
+    let source = match candidate.kind {
+        SuggestionKind::OpenTab(_) => 15.0,
+        SuggestionKind::Bookmark => 20.0,
+        SuggestionKind::History | SuggestionKind::Search => 0.0,
+    };
+    let frequency = (1.0 + candidate.visit_count as f64).ln() * 8.0;
+    let recency = if candidate.last_visit > 0.0 {
+        let age_days = ((now - candidate.last_visit) / DAY_MS).max(0.0);
+        20.0 * 0.5f64.powf(age_days / 7.0)
+    } else {
+        0.0
+    };
+    source + frequency + recency
+}
+
+/// Which row a page listed more than once is shown as, lowest first
+fn precedence(kind: SuggestionKind) -> u8 {
+    match kind {
+        SuggestionKind::OpenTab(_) => 0,
+        SuggestionKind::Bookmark => 1,
+        SuggestionKind::History => 2,
+        SuggestionKind::Search => 3,
+    }
+}
+
+fn strip_scheme(url: &str) -> &str {
+    let url = url.strip_prefix("https://").or_else(|| url.strip_prefix("http://")).unwrap_or(url);
+    url.strip_prefix("www.").unwrap_or(url)
+}
+
+/// Reads an OpenSearch suggestions response, `["query", ["s1", "s2", ...]]`.
+/// DuckDuckGo's `[{"phrase": "s1"}, ...]` form is accepted too.
+pub fn parse_suggestions(json: &str) -> Vec<String> {
+    let Ok(value) = serde_json::from_str::<serde_json::Value>(json) else {
+        return Vec::new();
+    };
+    let Some(items) = value.as_array() else {
+        return Vec::new();
+    };
+    match items.get(1).and_then(|v| v.as_array()) {
+        Some(list) => list.iter().filter_map(|v| v.as_str()).map(String::from).collect(),
+        None => items
+            .iter()
+            .filter_map(|v| v.get("phrase").and_then(|p| p.as_str()))
+            .map(String::from)
+            .collect(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const NOW: f64 = 1_000.0 * DAY_MS;
+
+    fn history<'a>(title: &'a str, url: &'a str, visit_count: u32, days_ago: f64) -> Candidate<'a> {
+        Candidate { kind: SuggestionKind::History, title, url, visit_count, last_visit: NOW - days_ago * DAY_MS }
+    }
+
+    fn urls(suggestions: &[Suggestion]) -> Vec<&str> {
+        suggestions.iter().map(|s| s.url.as_str()).collect()
+    }
+
+    #[test]
+    fn address_prefixes_rank_over_substrings() {
+        let candidates = vec![
+            history("Issues", "https://example.com/about-github", 1, 1.0),
+            history("Code hosting", "https://github.com/", 1, 1.0),
+            history("Rust", "https://github.com/rust-lang/rust/issues", 1, 1.0),
+        ];
+        let ranked = rank("git", candidates, &[], NOW);
+        assert_eq!(
+            urls(&ranked),
+            ["https://github.com/", "https://github.com/rust-lang/rust/issues", "https://example.com/about-github"]
+        );
+    }
+
+    #[test]
+    fn title_words_rank_over_address_substrings() {
+        let candidates = vec![
+            history("Unrelated", "https://example.com/recipes/soup", 1, 1.0),
+            history("Soup recipes", "https://example.org/a", 1, 1.0),
+            history("Tomatosoup", "https://example.net/b", 1, 1.0),
+        ];
+        let ranked = rank("soup", candidates, &[], NOW);
+        assert_eq!(urls(&ranked), ["https://example.org/a", "https://example.net/b", "https://example.com/recipes/soup"]);
+    }
+
+    #[test]
+    fn every_word_has_to_match() {
+        let candidates = vec![history("Rust book", "https://doc.rust-lang.org/book/", 1, 1.0), history("Rust", "https://rust-lang.org/", 1, 1.0)];
+        assert_eq!(urls(&rank("rust book", candidates.clone(), &[], NOW)), ["https://doc.rust-lang.org/book/"]);
+        assert!(rank("rust cookbook", candidates.clone(), &[], NOW).is_empty());
+        assert!(rank("   ", candidates, &[], NOW).is_empty());
+    }
+
+    #[test]
+    fn frequent_and_recent_pages_rank_higher() {
+        let by_frequency = vec![
+            history("Docs one", "https://example.com/one", 1, 3.0),
+            history("Docs two", "https://example.com/two", 50, 3.0),
+        ];
+        assert_eq!(urls(&rank("docs", by_frequency, &[], NOW)), ["https://example.com/two", "https://example.com/one"]);
+
+        let by_recency = vec![
+            history("Docs old", "https://example.com/old", 3, 60.0),
+            history("Docs new", "https://example.com/new", 3, 0.5),
+        ];
+        assert_eq!(urls(&rank("docs", by_recency, &[], NOW)), ["https://example.com/new", "https://example.com/old"]);
+
+        // Pages never visited get no recency bonus, not a huge one
+        let unknown = vec![
+            Candidate { kind: SuggestionKind::History, title: "Docs never", url: "https://example.com/never", visit_count: 0, last_visit: 0.0 },
+            history("Docs today", "https://example.com/today", 0, 0.0),
+        ];
+        assert_eq!(urls(&rank("docs", unknown, &[], NOW)), ["https://example.com/today", "https://example.com/never"]);
+    }
+
+    #[test]
+    fn duplicates_keep_the_open_tab_or_bookmark() {
+        let candidates = vec![
+            history("Example", "https://www.example.com/", 40, 0.1),
+            Candidate { kind: SuggestionKind::OpenTab(7), title: "Example tab", url: "http://example.com", visit_count: 0, last_visit: 0.0 },
+            Candidate { kind: SuggestionKind::Bookmark, title: "Example mark", url: "https://example.com/", visit_count: 0, last_visit: 0.0 },
+            Candidate { kind: SuggestionKind::Bookmark, title: "Example docs", url: "https://example.com/docs", visit_count: 0, last_visit: 0.0 },
+            history("Example docs", "https://example.com/docs", 1, 1.0),
+        ];
+        let ranked = rank("example", candidates, &[], NOW);
+        assert_eq!(ranked.len(), 2);
+        assert_eq!(ranked[0].kind, SuggestionKind::OpenTab(7));
+        assert_eq!(ranked[0].title, "Example tab");
+        assert_eq!(ranked[1].kind, SuggestionKind::Bookmark);
+        assert_eq!(ranked[1].url, "https://example.com/docs");
+        // The merged row keeps the history entry's stronger score
+        let history_only = rank("example", vec![history("Example", "https://www.example.com/", 40, 0.1)], &[], NOW);
+        assert_eq!(ranked[0].score, history_only[0].score);
+    }
+
+    #[test]
+    fn search_suggestions_follow_pages() {
+        let pages: Vec<Candidate> = (0..10).map(|_| history("Rust page", "https://rust.example/", 1, 1.0)).collect();
+        let searches: Vec<String> = ["rust", "Rust ", "rust lang", "", "rust book", "rust analyzer"].map(String::from).to_vec();
+        let ranked = rank("rust", pages, &searches, NOW);
+        let kinds: Vec<SuggestionKind> = ranked.iter().map(|s| s.kind).collect();
+        assert_eq!(kinds[0], SuggestionKind::History);
+        assert_eq!(&urls(&ranked)[1..], ["rust", "rust lang", "rust book", "rust analyzer"]);
+
+        let many_pages: Vec<String> = (0..10).map(|i| format!("https://example.com/rust/{}", i)).collect();
+        let candidates = many_pages.iter().map(|url| history("Rust", url, 1, 1.0)).collect();
+        let ranked = rank("rust", candidates, &searches, NOW);
+        assert_eq!(ranked.len(), MAX_SUGGESTIONS);
+        assert_eq!(ranked.iter().filter(|s| s.kind == SuggestionKind::History).count(), MAX_PAGE_SUGGESTIONS);
+
+        let candidates = many_pages.iter().map(|url| history("Rust", url, 1, 1.0)).collect();
+        assert_eq!(rank("rust", candidates, &[], NOW).len(), MAX_SUGGESTIONS);
+    }
+
+    #[test]
+    fn parses_opensearch_and_duckduckgo_suggestions() {
+        assert_eq!(parse_suggestions(r#"["rust", ["rust lang", "rust book"]]"#), ["rust lang", "rust book"]);
+        assert_eq!(
+            parse_suggestions(r#"["rust", ["rust lang"], ["Rust Programming Language"], ["https://rust-lang.org"]]"#),
+            ["rust lang"]
+        );
+        assert_eq!(parse_suggestions(r#"[{"phrase": "rust lang"}, {"phrase": "rust book"}]"#), ["rust lang", "rust book"]);
+        assert_eq!(parse_suggestions(r#"["rust", ["rust lang", 5, null, "rust book"]]"#), ["rust lang", "rust book"]);
+    }
+
+    #[test]
+    fn malformed_suggestions_are_empty() {
+        for json in ["", "not json", "[\"rust\", [\"unterminated", "{\"rust\": []}", "\"rust\"", "null", "[]", "[\"rust\"]", "[1, 2]"] {
+            assert!(parse_suggestions(json).is_empty(), "parsing {:?}", json);
+        }
+    }
+}
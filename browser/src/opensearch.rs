@@ -0,0 +1,220 @@
+//! OpenSearch description documents, which sites link to with
+//! `<link rel="search" type="application/opensearchdescription+xml">` to
+//! describe their search page.
+//!
+//! Only what a [`SearchEngine`] can hold is read: the short name, the HTML
+//! results template, the JSON suggestions template and an image. Templates
+//! are turned into `%s` form, filling the optional parameters the way most
+//! browsers do.
+
+use url::Url;
+
+use crate::search_engines::{SearchEngine, QUERY_PLACEHOLDER};
+use crate::xml::{self, Element};
+
+pub const DESCRIPTION_TYPE: &str = "application/opensearchdescription+xml";
+const RESULTS_TYPE: &str = "text/html";
+const SUGGESTIONS_TYPE: &str = "application/x-suggestions+json";
+
+/// Reads the description fetched from `source` into an engine the user can
+/// add. The engine has no id yet, and its keyword is the site's host name.
+pub fn parse_description(xml: &str, source: &str) -> Result<SearchEngine, String> {
+    let root = xml::parse(xml)?;
+    // Older Firefox plugins use the same elements under another root
+    if !root.name.eq_ignore_ascii_case("OpenSearchDescription") && !root.name.eq_ignore_ascii_case("SearchPlugin") {
+        return Err(format!("Expected an OpenSearch description, found <{}>", root.name));
+    }
+    let base = Url::parse(source).map_err(|err| format!("Bad description address: {}", err))?;
+
+    let name = root
+        .child("ShortName")
+        .map(|el| el.text())
+        .filter(|name| !name.is_empty())
+        .ok_or("The description has no ShortName")?;
+    let search_url = find_template(&root, RESULTS_TYPE, &base).ok_or("The description has no search page template")?;
+    let suggest_url = find_template(&root, SUGGESTIONS_TYPE, &base);
+    let keyword = Url::parse(&search_url)
+        .ok()
+        .and_then(|url| url.host_str().map(|host| host.trim_start_matches("www.").to_string()))
+        .unwrap_or_default();
+
+    Ok(SearchEngine {
+        id: String::new(),
+        name,
+        search_url,
+        suggest_url,
+        icon: find_image(&root, &base).unwrap_or_default(),
+        color: String::new(),
+        keyword,
+    })
+}
+
+/// The first usable GET template of the given type
+fn find_template(root: &Element, kind: &str, base: &Url) -> Option<String> {
+    root.children_named("Url")
+        .filter(|el| el.attribute("type").is_some_and(|t| t.trim().eq_ignore_ascii_case(kind)))
+        .filter(|el| el.attribute("method").is_none_or(|m| m.eq_ignore_ascii_case("get")))
+        .find_map(|el| template_url(el, base))
+}
+
+/// Turns a `<Url template="...">` and its `<Param>`s into an absolute `%s` URL
+fn template_url(url: &Element, base: &Url) -> Option<String> {
+    let mut template = fill_parameters(url.attribute("template")?, url)?;
+    // Firefox's extension: extra query parameters as child elements
+    let params: Vec<String> = url
+        .children_named("Param")
+        .filter_map(|p| Some(format!("{}={}", p.attribute("name")?, fill_parameters(p.attribute("value")?, url)?)))
+        .collect();
+    if !params.is_empty() {
+        template.push(if template.contains('?') { '&' } else { '?' });
+        template.push_str(&params.join("&"));
+    }
+    if !template.contains(QUERY_PLACEHOLDER) {
+        return None;
+    }
+    let resolved = base.join(&template).ok()?;
+    matches!(resolved.scheme(), "http" | "https").then(|| resolved.to_string())
+}
+
+/// Replaces `{searchTerms}` with `%s` and gives every other parameter a
+/// value; `None` when a parameter is never closed
+fn fill_parameters(template: &str, url: &Element) -> Option<String> {
+    let mut result = String::with_capacity(template.len());
+    let mut rest = template;
+    while let Some(open) = rest.find('{') {
+        result.push_str(&rest[..open]);
+        let close = open + rest[open..].find('}')?;
+        let param = rest[open + 1..close].trim_end_matches('?');
+        let name = param.rsplit_once(':').map_or(param, |(_, local)| local);
+        let value = match name {
+            "searchTerms" => QUERY_PLACEHOLDER.to_string(),
+            "inputEncoding" | "outputEncoding" => String::from("UTF-8"),
+            "language" => String::from("*"),
+            "startIndex" => url.attribute("indexOffset").unwrap_or("1").to_string(),
+            "startPage" => url.attribute("pageOffset").unwrap_or("1").to_string(),
+            // Optional and unknown parameters are left empty
+            _ => String::new(),
+        };
+        result.push_str(&value);
+        rest = &rest[close + 1..];
+    }
+    result.push_str(rest);
+    Some(result)
+}
+
+/// The smallest image of at least 16 pixels, or any image at all
+fn find_image(root: &Element, base: &Url) -> Option<String> {
+    let mut images: Vec<(u32, String)> = root
+        .children_named("Image")
+        .filter_map(|el| {
+            let address = base.join(&el.text()).ok()?;
+            if !matches!(address.scheme(), "http" | "https" | "data") {
+                return None;
+            }
+            let size = el.attribute("width").and_then(|w| w.trim().parse().ok()).unwrap_or(0);
+            Some((size, address.to_string()))
+        })
+        .collect();
+    images.sort_by_key(|(size, _)| if *size >= 16 { *size } else { u32::MAX });
+    images.into_iter().next().map(|(_, address)| address)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// As served by Wikipedia
+    const WIKIPEDIA: &str = r#"<?xml version="1.0"?>
+<OpenSearchDescription xmlns="http://a9.com/-/spec/opensearch/1.1/" xmlns:moz="http://www.mozilla.org/2006/browser/search/">
+	<ShortName>Wikipedia (en)</ShortName>
+	<Description>Wikipedia (en)</Description>
+	<Image height="16" width="16" type="image/x-icon">https://en.wikipedia.org/static/favicon/wikipedia.ico</Image>
+	<Url type="text/html" method="get" template="https://en.wikipedia.org/w/index.php?title=Special:Search&amp;search={searchTerms}"/>
+	<Url type="application/x-suggestions+json" method="get" template="https://en.wikipedia.org/w/api.php?action=opensearch&amp;search={searchTerms}&amp;namespace=0"/>
+	<Url type="application/x-suggestions+xml" method="get" template="https://en.wikipedia.org/w/api.php?action=opensearch&amp;format=xml&amp;search={searchTerms}&amp;namespace=0"/>
+	<Url type="application/opensearchdescription+xml" rel="self" template="https://en.wikipedia.org/w/rest.php/v1/search"/>
+	<moz:SearchForm>https://en.wikipedia.org/wiki/Special:Search</moz:SearchForm>
+</OpenSearchDescription>
+"#;
+
+    /// An older Firefox plugin: relative templates, `<Param>` children, a POST
+    /// form listed first and optional parameters
+    const FIREFOX_PLUGIN: &str = r#"<SearchPlugin xmlns="http://www.mozilla.org/2006/browser/search/" xmlns:os="http://a9.com/-/spec/opensearch/1.1/">
+<os:ShortName>Example Search</os:ShortName>
+<os:InputEncoding>UTF-8</os:InputEncoding>
+<os:Image width="64" height="64">/icons/large.png</os:Image>
+<os:Image width="16" height="16">/icons/small.png</os:Image>
+<os:Url type="text/html" method="POST" template="https://post.example.com/search"/>
+<os:Url type="text/html" template="/search" indexOffset="0">
+  <Param name="q" value="{searchTerms}"/>
+  <Param name="start" value="{startIndex?}"/>
+  <Param name="lang" value="{language}"/>
+  <Param name="ie" value="{inputEncoding}"/>
+</os:Url>
+<os:Url type="application/x-suggestions+json" template="https://suggest.example.com/complete?q={searchTerms}&amp;n={count?}&amp;p={startPage}&amp;x={custom:thing?}"/>
+</SearchPlugin>
+"#;
+
+    #[test]
+    fn reads_a_real_description() {
+        let engine = parse_description(WIKIPEDIA, "https://en.wikipedia.org/w/opensearch_desc.php").unwrap();
+        assert_eq!(
+            engine,
+            SearchEngine {
+                id: String::new(),
+                name: String::from("Wikipedia (en)"),
+                search_url: String::from("https://en.wikipedia.org/w/index.php?title=Special:Search&search=%s"),
+                suggest_url: Some(String::from("https://en.wikipedia.org/w/api.php?action=opensearch&search=%s&namespace=0")),
+                icon: String::from("https://en.wikipedia.org/static/favicon/wikipedia.ico"),
+                color: String::new(),
+                keyword: String::from("en.wikipedia.org"),
+            }
+        );
+    }
+
+    #[test]
+    fn fills_parameters_and_resolves_relative_templates() {
+        let engine = parse_description(FIREFOX_PLUGIN, "https://www.example.com/opensearch.xml").unwrap();
+        assert_eq!(engine.name, "Example Search");
+        assert_eq!(engine.search_url, "https://www.example.com/search?q=%s&start=0&lang=*&ie=UTF-8");
+        assert_eq!(engine.suggest_url.as_deref(), Some("https://suggest.example.com/complete?q=%s&n=&p=1&x="));
+        assert_eq!(engine.icon, "https://www.example.com/icons/small.png");
+        assert_eq!(engine.keyword, "example.com");
+    }
+
+    #[test]
+    fn descriptions_without_the_essentials_are_refused() {
+        let source = "https://example.com/opensearch.xml";
+        let describe = |body: &str| format!("<OpenSearchDescription>{}</OpenSearchDescription>", body);
+        let html = r#"<Url type="text/html" template="https://example.com/?q={searchTerms}"/>"#;
+
+        assert!(parse_description(&describe(&format!("<ShortName>Ok</ShortName>{}", html)), source).is_ok());
+        for (xml, reason) in [
+            (describe(html), "no name"),
+            (describe(&format!("<ShortName>  </ShortName>{}", html)), "blank name"),
+            (describe("<ShortName>Ok</ShortName>"), "no template"),
+            (describe(r#"<ShortName>Ok</ShortName><Url type="text/html" template="https://example.com/"/>"#), "no search terms"),
+            (describe(r#"<ShortName>Ok</ShortName><Url type="text/html" method="post" template="https://example.com/?q={searchTerms}"/>"#), "POST only"),
+            (describe(r#"<ShortName>Ok</ShortName><Url type="text/html" template="javascript:run('{searchTerms}')"/>"#), "script template"),
+            (describe(r#"<ShortName>Ok</ShortName><Url type="text/html" template="https://example.com/?q={searchTerms"/>"#), "unclosed parameter"),
+            (format!("<html>{}</html>", html), "another document"),
+            (String::from("<OpenSearchDescription><ShortName>Broken</OpenSearchDescription>"), "malformed XML"),
+            (String::new(), "empty"),
+        ] {
+            assert!(parse_description(&xml, source).is_err(), "accepted {}", reason);
+        }
+        let ok = describe(&format!("<ShortName>Ok</ShortName>{}", html));
+        assert!(parse_description(&ok, "not a url").is_err());
+    }
+
+    #[test]
+    fn images_must_be_web_or_data_addresses() {
+        let xml = r#"<OpenSearchDescription><ShortName>Ok</ShortName>
+            <Image width="16">javascript:alert(1)</Image>
+            <Image width="8">data:image/png;base64,AAAA</Image>
+            <Url type="text/html" template="https://example.com/?q={searchTerms}"/></OpenSearchDescription>"#;
+        let engine = parse_description(xml, "https://example.com/").unwrap();
+        assert_eq!(engine.icon, "data:image/png;base64,AAAA");
+        assert_eq!(engine.suggest_url, None);
+    }
+}
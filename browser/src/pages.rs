@@ -0,0 +1,358 @@
+//! Internal `graphite://` pages.
+//!
+//! Every page is registered in [`PAGES`] under the name that follows the
+//! scheme, together with its tab title, icon and the view that renders it
+//! into the content area. Unknown names render the not-found page. Pages can
+//! read parameters from a query string, as in
+//! `graphite://error?url=...&message=...`.
+
+use wasm_bindgen::JsValue;
+use web_sys::HtmlInputElement;
+use yew::html::Scope;
+use yew::prelude::*;
+
+use crate::keymap::ACTIONS;
+use crate::proxy_config::{decode_component, encode_component};
+use crate::{App, HistoryEntry, Msg};
+
+pub const SCHEME: &str = "graphite://";
+pub const HOME_URL: &str = "graphite://home";
+
+pub struct InternalPage {
+    pub name: &'static str,
+    pub title: &'static str,
+    pub icon: &'static str,
+    view: fn(&App, &Scope<App>, &PageUrl) -> Html,
+}
+
+pub static PAGES: &[InternalPage] = &[
+    InternalPage { name: "home", title: "Home", icon: "icon-home", view: home },
+    InternalPage { name: "settings", title: "Settings", icon: "icon-settings", view: settings },
+    InternalPage { name: "history", title: "History", icon: "icon-history", view: history },
+    InternalPage { name: "downloads", title: "Downloads", icon: "icon-download", view: downloads },
+    InternalPage { name: "shortcuts", title: "Keyboard Shortcuts", icon: "icon-keyboard", view: shortcuts },
+    InternalPage { name: "about", title: "About", icon: "icon-info", view: about },
+    InternalPage { name: "error", title: "Can't load page", icon: "icon-error", view: error },
+];
+
+static NOT_FOUND: InternalPage = InternalPage { name: "not-found", title: "Page not found", icon: "icon-error", view: not_found };
+
+/// A parsed `graphite://name?key=value` address
+pub struct PageUrl {
+    pub name: String,
+    pub params: Vec<(String, String)>,
+}
+
+impl PageUrl {
+    pub fn parse(url: &str) -> Option<Self> {
+        let rest = url.strip_prefix(SCHEME)?;
+        let rest = rest.split('#').next().unwrap_or("");
+        let (path, query) = rest.split_once('?').unwrap_or((rest, ""));
+        let params = query
+            .split('&')
+            .filter(|p| !p.is_empty())
+            .map(|p| {
+                let (key, value) = p.split_once('=').unwrap_or((p, ""));
+                (decode_component(key), decode_component(value))
+            })
+            .collect();
+        Some(Self { name: path.trim_end_matches('/').to_ascii_lowercase(), params })
+    }
+
+    pub fn param(&self, key: &str) -> Option<&str> {
+        self.params.iter().find(|(k, _)| k == key).map(|(_, v)| v.as_str())
+    }
+}
+
+pub fn is_internal(url: &str) -> bool {
+    url.starts_with(SCHEME)
+}
+
+/// The page registered for an internal URL, or the not-found page
+pub fn find(url: &str) -> &'static InternalPage {
+    let Some(page_url) = PageUrl::parse(url) else {
+        return &NOT_FOUND;
+    };
+    // A bare `graphite://` goes home
+    let name = if page_url.name.is_empty() { "home" } else { page_url.name.as_str() };
+    PAGES.iter().find(|p| p.name == name).unwrap_or(&NOT_FOUND)
+}
+
+pub fn render(app: &App, link: &Scope<App>, url: &str) -> Html {
+    let page_url = PageUrl::parse(url).unwrap_or(PageUrl { name: String::new(), params: Vec::new() });
+    (find(url).view)(app, link, &page_url)
+}
+
+/// Address of the error page explaining why `url` couldn't be opened
+pub fn error_url(url: &str, message: &str) -> String {
+    format!("{}error?url={}&message={}", SCHEME, encode_component(url), encode_component(message))
+}
+
+fn home(app: &App, link: &Scope<App>, _: &PageUrl) -> Html {
+    let private = app.is_private_tab(app.state.active_tab_id);
+    html! {
+        <div class="home-page">
+            <h1 class="browser-title">{"graphite"}</h1>
+            <p class="browser-tagline">{"a simple, sleek, modern, minimalist web browser"}</p>
+            if private {
+                <p class="private-note">
+                    <span class="tab-private icon icon-private"></span>
+                    {"This is a private tab. It won't be saved, the pages it visits stay out of your history, and it keeps its own cookies when the WebSocket proxy supports it."}
+                </p>
+            }
+            <div class="home-search-container">
+                <input 
+                    type="text" 
+                    class="home-search"
+                    placeholder="Search or enter a URL"
+                    onkeypress={link.callback(|e: KeyboardEvent| {
+                        if e.key() == "Enter" {
+                            let input: HtmlInputElement = e.target_unchecked_into();
+                            Msg::Navigate(input.value())
+                        } else {
+                            Msg::NoOp
+                        }
+                    })}
+                />
+                <button class="home-search-btn">
+                    <span class="icon icon-search"></span>
+                </button>
+            </div>
+        </div>
+    }
+}
+
+fn settings(app: &App, link: &Scope<App>, _: &PageUrl) -> Html {
+    html! {
+        <div class="internal-page">
+            <h1 class="page-title">{"Settings"}</h1>
+            <div class="page-section">
+                { app.render_settings(link) }
+            </div>
+        </div>
+    }
+}
+
+fn downloads(app: &App, link: &Scope<App>, _: &PageUrl) -> Html {
+    html! {
+        <div class="internal-page">
+            <h1 class="page-title">{"Downloads"}</h1>
+            <div class="page-section">
+                { app.render_downloads(link) }
+            </div>
+        </div>
+    }
+}
+
+fn shortcuts(app: &App, link: &Scope<App>, _: &PageUrl) -> Html {
+    let keymap = &app.state.keymap;
+    html! {
+        <div class="internal-page">
+            <div class="history-header-row">
+                <h1 class="page-title">{"Keyboard Shortcuts"}</h1>
+                <button class="theme-btn" onclick={link.callback(|_| Msg::ResetShortcuts(None))}>{"Reset all"}</button>
+            </div>
+            <p class="page-text">
+                {"Shortcuts marked with a dot may be kept by the browser Graphite runs in, so another key for the same action is a safer choice."}
+            </p>
+            { for keymap.conflicts().into_iter().map(|(combo, actions)| {
+                let labels: Vec<&str> = actions.iter().map(|a| a.label).collect();
+                html! {
+                    <p class="shortcut-warning">
+                        {format!("{} is bound to {}. Only {} runs.", combo, labels.join(" and "), labels[0])}
+                    </p>
+                }
+            })}
+            if let Some(error) = &app.shortcut_error {
+                <p class="shortcut-warning">{error}</p>
+            }
+            { for ACTIONS.iter().map(|action| {
+                let id = action.id;
+                let recording = app.recording_shortcut == Some(id);
+                html! {
+                    <div class="history-item shortcut-row">
+                        <span class="shortcut-label">{action.label}</span>
+                        <span class="shortcut-keys">
+                            { for keymap.keys(action).into_iter().map(|combo| {
+                                let reserved = combo.is_host_reserved();
+                                let label = combo.to_string();
+                                html! {
+                                    <span
+                                        class={classes!("shortcut-key", reserved.then_some("reserved"))}
+                                        title={reserved.then_some("Your browser may keep this shortcut for itself")}
+                                    >
+                                        {label}
+                                        <button
+                                            class="shortcut-remove"
+                                            title="Remove"
+                                            onclick={link.callback(move |_| Msg::RemoveShortcut(id, combo.clone()))}
+                                        ><span class="icon icon-close"></span></button>
+                                    </span>
+                                }
+                            })}
+                            if recording {
+                                <span class="shortcut-key recording">{"Press a key combination, or Escape"}</span>
+                            }
+                        </span>
+                        if recording {
+                            <button class="theme-btn" onclick={link.callback(|_| Msg::RecordShortcut(None))}>{"Cancel"}</button>
+                        } else {
+                            <button class="theme-btn" onclick={link.callback(move |_| Msg::RecordShortcut(Some(id)))}>{"Add"}</button>
+                        }
+                        if keymap.is_customized(action) {
+                            <button class="theme-btn" onclick={link.callback(move |_| Msg::ResetShortcuts(Some(id)))}>{"Reset"}</button>
+                        }
+                    </div>
+                }
+            })}
+        </div>
+    }
+}
+
+fn about(_: &App, link: &Scope<App>, _: &PageUrl) -> Html {
+    html! {
+        <div class="internal-page">
+            <h1 class="page-title">{"About graphite"}</h1>
+            <p class="page-text">{format!("Version {}", env!("CARGO_PKG_VERSION"))}</p>
+            <p class="page-text">{env!("CARGO_PKG_DESCRIPTION")}</p>
+            <h2 class="page-subtitle">{"Internal pages"}</h2>
+            { for PAGES.iter().filter(|p| p.name != "error").map(|page| {
+                let url = format!("{}{}", SCHEME, page.name);
+                html! {
+                    <div class="history-item">
+                        <span class={classes!("bookmark-icon", "icon", page.icon)}></span>
+                        <span class="history-item-title" onclick={link.callback({
+                            let url = url.clone();
+                            move |_| Msg::Navigate(url.clone())
+                        })}>{page.title}</span>
+                        <span class="history-host">{url}</span>
+                    </div>
+                }
+            })}
+        </div>
+    }
+}
+
+fn error(app: &App, link: &Scope<App>, page_url: &PageUrl) -> Html {
+    let url = page_url.param("url").unwrap_or("").to_string();
+    // Only addresses that can't be opened end up here, so trying again would
+    // just come back; the text may have been meant as a search
+    let search = app.search_engine().search_url(&url);
+    let message = page_url.param("message").unwrap_or("Something went wrong").to_string();
+    html! {
+        <div class="internal-page">
+            <span class="page-icon icon icon-error"></span>
+            <h1 class="page-title">{"Can't load page"}</h1>
+            if !url.is_empty() {
+                <p class="page-text page-url">{&url}</p>
+            }
+            <p class="page-text">{message}</p>
+            if !url.is_empty() {
+                <button class="theme-btn" onclick={link.callback(move |_| Msg::Navigate(search.clone()))}>{"Search for this instead"}</button>
+            }
+        </div>
+    }
+}
+
+fn not_found(app: &App, link: &Scope<App>, _: &PageUrl) -> Html {
+    let url = app.state.tabs
+        .iter()
+        .find(|t| t.id == app.state.active_tab_id)
+        .map(|t| t.url.clone())
+        .unwrap_or_default();
+    html! {
+        <div class="internal-page">
+            <span class="page-icon icon icon-error"></span>
+            <h1 class="page-title">{"Page not found"}</h1>
+            <p class="page-text">{format!("{} isn't an internal page.", url)}</p>
+            <button class="theme-btn" onclick={link.callback(|_| Msg::Navigate(format!("{}about", SCHEME)))}>
+                {"See all internal pages"}
+            </button>
+        </div>
+    }
+}
+
+fn history(app: &App, link: &Scope<App>, _: &PageUrl) -> Html {
+    const HOUR_MS: f64 = 60.0 * 60.0 * 1000.0;
+    let now = js_sys::Date::now();
+    let today = String::from(js_sys::Date::new(&JsValue::from_f64(now)).to_date_string());
+    let yesterday = String::from(js_sys::Date::new(&JsValue::from_f64(now - 24.0 * HOUR_MS)).to_date_string());
+
+    // Entries are newest first, so each day's entries are contiguous
+    let mut days: Vec<(String, Vec<&HistoryEntry>)> = Vec::new();
+    for entry in app.state.history.search(&app.history_query) {
+        let date = js_sys::Date::new(&JsValue::from_f64(entry.last_visit));
+        let day = String::from(date.to_date_string());
+        match days.last_mut() {
+            Some((last, entries)) if *last == day => entries.push(entry),
+            _ => days.push((day, vec![entry])),
+        }
+    }
+
+    html! {
+        <div class="internal-page">
+            <div class="history-header-row">
+                <h1 class="page-title">{"History"}</h1>
+                <input
+                    type="text"
+                    class="history-search"
+                    placeholder="Search history"
+                    value={app.history_query.clone()}
+                    oninput={link.callback(|e: InputEvent| {
+                        let input: HtmlInputElement = e.target_unchecked_into();
+                        Msg::SetHistoryQuery(input.value())
+                    })}
+                />
+            </div>
+            <div class="history-clear">
+                <span class="history-clear-label">{"Clear:"}</span>
+                { for [("Last hour", Some(HOUR_MS)), ("Last day", Some(24.0 * HOUR_MS)), ("Last 7 days", Some(7.0 * 24.0 * HOUR_MS)), ("Last 4 weeks", Some(28.0 * 24.0 * HOUR_MS)), ("All time", None)]
+                    .into_iter()
+                    .map(|(label, range)| html! {
+                        <button class="theme-btn" onclick={link.callback(move |_| Msg::ClearHistory(range))}>{label}</button>
+                    })
+                }
+            </div>
+            if days.is_empty() {
+                <p class="history-empty">
+                    { if app.history_query.trim().is_empty() { "No browsing history" } else { "No matching pages" } }
+                </p>
+            }
+            { for days.into_iter().map(|(day, entries)| html! {
+                <section class="history-day">
+                    <h2 class="history-day-title">
+                        { if day == today { String::from("Today") } else if day == yesterday { String::from("Yesterday") } else { day } }
+                    </h2>
+                    { for entries.into_iter().map(|entry| history_entry(link, entry)) }
+                </section>
+            })}
+        </div>
+    }
+}
+
+fn history_entry(link: &Scope<App>, entry: &HistoryEntry) -> Html {
+    let id = entry.id;
+    let url = entry.url.clone();
+    let date = js_sys::Date::new(&JsValue::from_f64(entry.last_visit));
+    let time = format!("{:02}:{:02}", date.get_hours(), date.get_minutes());
+    let title = if entry.title.is_empty() { entry.url.clone() } else { entry.title.clone() };
+
+    html! {
+        <div class="history-item" title={entry.url.clone()}>
+            <span class="history-time">{time}</span>
+            if let Some(favicon) = App::get_fallback_favicon(&entry.url) {
+                <img class="bookmark-icon icon" src={favicon} alt="" />
+            }
+            <span class="history-item-title" onclick={link.callback(move |_| Msg::Navigate(url.clone()))}>{title}</span>
+            <span class="history-host">{App::get_title_from_url(&entry.url)}</span>
+            if entry.visit_count > 1 {
+                <span class="history-visits">{format!("{} visits", entry.visit_count)}</span>
+            }
+            <button
+                class="download-btn"
+                onclick={link.callback(move |_| Msg::DeleteHistoryEntry(id))}
+                title="Remove from history"
+            ><span class="icon icon-delete"></span></button>
+        </div>
+    }
+}
@@ -0,0 +1,192 @@
+//! The command palette: everything the browser can do, found by typing.
+//!
+//! The app lists what is on offer right now as [`Entry`]s: the actions in
+//! [`keymap::ACTIONS`](crate::keymap::ACTIONS), open tabs, saved tab groups,
+//! search engines, themes, internal pages and, once something is typed,
+//! bookmarks and history. [`search`] fuzzy-matches them against the query
+//! and ranks them.
+//! An action only has to be registered in the keymap to show up here.
+
+use crate::{Msg, Theme};
+
+/// Most rows shown at once
+pub const MAX_ENTRIES: usize = 50;
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum Target {
+    /// A keymap action, by its id
+    Action(&'static str),
+    Tab(u32),
+    /// A saved tab group to restore, by its index
+    SavedGroup(usize),
+    /// A search engine to make the default, by its id
+    SearchEngine(String),
+    Theme(Theme),
+    /// An internal page, bookmark or history entry to open
+    Url(String),
+}
+
+impl Target {
+    pub fn msg(&self) -> Msg {
+        match self {
+            Target::Action(id) => Msg::RunAction(id),
+            Target::Tab(id) => Msg::SelectTab(*id),
+            Target::SavedGroup(index) => Msg::RestoreGroup(*index),
+            Target::SearchEngine(id) => Msg::SetSearchEngine(id.clone()),
+            Target::Theme(theme) => Msg::SetTheme(*theme),
+            Target::Url(url) => Msg::Navigate(url.clone()),
+        }
+    }
+
+    /// Commands come before the pages that merely match as well
+    fn bonus(&self) -> f64 {
+        match self {
+            Target::Action(_) => 6.0,
+            Target::Tab(_) | Target::SavedGroup(_) => 4.0,
+            Target::SearchEngine(_) | Target::Theme(_) => 2.0,
+            Target::Url(_) => 0.0,
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Entry {
+    pub target: Target,
+    pub title: String,
+    /// What kind of entry it is, or the address it opens
+    pub detail: String,
+    pub icon: &'static str,
+    pub shortcut: Option<String>,
+}
+
+/// The entries matching `query`, best first. Without a query they keep the
+/// order they were listed in.
+pub fn search(query: &str, entries: Vec<Entry>) -> Vec<Entry> {
+    let query: String = query.to_lowercase().chars().filter(|c| !c.is_whitespace()).collect();
+    if query.is_empty() {
+        return entries.into_iter().take(MAX_ENTRIES).collect();
+    }
+    let mut scored: Vec<(f64, Entry)> = entries
+        .into_iter()
+        .filter_map(|entry| {
+            let title = fuzzy_score(&query, &entry.title);
+            // Addresses match too, but count for less than names
+            let detail = fuzzy_score(&query, &entry.detail).map(|score| score - 10.0);
+            let score = title.into_iter().chain(detail).reduce(f64::max)?;
+            Some((score + entry.target.bonus(), entry))
+        })
+        .collect();
+    // Stable, so equally good entries stay in listing order
+    scored.sort_by(|a, b| b.0.total_cmp(&a.0));
+    scored.into_iter().take(MAX_ENTRIES).map(|(_, entry)| entry).collect()
+}
+
+/// Scores `text` for containing the query's characters in order, or `None`
+/// when it doesn't. Characters starting a word or following the previous
+/// match count for more, and gaps count against.
+fn fuzzy_score(query: &str, text: &str) -> Option<f64> {
+    let text: Vec<char> = text.to_lowercase().chars().collect();
+    let mut score = 0.0;
+    let mut from = 0;
+    let mut previous: Option<usize> = None;
+    for wanted in query.chars() {
+        // Prefer the next word that starts with the character over its
+        // first appearance inside a word
+        let at_word_start = |i: usize| text[i] == wanted && (i == 0 || !text[i - 1].is_alphanumeric());
+        let found = match previous.map(|p| p + 1).filter(|&next| text.get(next) == Some(&wanted)) {
+            Some(next) => next,
+            None => (from..text.len())
+                .find(|&i| at_word_start(i))
+                .or_else(|| (from..text.len()).find(|&i| text[i] == wanted))?,
+        };
+        score += 1.0;
+        if found == 0 || !text[found - 1].is_alphanumeric() {
+            score += 8.0;
+        }
+        score += match previous {
+            Some(p) if p + 1 == found => 5.0,
+            Some(p) => -((found - p - 1) as f64).min(5.0) * 0.5,
+            None => -(found as f64).min(10.0) * 0.2,
+        };
+        previous = Some(found);
+        from = found + 1;
+    }
+    Some(score)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn entry(target: Target, title: &str, detail: &str) -> Entry {
+        Entry { target, title: title.to_string(), detail: detail.to_string(), icon: "icon-tab", shortcut: None }
+    }
+
+    fn page(title: &str) -> Entry {
+        entry(Target::Url(format!("https://{}.test/", title.len())), title, "")
+    }
+
+    fn titles(entries: &[Entry]) -> Vec<&str> {
+        entries.iter().map(|e| e.title.as_str()).collect()
+    }
+
+    #[test]
+    fn characters_match_in_order() {
+        assert!(fuzzy_score("nwtb", "New tab").is_some());
+        assert!(fuzzy_score("ntw", "New tab").is_none());
+        assert!(fuzzy_score("tabs", "New tab").is_none());
+        assert!(fuzzy_score("", "New tab").is_some());
+        assert!(fuzzy_score("NEW", "new tab").is_none(), "the query is lowercased by search");
+    }
+
+    #[test]
+    fn word_starts_and_runs_beat_scattered_matches() {
+        let score = |text: &str| fuzzy_score("st", text).unwrap();
+        // Starting words, then following on, then inside words
+        assert!(score("Show tabs") > score("Stop"));
+        assert!(score("Stop") > score("Settings"));
+        assert!(score("Settings") > score("Most"));
+        // A later word's start is chosen over an earlier letter inside a word
+        assert!(fuzzy_score("tab", "Stab tabs").unwrap() > fuzzy_score("tab", "Stab").unwrap());
+
+        let found = search("st", ["Most", "Tsar", "Settings", "Stop", "Show tabs"].map(page).to_vec());
+        assert_eq!(titles(&found), ["Show tabs", "Stop", "Settings", "Most"]);
+    }
+
+    #[test]
+    fn names_count_for_more_than_addresses() {
+        let entries = vec![
+            entry(Target::Url(String::from("https://stackoverflow.com/")), "Questions", "https://stackoverflow.com/"),
+            entry(Target::Url(String::from("https://stop.test/")), "Stop", "https://stop.test/"),
+        ];
+        assert_eq!(titles(&search("stack", entries.clone())), ["Questions"]);
+        assert_eq!(titles(&search("st", entries)), ["Stop", "Questions"]);
+    }
+
+    #[test]
+    fn commands_come_before_pages() {
+        let entries = vec![
+            entry(Target::Url(String::from("graphite://settings")), "Settings", "graphite://settings"),
+            entry(Target::Tab(3), "Settings", "Tab"),
+            entry(Target::Action("toggle-settings"), "Settings", "Command"),
+        ];
+        let found = search("settings", entries);
+        assert_eq!(found.iter().map(|e| e.target.clone()).collect::<Vec<_>>(), [
+            Target::Action("toggle-settings"),
+            Target::Tab(3),
+            Target::Url(String::from("graphite://settings")),
+        ]);
+    }
+
+    #[test]
+    fn queries_ignore_case_and_spaces() {
+        let entries = ["Close tab", "New tab", "New private tab"].map(page).to_vec();
+        assert_eq!(titles(&search("NEW TAB", entries.clone())), ["New tab", "New private tab"]);
+        assert_eq!(titles(&search("newtab", entries.clone())), ["New tab", "New private tab"]);
+        // Without a query everything is listed as given, up to the limit
+        assert_eq!(titles(&search("  ", entries)), ["Close tab", "New tab", "New private tab"]);
+        let many: Vec<Entry> = (0..MAX_ENTRIES + 5).map(|i| page(&format!("Page {}", i))).collect();
+        assert_eq!(search("", many.clone()).len(), MAX_ENTRIES);
+        assert_eq!(search("page", many).len(), MAX_ENTRIES);
+    }
+}
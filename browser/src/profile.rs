@@ -0,0 +1,342 @@
+//! Moving a whole profile between browsers as one JSON file.
+//!
+//! The file wraps the versioned state (see [`schema`]) with a format marker
+//! and the export time. Downloads stay behind, since their files live in this
+//! browser's database. On import the state is migrated like a saved one, and
+//! anything that can't be read is reported field by field. It then either
+//! replaces the current profile or is merged into it.
+
+use std::collections::HashSet;
+
+use serde_json::{json, Map, Value};
+
+use crate::{schema, search_engines, BrowserState, SearchEngine, Tab};
+
+/// Marks a file as a Graphite profile
+const FORMAT: &str = "graphite-profile";
+
+/// Fields that only make sense in the browser that wrote them
+const LOCAL_FIELDS: [&str; 2] = ["downloads", "next_download_id"];
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ImportMode {
+    /// Adds the file's tabs, bookmarks, history and search engines and keeps
+    /// the current settings
+    Merge,
+    /// Swaps in everything from the file except downloads
+    Replace,
+}
+
+/// A profile read from a file, waiting for the user to choose how to import it
+#[derive(Clone, Debug, PartialEq)]
+pub struct Imported {
+    pub state: BrowserState,
+    /// Milliseconds since the epoch, for files that record it
+    pub exported_at: Option<f64>,
+}
+
+impl Imported {
+    pub fn summary(&self) -> String {
+        format!(
+            "{}, {}, {} and {}",
+            count(self.state.tabs.iter().filter(|t| !t.is_blank()).count(), "tab", "tabs"),
+            count(self.state.bookmarks.all().len(), "bookmark", "bookmarks"),
+            count(self.state.history.entries.len(), "history entry", "history entries"),
+            count(self.state.custom_search_engines.len(), "search engine", "search engines"),
+        )
+    }
+}
+
+/// The profile file for `state`
+pub fn export(state: &BrowserState, now: f64) -> Result<String, String> {
+    let mut fields = schema::encode(state)?;
+    for field in LOCAL_FIELDS {
+        fields.remove(field);
+    }
+    let file = json!({ "format": FORMAT, "exported_at": now, "state": fields });
+    serde_json::to_string_pretty(&file).map_err(|err| err.to_string())
+}
+
+/// Reads a profile file, or lists everything wrong with it. A bare saved
+/// state, such as one of the backups kept when a save couldn't be read, is
+/// accepted too.
+pub fn parse(json: &str) -> Result<Imported, Vec<String>> {
+    let value: Value = serde_json::from_str(json).map_err(|err| vec![format!("Not a JSON file: {}", err)])?;
+    let Value::Object(mut file) = value else {
+        return Err(vec![String::from("Not a Graphite profile: expected a JSON object")]);
+    };
+    let (fields, exported_at) = match file.remove("format") {
+        Some(Value::String(format)) if format == FORMAT => {
+            let exported_at = file.get("exported_at").and_then(Value::as_f64);
+            (file.remove("state").ok_or_else(|| vec![String::from("state: missing")])?, exported_at)
+        }
+        Some(format) => return Err(vec![format!("Not a Graphite profile: the format is {}", format)]),
+        None if file.contains_key("tabs") => (Value::Object(file), None),
+        None => return Err(vec![String::from("Not a Graphite profile: there is no format marker")]),
+    };
+
+    let mut fields = schema::migrate(fields).map_err(|err| vec![format!("state: {}", err)])?;
+    for field in LOCAL_FIELDS {
+        fields.remove(field);
+    }
+    fields.insert(String::from("downloads"), Value::Array(Vec::new()));
+
+    let state = match serde_json::from_value(Value::Object(fields.clone())) {
+        Ok(state) => state,
+        Err(err) => return Err(field_errors(&fields, err.to_string())),
+    };
+    let state = validate(state)?;
+    Ok(Imported { state, exported_at })
+}
+
+/// Finds the fields that don't read by trying each one on its own in an
+/// otherwise default state
+fn field_errors(fields: &Map<String, Value>, overall: String) -> Vec<String> {
+    let Ok(defaults) = schema::encode(&BrowserState::default()) else {
+        return vec![overall];
+    };
+    let mut errors: Vec<String> = fields
+        .iter()
+        .filter_map(|(name, value)| {
+            let mut probe = defaults.clone();
+            probe.insert(name.clone(), value.clone());
+            let err = serde_json::from_value::<BrowserState>(Value::Object(probe)).err()?;
+            Some(format!("{}: {}", name, err))
+        })
+        .collect();
+    // A required field that is missing altogether only shows up as a whole
+    if errors.is_empty() {
+        errors.push(overall);
+    }
+    errors
+}
+
+/// Checks what the types alone don't, and repairs id counters that fell
+/// behind the ids in use
+fn validate(mut state: BrowserState) -> Result<BrowserState, Vec<String>> {
+    let mut errors = Vec::new();
+
+    let mut ids = HashSet::new();
+    for (i, tab) in state.tabs.iter().enumerate() {
+        if !ids.insert(tab.id) {
+            errors.push(format!("tabs[{}].id: {} is used by another tab", i, tab.id));
+        }
+        if tab.history_index >= tab.history.len().max(1) {
+            errors.push(format!("tabs[{}].history_index: {} is past the end of its history", i, tab.history_index));
+        }
+    }
+    if !state.tabs.is_empty() && !ids.contains(&state.active_tab_id) {
+        errors.push(format!("active_tab_id: no tab has id {}", state.active_tab_id));
+    }
+
+    let mut checked: Vec<SearchEngine> = search_engines::built_in();
+    let mut engine_ids = HashSet::new();
+    for (i, engine) in state.custom_search_engines.iter().enumerate() {
+        if engine.id.is_empty() || !engine_ids.insert(engine.id.to_lowercase()) {
+            errors.push(format!("custom_search_engines[{}].id: \"{}\" is missing or used twice", i, engine.id));
+        }
+        match engine.validated(&checked) {
+            Ok(engine) => checked.push(engine),
+            Err(err) => errors.push(format!("custom_search_engines[{}]: {}", i, err)),
+        }
+    }
+    if search_engines::find(&search_engines::all(&state.custom_search_engines), &state.search_engine).is_none() {
+        errors.push(format!("search_engine: there is no engine with id \"{}\"", state.search_engine));
+    }
+
+    if !errors.is_empty() {
+        return Err(errors);
+    }
+    let next_tab_id = state.tabs.iter().map(|t| t.id + 1).max().unwrap_or(0);
+    state.next_tab_id = state.next_tab_id.max(next_tab_id);
+    state.bookmarks.next_id = state.bookmarks.next_id.max(state.bookmarks.max_id() + 1);
+    Ok(state)
+}
+
+/// Adds an imported profile to `state`, leaving its settings alone. Tabs are
+/// returned rather than added, since opening them is up to the caller.
+pub fn merge(state: &mut BrowserState, imported: BrowserState) -> (Vec<Tab>, String) {
+    let bookmarks = state.bookmarks.merge(imported.bookmarks);
+    let history = state.history.merge(imported.history);
+
+    let mut engines = 0;
+    for engine in imported.custom_search_engines {
+        if state.custom_search_engines.iter().any(|e| e.search_url == engine.search_url) {
+            continue;
+        }
+        let others = search_engines::all(&state.custom_search_engines);
+        let mut engine = SearchEngine { id: search_engines::new_id(&state.custom_search_engines), ..engine };
+        // A keyword taken here is dropped rather than the engine
+        if others.iter().any(|e| !engine.keyword.is_empty() && e.keyword.eq_ignore_ascii_case(&engine.keyword)) {
+            engine.keyword.clear();
+        }
+        state.custom_search_engines.push(engine);
+        engines += 1;
+    }
+
+    // Groups and openers name ids from the other profile, which mean nothing here
+    let tabs: Vec<Tab> = imported.tabs
+        .into_iter()
+        .filter(|t| !t.is_blank())
+        .map(|t| Tab { group: None, opener: None, ..t })
+        .collect();
+    let summary = format!(
+        "Added {}, {}, {} and {}",
+        count(tabs.len(), "tab", "tabs"),
+        count(bookmarks, "bookmark", "bookmarks"),
+        count(history, "history entry", "history entries"),
+        count(engines, "search engine", "search engines"),
+    );
+    (tabs, summary)
+}
+
+/// The imported profile with this browser's downloads carried over
+pub fn replace(state: &BrowserState, mut imported: BrowserState) -> BrowserState {
+    imported.downloads = state.downloads.clone();
+    imported.next_download_id = state.next_download_id;
+    imported
+}
+
+fn count(n: usize, one: &str, many: &str) -> String {
+    format!("{} {}", n, if n == 1 { one } else { many })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn tab(id: u32, url: &str) -> Tab {
+        Tab { id, url: url.to_string(), history: vec![url.to_string()], ..Tab::default() }
+    }
+
+    fn engine(id: &str, search_url: &str, keyword: &str) -> SearchEngine {
+        SearchEngine { id: id.to_string(), name: id.to_string(), search_url: search_url.to_string(), keyword: keyword.to_string(), ..SearchEngine::default() }
+    }
+
+    /// An exported profile with the state's fields edited
+    fn file(edit: impl FnOnce(&mut Map<String, Value>)) -> String {
+        let state = BrowserState { tabs: vec![tab(0, "https://example.com/")], ..BrowserState::default() };
+        let mut file: Value = serde_json::from_str(&export(&state, 1_000.0).unwrap()).unwrap();
+        edit(file["state"].as_object_mut().unwrap());
+        file.to_string()
+    }
+
+    #[test]
+    fn exports_read_back() {
+        let state = BrowserState {
+            tabs: vec![tab(0, "https://example.com/"), tab(4, "https://rust-lang.org/")],
+            active_tab_id: 4,
+            next_tab_id: 5,
+            custom_search_engines: vec![engine("custom-1", "https://crates.io/search?q=%s", "cr")],
+            ..BrowserState::default()
+        };
+        let imported = parse(&export(&state, 1_000.0).unwrap()).unwrap();
+        assert_eq!(imported, Imported { state, exported_at: Some(1_000.0) });
+        assert_eq!(imported.summary(), "2 tabs, 0 bookmarks, 0 history entries and 1 search engine");
+    }
+
+    #[test]
+    fn bad_fields_are_reported_by_name() {
+        let errors = parse(&file(|state| {
+            state.insert(String::from("max_live_frames"), json!("four"));
+            state.insert(String::from("theme"), json!(7));
+        }))
+        .unwrap_err();
+        assert_eq!(errors.len(), 2, "{errors:?}");
+        assert!(errors.iter().any(|e| e.starts_with("max_live_frames: ")), "{errors:?}");
+        assert!(errors.iter().any(|e| e.starts_with("theme: ")), "{errors:?}");
+    }
+
+    #[test]
+    fn tabs_are_checked_against_each_other() {
+        let errors = parse(&file(|state| {
+            let past_the_end = Tab { history_index: 3, ..tab(2, "https://b.test/") };
+            state.insert(String::from("tabs"), json!([tab(2, "https://a.test/"), past_the_end]));
+            state.insert(String::from("active_tab_id"), json!(9));
+        }))
+        .unwrap_err();
+        assert_eq!(
+            errors,
+            [
+                "tabs[1].id: 2 is used by another tab",
+                "tabs[1].history_index: 3 is past the end of its history",
+                "active_tab_id: no tab has id 9",
+            ]
+        );
+    }
+
+    #[test]
+    fn engines_are_checked() {
+        let errors = parse(&file(|state| {
+            state.insert(String::from("search_engine"), json!("altavista"));
+        }))
+        .unwrap_err();
+        assert_eq!(errors, ["search_engine: there is no engine with id \"altavista\""]);
+
+        let errors = parse(&file(|state| {
+            let engines = [engine("custom-1", "https://a.test/?q=%s", "a"), engine("custom-1", "https://b.test/", "google.com")];
+            state.insert(String::from("custom_search_engines"), json!(engines));
+        }))
+        .unwrap_err();
+        assert_eq!(errors.len(), 2, "{errors:?}");
+        assert!(errors[0].starts_with("custom_search_engines[1].id: "));
+        assert!(errors[1].starts_with("custom_search_engines[1]: Search URL: "));
+    }
+
+    #[test]
+    fn counters_behind_the_ids_in_use_are_repaired() {
+        let imported = parse(&file(|state| {
+            state.insert(String::from("tabs"), json!([tab(7, "https://example.com/")]));
+            state.insert(String::from("active_tab_id"), json!(7));
+            state.insert(String::from("next_tab_id"), json!(1));
+        }))
+        .unwrap();
+        assert_eq!(imported.state.next_tab_id, 8);
+    }
+
+    #[test]
+    fn bare_states_are_accepted_and_other_files_refused() {
+        let bare = r#"{
+            "tabs": [{"id": 1, "title": "Example", "url": "https://example.com/", "favicon": null, "is_loading": false}],
+            "active_tab_id": 1,
+            "next_tab_id": 2,
+            "search_engine": "DuckDuckGo",
+            "proxy_server": "",
+            "downloads": [{"id": 0, "filename": "google.png", "completed": true}]
+        }"#;
+        let imported = parse(bare).unwrap();
+        assert_eq!(imported.exported_at, None);
+        assert_eq!(imported.state.tabs[0].url, "https://example.com/");
+        assert_eq!(imported.state.search_engine, "duckduckgo");
+        // Downloads stay with the browser that made them
+        assert!(imported.state.downloads.is_empty());
+
+        assert_eq!(parse(r#"{"bookmarks": []}"#).unwrap_err(), ["Not a Graphite profile: there is no format marker"]);
+        assert_eq!(parse(r#"{"format": "other", "state": {}}"#).unwrap_err(), ["Not a Graphite profile: the format is \"other\""]);
+        assert_eq!(parse(r#"{"format": "graphite-profile"}"#).unwrap_err(), ["state: missing"]);
+        assert_eq!(parse("[]").unwrap_err(), ["Not a Graphite profile: expected a JSON object"]);
+        assert!(parse("{").unwrap_err()[0].starts_with("Not a JSON file: "));
+    }
+
+    #[test]
+    fn merging_skips_known_engines_and_taken_keywords() {
+        let mut state = BrowserState {
+            custom_search_engines: vec![engine("custom-1", "https://crates.io/search?q=%s", "cr")],
+            ..BrowserState::default()
+        };
+        let imported = BrowserState {
+            tabs: vec![Tab::default(), Tab { group: Some(3), opener: Some(0), ..tab(5, "https://example.com/") }],
+            custom_search_engines: vec![
+                engine("custom-1", "https://crates.io/search?q=%s", "crates"),
+                engine("custom-1", "https://docs.rs/releases/search?query=%s", "CR"),
+                engine("custom-2", "https://github.com/search?q=%s", "gh"),
+            ],
+            ..BrowserState::default()
+        };
+        let (tabs, summary) = merge(&mut state, imported);
+        assert_eq!(summary, "Added 1 tab, 0 bookmarks, 0 history entries and 2 search engines");
+        assert_eq!(tabs, [tab(5, "https://example.com/")]);
+        let engines: Vec<(&str, &str)> = state.custom_search_engines.iter().map(|e| (e.id.as_str(), e.keyword.as_str())).collect();
+        assert_eq!(engines, [("custom-1", "cr"), ("custom-2", ""), ("custom-3", "gh")]);
+    }
+}
@@ -0,0 +1,140 @@
+//! Named profiles, each with its own saved state.
+//!
+//! The list of profiles, and which one new pages open, is kept in
+//! LocalStorage outside every profile. Everything else a profile saves lives
+//! under names derived from its id: its own IndexedDB database, LocalStorage
+//! prefix and running marker. The default profile keeps the names used
+//! before there were profiles, so nothing saved then has to move.
+//!
+//! A page sticks with the profile it started in. Switching saves the state
+//! and reloads the page into the other profile.
+
+use std::cell::RefCell;
+
+use gloo::storage::{LocalStorage, Storage};
+use serde::{Deserialize, Serialize};
+
+pub const DEFAULT_PROFILE_ID: &str = "default";
+const REGISTRY_KEY: &str = "graphite_profiles";
+/// Badge colours handed out to new profiles in turn
+const COLORS: [&str; 6] = ["#1a73e8", "#d93025", "#188038", "#e37400", "#9334e6", "#007b83"];
+
+thread_local! {
+    static CURRENT: RefCell<Option<Profile>> = const { RefCell::new(None) };
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Profile {
+    pub id: String,
+    pub name: String,
+    pub color: String,
+}
+
+impl Default for Profile {
+    fn default() -> Self {
+        Self { id: String::from(DEFAULT_PROFILE_ID), name: String::from("Default"), color: String::from(COLORS[0]) }
+    }
+}
+
+impl Profile {
+    pub fn is_default(&self) -> bool {
+        self.id == DEFAULT_PROFILE_ID
+    }
+
+    /// The profile's own version of a storage name: `base` itself for the
+    /// default profile, `base@id` for the others
+    pub fn namespaced(&self, base: &str) -> String {
+        if self.is_default() {
+            base.to_string()
+        } else {
+            format!("{}@{}", base, self.id)
+        }
+    }
+
+    /// First letter of the name, for the toolbar badge
+    pub fn initial(&self) -> String {
+        self.name.chars().next().map(|c| c.to_uppercase().collect()).unwrap_or_default()
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Registry {
+    pub profiles: Vec<Profile>,
+    /// The profile pages open in
+    pub active: String,
+}
+
+impl Default for Registry {
+    fn default() -> Self {
+        Self { profiles: vec![Profile::default()], active: String::from(DEFAULT_PROFILE_ID) }
+    }
+}
+
+impl Registry {
+    fn load() -> Self {
+        let mut registry: Registry = LocalStorage::get(REGISTRY_KEY).unwrap_or_default();
+        if registry.profiles.is_empty() {
+            registry = Registry::default();
+        }
+        if registry.find(&registry.active).is_none() {
+            registry.active = registry.profiles[0].id.clone();
+        }
+        registry
+    }
+
+    pub fn save(&self) -> Result<(), String> {
+        LocalStorage::set(REGISTRY_KEY, self).map_err(|err| err.to_string())
+    }
+
+    pub fn find(&self, id: &str) -> Option<&Profile> {
+        self.profiles.iter().find(|p| p.id == id)
+    }
+
+    /// Adds a profile with a name no other profile has
+    pub fn add(&mut self, name: &str) -> Result<Profile, String> {
+        let name = name.trim();
+        if name.is_empty() {
+            return Err(String::from("The profile needs a name."));
+        }
+        if self.profiles.iter().any(|p| p.name.eq_ignore_ascii_case(name)) {
+            return Err(format!("There is already a profile called {}.", name));
+        }
+        let next = self
+            .profiles
+            .iter()
+            .filter_map(|p| p.id.strip_prefix("profile-")?.parse::<u32>().ok())
+            .max()
+            .map_or(1, |n| n + 1);
+        let profile = Profile {
+            id: format!("profile-{}", next),
+            name: name.to_string(),
+            color: String::from(COLORS[self.profiles.len() % COLORS.len()]),
+        };
+        self.profiles.push(profile.clone());
+        Ok(profile)
+    }
+
+    pub fn remove(&mut self, id: &str) -> Option<Profile> {
+        let index = self.profiles.iter().position(|p| p.id == id)?;
+        Some(self.profiles.remove(index))
+    }
+}
+
+/// Reads the profile list and settles which profile this page uses. Called
+/// once, before anything is loaded from storage.
+pub fn start() -> Registry {
+    let registry = Registry::load();
+    let profile = registry.find(&registry.active).cloned().unwrap_or_default();
+    CURRENT.with(|current| *current.borrow_mut() = Some(profile));
+    registry
+}
+
+/// The profile this page uses
+pub fn current() -> Profile {
+    CURRENT.with(|current| current.borrow().clone()).unwrap_or_default()
+}
+
+/// This page's profile's version of a storage name
+pub fn namespaced(base: &str) -> String {
+    current().namespaced(base)
+}
@@ -0,0 +1,790 @@
+//! WebSocket proxy transport.
+//!
+//! When the WebSocket proxy backend is configured, pages are not loaded into
+//! the tab's iframe directly. Instead they are fetched over one shared
+//! WebSocket connection and served to the iframe as a Blob URL.
+//!
+//! # Framing protocol
+//!
+//! Control frames are WebSocket text frames holding a JSON object with a
+//! `type` tag. Body data travels in binary frames whose first four bytes are
+//! the big-endian request id, followed by raw body bytes.
+//!
+//! Client to server:
+//!
+//! ```text
+//! {"type":"request","id":1,"method":"GET","url":"https://example.com/","headers":[["accept","text/html"]]}
+//! <binary: id + request body chunk>   (zero or more)
+//! {"type":"end","id":1}
+//! ```
+//!
+//! Server to client:
+//!
+//! ```text
+//! {"type":"response","id":1,"status":200,"status_text":"OK","headers":[["content-type","text/html"]],"url":"https://example.com/"}
+//! <binary: id + response body chunk>  (zero or more)
+//! {"type":"end","id":1}
+//! ```
+//!
+//! The server follows redirects itself and may report the final location in
+//! the optional `url` field. A `{"type":"error","id":1,"message":"..."}` frame
+//! fails the request at any point. Request ids are only unique per connection.
+//!
+//! A request may name a cookie `jar`, which private tabs use to keep their
+//! cookies apart from everyone else's. Servers that keep a single jar per
+//! connection can ignore the field.
+//!
+//! # Page bridge
+//!
+//! Blob URLs take the browser's own origin, so proxied pages are framed
+//! without `allow-same-origin` and run in an opaque origin instead, where
+//! they can't touch the browser's storage or DOM. The browser can't read
+//! them either, so HTML pages get a small script that posts what the browser
+//! needs to know to the parent window as [`BridgeMessage`] JSON: the title
+//! and icons once the page has loaded, key presses that may be shortcuts,
+//! and links to open in a new tab. The browser answers a muted tab's script
+//! with a [`mute_message`], and the script mutes the page's media.
+//!
+//! The script also stops the page navigating its frame itself, which would
+//! leave the proxy and leave the tab showing a URL it's no longer at. Links
+//! followed in the same tab, submitted forms and, where the Navigation API
+//! exists, `location` assignments are posted to the browser instead, which
+//! fetches the next page through the proxy. Forms are sent URL-encoded, so
+//! file uploads don't survive the trip.
+//!
+//! # Limits
+//!
+//! Only the document itself is proxied. Its `<base>` points at the original
+//! address, so scripts, stylesheets, images, frames and requests the page
+//! makes go straight to the site, outside the proxy, and fail where the proxy
+//! is needed to reach it.
+
+use std::cell::{Cell, RefCell};
+use std::collections::HashMap;
+use std::rc::Rc;
+
+use futures::channel::{mpsc, oneshot};
+use futures::{SinkExt, StreamExt};
+use gloo_net::websocket::futures::WebSocket;
+use gloo_net::websocket::Message;
+use serde::{Deserialize, Serialize};
+use wasm_bindgen_futures::spawn_local;
+use web_sys::{Blob, BlobPropertyBag, Url};
+
+use crate::idb::js_error;
+
+#[derive(Debug, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "lowercase")]
+enum Frame {
+    Request {
+        id: u32,
+        method: String,
+        url: String,
+        headers: Vec<(String, String)>,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        jar: Option<String>,
+    },
+    Response {
+        id: u32,
+        status: u16,
+        status_text: String,
+        headers: Vec<(String, String)>,
+        #[serde(default)]
+        url: Option<String>,
+    },
+    End {
+        id: u32,
+    },
+    Error {
+        id: u32,
+        message: String,
+    },
+}
+
+/// A complete HTTP response received through the proxy
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct ProxyResponse {
+    pub status: u16,
+    pub status_text: String,
+    pub headers: Vec<(String, String)>,
+    /// Final URL after redirects, when the server reported one
+    pub url: Option<String>,
+    pub body: Vec<u8>,
+}
+
+impl ProxyResponse {
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.headers
+            .iter()
+            .find(|(k, _)| k.eq_ignore_ascii_case(name))
+            .map(|(_, v)| v.as_str())
+    }
+
+    pub fn content_type(&self) -> &str {
+        self.header("content-type").unwrap_or("application/octet-stream")
+    }
+
+    /// Wraps the response in a Blob URL the iframe can load. HTML documents get
+    /// a `<base>` so relative links resolve against the original page, and
+    /// the bridge script. Only the document goes through the proxy: whatever
+    /// the `<base>` resolves is loaded directly.
+    pub fn into_object_url(self, page_url: &str) -> Result<String, String> {
+        let content_type = self.content_type().to_string();
+        let base = self.url.as_deref().unwrap_or(page_url);
+        if content_type.starts_with("text/html") {
+            let html = String::from_utf8_lossy(&self.body);
+            create_object_url(inject_head(&html, base).as_bytes(), &content_type)
+        } else {
+            create_object_url(&self.body, &content_type)
+        }
+    }
+}
+
+/// Builds a Blob URL for a small HTML page explaining why a load failed
+pub fn error_page_url(url: &str, message: &str) -> Result<String, String> {
+    let html = format!(
+        "<!DOCTYPE html><html><head><title>Can't load page</title></head>\
+         <body style=\"font-family: Arial, sans-serif; padding: 40px; color: #444;\">\
+         <h2>Can't load page</h2><p>{}</p><p style=\"color: #999;\">{}</p></body></html>",
+        escape_html(url),
+        escape_html(message),
+    );
+    create_object_url(html.as_bytes(), "text/html")
+}
+
+pub fn revoke_object_url(url: &str) {
+    let _ = Url::revoke_object_url(url);
+}
+
+fn create_object_url(bytes: &[u8], content_type: &str) -> Result<String, String> {
+    let parts = js_sys::Array::of1(&js_sys::Uint8Array::from(bytes));
+    let options = BlobPropertyBag::new();
+    options.set_type(content_type);
+    let blob = Blob::new_with_u8_array_sequence_and_options(&parts, &options).map_err(js_error)?;
+    Url::create_object_url_with_blob(&blob).map_err(js_error)
+}
+
+/// Posts [`BridgeMessage`]s to the browser. Only keys that can be shortcuts
+/// are sent, so what is typed into the page stays there.
+const BRIDGE_SCRIPT: &str = r##"(function () {
+  function post(message) { parent.postMessage(JSON.stringify(message), "*"); }
+  addEventListener("load", function () {
+    var icon = document.querySelector("link[rel~='icon']");
+    var search = document.querySelector("link[rel~='search'][type='DESCRIPTION_TYPE']");
+    post({
+      kind: "loaded",
+      title: document.title.trim() || null,
+      favicon: (icon && icon.href) || null,
+      search_description: (search && (search.getAttribute("href") || "").trim()) || null
+    });
+  });
+  // Navigating this frame itself would leave the proxy, so the browser
+  // fetches the next page instead. `body` holds a POST form's fields.
+  function navigate(url, body) {
+    post({ kind: "navigate", url: url, body: body });
+  }
+  function isWeb(url) { return /^https?:/i.test(url); }
+  // The `<base>` is the page's real address; the document's own is a blob:
+  function withoutHash(url) { return url.split("#")[0]; }
+  function inThisFrame(target) { return !target || /^_(self|top|parent)$/i.test(target); }
+  // Links meant for a new tab open in one of the browser's, under this one,
+  // rather than escaping into a real window
+  function followLink(e) {
+    if (e.defaultPrevented || e.button > 1) return;
+    var anchor = e.target && e.target.closest && e.target.closest("a[href]");
+    if (!anchor || anchor.hasAttribute("download") || !isWeb(anchor.href)) return;
+    var background = e.button === 1 || e.ctrlKey || e.metaKey;
+    var target = anchor.getAttribute("target") || "";
+    if (background || target.toLowerCase() === "_blank") {
+      e.preventDefault();
+      post({ kind: "open", url: anchor.href, background: background });
+    } else if (e.type === "click" && !e.shiftKey && !e.altKey && inThisFrame(target)) {
+      e.preventDefault();
+      var hash = anchor.href.indexOf("#");
+      if (hash >= 0 && withoutHash(anchor.href) === withoutHash(document.baseURI)) {
+        location.hash = anchor.href.slice(hash);
+      } else {
+        navigate(anchor.href, null);
+      }
+    }
+  }
+  addEventListener("click", followLink);
+  addEventListener("auxclick", followLink);
+  addEventListener("submit", function (e) {
+    var form = e.target, submitter = e.submitter;
+    var target = (submitter && submitter.getAttribute("formtarget")) || form.getAttribute("target") || "";
+    var method = ((submitter && submitter.getAttribute("formmethod")) || form.getAttribute("method") || "get").toLowerCase();
+    var action = new URL((submitter && submitter.getAttribute("formaction")) || form.getAttribute("action") || "", document.baseURI);
+    if (e.defaultPrevented || method === "dialog" || !isWeb(action.href) || !(inThisFrame(target) || target.toLowerCase() === "_blank")) return;
+    e.preventDefault();
+    var fields = new URLSearchParams(new FormData(form, submitter)).toString();
+    if (method === "post") {
+      navigate(action.href, fields);
+    } else {
+      action.search = fields;
+      navigate(action.href, null);
+    }
+  });
+  // Whatever else navigates, like assigning `location`, where the browser
+  // lets a page cancel it
+  if (window.navigation) {
+    navigation.addEventListener("navigate", function (e) {
+      var url = e.destination.url;
+      if (!e.cancelable || e.hashChange || e.downloadRequest !== null || !isWeb(url)) return;
+      if (e.navigationType === "reload" || e.navigationType === "traverse") return;
+      e.preventDefault();
+      navigate(url, e.formData ? new URLSearchParams(e.formData).toString() : null);
+    });
+  }
+  // A muted tab's media stays muted, including media that starts playing
+  // later; unmuting only unmutes what was muted here
+  var muted = false;
+  function mute(media) {
+    if (muted && !media.muted) {
+      media.muted = true;
+      media.setAttribute("MUTED_ATTRIBUTE", "");
+    }
+  }
+  addEventListener("message", function (e) {
+    if (e.source !== parent || typeof e.data !== "string") return;
+    var message;
+    try { message = JSON.parse(e.data); } catch (_) { return; }
+    if (!message || message.kind !== "graphite-mute") return;
+    muted = !!message.muted;
+    if (muted) {
+      document.querySelectorAll("audio, video").forEach(mute);
+    } else {
+      document.querySelectorAll("[MUTED_ATTRIBUTE]").forEach(function (media) {
+        media.muted = false;
+        media.removeAttribute("MUTED_ATTRIBUTE");
+      });
+    }
+  });
+  addEventListener("play", function (e) { mute(e.target); }, true);
+  addEventListener("keydown", function (e) {
+    if (!(e.ctrlKey || e.metaKey || e.altKey || e.key === "Escape" || /^F\d+$/.test(e.key))) return;
+    var target = e.target;
+    post({
+      kind: "key",
+      key: e.key,
+      code: e.code,
+      ctrl: e.ctrlKey || e.metaKey,
+      alt: e.altKey,
+      shift: e.shiftKey,
+      typing: !!(target && (target.isContentEditable || /^(input|textarea|select)$/i.test(target.tagName || "")))
+    });
+  }, true);
+  post({ kind: "ready" });
+})();"##;
+
+/// Marks the media elements the browser muted, so unmuting leaves the ones
+/// the page muted itself alone
+pub const MUTED_ATTRIBUTE: &str = "data-graphite-muted";
+
+/// What a proxied page's bridge script tells the browser
+#[derive(Clone, Debug, PartialEq, Deserialize)]
+#[serde(tag = "kind", rename_all = "lowercase")]
+pub enum BridgeMessage {
+    /// The script is running, before the page has loaded
+    Ready,
+    Loaded {
+        title: Option<String>,
+        favicon: Option<String>,
+        /// `href` of the page's OpenSearch description link, as written
+        search_description: Option<String>,
+    },
+    /// A link the page opens in a new tab: middle- or Ctrl-clicked, in the
+    /// background, or targeted at `_blank`, in front
+    Open {
+        url: String,
+        background: bool,
+    },
+    /// A page the tab should go to next, from a link, form or script
+    Navigate {
+        url: String,
+        /// URL-encoded fields of a POST form, `None` for a GET
+        #[serde(default)]
+        body: Option<String>,
+    },
+    Key {
+        key: String,
+        code: String,
+        /// Control, or Command on a Mac
+        ctrl: bool,
+        alt: bool,
+        shift: bool,
+        /// The key went to a text field
+        typing: bool,
+    },
+}
+
+impl BridgeMessage {
+    /// Reads a message posted to the window, which may come from anywhere
+    pub fn parse(data: &str) -> Option<Self> {
+        serde_json::from_str(data).ok()
+    }
+}
+
+/// How a form's fields are sent with a [`BridgeMessage::Navigate`] body
+pub const FORM_CONTENT_TYPE: &str = "application/x-www-form-urlencoded";
+
+/// What the browser posts to a page's bridge script to mute or unmute it
+pub fn mute_message(muted: bool) -> String {
+    serde_json::json!({ "kind": "graphite-mute", "muted": muted }).to_string()
+}
+
+/// Puts the `<base>` and the bridge script at the start of the page's head
+fn inject_head(html: &str, base: &str) -> String {
+    let script = BRIDGE_SCRIPT
+        .replace("DESCRIPTION_TYPE", crate::opensearch::DESCRIPTION_TYPE)
+        .replace("MUTED_ATTRIBUTE", MUTED_ATTRIBUTE);
+    let tag = format!("<base href=\"{}\"><script>{}</script>", escape_html(base), script);
+    let lower = html.to_ascii_lowercase();
+    // Not `<header>`, which would put the `<base>` in the body, where it's ignored
+    let head = lower
+        .match_indices("<head")
+        .map(|(start, _)| start + "<head".len())
+        .find(|&end| lower[end..].starts_with(|c: char| c == '>' || c == '/' || c.is_ascii_whitespace()));
+    match head.and_then(|start| lower[start..].find('>').map(|end| start + end + 1)) {
+        Some(pos) => format!("{}{}{}", &html[..pos], tag, &html[pos..]),
+        // Anything before the doctype would put the page in quirks mode
+        None => match lower.trim_start().starts_with("<!doctype").then(|| lower.find('>')).flatten() {
+            Some(end) => format!("{}{}{}", &html[..end + 1], tag, &html[end + 1..]),
+            None => format!("{}{}", tag, html),
+        },
+    }
+}
+
+fn escape_html(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+/// A binary frame: the request id, then a chunk of body
+fn encode_chunk(id: u32, bytes: &[u8]) -> Vec<u8> {
+    let mut chunk = id.to_be_bytes().to_vec();
+    chunk.extend_from_slice(bytes);
+    chunk
+}
+
+/// Splits a binary frame into its request id and body chunk
+fn decode_chunk(bytes: &[u8]) -> Option<(u32, &[u8])> {
+    let id = bytes.get(..4)?;
+    Some((u32::from_be_bytes([id[0], id[1], id[2], id[3]]), &bytes[4..]))
+}
+
+struct PendingRequest {
+    head: Option<ProxyResponse>,
+    body: Vec<u8>,
+    sender: oneshot::Sender<Result<ProxyResponse, String>>,
+}
+
+struct Connection {
+    url: String,
+    outgoing: mpsc::UnboundedSender<Message>,
+    pending: RefCell<HashMap<u32, PendingRequest>>,
+    next_id: Cell<u32>,
+    closed: Cell<bool>,
+}
+
+impl Connection {
+    fn handle_message(&self, message: Message) {
+        match message {
+            Message::Text(text) => match serde_json::from_str::<Frame>(&text) {
+                Ok(frame) => self.handle_frame(frame),
+                Err(err) => log::warn!("Ignoring malformed proxy frame: {}", err),
+            },
+            Message::Bytes(bytes) => {
+                let Some((id, chunk)) = decode_chunk(&bytes) else {
+                    return;
+                };
+                if let Some(request) = self.pending.borrow_mut().get_mut(&id) {
+                    request.body.extend_from_slice(chunk);
+                }
+            }
+        }
+    }
+
+    fn handle_frame(&self, frame: Frame) {
+        match frame {
+            Frame::Response { id, status, status_text, headers, url } => {
+                if let Some(request) = self.pending.borrow_mut().get_mut(&id) {
+                    request.head = Some(ProxyResponse { status, status_text, headers, url, body: Vec::new() });
+                }
+            }
+            Frame::End { id } => {
+                if let Some(request) = self.pending.borrow_mut().remove(&id) {
+                    let result = match request.head {
+                        Some(head) => Ok(ProxyResponse { body: request.body, ..head }),
+                        None => Err(String::from("Proxy ended the response without a status")),
+                    };
+                    let _ = request.sender.send(result);
+                }
+            }
+            Frame::Error { id, message } => {
+                if let Some(request) = self.pending.borrow_mut().remove(&id) {
+                    let _ = request.sender.send(Err(message));
+                }
+            }
+            Frame::Request { .. } => log::warn!("Ignoring request frame sent by proxy"),
+        }
+    }
+
+    fn fail_all(&self, reason: &str) {
+        self.closed.set(true);
+        for (_, request) in self.pending.borrow_mut().drain() {
+            let _ = request.sender.send(Err(reason.to_string()));
+        }
+    }
+}
+
+/// A shared connection to a WebSocket proxy server. Cloning is cheap and all
+/// clones multiplex requests over the same socket.
+#[derive(Clone)]
+pub struct ProxyClient {
+    connection: Rc<Connection>,
+    /// Cookie jar the server is asked to use for this client's requests
+    jar: Option<Rc<str>>,
+}
+
+impl ProxyClient {
+    pub fn connect(url: &str) -> Result<Self, String> {
+        let socket = WebSocket::open(url.trim()).map_err(|e| e.to_string())?;
+        let (mut sink, mut stream) = socket.split();
+        let (outgoing, mut queue) = mpsc::unbounded::<Message>();
+        let connection = Rc::new(Connection {
+            url: url.trim().to_string(),
+            outgoing,
+            pending: RefCell::new(HashMap::new()),
+            next_id: Cell::new(1),
+            closed: Cell::new(false),
+        });
+
+        // Writer: the sink waits for the socket to open before sending
+        let writer = Rc::clone(&connection);
+        spawn_local(async move {
+            while let Some(message) = queue.next().await {
+                if let Err(err) = sink.send(message).await {
+                    writer.fail_all(&format!("Proxy connection failed: {}", err));
+                    break;
+                }
+            }
+        });
+
+        // Reader: dispatches frames to their pending requests
+        let reader = Rc::clone(&connection);
+        spawn_local(async move {
+            while let Some(message) = stream.next().await {
+                match message {
+                    Ok(message) => reader.handle_message(message),
+                    Err(err) => {
+                        reader.fail_all(&format!("Proxy connection failed: {}", err));
+                        return;
+                    }
+                }
+            }
+            reader.fail_all("Proxy connection closed");
+        });
+
+        Ok(Self { connection, jar: None })
+    }
+
+    /// A client on the same connection whose requests use their own cookie jar
+    pub fn with_jar(&self, jar: &str) -> Self {
+        Self { connection: Rc::clone(&self.connection), jar: Some(Rc::from(jar)) }
+    }
+
+    pub fn url(&self) -> &str {
+        &self.connection.url
+    }
+
+    pub fn is_closed(&self) -> bool {
+        self.connection.closed.get()
+    }
+
+    /// Fetches `url` with a plain GET request
+    pub async fn fetch(&self, url: &str) -> Result<ProxyResponse, String> {
+        self.request("GET", url, Vec::new(), None).await
+    }
+
+    pub async fn request(
+        &self,
+        method: &str,
+        url: &str,
+        headers: Vec<(String, String)>,
+        body: Option<Vec<u8>>,
+    ) -> Result<ProxyResponse, String> {
+        let connection = &self.connection;
+        if connection.closed.get() {
+            return Err(String::from("Proxy connection closed"));
+        }
+
+        let id = connection.next_id.get();
+        connection.next_id.set(id.wrapping_add(1));
+        let (sender, receiver) = oneshot::channel();
+        connection.pending.borrow_mut().insert(id, PendingRequest { head: None, body: Vec::new(), sender });
+
+        let request = Frame::Request {
+            id,
+            method: method.to_string(),
+            url: url.to_string(),
+            headers,
+            jar: self.jar.as_deref().map(str::to_string),
+        };
+        let mut messages = vec![Message::Text(serde_json::to_string(&request).map_err(|e| e.to_string())?)];
+        if let Some(body) = body {
+            messages.push(Message::Bytes(encode_chunk(id, &body)));
+        }
+        messages.push(Message::Text(serde_json::to_string(&Frame::End { id }).map_err(|e| e.to_string())?));
+        for message in messages {
+            if connection.outgoing.unbounded_send(message).is_err() {
+                connection.pending.borrow_mut().remove(&id);
+                return Err(String::from("Proxy connection closed"));
+            }
+        }
+
+        receiver.await.unwrap_or_else(|_| Err(String::from("Proxy request was dropped")))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use futures::executor::block_on;
+    use futures::future::join;
+
+    /// A client whose connection leads to the returned queue instead of a socket
+    fn client() -> (ProxyClient, mpsc::UnboundedReceiver<Message>) {
+        let (outgoing, queue) = mpsc::unbounded();
+        let connection = Rc::new(Connection {
+            url: String::from("ws://localhost/proxy"),
+            outgoing,
+            pending: RefCell::new(HashMap::new()),
+            next_id: Cell::new(1),
+            closed: Cell::new(false),
+        });
+        (ProxyClient { connection, jar: None }, queue)
+    }
+
+    /// Stands in for a proxy server for `count` requests. It answers with the
+    /// request's method, jar and body echoed back in two body chunks, and
+    /// fails requests for `https://fail.test/`.
+    async fn stand_in(connection: Rc<Connection>, mut queue: mpsc::UnboundedReceiver<Message>, count: usize) {
+        let mut requests: HashMap<u32, (String, String, Option<String>, Vec<u8>)> = HashMap::new();
+        let mut answered = 0;
+        while answered < count {
+            let Some(message) = queue.next().await else {
+                return;
+            };
+            let text = match message {
+                Message::Bytes(bytes) => {
+                    let (id, chunk) = decode_chunk(&bytes).expect("binary frame without an id");
+                    requests.get_mut(&id).expect("body before request").3.extend_from_slice(chunk);
+                    continue;
+                }
+                Message::Text(text) => text,
+            };
+            match serde_json::from_str::<Frame>(&text).expect("malformed client frame") {
+                Frame::Request { id, method, url, jar, .. } => {
+                    requests.insert(id, (method, url, jar, Vec::new()));
+                }
+                Frame::End { id } => {
+                    let (method, url, jar, body) = requests.remove(&id).expect("end without request");
+                    let send = |frame: Frame| connection.handle_message(Message::Text(serde_json::to_string(&frame).unwrap()));
+                    if url == "https://fail.test/" {
+                        send(Frame::Error { id, message: String::from("Host not found") });
+                    } else {
+                        send(Frame::Response {
+                            id,
+                            status: 200,
+                            status_text: String::from("OK"),
+                            headers: vec![
+                                (String::from("Content-Type"), String::from("text/plain")),
+                                (String::from("x-method"), method),
+                                (String::from("x-jar"), jar.unwrap_or_default()),
+                            ],
+                            url: Some(format!("{}final", url)),
+                        });
+                        let mut echoed = b"echo:".to_vec();
+                        echoed.extend_from_slice(&body);
+                        let (first, second) = echoed.split_at(echoed.len() / 2);
+                        connection.handle_message(Message::Bytes(encode_chunk(id, first)));
+                        connection.handle_message(Message::Bytes(encode_chunk(id, second)));
+                        send(Frame::End { id });
+                    }
+                    answered += 1;
+                }
+                frame => panic!("unexpected client frame {:?}", frame),
+            }
+        }
+    }
+
+    #[test]
+    fn request_frame_matches_the_documented_json() {
+        let frame = Frame::Request {
+            id: 1,
+            method: String::from("GET"),
+            url: String::from("https://example.com/"),
+            headers: vec![(String::from("accept"), String::from("text/html"))],
+            jar: None,
+        };
+        assert_eq!(
+            serde_json::to_string(&frame).unwrap(),
+            r#"{"type":"request","id":1,"method":"GET","url":"https://example.com/","headers":[["accept","text/html"]]}"#
+        );
+        let with_jar = Frame::Request { id: 2, method: String::from("GET"), url: String::from("https://a.test/"), headers: vec![], jar: Some(String::from("private-1")) };
+        assert!(serde_json::to_string(&with_jar).unwrap().ends_with(r#""jar":"private-1"}"#));
+        assert_eq!(serde_json::to_string(&Frame::End { id: 1 }).unwrap(), r#"{"type":"end","id":1}"#);
+    }
+
+    #[test]
+    fn server_frames_decode() {
+        let response = r#"{"type":"response","id":1,"status":200,"status_text":"OK","headers":[["content-type","text/html"]],"url":"https://example.com/"}"#;
+        assert_eq!(
+            serde_json::from_str::<Frame>(response).unwrap(),
+            Frame::Response {
+                id: 1,
+                status: 200,
+                status_text: String::from("OK"),
+                headers: vec![(String::from("content-type"), String::from("text/html"))],
+                url: Some(String::from("https://example.com/")),
+            }
+        );
+        // The final URL is optional
+        let without_url = r#"{"type":"response","id":3,"status":404,"status_text":"Not Found","headers":[]}"#;
+        assert!(matches!(serde_json::from_str::<Frame>(without_url).unwrap(), Frame::Response { url: None, .. }));
+        assert_eq!(
+            serde_json::from_str::<Frame>(r#"{"type":"error","id":4,"message":"Timed out"}"#).unwrap(),
+            Frame::Error { id: 4, message: String::from("Timed out") }
+        );
+        assert!(serde_json::from_str::<Frame>(r#"{"type":"push","id":1}"#).is_err());
+    }
+
+    #[test]
+    fn binary_frames_carry_a_big_endian_id() {
+        let chunk = encode_chunk(0x0102_0304, b"body");
+        assert_eq!(chunk, [1, 2, 3, 4, b'b', b'o', b'd', b'y']);
+        assert_eq!(decode_chunk(&chunk), Some((0x0102_0304, &b"body"[..])));
+        assert_eq!(decode_chunk(&[0, 0, 0, 7]), Some((7, &[][..])));
+        assert_eq!(decode_chunk(&[0, 0, 7]), None);
+    }
+
+    #[test]
+    fn requests_round_trip_through_a_stand_in_server() {
+        let (client, queue) = client();
+        let server = stand_in(Rc::clone(&client.connection), queue, 1);
+        let (response, ()) = block_on(join(client.fetch("https://example.com/"), server));
+        let response = response.unwrap();
+        assert_eq!(response.status, 200);
+        assert_eq!(response.content_type(), "text/plain");
+        assert_eq!(response.header("X-METHOD"), Some("GET"));
+        assert_eq!(response.url.as_deref(), Some("https://example.com/final"));
+        assert_eq!(response.body, b"echo:");
+    }
+
+    #[test]
+    fn request_bodies_and_jars_reach_the_server() {
+        let (client, queue) = client();
+        let private = client.with_jar("private-1");
+        let server = stand_in(Rc::clone(&client.connection), queue, 1);
+        let request = private.request("POST", "https://example.com/form", vec![], Some(b"a=1&b=2".to_vec()));
+        let (response, ()) = block_on(join(request, server));
+        let response = response.unwrap();
+        assert_eq!(response.header("x-method"), Some("POST"));
+        assert_eq!(response.header("x-jar"), Some("private-1"));
+        assert_eq!(response.body, b"echo:a=1&b=2");
+    }
+
+    #[test]
+    fn concurrent_requests_are_kept_apart() {
+        let (client, queue) = client();
+        let server = stand_in(Rc::clone(&client.connection), queue, 3);
+        let requests = futures::future::join3(
+            client.request("PUT", "https://a.test/", vec![], Some(b"first".to_vec())),
+            client.fetch("https://fail.test/"),
+            client.request("POST", "https://b.test/", vec![], Some(b"second".to_vec())),
+        );
+        let ((first, failed, second), ()) = block_on(join(requests, server));
+        assert_eq!(first.unwrap().body, b"echo:first");
+        assert_eq!(failed, Err(String::from("Host not found")));
+        assert_eq!(second.unwrap().body, b"echo:second");
+    }
+
+    #[test]
+    fn malformed_and_unknown_frames_are_ignored() {
+        let (client, _queue) = client();
+        let connection = &client.connection;
+        let (sender, mut receiver) = oneshot::channel();
+        connection.pending.borrow_mut().insert(9, PendingRequest { head: None, body: Vec::new(), sender });
+        connection.handle_message(Message::Text(String::from("not json")));
+        connection.handle_message(Message::Bytes(vec![0, 0]));
+        connection.handle_message(Message::Bytes(encode_chunk(10, b"someone else's")));
+        connection.handle_message(Message::Text(String::from(r#"{"type":"end","id":9}"#)));
+        let result = receiver.try_recv().unwrap().unwrap();
+        assert_eq!(result, Err(String::from("Proxy ended the response without a status")));
+    }
+
+    #[test]
+    fn closing_fails_pending_and_later_requests() {
+        let (client, _queue) = client();
+        let (sender, mut receiver) = oneshot::channel();
+        client.connection.pending.borrow_mut().insert(1, PendingRequest { head: None, body: Vec::new(), sender });
+        client.connection.fail_all("Proxy connection closed");
+        assert_eq!(receiver.try_recv().unwrap(), Some(Err(String::from("Proxy connection closed"))));
+        assert!(client.is_closed());
+        assert_eq!(block_on(client.fetch("https://example.com/")), Err(String::from("Proxy connection closed")));
+    }
+
+    #[test]
+    fn html_heads_get_the_base_and_bridge() {
+        let html = inject_head("<html><HEAD lang=\"en\"><title>t</title></head></html>", "https://example.com/a?b=\"c\"");
+        assert!(html.starts_with("<html><HEAD lang=\"en\"><base href=\"https://example.com/a?b=&quot;c&quot;\"><script>"));
+        assert!(html.contains("application/opensearchdescription+xml"));
+        assert!(html.contains("[data-graphite-muted]") && !html.contains("MUTED_ATTRIBUTE"));
+        assert!(html.ends_with("</script><title>t</title></head></html>"));
+        // Without a head, the tags go first, after any doctype
+        assert!(inject_head("<p>hi</p>", "https://example.com/").ends_with("</script><p>hi</p>"));
+        let html = inject_head("<!DOCTYPE html><header>top</header>", "https://example.com/");
+        assert!(html.starts_with("<!DOCTYPE html><base href=\"https://example.com/\"><script>"));
+        assert!(html.ends_with("</script><header>top</header>"));
+        // A header before the real head doesn't count
+        let html = inject_head("<!-- <header> --><head\n><title>t</title></head>", "https://example.com/");
+        assert!(html.starts_with("<!-- <header> --><head\n><base href="));
+    }
+
+    #[test]
+    fn bridge_messages_parse() {
+        let loaded = r#"{"kind":"loaded","title":"Example","favicon":null,"search_description":"/search.xml"}"#;
+        assert_eq!(
+            BridgeMessage::parse(loaded),
+            Some(BridgeMessage::Loaded {
+                title: Some(String::from("Example")),
+                favicon: None,
+                search_description: Some(String::from("/search.xml")),
+            })
+        );
+        let key = r#"{"kind":"key","key":"t","code":"KeyT","ctrl":true,"alt":false,"shift":false,"typing":true}"#;
+        assert!(matches!(BridgeMessage::parse(key), Some(BridgeMessage::Key { ctrl: true, typing: true, .. })));
+        assert_eq!(
+            BridgeMessage::parse(r#"{"kind":"open","url":"https://example.com/next","background":true}"#),
+            Some(BridgeMessage::Open { url: String::from("https://example.com/next"), background: true })
+        );
+        assert_eq!(BridgeMessage::parse(r#"{"kind":"ready"}"#), Some(BridgeMessage::Ready));
+        assert_eq!(
+            BridgeMessage::parse(r#"{"kind":"navigate","url":"https://example.com/search","body":"q=a+b"}"#),
+            Some(BridgeMessage::Navigate { url: String::from("https://example.com/search"), body: Some(String::from("q=a+b")) })
+        );
+        assert_eq!(
+            BridgeMessage::parse(r#"{"kind":"navigate","url":"https://example.com/next","body":null}"#),
+            Some(BridgeMessage::Navigate { url: String::from("https://example.com/next"), body: None })
+        );
+        // Other windows post all sorts of things
+        assert_eq!(BridgeMessage::parse("hello"), None);
+        // Including the browser's own messages to the page
+        assert_eq!(BridgeMessage::parse(&mute_message(true)), None);
+        assert_eq!(BridgeMessage::parse(r#"{"kind":"resize","height":10}"#), None);
+    }
+}
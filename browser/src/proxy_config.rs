@@ -0,0 +1,462 @@
+//! Proxy backends and the URL codecs they use.
+//!
+//! Each backend knows how to turn a real URL into the address the iframe
+//! should load, and how to recover the real URL from a proxied address so the
+//! URL bar and tab title never show proxy internals.
+
+use base64::engine::general_purpose::STANDARD;
+use base64::Engine;
+use serde::{Deserialize, Deserializer, Serialize};
+
+/// How target URLs are embedded in a path-prefix proxy address
+#[derive(Clone, Copy, Debug, Default, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum UrlCodec {
+    /// Percent-encoded, like `encodeURIComponent`
+    Plain,
+    /// Every second character XOR 2, then percent-encoded (Ultraviolet's default)
+    #[default]
+    Xor,
+    /// Percent-encoded, then base64
+    Base64,
+}
+
+impl UrlCodec {
+    pub const ALL: [UrlCodec; 3] = [UrlCodec::Plain, UrlCodec::Xor, UrlCodec::Base64];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            UrlCodec::Plain => "Plain",
+            UrlCodec::Xor => "XOR",
+            UrlCodec::Base64 => "Base64",
+        }
+    }
+
+    pub fn encode(&self, url: &str) -> String {
+        match self {
+            UrlCodec::Plain => encode_component(url),
+            UrlCodec::Xor => encode_component(&xor(url)),
+            UrlCodec::Base64 => STANDARD.encode(encode_component(url)),
+        }
+    }
+
+    pub fn decode(&self, encoded: &str) -> Option<String> {
+        match self {
+            UrlCodec::Plain => Some(decode_component(encoded)),
+            UrlCodec::Xor => {
+                // Pages may append their own query string after the encoded target
+                let (target, search) = match encoded.split_once('?') {
+                    Some((target, search)) => (target, Some(search)),
+                    None => (encoded, None),
+                };
+                let mut url = xor(&decode_component(target));
+                if let Some(search) = search {
+                    url.push('?');
+                    url.push_str(search);
+                }
+                Some(url)
+            }
+            UrlCodec::Base64 => {
+                let bytes = STANDARD.decode(decode_component(encoded)).ok()?;
+                Some(decode_component(&String::from_utf8(bytes).ok()?))
+            }
+        }
+    }
+}
+
+/// The kinds of proxy backend, used to label the settings selector
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ProxyKind {
+    Direct,
+    WebSocket,
+    Query,
+    Prefix,
+    Cors,
+}
+
+impl ProxyKind {
+    pub const ALL: [ProxyKind; 5] = [
+        ProxyKind::Direct,
+        ProxyKind::WebSocket,
+        ProxyKind::Query,
+        ProxyKind::Prefix,
+        ProxyKind::Cors,
+    ];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            ProxyKind::Direct => "No proxy",
+            ProxyKind::WebSocket => "WebSocket",
+            ProxyKind::Query => "Query (?url=)",
+            ProxyKind::Prefix => "Path prefix",
+            ProxyKind::Cors => "CORS proxy",
+        }
+    }
+
+    pub fn placeholder(&self) -> &'static str {
+        match self {
+            ProxyKind::Direct => "",
+            ProxyKind::WebSocket => "Enter a wss:// or ws:// proxy",
+            ProxyKind::Query => "https://proxy.example.com/fetch",
+            ProxyKind::Prefix => "/service/",
+            ProxyKind::Cors => "https://cors.example.com/",
+        }
+    }
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Serialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum ProxyConfig {
+    /// Load pages straight into the iframe
+    #[default]
+    Direct,
+    /// Fetch pages over the WebSocket transport in `proxy.rs`
+    WebSocket { url: String },
+    /// `{endpoint}?{param}={percent-encoded url}`
+    Query { endpoint: String, param: String },
+    /// `{prefix}{codec-encoded url}`, as used by Ultraviolet and Scramjet
+    Prefix { prefix: String, codec: UrlCodec },
+    /// `{endpoint}{url}`, as used by plain HTTP CORS proxies
+    Cors { endpoint: String },
+}
+
+impl ProxyConfig {
+    /// Interprets the free-form proxy string saved by older versions
+    pub fn from_legacy(proxy: &str) -> Self {
+        let proxy = proxy.trim();
+        if proxy.is_empty() {
+            ProxyConfig::Direct
+        } else if proxy.starts_with("ws://") || proxy.starts_with("wss://") {
+            ProxyConfig::WebSocket { url: proxy.to_string() }
+        } else {
+            ProxyConfig::Query { endpoint: proxy.to_string(), param: String::from("url") }
+        }
+    }
+
+    pub fn kind(&self) -> ProxyKind {
+        match self {
+            ProxyConfig::Direct => ProxyKind::Direct,
+            ProxyConfig::WebSocket { .. } => ProxyKind::WebSocket,
+            ProxyConfig::Query { .. } => ProxyKind::Query,
+            ProxyConfig::Prefix { .. } => ProxyKind::Prefix,
+            ProxyConfig::Cors { .. } => ProxyKind::Cors,
+        }
+    }
+
+    /// The server address the user typed, whatever the backend
+    pub fn endpoint(&self) -> &str {
+        match self {
+            ProxyConfig::Direct => "",
+            ProxyConfig::WebSocket { url } => url,
+            ProxyConfig::Query { endpoint, .. } => endpoint,
+            ProxyConfig::Prefix { prefix, .. } => prefix,
+            ProxyConfig::Cors { endpoint } => endpoint,
+        }
+    }
+
+    /// Switches backend while keeping the typed address
+    pub fn with_kind(&self, kind: ProxyKind) -> Self {
+        let endpoint = self.endpoint().to_string();
+        match kind {
+            ProxyKind::Direct => ProxyConfig::Direct,
+            ProxyKind::WebSocket => ProxyConfig::WebSocket { url: endpoint },
+            ProxyKind::Query => ProxyConfig::Query { endpoint, param: String::from("url") },
+            ProxyKind::Prefix => ProxyConfig::Prefix { prefix: endpoint, codec: UrlCodec::default() },
+            ProxyKind::Cors => ProxyConfig::Cors { endpoint },
+        }
+    }
+
+    pub fn with_endpoint(&self, value: &str) -> Self {
+        let value = value.trim().to_string();
+        match self.clone() {
+            ProxyConfig::Direct => ProxyConfig::Direct,
+            ProxyConfig::WebSocket { .. } => ProxyConfig::WebSocket { url: value },
+            ProxyConfig::Query { param, .. } => ProxyConfig::Query { endpoint: value, param },
+            ProxyConfig::Prefix { codec, .. } => ProxyConfig::Prefix { prefix: value, codec },
+            ProxyConfig::Cors { .. } => ProxyConfig::Cors { endpoint: value },
+        }
+    }
+
+    pub fn with_codec(&self, codec: UrlCodec) -> Self {
+        match self {
+            ProxyConfig::Prefix { prefix, .. } => ProxyConfig::Prefix { prefix: prefix.clone(), codec },
+            other => other.clone(),
+        }
+    }
+
+    pub fn is_websocket(&self) -> bool {
+        matches!(self, ProxyConfig::WebSocket { url } if !url.is_empty())
+    }
+
+    /// Address the iframe should load for `url`
+    pub fn encode(&self, url: &str) -> String {
+        match self {
+            ProxyConfig::Query { endpoint, param } if !endpoint.is_empty() => {
+                let separator = if endpoint.contains('?') { '&' } else { '?' };
+                format!("{}{}{}={}", endpoint, separator, param, encode_component(url))
+            }
+            ProxyConfig::Prefix { prefix, codec } if !prefix.is_empty() => {
+                format!("{}{}", prefix, codec.encode(url))
+            }
+            ProxyConfig::Cors { endpoint } if !endpoint.is_empty() => format!("{}{}", endpoint, url),
+            _ => url.to_string(),
+        }
+    }
+
+    /// Recovers the real URL from an address produced by `encode`, or from a
+    /// location the proxied page navigated to on its own. Returns `None` when
+    /// the address doesn't belong to this proxy.
+    pub fn decode(&self, proxied: &str) -> Option<String> {
+        match self {
+            ProxyConfig::Query { endpoint, param } if !endpoint.is_empty() => {
+                let rest = strip_endpoint(proxied, endpoint)?;
+                // `/fetch` only starts `/fetcher?url=…`; an endpoint with its
+                // own query goes on with `&`
+                let boundary = ['?', '/', '&'];
+                if !(rest.is_empty() || rest.starts_with(boundary) || endpoint.ends_with(boundary)) {
+                    return None;
+                }
+                let query = rest.split_once('?').map(|(_, q)| q).unwrap_or(rest);
+                let query = query.strip_prefix('&').unwrap_or(query);
+                query
+                    .split('&')
+                    .find_map(|pair| pair.strip_prefix(param.as_str())?.strip_prefix('='))
+                    .map(decode_component)
+            }
+            ProxyConfig::Prefix { prefix, codec } if !prefix.is_empty() => {
+                codec.decode(strip_endpoint(proxied, prefix)?)
+            }
+            ProxyConfig::Cors { endpoint } if !endpoint.is_empty() => {
+                Some(strip_endpoint(proxied, endpoint)?.to_string())
+            }
+            _ => Some(proxied.to_string()),
+        }
+    }
+}
+
+impl<'de> Deserialize<'de> for ProxyConfig {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        // Older saved states stored the proxy as a single string
+        #[derive(Deserialize)]
+        #[serde(untagged)]
+        enum Stored {
+            Legacy(String),
+            Config(Tagged),
+        }
+
+        #[derive(Deserialize)]
+        #[serde(tag = "kind", rename_all = "snake_case")]
+        enum Tagged {
+            Direct,
+            WebSocket { url: String },
+            Query { endpoint: String, param: String },
+            Prefix { prefix: String, codec: UrlCodec },
+            Cors { endpoint: String },
+        }
+
+        Ok(match Stored::deserialize(deserializer)? {
+            Stored::Legacy(proxy) => ProxyConfig::from_legacy(&proxy),
+            Stored::Config(Tagged::Direct) => ProxyConfig::Direct,
+            Stored::Config(Tagged::WebSocket { url }) => ProxyConfig::WebSocket { url },
+            Stored::Config(Tagged::Query { endpoint, param }) => ProxyConfig::Query { endpoint, param },
+            Stored::Config(Tagged::Prefix { prefix, codec }) => ProxyConfig::Prefix { prefix, codec },
+            Stored::Config(Tagged::Cors { endpoint }) => ProxyConfig::Cors { endpoint },
+        })
+    }
+}
+
+/// Strips a proxy endpoint from the front of `address`. Endpoints given as a
+/// bare path (`/service/`) match the path of an absolute address.
+fn strip_endpoint<'a>(address: &'a str, endpoint: &str) -> Option<&'a str> {
+    if let Some(rest) = address.strip_prefix(endpoint) {
+        return Some(rest);
+    }
+    if endpoint.starts_with('/') {
+        let after_scheme = address.find("://")? + 3;
+        let path_start = after_scheme + address[after_scheme..].find('/')?;
+        return address[path_start..].strip_prefix(endpoint);
+    }
+    None
+}
+
+fn xor(text: &str) -> String {
+    text.chars()
+        .enumerate()
+        .map(|(i, c)| if i % 2 == 1 { char::from_u32(c as u32 ^ 2).unwrap_or(c) } else { c })
+        .collect()
+}
+
+/// Percent-encodes everything `encodeURIComponent` would
+pub fn encode_component(text: &str) -> String {
+    let mut encoded = String::with_capacity(text.len());
+    for byte in text.bytes() {
+        match byte {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9'
+            | b'-' | b'_' | b'.' | b'!' | b'~' | b'*' | b'\'' | b'(' | b')' => encoded.push(byte as char),
+            _ => encoded.push_str(&format!("%{:02X}", byte)),
+        }
+    }
+    encoded
+}
+
+/// Reverses `encode_component`, leaving malformed escapes untouched
+pub fn decode_component(text: &str) -> String {
+    let bytes = text.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        if bytes[i] == b'%' && i + 2 < bytes.len() {
+            let hex = std::str::from_utf8(&bytes[i + 1..i + 3]).ok();
+            if let Some(byte) = hex.and_then(|h| u8::from_str_radix(h, 16).ok()) {
+                decoded.push(byte);
+                i += 3;
+                continue;
+            }
+        }
+        decoded.push(bytes[i]);
+        i += 1;
+    }
+    String::from_utf8_lossy(&decoded).into_owned()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const URLS: [&str; 5] = [
+        "https://example.com/",
+        "https://example.com/search?q=a b&lang=en#top",
+        "http://localhost:8080/path/with%20escapes",
+        "https://例え.jp/ページ?q=ü",
+        "",
+    ];
+
+    fn prefix(codec: UrlCodec) -> ProxyConfig {
+        ProxyConfig::Prefix { prefix: String::from("/service/"), codec }
+    }
+
+    #[test]
+    fn codecs_round_trip() {
+        for codec in UrlCodec::ALL {
+            for url in URLS {
+                assert_eq!(codec.decode(&codec.encode(url)).as_deref(), Some(url), "{} {url:?}", codec.name());
+            }
+        }
+    }
+
+    #[test]
+    fn codecs_match_known_vectors() {
+        assert_eq!(UrlCodec::Plain.encode("https://example.com/"), "https%3A%2F%2Fexample.com%2F");
+        // Ultraviolet's address for the same page
+        assert_eq!(UrlCodec::Xor.encode("https://example.com/"), "hvtrs8%2F-ezaopne%2Ccmm-");
+        assert_eq!(UrlCodec::Xor.decode("hvtrs8%2F-ezaopne%2Ccmm-").as_deref(), Some("https://example.com/"));
+        assert_eq!(UrlCodec::Base64.encode("https://example.com/"), "aHR0cHMlM0ElMkYlMkZleGFtcGxlLmNvbSUyRg==");
+        assert_eq!(
+            UrlCodec::Base64.decode("aHR0cHMlM0ElMkYlMkZleGFtcGxlLmNvbSUyRg%3D%3D").as_deref(),
+            Some("https://example.com/")
+        );
+    }
+
+    #[test]
+    fn codecs_decode_what_pages_do_to_addresses() {
+        // A form submitted from the page appends its own query
+        assert_eq!(UrlCodec::Xor.decode("hvtrs8%2F-ezaopne%2Ccmm-?q=a").as_deref(), Some("https://example.com/?q=a"));
+        assert_eq!(UrlCodec::Base64.decode("not base64!"), None);
+        assert_eq!(UrlCodec::Base64.decode(&STANDARD.encode([0xff, 0xfe])), None);
+        assert_eq!(decode_component("100%"), "100%");
+        assert_eq!(decode_component("%zz%41"), "%zzA");
+    }
+
+    #[test]
+    fn configs_round_trip() {
+        let configs = [
+            ProxyConfig::Direct,
+            ProxyConfig::WebSocket { url: String::from("wss://proxy.example.com/") },
+            ProxyConfig::Query { endpoint: String::from("https://proxy.example.com/fetch"), param: String::from("url") },
+            ProxyConfig::Query { endpoint: String::from("https://proxy.example.com/fetch?key=1"), param: String::from("u") },
+            prefix(UrlCodec::Plain),
+            prefix(UrlCodec::Xor),
+            prefix(UrlCodec::Base64),
+            ProxyConfig::Cors { endpoint: String::from("https://cors.example.com/") },
+        ];
+        for config in configs {
+            for url in &URLS[..4] {
+                assert_eq!(config.decode(&config.encode(url)).as_deref(), Some(*url), "{config:?}");
+            }
+        }
+    }
+
+    #[test]
+    fn configs_build_addresses() {
+        let query = ProxyConfig::Query { endpoint: String::from("https://p.example/fetch"), param: String::from("url") };
+        assert_eq!(query.encode("https://example.com/"), "https://p.example/fetch?url=https%3A%2F%2Fexample.com%2F");
+        let query = query.with_endpoint("https://p.example/fetch?key=1");
+        assert_eq!(query.encode("https://example.com/"), "https://p.example/fetch?key=1&url=https%3A%2F%2Fexample.com%2F");
+        assert_eq!(prefix(UrlCodec::Xor).encode("https://example.com/"), "/service/hvtrs8%2F-ezaopne%2Ccmm-");
+        let cors = ProxyConfig::Cors { endpoint: String::from("https://cors.example.com/") };
+        assert_eq!(cors.encode("https://example.com/"), "https://cors.example.com/https://example.com/");
+        // Without an endpoint there is nothing to go through
+        assert_eq!(ProxyConfig::Cors { endpoint: String::new() }.encode("https://example.com/"), "https://example.com/");
+        assert_eq!(ProxyConfig::Direct.encode("https://example.com/"), "https://example.com/");
+    }
+
+    #[test]
+    fn configs_read_addresses_back() {
+        // The frame reports its location as an absolute address
+        let config = prefix(UrlCodec::Xor);
+        assert_eq!(
+            config.decode("https://mirror.example/service/hvtrs8%2F-ezaopne%2Ccmm-").as_deref(),
+            Some("https://example.com/")
+        );
+        assert_eq!(config.decode("https://mirror.example/other/hvtrs8%2F-ezaopne%2Ccmm-"), None);
+        let query = ProxyConfig::Query { endpoint: String::from("https://p.example/fetch"), param: String::from("url") };
+        assert_eq!(query.decode("https://p.example/fetch?lang=en&url=https%3A%2F%2Fexample.com%2F").as_deref(), Some("https://example.com/"));
+        assert_eq!(query.decode("https://p.example/fetch?lang=en"), None);
+        assert_eq!(query.decode("https://p.example/fetch/?url=https%3A%2F%2Fexample.com%2F").as_deref(), Some("https://example.com/"));
+        // Another path that merely starts with the endpoint
+        assert_eq!(query.decode("https://p.example/fetcher?url=https%3A%2F%2Fexample.com%2F"), None);
+        let query = query.with_endpoint("https://p.example/fetch?key=1");
+        assert_eq!(query.decode("https://p.example/fetch?key=1&url=https%3A%2F%2Fexample.com%2F").as_deref(), Some("https://example.com/"));
+        assert_eq!(query.decode("https://p.example/fetch?key=12&url=https%3A%2F%2Fexample.com%2F"), None);
+        assert_eq!(query.decode("https://elsewhere.example/fetch?url=x"), None);
+        assert_eq!(ProxyConfig::Direct.decode("https://example.com/").as_deref(), Some("https://example.com/"));
+    }
+
+    #[test]
+    fn switching_kinds_keeps_the_address() {
+        let config = ProxyConfig::from_legacy("https://p.example/fetch");
+        assert_eq!(
+            config.with_kind(ProxyKind::Prefix),
+            ProxyConfig::Prefix { prefix: String::from("https://p.example/fetch"), codec: UrlCodec::Xor }
+        );
+        assert_eq!(config.with_kind(ProxyKind::Prefix).with_codec(UrlCodec::Base64).kind(), ProxyKind::Prefix);
+        assert_eq!(config.with_kind(ProxyKind::Direct).with_kind(ProxyKind::Cors).endpoint(), "");
+        assert_eq!(config.with_codec(UrlCodec::Base64), config);
+    }
+
+    #[test]
+    fn legacy_strings_decode() {
+        assert_eq!(ProxyConfig::from_legacy("  "), ProxyConfig::Direct);
+        assert_eq!(ProxyConfig::from_legacy("wss://p.example/ "), ProxyConfig::WebSocket { url: String::from("wss://p.example/") });
+        assert_eq!(ProxyConfig::from_legacy("ws://localhost:8080"), ProxyConfig::WebSocket { url: String::from("ws://localhost:8080") });
+        assert_eq!(
+            ProxyConfig::from_legacy("https://p.example/fetch"),
+            ProxyConfig::Query { endpoint: String::from("https://p.example/fetch"), param: String::from("url") }
+        );
+        let stored: ProxyConfig = serde_json::from_str(r#""wss://p.example/""#).unwrap();
+        assert_eq!(stored, ProxyConfig::WebSocket { url: String::from("wss://p.example/") });
+        let stored: ProxyConfig = serde_json::from_str(r#""""#).unwrap();
+        assert_eq!(stored, ProxyConfig::Direct);
+    }
+
+    #[test]
+    fn configs_serialize_tagged() {
+        let config = prefix(UrlCodec::Base64);
+        let json = serde_json::to_string(&config).unwrap();
+        assert_eq!(json, r#"{"kind":"prefix","prefix":"/service/","codec":"base64"}"#);
+        assert_eq!(serde_json::from_str::<ProxyConfig>(&json).unwrap(), config);
+        assert_eq!(serde_json::from_str::<ProxyConfig>(r#"{"kind":"direct"}"#).unwrap(), ProxyConfig::Direct);
+        assert!(serde_json::from_str::<ProxyConfig>(r#"{"kind":"socks"}"#).is_err());
+        assert!(serde_json::from_str::<ProxyConfig>("42").is_err());
+    }
+}
@@ -0,0 +1,439 @@
+//! Versioning for the saved browser state.
+//!
+//! The encoded state carries a `version` number; states written before
+//! there was one count as version 0. On load each migration in [`MIGRATIONS`]
+//! lifts the JSON by one version, and only then is it read into a
+//! [`BrowserState`]. A state that still can't be read, or that was written by
+//! a newer build, is copied to a backup key before the browser starts over.
+//!
+//! Adding a field with a serde default needs no migration. Renaming,
+//! removing or reshaping one does: append a function to [`MIGRATIONS`].
+
+use gloo::storage::{LocalStorage, Storage};
+use serde_json::{Map, Value};
+
+use crate::BrowserState;
+
+/// Where the whole state was saved as one JSON string, before it was split
+/// across object stores
+const LEGACY_KEY: &str = "graphite_state";
+/// Prefix of the keys unreadable states are copied to
+const BACKUP_KEY_PREFIX: &str = "graphite_state_backup_";
+const VERSION_FIELD: &str = "version";
+
+type Migration = fn(&mut Map<String, Value>);
+
+/// Migration `i` turns a version `i` state into version `i + 1`
+const MIGRATIONS: &[Migration] = &[migrate_unversioned];
+
+pub const CURRENT_VERSION: u64 = MIGRATIONS.len() as u64;
+
+/// Reads a stored state, migrating it from whichever version wrote it
+pub fn decode(value: Value) -> Result<BrowserState, String> {
+    let state = migrate(value)?;
+    serde_json::from_value(Value::Object(state)).map_err(|err| err.to_string())
+}
+
+/// Brings a stored state up to the current version without reading it, so
+/// callers can inspect the fields themselves
+pub fn migrate(value: Value) -> Result<Map<String, Value>, String> {
+    let Value::Object(mut state) = value else {
+        return Err(String::from("not a JSON object"));
+    };
+    let version = match state.remove(VERSION_FIELD) {
+        None => 0,
+        Some(version) => version.as_u64().ok_or_else(|| format!("bad version {}", version))?,
+    };
+    if version > CURRENT_VERSION {
+        return Err(format!(
+            "written by a newer Graphite (schema {}, this one reads up to {})",
+            version, CURRENT_VERSION
+        ));
+    }
+    for migrate in &MIGRATIONS[version as usize..] {
+        migrate(&mut state);
+    }
+    Ok(state)
+}
+
+/// The state as a JSON object stamped with the current version. Private
+/// tabs and downloads are left out, so they never reach storage or a file.
+pub fn encode(state: &BrowserState) -> Result<Map<String, Value>, String> {
+    let Value::Object(mut fields) = serde_json::to_value(state).map_err(|err| err.to_string())? else {
+        return Err(String::from("the state didn't encode to an object"));
+    };
+    let is_private = |item: &Value| item.get("private").and_then(Value::as_bool).unwrap_or(false);
+    if let Some(Value::Array(downloads)) = fields.get_mut("downloads") {
+        downloads.retain(|d| !is_private(d));
+    }
+    if let Some(Value::Array(tabs)) = fields.get_mut("tabs") {
+        tabs.retain(|t| !is_private(t));
+    }
+    // A private active tab gives way to the first saved one
+    let saved_ids: Vec<Value> = match fields.get("tabs") {
+        Some(Value::Array(tabs)) => tabs.iter().filter_map(|t| t.get("id").cloned()).collect(),
+        _ => Vec::new(),
+    };
+    if let Some(first) = saved_ids.first() {
+        if !fields.get("active_tab_id").is_some_and(|id| saved_ids.contains(id)) {
+            fields.insert(String::from("active_tab_id"), first.clone());
+        }
+    }
+    fields.insert(String::from(VERSION_FIELD), Value::from(CURRENT_VERSION));
+    Ok(fields)
+}
+
+/// Decodes a saved state, or backs it up and starts over with a message
+/// telling the user where the old data went
+pub fn decode_or_backup(json: &str) -> (BrowserState, Option<String>) {
+    decode_or_backup_at(json, js_sys::Date::now() as u64, |key, json| LocalStorage::raw().set_item(key, json).is_ok())
+}
+
+/// [`decode_or_backup`] with the clock and the backup write passed in
+fn decode_or_backup_at(json: &str, now: u64, backup: impl FnOnce(&str, &str) -> bool) -> (BrowserState, Option<String>) {
+    let result = serde_json::from_str(json).map_err(|err| err.to_string()).and_then(decode);
+    match result {
+        Ok(state) => (state, None),
+        Err(err) => {
+            log::error!("Failed to read the saved browser state: {}", err);
+            let key = format!("{}{}", BACKUP_KEY_PREFIX, now);
+            let notice = if backup(&key, json) {
+                format!(
+                    "Your saved tabs and settings couldn't be read ({}), so Graphite started fresh. The old data was kept under the storage key {}.",
+                    err, key
+                )
+            } else {
+                format!("Your saved tabs and settings couldn't be read ({}) or backed up, so Graphite started fresh.", err)
+            };
+            (BrowserState::default(), Some(notice))
+        }
+    }
+}
+
+/// The state saved by builds that kept it in one LocalStorage string
+pub fn load_legacy() -> Option<(BrowserState, Option<String>)> {
+    let json = LocalStorage::raw().get_item(LEGACY_KEY).ok().flatten()?;
+    Some(decode_or_backup(&json))
+}
+
+/// Forgets the single-string state once it has been saved the new way
+pub fn remove_legacy() {
+    LocalStorage::delete(LEGACY_KEY);
+}
+
+/// Version 0 to 1: states from before versioning. The proxy was a string
+/// named `proxy_server`, the search engine an enum variant name, tabs had no
+/// back/forward stack, and a global `history` URL list with its
+/// `history_index` was saved but never used.
+fn migrate_unversioned(state: &mut Map<String, Value>) {
+    if let Some(proxy) = state.remove("proxy_server") {
+        state.entry("proxy").or_insert(proxy);
+    }
+    if let Some(Value::String(engine)) = state.get_mut("search_engine") {
+        *engine = engine.to_lowercase();
+    }
+    if state.get("history").is_some_and(Value::is_array) {
+        state.remove("history");
+    }
+    state.remove("history_index");
+    if let Some(Value::Array(tabs)) = state.get_mut("tabs") {
+        for tab in tabs.iter_mut().filter_map(Value::as_object_mut) {
+            if !tab.contains_key("history") {
+                let url = tab.get("url").cloned().unwrap_or(Value::Null);
+                if url.is_string() {
+                    tab.insert(String::from("history"), Value::Array(vec![url]));
+                    tab.insert(String::from("history_index"), Value::from(0));
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::proxy_config::{ProxyConfig, UrlCodec};
+    use crate::{Bookmark, BookmarkNode, Bookmarks, BrowsingHistory, Download, DownloadStatus, HistoryEntry, SearchEngine, Tab, Theme};
+
+    /// The first saved shape: a proxy string, an enum engine name and an
+    /// unused global list of URLs
+    const UNVERSIONED: &str = r#"{
+        "tabs": [
+            {"id": 0, "title": "Home", "url": "graphite://home", "favicon": null, "is_loading": false},
+            {"id": 3, "title": "Example Domain", "url": "https://example.com/", "favicon": "https://example.com/favicon.ico", "is_loading": false}
+        ],
+        "active_tab_id": 3,
+        "next_tab_id": 4,
+        "search_engine": "DuckDuckGo",
+        "proxy_server": "wss://proxy.example.net/ws",
+        "downloads": [
+            {"id": 0, "filename": "google.png", "completed": true}
+        ],
+        "history": ["https://example.com/"],
+        "history_index": 0
+    }"#;
+
+    /// Once the proxy became a tagged config and tabs kept back/forward stacks
+    const PROXY_CONFIG: &str = r#"{
+        "tabs": [
+            {"id": 1, "title": "Example Domain", "url": "https://example.com/", "favicon": null, "is_loading": false,
+             "history": ["graphite://home", "https://example.com/"], "history_index": 1}
+        ],
+        "active_tab_id": 1,
+        "next_tab_id": 2,
+        "search_engine": "Google",
+        "proxy": {"kind": "prefix", "prefix": "/service/", "codec": "xor"},
+        "downloads": [],
+        "history": [],
+        "history_index": 0,
+        "max_live_frames": 4
+    }"#;
+
+    /// Once `history` became a log of visits, with downloads, themes and bookmarks
+    const HISTORY_ENTRIES: &str = r#"{
+        "tabs": [
+            {"id": 0, "title": "Example Domain", "url": "https://example.com/", "favicon": null, "is_loading": false,
+             "history": ["https://example.com/"], "history_index": 0}
+        ],
+        "active_tab_id": 0,
+        "next_tab_id": 1,
+        "search_engine": "Bing",
+        "proxy": {"kind": "direct"},
+        "downloads": [
+            {"id": 2, "filename": "report.pdf", "url": "https://example.com/report.pdf", "mime_type": "application/pdf",
+             "received_bytes": 2048, "total_bytes": 2048, "status": "Completed", "started_at": 1000.0, "finished_at": 2000.0}
+        ],
+        "next_download_id": 3,
+        "history": {
+            "entries": [
+                {"id": 4, "url": "https://example.com/", "title": "Example Domain", "last_visit": 5000.0, "visit_count": 2, "tab_id": 0}
+            ],
+            "next_id": 5
+        },
+        "history_retention_days": 30,
+        "max_live_frames": 6,
+        "theme": "Dark",
+        "custom_theme": null,
+        "bookmarks": {
+            "bar": {"id": 0, "title": "Bookmarks bar", "children": [
+                {"Bookmark": {"id": 2, "title": "Example", "url": "https://example.com/", "added_at": 100.0}}
+            ], "added_at": 0.0},
+            "other": {"id": 1, "title": "Other bookmarks", "children": [], "added_at": 0.0},
+            "next_id": 3
+        }
+    }"#;
+
+    /// Once the engine was saved by id, alongside custom engines
+    const SEARCH_ENGINE_ID: &str = r##"{
+        "tabs": [
+            {"id": 0, "title": "Home", "url": "graphite://home", "favicon": null, "is_loading": false,
+             "history": ["graphite://home"], "history_index": 0}
+        ],
+        "active_tab_id": 0,
+        "next_tab_id": 1,
+        "search_engine": "custom-0",
+        "custom_search_engines": [
+            {"id": "custom-0", "name": "Docs", "search_url": "https://docs.example.com/?q=%s", "suggest_url": null,
+             "icon": "D", "color": "#336699", "keyword": "docs"}
+        ],
+        "proxy": {"kind": "query", "endpoint": "https://proxy.example.com/fetch", "param": "u"},
+        "downloads": [],
+        "next_download_id": 0,
+        "history": {"entries": [], "next_id": 0},
+        "history_retention_days": 0,
+        "max_live_frames": 6,
+        "theme": "System",
+        "custom_theme": null,
+        "bookmarks": {
+            "bar": {"id": 0, "title": "Bookmarks bar", "children": [], "added_at": 0.0},
+            "other": {"id": 1, "title": "Other bookmarks", "children": [], "added_at": 0.0},
+            "next_id": 2
+        }
+    }"##;
+
+    fn decode_str(json: &str) -> Result<BrowserState, String> {
+        decode(serde_json::from_str(json).unwrap())
+    }
+
+    fn tab(id: u32, title: &str, history: &[&str], history_index: usize) -> Tab {
+        Tab {
+            id,
+            title: title.to_string(),
+            url: history[history_index].to_string(),
+            history: history.iter().map(|url| url.to_string()).collect(),
+            history_index,
+            ..Tab::default()
+        }
+    }
+
+    #[test]
+    fn unversioned_baseline() {
+        let placeholder = Download {
+            id: 0,
+            filename: String::from("google.png"),
+            url: String::new(),
+            mime_type: None,
+            received_bytes: 0,
+            total_bytes: None,
+            status: DownloadStatus::InProgress,
+            started_at: 0.0,
+            finished_at: None,
+            private: false,
+        };
+        let expected = BrowserState {
+            tabs: vec![
+                tab(0, "Home", &["graphite://home"], 0),
+                Tab {
+                    favicon: Some(String::from("https://example.com/favicon.ico")),
+                    ..tab(3, "Example Domain", &["https://example.com/"], 0)
+                },
+            ],
+            active_tab_id: 3,
+            next_tab_id: 4,
+            search_engine: String::from("duckduckgo"),
+            proxy: ProxyConfig::WebSocket { url: String::from("wss://proxy.example.net/ws") },
+            // Placeholders without a source, dropped once the browser starts
+            downloads: vec![placeholder],
+            ..BrowserState::default()
+        };
+        assert_eq!(decode_str(UNVERSIONED), Ok(expected));
+    }
+
+    #[test]
+    fn proxy_config_shape() {
+        let expected = BrowserState {
+            tabs: vec![tab(1, "Example Domain", &["graphite://home", "https://example.com/"], 1)],
+            active_tab_id: 1,
+            next_tab_id: 2,
+            search_engine: String::from("google"),
+            proxy: ProxyConfig::Prefix { prefix: String::from("/service/"), codec: UrlCodec::Xor },
+            max_live_frames: 4,
+            ..BrowserState::default()
+        };
+        assert_eq!(decode_str(PROXY_CONFIG), Ok(expected));
+    }
+
+    #[test]
+    fn history_entries_shape() {
+        let mut bookmarks = Bookmarks::default();
+        bookmarks.bar.children.push(BookmarkNode::Bookmark(Bookmark {
+            id: 2,
+            title: String::from("Example"),
+            url: String::from("https://example.com/"),
+            added_at: 100.0,
+        }));
+        bookmarks.next_id = 3;
+        let expected = BrowserState {
+            tabs: vec![tab(0, "Example Domain", &["https://example.com/"], 0)],
+            search_engine: String::from("bing"),
+            proxy: ProxyConfig::Direct,
+            downloads: vec![Download {
+                id: 2,
+                filename: String::from("report.pdf"),
+                url: String::from("https://example.com/report.pdf"),
+                mime_type: Some(String::from("application/pdf")),
+                received_bytes: 2048,
+                total_bytes: Some(2048),
+                status: DownloadStatus::Completed,
+                started_at: 1000.0,
+                finished_at: Some(2000.0),
+                private: false,
+            }],
+            next_download_id: 3,
+            history: BrowsingHistory {
+                entries: vec![HistoryEntry {
+                    id: 4,
+                    url: String::from("https://example.com/"),
+                    title: String::from("Example Domain"),
+                    last_visit: 5000.0,
+                    visit_count: 2,
+                    tab_id: 0,
+                }],
+                next_id: 5,
+            },
+            history_retention_days: 30,
+            theme: Theme::Dark,
+            bookmarks,
+            ..BrowserState::default()
+        };
+        assert_eq!(decode_str(HISTORY_ENTRIES), Ok(expected));
+    }
+
+    #[test]
+    fn search_engine_id_shape() {
+        let expected = BrowserState {
+            search_engine: String::from("custom-0"),
+            custom_search_engines: vec![SearchEngine {
+                id: String::from("custom-0"),
+                name: String::from("Docs"),
+                search_url: String::from("https://docs.example.com/?q=%s"),
+                suggest_url: None,
+                icon: String::from("D"),
+                color: String::from("#336699"),
+                keyword: String::from("docs"),
+            }],
+            proxy: ProxyConfig::Query { endpoint: String::from("https://proxy.example.com/fetch"), param: String::from("u") },
+            history_retention_days: 0,
+            theme: Theme::System,
+            ..BrowserState::default()
+        };
+        assert_eq!(decode_str(SEARCH_ENGINE_ID), Ok(expected));
+    }
+
+    #[test]
+    fn encoded_states_decode_unchanged() {
+        for fixture in [UNVERSIONED, PROXY_CONFIG, HISTORY_ENTRIES, SEARCH_ENGINE_ID] {
+            let state = decode_str(fixture).unwrap();
+            let encoded = encode(&state).unwrap();
+            assert_eq!(encoded.get(VERSION_FIELD), Some(&Value::from(CURRENT_VERSION)));
+            assert_eq!(decode(Value::Object(encoded)), Ok(state));
+        }
+    }
+
+    #[test]
+    fn missing_collections_are_empty() {
+        let minimal = serde_json::json!({ "version": CURRENT_VERSION, "tabs": [], "active_tab_id": 0, "next_tab_id": 0 });
+        let state = decode(minimal).unwrap();
+        assert!(state.downloads.is_empty());
+        assert!(state.history.entries.is_empty());
+        assert!(state.recently_closed.is_empty());
+    }
+
+    #[test]
+    fn newer_and_malformed_versions_are_refused() {
+        let newer = serde_json::json!({ "version": CURRENT_VERSION + 1, "tabs": [] });
+        assert!(decode(newer).unwrap_err().contains("newer Graphite"));
+        assert!(decode(serde_json::json!({ "version": "one" })).is_err());
+        assert!(decode(serde_json::json!([1, 2, 3])).is_err());
+    }
+
+    #[test]
+    fn corrupt_input_is_backed_up() {
+        for corrupt in ["{\"tabs\": [", "{\"tabs\": 5}", "null"] {
+            let mut saved = None;
+            let (state, notice) = decode_or_backup_at(corrupt, 1234, |key, json| {
+                saved = Some((key.to_string(), json.to_string()));
+                true
+            });
+            assert_eq!(state, BrowserState::default());
+            assert_eq!(saved, Some((String::from("graphite_state_backup_1234"), corrupt.to_string())));
+            assert!(notice.unwrap().contains("graphite_state_backup_1234"), "notice for {:?}", corrupt);
+        }
+    }
+
+    #[test]
+    fn failed_backups_are_reported() {
+        let (state, notice) = decode_or_backup_at("not json", 1, |_, _| false);
+        assert_eq!(state, BrowserState::default());
+        let notice = notice.unwrap();
+        assert!(notice.contains("or backed up"));
+        assert!(!notice.contains("graphite_state_backup_"));
+    }
+
+    #[test]
+    fn readable_input_is_not_backed_up() {
+        let (state, notice) = decode_or_backup_at(SEARCH_ENGINE_ID, 1, |_, _| panic!("backed up a readable state"));
+        assert_eq!(state.search_engine, "custom-0");
+        assert_eq!(notice, None);
+    }
+}
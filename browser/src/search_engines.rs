@@ -0,0 +1,309 @@
+//! Search engines: the built-in defaults and the user's own.
+//!
+//! An engine is a results URL with a `%s` where the query goes, plus an
+//! optional OpenSearch suggestions URL in the same form. Engines with a
+//! keyword can be searched directly from the URL bar by typing the keyword,
+//! a space and the query.
+
+use serde::{Deserialize, Serialize};
+use url::Url;
+
+use crate::proxy_config::encode_component;
+
+/// Placeholder replaced by the query in engine URLs
+pub const QUERY_PLACEHOLDER: &str = "%s";
+
+/// Engine used when the selected one no longer exists
+pub const DEFAULT_ENGINE_ID: &str = "google";
+
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct SearchEngine {
+    pub id: String,
+    pub name: String,
+    /// Results page, with `%s` where the query goes
+    pub search_url: String,
+    /// OpenSearch suggestions endpoint answering `["query", ["suggestion", ...]]`
+    #[serde(default)]
+    pub suggest_url: Option<String>,
+    /// An image URL, or a character or two drawn on a coloured badge
+    #[serde(default)]
+    pub icon: String,
+    /// Badge colour, the accent colour when empty
+    #[serde(default)]
+    pub color: String,
+    /// Typed before a query in the URL bar to search this engine; may be empty
+    #[serde(default)]
+    pub keyword: String,
+}
+
+/// A text field of the engine form in settings
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum EngineField {
+    Name,
+    SearchUrl,
+    SuggestUrl,
+    Icon,
+    Keyword,
+}
+
+impl SearchEngine {
+    pub fn set(&mut self, field: EngineField, value: String) {
+        match field {
+            EngineField::Name => self.name = value,
+            EngineField::SearchUrl => self.search_url = value,
+            EngineField::SuggestUrl => self.suggest_url = Some(value),
+            EngineField::Icon => self.icon = value,
+            EngineField::Keyword => self.keyword = value,
+        }
+    }
+
+    pub fn search_url(&self, query: &str) -> String {
+        expand(&self.search_url, query)
+    }
+
+    pub fn suggest_url(&self, query: &str) -> Option<String> {
+        self.suggest_url.as_deref().map(|template| expand(template, query))
+    }
+
+    /// The icon as an image address, when it is one
+    pub fn icon_image(&self) -> Option<&str> {
+        let icon = self.icon.trim();
+        ["https://", "http://", "data:image/"].iter().any(|p| icon.starts_with(p)).then_some(icon)
+    }
+
+    /// Text for the badge when the icon isn't an image
+    pub fn badge(&self) -> String {
+        let icon = self.icon.trim();
+        if icon.is_empty() {
+            self.name.chars().next().map(|c| c.to_uppercase().collect()).unwrap_or_default()
+        } else {
+            icon.chars().take(2).collect()
+        }
+    }
+
+    /// Tidies a draft from the settings form and checks it against the other
+    /// engines, which must not include the draft itself
+    pub fn validated(&self, others: &[SearchEngine]) -> Result<SearchEngine, String> {
+        let name = self.name.trim();
+        if name.is_empty() {
+            return Err(String::from("The engine needs a name."));
+        }
+        let search_url = self.search_url.trim();
+        check_template(search_url).map_err(|err| format!("Search URL: {}", err))?;
+        let suggest_url = match self.suggest_url.as_deref().map(str::trim) {
+            Some(url) if !url.is_empty() => {
+                check_template(url).map_err(|err| format!("Suggestion URL: {}", err))?;
+                Some(url.to_string())
+            }
+            _ => None,
+        };
+        let keyword = self.keyword.trim().to_lowercase();
+        if keyword.contains(char::is_whitespace) {
+            return Err(String::from("Keywords can't contain spaces."));
+        }
+        if !keyword.is_empty() {
+            if let Some(other) = others.iter().find(|e| e.keyword.eq_ignore_ascii_case(&keyword)) {
+                return Err(format!("\"{}\" is already the keyword for {}.", keyword, other.name));
+            }
+        }
+        Ok(SearchEngine {
+            id: self.id.clone(),
+            name: name.to_string(),
+            search_url: search_url.to_string(),
+            suggest_url,
+            icon: self.icon.trim().to_string(),
+            color: self.color.trim().to_string(),
+            keyword,
+        })
+    }
+}
+
+fn expand(template: &str, query: &str) -> String {
+    template.replace(QUERY_PLACEHOLDER, &encode_component(query))
+}
+
+/// An engine URL has to be a web address with somewhere to put the query
+fn check_template(template: &str) -> Result<(), String> {
+    if !template.contains(QUERY_PLACEHOLDER) {
+        return Err(format!("use {} where the search terms go.", QUERY_PLACEHOLDER));
+    }
+    match Url::parse(&expand(template, "test")) {
+        Ok(url) if matches!(url.scheme(), "http" | "https") && url.host().is_some() => Ok(()),
+        _ => Err(String::from("enter a full http:// or https:// address.")),
+    }
+}
+
+fn engine(id: &str, name: &str, search_url: &str, suggest_url: &str, icon: &str, color: &str, keyword: &str) -> SearchEngine {
+    SearchEngine {
+        id: id.to_string(),
+        name: name.to_string(),
+        search_url: search_url.to_string(),
+        suggest_url: Some(suggest_url.to_string()),
+        icon: icon.to_string(),
+        color: color.to_string(),
+        keyword: keyword.to_string(),
+    }
+}
+
+/// The engines every browser starts with. Their ids are the lowercased names
+/// of the enum variants older saved states used for the selected engine.
+pub fn built_in() -> Vec<SearchEngine> {
+    vec![
+        engine(
+            "yahoo", "Yahoo",
+            "https://search.yahoo.com/search?p=%s",
+            "https://search.yahoo.com/sugg/os?command=%s&output=fxjson",
+            "Y!", "#6001d2", "yahoo.com",
+        ),
+        engine(
+            "google", "Google",
+            "https://www.google.com/search?q=%s",
+            "https://suggestqueries.google.com/complete/search?client=firefox&q=%s",
+            "G", "#4285f4", "google.com",
+        ),
+        engine(
+            "bing", "Bing",
+            "https://www.bing.com/search?q=%s",
+            "https://api.bing.com/osjson.aspx?query=%s",
+            "b", "#00809d", "bing.com",
+        ),
+        engine(
+            "duckduckgo", "DuckDuckGo",
+            "https://duckduckgo.com/?q=%s",
+            "https://duckduckgo.com/ac/?q=%s&type=list",
+            "🦆", "#de5833", "duckduckgo.com",
+        ),
+        engine(
+            "brave", "Brave",
+            "https://search.brave.com/search?q=%s",
+            "https://search.brave.com/api/suggest?q=%s",
+            "🦁", "#fb542b", "brave.com",
+        ),
+    ]
+}
+
+/// Built-in engines followed by the user's own
+pub fn all(custom: &[SearchEngine]) -> Vec<SearchEngine> {
+    let mut engines = built_in();
+    engines.extend(custom.iter().cloned());
+    engines
+}
+
+/// Finds an engine by id. Ids match case-insensitively so that selections
+/// saved as enum variant names (`"DuckDuckGo"`) still resolve.
+pub fn find<'a>(engines: &'a [SearchEngine], id: &str) -> Option<&'a SearchEngine> {
+    engines.iter().find(|e| e.id.eq_ignore_ascii_case(id))
+}
+
+/// An id no custom engine uses yet
+pub fn new_id(custom: &[SearchEngine]) -> String {
+    let next = custom
+        .iter()
+        .filter_map(|e| e.id.strip_prefix("custom-")?.parse::<u32>().ok())
+        .max()
+        .map_or(0, |n| n + 1);
+    format!("custom-{}", next)
+}
+
+/// Splits `keyword query` input into the engine with that keyword and the query
+pub fn split_keyword<'a, 'e>(engines: &'e [SearchEngine], input: &'a str) -> Option<(&'e SearchEngine, &'a str)> {
+    let (keyword, query) = input.trim().split_once(char::is_whitespace)?;
+    let query = query.trim();
+    if query.is_empty() {
+        return None;
+    }
+    let engine = engines.iter().find(|e| !e.keyword.is_empty() && e.keyword.eq_ignore_ascii_case(keyword))?;
+    Some((engine, query))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn draft(name: &str, search_url: &str, keyword: &str) -> SearchEngine {
+        SearchEngine { id: String::from("custom-0"), name: name.to_string(), search_url: search_url.to_string(), keyword: keyword.to_string(), ..SearchEngine::default() }
+    }
+
+    #[test]
+    fn drafts_are_tidied() {
+        let engine = SearchEngine {
+            suggest_url: Some(String::from("  ")),
+            icon: String::from(" GH "),
+            ..draft("  GitHub ", " https://github.com/search?q=%s ", " GH ")
+        };
+        let engine = engine.validated(&built_in()).unwrap();
+        assert_eq!(engine.name, "GitHub");
+        assert_eq!(engine.search_url, "https://github.com/search?q=%s");
+        assert_eq!(engine.suggest_url, None);
+        assert_eq!(engine.icon, "GH");
+        assert_eq!(engine.keyword, "gh");
+    }
+
+    #[test]
+    fn templates_are_checked() {
+        let check = |url: &str| draft("Test", url, "").validated(&[]).map(|_| ());
+        assert_eq!(check("https://a.test/search?q="), Err(String::from("Search URL: use %s where the search terms go.")));
+        assert_eq!(check("ftp://a.test/?q=%s"), Err(String::from("Search URL: enter a full http:// or https:// address.")));
+        assert_eq!(check("a.test/?q=%s"), Err(String::from("Search URL: enter a full http:// or https:// address.")));
+        assert_eq!(check("http://a.test/%s"), Ok(()));
+        let suggest = SearchEngine { suggest_url: Some(String::from("https://a.test/suggest")), ..draft("Test", "https://a.test/?q=%s", "") };
+        assert_eq!(suggest.validated(&[]), Err(String::from("Suggestion URL: use %s where the search terms go.")));
+        assert_eq!(draft(" ", "https://a.test/?q=%s", "").validated(&[]), Err(String::from("The engine needs a name.")));
+    }
+
+    #[test]
+    fn keywords_are_checked() {
+        let engines = built_in();
+        assert_eq!(
+            draft("Test", "https://a.test/?q=%s", "Bing.COM").validated(&engines),
+            Err(String::from("\"bing.com\" is already the keyword for Bing."))
+        );
+        assert_eq!(
+            draft("Test", "https://a.test/?q=%s", "my engine").validated(&engines),
+            Err(String::from("Keywords can't contain spaces."))
+        );
+        assert!(draft("Test", "https://a.test/?q=%s", "").validated(&engines).is_ok());
+    }
+
+    #[test]
+    fn keywords_pick_the_engine() {
+        let mut engines = built_in();
+        engines.push(SearchEngine { id: String::from("custom-0"), ..draft("GitHub", "https://github.com/search?q=%s", "gh") });
+        let (engine, query) = split_keyword(&engines, "  GH rust-lang  ").unwrap();
+        assert_eq!((engine.id.as_str(), query), ("custom-0", "rust-lang"));
+        assert_eq!(engine.search_url(query), "https://github.com/search?q=rust-lang");
+        let (engine, query) = split_keyword(&engines, "duckduckgo.com what is wasm").unwrap();
+        assert_eq!((engine.id.as_str(), query), ("duckduckgo", "what is wasm"));
+        // A keyword alone, or a word that isn't one, is searched as typed
+        assert!(split_keyword(&engines, "gh").is_none());
+        assert!(split_keyword(&engines, "gh   ").is_none());
+        assert!(split_keyword(&engines, "rust lang").is_none());
+    }
+
+    #[test]
+    fn queries_are_encoded_into_templates() {
+        let google = find(&built_in(), "Google").cloned().unwrap();
+        assert_eq!(google.search_url("a&b c"), "https://www.google.com/search?q=a%26b%20c");
+        assert_eq!(
+            google.suggest_url("ü").as_deref(),
+            Some("https://suggestqueries.google.com/complete/search?client=firefox&q=%C3%BC")
+        );
+    }
+
+    #[test]
+    fn new_ids_follow_the_highest() {
+        assert_eq!(new_id(&[]), "custom-0");
+        let engines = [draft("A", "", ""), SearchEngine { id: String::from("custom-7"), ..draft("B", "", "") }, SearchEngine { id: String::from("imported"), ..draft("C", "", "") }];
+        assert_eq!(new_id(&engines), "custom-8");
+    }
+
+    #[test]
+    fn icons_and_badges() {
+        let engine = SearchEngine { icon: String::from("https://a.test/icon.png"), ..draft("example", "", "") };
+        assert_eq!(engine.icon_image(), Some("https://a.test/icon.png"));
+        let engine = SearchEngine { icon: String::new(), ..engine };
+        assert_eq!((engine.icon_image(), engine.badge()), (None, String::from("E")));
+        let engine = SearchEngine { icon: String::from("🦆 duck"), ..engine };
+        assert_eq!(engine.badge(), "🦆 ");
+    }
+}
@@ -0,0 +1,163 @@
+//! Recently closed tabs, and noticing when the last session never shut down.
+//!
+//! Every running instance of the browser keeps a heartbeat in storage: the
+//! time it last checked in, under an id of its own, refreshed on an
+//! interval and removed when the page is hidden for good. Other open
+//! Graphite tabs keep theirs fresh, so only a heartbeat that has gone stale
+//! on startup means a session crashed or was killed, possibly by one of its
+//! own pages. Its tabs are then set aside as a closed window instead of
+//! being loaded again.
+
+use std::cell::RefCell;
+use std::collections::BTreeMap;
+
+use gloo::storage::{LocalStorage, Storage};
+use gloo::timers::callback::Interval;
+use serde::{Deserialize, Serialize};
+
+use crate::profiles::{self, Profile};
+use crate::Tab;
+
+/// Most entries kept in the recently closed list
+pub const MAX_CLOSED: usize = 25;
+
+/// Last check-in time of each running instance by id; namespaced per profile
+const HEARTBEATS_KEY: &str = "graphite_session_heartbeats";
+/// The single running flag of older builds, which couldn't tell a second
+/// open tab from a crash
+const LEGACY_RUNNING_KEY: &str = "graphite_session_running";
+/// How often a running instance checks in
+const HEARTBEAT_MS: u32 = 10_000;
+/// Age at which a heartbeat means its instance is gone. Browsers run the
+/// timers of hidden pages as rarely as once a minute, so this leaves room
+/// for an instance in a background tab.
+const STALE_MS: f64 = 150_000.0;
+
+thread_local! {
+    static INSTANCE_ID: String = format!("{:08x}", (js_sys::Math::random() * f64::from(u32::MAX)) as u32);
+    static HEARTBEAT: RefCell<Option<Interval>> = const { RefCell::new(None) };
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum ClosedEntry {
+    /// A tab, with its back/forward history and where it sat in the tab strip
+    Tab { tab: Tab, index: usize, closed_at: f64 },
+    /// Every tab of a session that was set aside
+    Window { tabs: Vec<Tab>, active_tab_id: u32, closed_at: f64 },
+}
+
+impl ClosedEntry {
+    pub fn title(&self) -> String {
+        match self {
+            ClosedEntry::Tab { tab, .. } => tab.title.clone(),
+            ClosedEntry::Window { tabs, .. } if tabs.len() == 1 => String::from("1 tab"),
+            ClosedEntry::Window { tabs, .. } => format!("{} tabs", tabs.len()),
+        }
+    }
+
+    /// Tab titles for a window, the address for a tab
+    pub fn detail(&self) -> String {
+        match self {
+            ClosedEntry::Tab { tab, .. } => tab.url.clone(),
+            ClosedEntry::Window { tabs, .. } => tabs.iter().map(|t| t.title.as_str()).collect::<Vec<_>>().join(", "),
+        }
+    }
+}
+
+/// Puts `entry` at the front of the recently closed list
+pub fn push_closed(closed: &mut Vec<ClosedEntry>, entry: ClosedEntry) {
+    closed.insert(0, entry);
+    closed.truncate(MAX_CLOSED);
+}
+
+/// Registers this instance as running and reports whether an earlier one
+/// stopped checking in, meaning it ended without shutting down
+pub fn begin() -> bool {
+    let key = profiles::namespaced(HEARTBEATS_KEY);
+    let mut beats = read_heartbeats(&key);
+    let unclean = INSTANCE_ID.with(|id| check_in(&mut beats, id, js_sys::Date::now()));
+    write_heartbeats(&key, &beats);
+    LocalStorage::delete(profiles::namespaced(LEGACY_RUNNING_KEY));
+    HEARTBEAT.with(|heartbeat| {
+        heartbeat.borrow_mut().get_or_insert_with(|| Interval::new(HEARTBEAT_MS, beat));
+    });
+    unclean
+}
+
+/// Records a clean shutdown of this instance
+pub fn end() {
+    HEARTBEAT.with(|heartbeat| heartbeat.borrow_mut().take());
+    let key = profiles::namespaced(HEARTBEATS_KEY);
+    let mut beats = read_heartbeats(&key);
+    INSTANCE_ID.with(|id| beats.remove(id));
+    write_heartbeats(&key, &beats);
+}
+
+/// Drops the heartbeats of a profile that is being deleted
+pub fn forget(profile: &Profile) {
+    LocalStorage::delete(profile.namespaced(HEARTBEATS_KEY));
+    LocalStorage::delete(profile.namespaced(LEGACY_RUNNING_KEY));
+}
+
+/// Refreshes this instance's heartbeat, keeping the others' as they are
+fn beat() {
+    let key = profiles::namespaced(HEARTBEATS_KEY);
+    let mut beats = read_heartbeats(&key);
+    INSTANCE_ID.with(|id| beats.insert(id.clone(), js_sys::Date::now()));
+    write_heartbeats(&key, &beats);
+}
+
+fn read_heartbeats(key: &str) -> BTreeMap<String, f64> {
+    LocalStorage::get(key).unwrap_or_default()
+}
+
+fn write_heartbeats(key: &str, beats: &BTreeMap<String, f64>) {
+    if beats.is_empty() {
+        LocalStorage::delete(key);
+    } else if let Err(err) = LocalStorage::set(key, beats) {
+        log::warn!("Failed to record the session heartbeat: {}", err);
+    }
+}
+
+/// Records `id` as running at `now` and drops the heartbeats of instances
+/// that stopped checking in, returning whether there were any
+fn check_in(beats: &mut BTreeMap<String, f64>, id: &str, now: f64) -> bool {
+    // Coming back from the back/forward cache isn't a crash of our own
+    beats.remove(id);
+    let count = beats.len();
+    beats.retain(|_, beat| now - *beat < STALE_MS);
+    let unclean = beats.len() != count;
+    beats.insert(id.to_string(), now);
+    unclean
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn other_live_instances_are_not_crashes() {
+        let mut beats = BTreeMap::from([(String::from("a"), 1_000.0)]);
+        assert!(!check_in(&mut beats, "b", 1_000.0 + STALE_MS - 1.0));
+        assert_eq!(beats.len(), 2);
+    }
+
+    #[test]
+    fn stale_heartbeats_are_crashes_once() {
+        let mut beats = BTreeMap::from([(String::from("a"), 1_000.0), (String::from("b"), 50_000.0)]);
+        let now = 1_000.0 + STALE_MS;
+        assert!(check_in(&mut beats, "c", now));
+        assert_eq!(beats, BTreeMap::from([(String::from("b"), 50_000.0), (String::from("c"), now)]));
+        assert!(!check_in(&mut beats, "d", now));
+    }
+
+    #[test]
+    fn an_instance_checking_in_again_is_not_a_crash() {
+        let mut beats = BTreeMap::from([(String::from("a"), 1_000.0)]);
+        let now = 1_000.0 + 10.0 * STALE_MS;
+        assert!(!check_in(&mut beats, "a", now));
+        assert_eq!(beats, BTreeMap::from([(String::from("a"), now)]));
+        assert!(!check_in(&mut BTreeMap::new(), "a", now));
+    }
+}
@@ -0,0 +1,615 @@
+//! Saving the browser state across one store per kind of data.
+//!
+//! The versioned state JSON (see [`schema`]) is divided into records: the
+//! settings, the open and recently closed tabs, the bookmark tree, and one
+//! record per history entry and per download. The app remembers the records
+//! it last wrote, so a save only touches the ones that changed. Records go to
+//! IndexedDB when it is available, otherwise to LocalStorage, and as a last
+//! resort they are only kept in memory for the life of the page. Either way
+//! they are kept apart per profile (see [`profiles`]).
+//!
+//! Backend writes finish after the page may already be gone, so as the page
+//! is hidden the whole state is also put in LocalStorage synchronously. That
+//! snapshot is read in preference to the records until a write succeeds.
+
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::fmt;
+use std::rc::Rc;
+
+use futures::future::{FutureExt, LocalBoxFuture};
+use gloo::storage::{LocalStorage, Storage};
+use serde_json::{Map, Value};
+use wasm_bindgen::prelude::*;
+
+use crate::profiles::{self, Profile};
+use crate::{idb, schema, session, BrowserState};
+
+pub const SETTINGS: &str = "settings";
+pub const SESSION: &str = "session";
+pub const BOOKMARKS: &str = "bookmarks";
+pub const HISTORY: &str = "history";
+pub const DOWNLOADS: &str = "downloads";
+
+/// Every object store the state is split across
+pub const STORES: [&str; 5] = [SETTINGS, SESSION, BOOKMARKS, HISTORY, DOWNLOADS];
+
+/// Start of the LocalStorage keys used when IndexedDB is unavailable, before
+/// the profile is added
+const LOCAL_PREFIX: &str = "graphite_store";
+/// LocalStorage key of the state saved as the page was hidden, before the
+/// profile is added
+const SNAPSHOT_KEY: &str = "graphite_state_snapshot";
+
+/// One saved value: a piece of the state JSON under a key in a store
+#[derive(Clone, Debug, PartialEq)]
+pub struct Record {
+    pub store: &'static str,
+    pub key: String,
+    pub json: String,
+}
+
+pub enum Change {
+    Put(Record),
+    Delete { store: &'static str, key: String },
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum StorageError {
+    /// The browser refused to store more data for this site
+    QuotaExceeded,
+    Failed(String),
+}
+
+impl fmt::Display for StorageError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            StorageError::QuotaExceeded => write!(f, "storage is full"),
+            StorageError::Failed(err) => write!(f, "{}", err),
+        }
+    }
+}
+
+impl StorageError {
+    fn from_js(err: JsValue) -> Self {
+        let quota = err
+            .dyn_ref::<web_sys::DomException>()
+            .is_some_and(|e| e.name() == "QuotaExceededError" || e.name() == "NS_ERROR_DOM_QUOTA_REACHED");
+        if quota {
+            StorageError::QuotaExceeded
+        } else {
+            StorageError::Failed(idb::js_error(err))
+        }
+    }
+}
+
+/// Somewhere records can be kept
+pub trait StorageBackend {
+    /// How the backend is described to the user
+    fn name(&self) -> &'static str;
+    /// Whether records outlive the page
+    fn is_persistent(&self) -> bool {
+        true
+    }
+    /// Every saved record, from all stores
+    fn load(&self) -> LocalBoxFuture<'static, Result<Vec<Record>, StorageError>>;
+    /// Applies the changes together, as far as the backend allows
+    fn write(&self, changes: Vec<Change>) -> LocalBoxFuture<'static, Result<(), StorageError>>;
+}
+
+pub struct IndexedDbBackend {
+    db: web_sys::IdbDatabase,
+}
+
+impl StorageBackend for IndexedDbBackend {
+    fn name(&self) -> &'static str {
+        "IndexedDB"
+    }
+
+    fn load(&self) -> LocalBoxFuture<'static, Result<Vec<Record>, StorageError>> {
+        async {
+            let mut records = Vec::new();
+            for store in STORES {
+                let entries = idb::entries(store).await.map_err(StorageError::Failed)?;
+                records.extend(entries.into_iter().filter_map(|(key, value)| {
+                    Some(Record { store, key: key.as_string()?, json: value.as_string()? })
+                }));
+            }
+            Ok(records)
+        }
+        .boxed_local()
+    }
+
+    fn write(&self, changes: Vec<Change>) -> LocalBoxFuture<'static, Result<(), StorageError>> {
+        let ops = changes
+            .into_iter()
+            .map(|change| match change {
+                Change::Put(record) => idb::Op::Put {
+                    store: record.store,
+                    key: JsValue::from_str(&record.key),
+                    value: JsValue::from_str(&record.json),
+                },
+                Change::Delete { store, key } => idb::Op::Delete { store, key: JsValue::from_str(&key) },
+            })
+            .collect();
+        idb::apply(&self.db, ops).map(|result| result.map_err(StorageError::from_js)).boxed_local()
+    }
+}
+
+/// Records as `graphite_store/<store>/<key>` LocalStorage entries, or
+/// `graphite_store@<profile>/<store>/<key>` outside the default profile
+pub struct LocalStorageBackend {
+    prefix: String,
+}
+
+impl LocalStorageBackend {
+    fn new(profile: &Profile) -> Self {
+        Self { prefix: format!("{}/", profile.namespaced(LOCAL_PREFIX)) }
+    }
+
+    fn keys(&self) -> Vec<String> {
+        let storage = LocalStorage::raw();
+        let count = storage.length().unwrap_or(0);
+        (0..count)
+            .filter_map(|i| storage.key(i).ok().flatten())
+            .filter(|name| name.starts_with(&self.prefix))
+            .collect()
+    }
+}
+
+impl StorageBackend for LocalStorageBackend {
+    fn name(&self) -> &'static str {
+        "local storage"
+    }
+
+    fn load(&self) -> LocalBoxFuture<'static, Result<Vec<Record>, StorageError>> {
+        let storage = LocalStorage::raw();
+        let records = self
+            .keys()
+            .into_iter()
+            .filter_map(|name| {
+                let (store, key) = name.strip_prefix(&self.prefix)?.split_once('/')?;
+                let store = STORES.into_iter().find(|s| *s == store)?;
+                let json = storage.get_item(&name).ok().flatten()?;
+                Some(Record { store, key: key.to_string(), json })
+            })
+            .collect();
+        async move { Ok(records) }.boxed_local()
+    }
+
+    fn write(&self, changes: Vec<Change>) -> LocalBoxFuture<'static, Result<(), StorageError>> {
+        let storage = LocalStorage::raw();
+        let result = changes.into_iter().try_for_each(|change| match change {
+            Change::Put(record) => storage
+                .set_item(&format!("{}{}/{}", self.prefix, record.store, record.key), &record.json)
+                .map_err(StorageError::from_js),
+            Change::Delete { store, key } => storage
+                .remove_item(&format!("{}{}/{}", self.prefix, store, key))
+                .map_err(StorageError::from_js),
+        });
+        async move { result }.boxed_local()
+    }
+}
+
+/// Keeps records only until the page closes
+#[derive(Default)]
+pub struct MemoryBackend {
+    records: Rc<RefCell<HashMap<(&'static str, String), String>>>,
+}
+
+impl StorageBackend for MemoryBackend {
+    fn name(&self) -> &'static str {
+        "memory"
+    }
+
+    fn is_persistent(&self) -> bool {
+        false
+    }
+
+    fn load(&self) -> LocalBoxFuture<'static, Result<Vec<Record>, StorageError>> {
+        let records = self
+            .records
+            .borrow()
+            .iter()
+            .map(|((store, key), json)| Record { store, key: key.clone(), json: json.clone() })
+            .collect();
+        async move { Ok(records) }.boxed_local()
+    }
+
+    fn write(&self, changes: Vec<Change>) -> LocalBoxFuture<'static, Result<(), StorageError>> {
+        let mut records = self.records.borrow_mut();
+        for change in changes {
+            match change {
+                Change::Put(record) => {
+                    records.insert((record.store, record.key), record.json);
+                }
+                Change::Delete { store, key } => {
+                    records.remove(&(store, key));
+                }
+            }
+        }
+        async { Ok(()) }.boxed_local()
+    }
+}
+
+/// The first backend that works here, for the current profile
+async fn open_backend() -> Rc<dyn StorageBackend> {
+    match idb::open().await {
+        Ok(db) => return Rc::new(IndexedDbBackend { db }),
+        Err(err) => log::warn!("IndexedDB is unavailable, saving to local storage instead: {}", err),
+    }
+    let backend = LocalStorageBackend::new(&profiles::current());
+    let probe = format!("{}probe", backend.prefix);
+    let storage = LocalStorage::raw();
+    if storage.set_item(&probe, "").is_ok() {
+        let _ = storage.remove_item(&probe);
+        return Rc::new(backend);
+    }
+    log::warn!("Local storage is unavailable, nothing will be saved");
+    Rc::new(MemoryBackend::default())
+}
+
+/// The records last written to each store, by store and key
+pub type Written = HashMap<(&'static str, String), String>;
+
+pub struct Loaded {
+    pub backend: Rc<dyn StorageBackend>,
+    pub state: BrowserState,
+    /// What the backend holds, for working out the first save's changes
+    pub written: Written,
+    /// Something the user should know about how loading went
+    pub notice: Option<String>,
+    /// The state came from the old single-string format and isn't saved yet
+    pub from_legacy: bool,
+}
+
+/// Opens storage and reads the saved state from it
+pub async fn load() -> Loaded {
+    let backend = open_backend().await;
+    let records = match backend.load().await {
+        Ok(records) => records,
+        Err(err) => {
+            // Saving over data that couldn't be read would lose it for good
+            log::error!("Failed to load the saved state from {}: {}", backend.name(), err);
+            return Loaded {
+                backend: Rc::new(MemoryBackend::default()),
+                state: BrowserState::default(),
+                written: Written::new(),
+                notice: Some(format!(
+                    "Your saved tabs and settings couldn't be loaded ({}). Changes made now won't be saved.",
+                    err
+                )),
+                from_legacy: false,
+            };
+        }
+    };
+
+    let written: Written = records.iter().map(|r| ((r.store, r.key.clone()), r.json.clone())).collect();
+    let (state, notice, from_legacy) = match (read_snapshot(), join(records)) {
+        // The page closed before its last changes reached the backend; the
+        // first save writes them through
+        (Some(state), _) => (state, None, false),
+        (None, Some(value)) => {
+            let (state, notice) = schema::decode_or_backup(&value.to_string());
+            (state, notice, false)
+        }
+        // Only the default profile existed when the state was one string
+        (None, None) => match schema::load_legacy().filter(|_| profiles::current().is_default()) {
+            Some((state, notice)) => (state, notice, true),
+            None => (BrowserState::default(), None, false),
+        },
+    };
+    Loaded { backend, state, written, notice, from_legacy }
+}
+
+/// Deletes everything a profile saved, in whichever backend it went to
+pub fn erase(profile: &Profile) {
+    if let Err(err) = idb::delete_database(&profile.namespaced(idb::DATABASE_NAME)) {
+        log::warn!("Failed to delete the database of profile {}: {}", profile.name, err);
+    }
+    let storage = LocalStorage::raw();
+    for key in LocalStorageBackend::new(profile).keys() {
+        let _ = storage.remove_item(&key);
+    }
+    LocalStorage::delete(profile.namespaced(SNAPSHOT_KEY));
+    session::forget(profile);
+}
+
+/// Saves the whole state to LocalStorage straight away, for when the page
+/// is going and a backend write might not finish
+pub fn write_snapshot(state: &BrowserState) {
+    let json = match schema::encode(state) {
+        Ok(fields) => Value::Object(fields).to_string(),
+        Err(err) => {
+            log::error!("Failed to encode the browser state: {}", err);
+            return;
+        }
+    };
+    if let Err(err) = LocalStorage::raw().set_item(&profiles::namespaced(SNAPSHOT_KEY), &json) {
+        log::warn!("Failed to save a snapshot of the browser state: {}", StorageError::from_js(err));
+    }
+}
+
+/// The state saved as the page was last hidden. One that can't be read is
+/// passed over for the records rather than backed up, as they still hold
+/// everything but the last changes.
+fn read_snapshot() -> Option<BrowserState> {
+    let json = LocalStorage::raw().get_item(&profiles::namespaced(SNAPSHOT_KEY)).ok().flatten()?;
+    match serde_json::from_str(&json).map_err(|err| err.to_string()).and_then(schema::decode) {
+        Ok(state) => Some(state),
+        Err(err) => {
+            log::warn!("Ignoring an unreadable snapshot of the browser state: {}", err);
+            None
+        }
+    }
+}
+
+/// Drops the snapshot once a backend write has caught up with it
+pub fn clear_snapshot() {
+    LocalStorage::delete(profiles::namespaced(SNAPSHOT_KEY));
+}
+
+/// Splits the state into records
+pub fn split(state: &BrowserState) -> Result<Vec<Record>, String> {
+    let mut state = schema::encode(state)?;
+    let mut records = Vec::new();
+
+    // Collections are saved an item per record, so one visit or download
+    // doesn't rewrite all the others
+    if let Some(Value::Object(history)) = state.get_mut("history") {
+        if let Some(Value::Array(entries)) = history.remove("entries") {
+            records.extend(entries.into_iter().filter_map(|entry| item_record(HISTORY, entry)));
+        }
+    }
+    if let Some(Value::Array(downloads)) = state.remove("downloads") {
+        records.extend(downloads.into_iter().filter_map(|download| item_record(DOWNLOADS, download)));
+    }
+    for field in ["tabs", "recently_closed"] {
+        if let Some(value) = state.remove(field) {
+            records.push(Record { store: SESSION, key: field.to_string(), json: value.to_string() });
+        }
+    }
+    if let Some(bookmarks) = state.remove("bookmarks") {
+        records.push(Record { store: BOOKMARKS, key: String::from("bookmarks"), json: bookmarks.to_string() });
+    }
+    records.push(Record { store: SETTINGS, key: String::from(SETTINGS), json: Value::Object(state).to_string() });
+    Ok(records)
+}
+
+fn item_record(store: &'static str, item: Value) -> Option<Record> {
+    let id = item.get("id")?.as_u64()?;
+    Some(Record { store, key: id.to_string(), json: item.to_string() })
+}
+
+/// Puts records back together into the state JSON, or `None` when nothing
+/// has been saved
+fn join(records: Vec<Record>) -> Option<Value> {
+    let mut settings = None;
+    let mut history = Vec::new();
+    let mut downloads = Vec::new();
+    let mut fields = Map::new();
+    for record in records {
+        let Ok(value) = serde_json::from_str::<Value>(&record.json) else {
+            log::warn!("Skipping unreadable {} record {}", record.store, record.key);
+            continue;
+        };
+        match record.store {
+            SETTINGS => settings = Some(value),
+            HISTORY => history.push(value),
+            DOWNLOADS => downloads.push(value),
+            _ => {
+                fields.insert(record.key, value);
+            }
+        }
+    }
+    let Value::Object(mut state) = settings? else {
+        return None;
+    };
+
+    // Newest first, as the app keeps them
+    let number = |v: &Value, field: &str| v.get(field).and_then(Value::as_f64).unwrap_or(0.0);
+    history.sort_by(|a, b| number(b, "last_visit").total_cmp(&number(a, "last_visit")).then(number(b, "id").total_cmp(&number(a, "id"))));
+    downloads.sort_by(|a, b| number(b, "id").total_cmp(&number(a, "id")));
+    if let Some(Value::Object(meta)) = state.get_mut("history") {
+        meta.insert(String::from("entries"), Value::Array(history));
+    }
+    state.insert(String::from("downloads"), Value::Array(downloads));
+    state.extend(fields);
+    Some(Value::Object(state))
+}
+
+/// The changes that turn `written` into `records`; `written` is updated to match
+pub fn diff(written: &mut Written, records: Vec<Record>) -> Vec<Change> {
+    let mut current = Written::with_capacity(records.len());
+    let mut changes = Vec::new();
+    for record in records {
+        let key = (record.store, record.key.clone());
+        if written.get(&key) != Some(&record.json) {
+            changes.push(Change::Put(record.clone()));
+        }
+        current.insert(key, record.json);
+    }
+    for (store, key) in written.keys() {
+        if !current.contains_key(&(*store, key.clone())) {
+            changes.push(Change::Delete { store, key: key.clone() });
+        }
+    }
+    *written = current;
+    changes
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bookmarks::BOOKMARK_BAR_ID;
+    use crate::session::ClosedEntry;
+    use crate::tab_groups::{GroupColor, TabGroup};
+    use crate::{Download, DownloadStatus, Tab};
+
+    fn download(id: u32, status: DownloadStatus) -> Download {
+        Download {
+            id,
+            filename: format!("file-{}.zip", id),
+            url: format!("https://example.com/file-{}.zip", id),
+            mime_type: Some(String::from("application/zip")),
+            received_bytes: 10,
+            total_bytes: Some(10),
+            status,
+            started_at: id as f64 * 1000.0,
+            finished_at: Some(id as f64 * 1000.0 + 500.0),
+            private: false,
+        }
+    }
+
+    fn tab(id: u32, url: &str) -> Tab {
+        Tab { id, title: format!("Tab {}", id), url: url.to_string(), history: vec![url.to_string()], ..Tab::default() }
+    }
+
+    /// A state using every kind of record, in the order the app keeps it
+    fn sample_state() -> BrowserState {
+        let mut state = BrowserState {
+            tabs: vec![tab(0, "https://example.com/"), Tab { group: Some(0), ..tab(2, "https://example.org/") }],
+            active_tab_id: 2,
+            next_tab_id: 3,
+            downloads: vec![download(5, DownloadStatus::Completed), download(3, DownloadStatus::Failed(String::from("Network")))],
+            next_download_id: 6,
+            tab_groups: vec![TabGroup { id: 0, name: String::from("Work"), color: GroupColor::Blue, collapsed: false }],
+            next_group_id: 1,
+            ..BrowserState::default()
+        };
+        state.history.record_visit("https://example.com/", "Example", 0, 1_000.0);
+        state.history.record_visit("https://example.org/", "Example org", 2, 2_000.0);
+        state.history.record_visit("https://example.net/", "Example net", 2, 2_000.0);
+        state.bookmarks.add_bookmark(BOOKMARK_BAR_ID, "Example", "https://example.com/", 500.0);
+        state.recently_closed.push(ClosedEntry::Tab { tab: tab(1, "https://example.net/"), index: 1, closed_at: 1_500.0 });
+        state
+    }
+
+    fn round_trip(state: &BrowserState) -> BrowserState {
+        let value = join(split(state).unwrap()).expect("no settings record");
+        schema::decode(value).unwrap()
+    }
+
+    /// What a change does, for comparing
+    fn describe(changes: &[Change]) -> Vec<(&'static str, String, bool)> {
+        let mut described: Vec<_> = changes
+            .iter()
+            .map(|change| match change {
+                Change::Put(record) => (record.store, record.key.clone(), true),
+                Change::Delete { store, key } => (*store, key.clone(), false),
+            })
+            .collect();
+        described.sort();
+        described
+    }
+
+    #[test]
+    fn joining_split_records_gives_back_the_state() {
+        let state = sample_state();
+        assert_eq!(round_trip(&state), state);
+        assert_eq!(round_trip(&BrowserState::default()), BrowserState::default());
+    }
+
+    #[test]
+    fn records_go_to_their_stores() {
+        let records = split(&sample_state()).unwrap();
+        let mut keys: Vec<(&str, &str)> = records.iter().map(|r| (r.store, r.key.as_str())).collect();
+        keys.sort();
+        assert_eq!(
+            keys,
+            [
+                (BOOKMARKS, "bookmarks"),
+                (DOWNLOADS, "3"),
+                (DOWNLOADS, "5"),
+                (HISTORY, "0"),
+                (HISTORY, "1"),
+                (HISTORY, "2"),
+                (SESSION, "recently_closed"),
+                (SESSION, "tabs"),
+                (SETTINGS, SETTINGS),
+            ]
+        );
+    }
+
+    #[test]
+    fn record_order_doesnt_matter() {
+        let state = sample_state();
+        let mut records = split(&state).unwrap();
+        records.reverse();
+        assert_eq!(schema::decode(join(records).unwrap()).unwrap(), state);
+    }
+
+    #[test]
+    fn private_tabs_and_downloads_stay_out() {
+        let mut state = sample_state();
+        state.tabs.push(Tab { private: true, ..tab(9, "https://secret.example/") });
+        state.active_tab_id = 9;
+        state.downloads.insert(0, Download { private: true, ..download(8, DownloadStatus::Completed) });
+        let restored = round_trip(&state);
+        assert_eq!(restored.tabs, sample_state().tabs);
+        assert_eq!(restored.active_tab_id, 0);
+        assert_eq!(restored.downloads, sample_state().downloads);
+    }
+
+    #[test]
+    fn unreadable_records_are_skipped() {
+        let mut records = split(&sample_state()).unwrap();
+        records.push(Record { store: HISTORY, key: String::from("7"), json: String::from("{not json") });
+        assert_eq!(schema::decode(join(records).unwrap()).unwrap(), sample_state());
+
+        assert_eq!(join(Vec::new()), None);
+        let without_settings = split(&sample_state()).unwrap().into_iter().filter(|r| r.store != SETTINGS).collect();
+        assert_eq!(join(without_settings), None);
+    }
+
+    #[test]
+    fn the_first_diff_writes_everything() {
+        let records = split(&sample_state()).unwrap();
+        let mut written = Written::new();
+        let changes = diff(&mut written, records.clone());
+        assert_eq!(changes.len(), records.len());
+        assert!(changes.iter().all(|c| matches!(c, Change::Put(_))));
+        assert_eq!(written.len(), records.len());
+    }
+
+    #[test]
+    fn diff_only_emits_changed_records() {
+        let mut state = sample_state();
+        let mut written = Written::new();
+        diff(&mut written, split(&state).unwrap());
+        assert!(diff(&mut written, split(&state).unwrap()).is_empty());
+
+        state.history.record_visit("https://example.com/", "Example again", 0, 3_000.0);
+        assert_eq!(describe(&diff(&mut written, split(&state).unwrap())), [(HISTORY, String::from("0"), true)]);
+
+        state.downloads.retain(|d| d.id != 3);
+        assert_eq!(describe(&diff(&mut written, split(&state).unwrap())), [(DOWNLOADS, String::from("3"), false)]);
+
+        state.tabs[0].title = String::from("Renamed");
+        state.theme = crate::Theme::Dark;
+        assert_eq!(
+            describe(&diff(&mut written, split(&state).unwrap())),
+            [(SESSION, String::from("tabs"), true), (SETTINGS, String::from(SETTINGS), true)]
+        );
+
+        state.bookmarks.add_bookmark(BOOKMARK_BAR_ID, "New", "https://example.net/", 4_000.0);
+        state.downloads.insert(0, download(6, DownloadStatus::Completed));
+        assert_eq!(
+            describe(&diff(&mut written, split(&state).unwrap())),
+            [(BOOKMARKS, String::from("bookmarks"), true), (DOWNLOADS, String::from("6"), true)]
+        );
+        assert!(diff(&mut written, split(&state).unwrap()).is_empty());
+    }
+
+    #[test]
+    fn cleared_records_are_written_again() {
+        // How a failed write is retried
+        let state = sample_state();
+        let mut written = Written::new();
+        diff(&mut written, split(&state).unwrap());
+        for json in written.values_mut() {
+            json.clear();
+        }
+        assert_eq!(diff(&mut written, split(&state).unwrap()).len(), written.len());
+    }
+}
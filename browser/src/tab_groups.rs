@@ -0,0 +1,204 @@
+//! Named, coloured groups of tabs.
+//!
+//! A tab names its group in [`Tab::group`]. The strip keeps a group's tabs
+//! next to each other, after the pinned tabs, which are never grouped;
+//! [`arrange`] restores that order whenever tabs are added, moved or
+//! pinned, and forgets groups that have no tabs left. A group can be saved,
+//! which closes its tabs and keeps them in a list to be restored together.
+
+use serde::{Deserialize, Serialize};
+
+use crate::Tab;
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub enum GroupColor {
+    #[default]
+    Grey,
+    Blue,
+    Red,
+    Yellow,
+    Green,
+    Pink,
+    Purple,
+    Cyan,
+    Orange,
+}
+
+impl GroupColor {
+    pub const ALL: [GroupColor; 9] = [
+        GroupColor::Grey,
+        GroupColor::Blue,
+        GroupColor::Red,
+        GroupColor::Yellow,
+        GroupColor::Green,
+        GroupColor::Pink,
+        GroupColor::Purple,
+        GroupColor::Cyan,
+        GroupColor::Orange,
+    ];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            GroupColor::Grey => "Grey",
+            GroupColor::Blue => "Blue",
+            GroupColor::Red => "Red",
+            GroupColor::Yellow => "Yellow",
+            GroupColor::Green => "Green",
+            GroupColor::Pink => "Pink",
+            GroupColor::Purple => "Purple",
+            GroupColor::Cyan => "Cyan",
+            GroupColor::Orange => "Orange",
+        }
+    }
+
+    /// The colour as CSS, readable on both light and dark themes
+    pub fn css(&self) -> &'static str {
+        match self {
+            GroupColor::Grey => "#7d8590",
+            GroupColor::Blue => "#1a73e8",
+            GroupColor::Red => "#d93025",
+            GroupColor::Yellow => "#e8a600",
+            GroupColor::Green => "#188038",
+            GroupColor::Pink => "#d01884",
+            GroupColor::Purple => "#9334e6",
+            GroupColor::Cyan => "#007b83",
+            GroupColor::Orange => "#fa903e",
+        }
+    }
+
+    /// The colour after `self`, so new groups don't all look the same
+    pub fn next(&self) -> Self {
+        let index = Self::ALL.iter().position(|c| c == self).unwrap_or(0);
+        Self::ALL[(index + 1) % Self::ALL.len()]
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct TabGroup {
+    pub id: u32,
+    /// May be empty, in which case the chip shows the number of tabs
+    pub name: String,
+    pub color: GroupColor,
+    /// Shown as a single chip, with only the active tab left visible
+    #[serde(default)]
+    pub collapsed: bool,
+}
+
+/// A group whose tabs were closed to be restored later
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct SavedGroup {
+    pub name: String,
+    pub color: GroupColor,
+    pub tabs: Vec<Tab>,
+    /// Milliseconds since the epoch
+    pub saved_at: f64,
+}
+
+impl SavedGroup {
+    pub fn title(&self) -> String {
+        let count = match self.tabs.len() {
+            1 => String::from("1 tab"),
+            n => format!("{} tabs", n),
+        };
+        if self.name.is_empty() {
+            count
+        } else {
+            format!("{} ({})", self.name, count)
+        }
+    }
+}
+
+/// Puts pinned tabs first and each group's tabs together where its first
+/// tab is, keeping the order otherwise. Pinned tabs and tabs of groups that
+/// don't exist lose their group, and groups without tabs are dropped.
+pub fn arrange(tabs: &mut Vec<Tab>, groups: &mut Vec<TabGroup>) {
+    for tab in tabs.iter_mut() {
+        if tab.pinned || tab.group.is_some_and(|id| !groups.iter().any(|g| g.id == id)) {
+            tab.group = None;
+        }
+    }
+    let (pinned, rest): (Vec<Tab>, Vec<Tab>) = std::mem::take(tabs).into_iter().partition(|t| t.pinned);
+    let mut arranged = pinned;
+    let mut placed: Vec<u32> = Vec::new();
+    for tab in rest.iter() {
+        match tab.group {
+            None => arranged.push(tab.clone()),
+            Some(group) if !placed.contains(&group) => {
+                placed.push(group);
+                arranged.extend(rest.iter().filter(|t| t.group == Some(group)).cloned());
+            }
+            Some(_) => {}
+        }
+    }
+    *tabs = arranged;
+    groups.retain(|g| placed.contains(&g.id));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn tab(id: u32, group: Option<u32>) -> Tab {
+        Tab { id, group, ..Tab::default() }
+    }
+
+    fn group(id: u32) -> TabGroup {
+        TabGroup { id, name: String::new(), color: GroupColor::default(), collapsed: false }
+    }
+
+    fn ids(tabs: &[Tab]) -> Vec<u32> {
+        tabs.iter().map(|t| t.id).collect()
+    }
+
+    #[test]
+    fn pinned_tabs_come_first_without_a_group() {
+        let mut tabs = vec![tab(0, None), tab(1, Some(7)), Tab { pinned: true, ..tab(2, Some(7)) }, tab(3, None)];
+        let mut groups = vec![group(7)];
+        arrange(&mut tabs, &mut groups);
+        assert_eq!(ids(&tabs), [2, 0, 1, 3]);
+        assert_eq!(tabs[0].group, None);
+        assert_eq!(tabs[2].group, Some(7));
+    }
+
+    #[test]
+    fn groups_gather_at_their_first_tab() {
+        let mut tabs = vec![
+            tab(0, Some(1)),
+            tab(1, None),
+            tab(2, Some(2)),
+            tab(3, Some(1)),
+            tab(4, None),
+            tab(5, Some(2)),
+            tab(6, Some(1)),
+        ];
+        let mut groups = vec![group(2), group(1)];
+        arrange(&mut tabs, &mut groups);
+        assert_eq!(ids(&tabs), [0, 3, 6, 1, 2, 5, 4]);
+        // Arranging again changes nothing
+        let before = tabs.clone();
+        arrange(&mut tabs, &mut groups);
+        assert_eq!(tabs, before);
+        assert_eq!(groups, [group(2), group(1)]);
+    }
+
+    #[test]
+    fn missing_and_empty_groups_are_forgotten() {
+        let mut tabs = vec![tab(0, Some(9)), tab(1, Some(1)), Tab { pinned: true, ..tab(2, Some(3)) }];
+        let mut groups = vec![group(1), group(3), group(4)];
+        arrange(&mut tabs, &mut groups);
+        assert_eq!(ids(&tabs), [2, 0, 1]);
+        assert_eq!(tabs.iter().map(|t| t.group).collect::<Vec<_>>(), [None, None, Some(1)]);
+        // The pinned tab's group and the one that never had tabs
+        assert_eq!(groups, [group(1)]);
+    }
+
+    #[test]
+    fn colours_cycle_and_saved_groups_count_their_tabs() {
+        assert_eq!(GroupColor::Grey.next(), GroupColor::Blue);
+        assert_eq!(GroupColor::Orange.next(), GroupColor::Grey);
+        let saved = SavedGroup { name: String::new(), color: GroupColor::Red, tabs: vec![tab(0, None)], saved_at: 0.0 };
+        assert_eq!(saved.title(), "1 tab");
+        let saved = SavedGroup { name: String::from("Work"), tabs: vec![tab(0, None), tab(1, None)], ..saved };
+        assert_eq!(saved.title(), "Work (2 tabs)");
+    }
+}
@@ -0,0 +1,134 @@
+//! How the tab strip is laid out.
+//!
+//! Tabs either run along the top, scrolling sideways once they reach their
+//! minimum width, or down a sidebar beside the page. The sidebar shows them
+//! as a tree: a tab opened from another, by a link or as a duplicate, sits
+//! indented under it. [`tree`] works the rows out from [`Tab::opener`]
+//! without changing the strip's order, so switching layouts loses nothing.
+
+use serde::{Deserialize, Serialize};
+
+use crate::Tab;
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub enum TabLayout {
+    #[default]
+    Horizontal,
+    Vertical,
+}
+
+impl TabLayout {
+    pub const ALL: [TabLayout; 2] = [TabLayout::Horizontal, TabLayout::Vertical];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            TabLayout::Horizontal => "Horizontal",
+            TabLayout::Vertical => "Vertical",
+        }
+    }
+
+    pub fn other(&self) -> Self {
+        match self {
+            TabLayout::Horizontal => TabLayout::Vertical,
+            TabLayout::Vertical => TabLayout::Horizontal,
+        }
+    }
+}
+
+/// The tab a tab hangs under in the tree. Openers that were closed, pinned
+/// or are in another group don't count, and neither do ones newer than the
+/// tab, which only come from edited state and could make a loop.
+fn parent(tabs: &[Tab], tab: &Tab) -> Option<u32> {
+    let opener = tab.opener.filter(|&id| id < tab.id && !tab.pinned)?;
+    tabs.iter()
+        .find(|t| t.id == opener)
+        .filter(|t| !t.pinned && t.group == tab.group)
+        .map(|t| t.id)
+}
+
+/// The tabs as sidebar rows with their depth: each tab is followed by the
+/// tabs it opened, and siblings keep their order in the strip. Groups stay
+/// together, since a tab only hangs under one in its own group.
+pub fn tree(tabs: &[Tab]) -> Vec<(&Tab, usize)> {
+    let parents: Vec<Option<u32>> = tabs.iter().map(|tab| parent(tabs, tab)).collect();
+    let mut rows = Vec::with_capacity(tabs.len());
+    // Popped from the end, so pushed in reverse
+    let mut stack: Vec<(usize, usize)> = (0..tabs.len()).rev().filter(|&i| parents[i].is_none()).map(|i| (i, 0)).collect();
+    while let Some((index, depth)) = stack.pop() {
+        let tab = &tabs[index];
+        rows.push((tab, depth));
+        let children = (0..tabs.len()).rev().filter(|&i| parents[i] == Some(tab.id));
+        stack.extend(children.map(|i| (i, depth + 1)));
+    }
+    rows
+}
+
+/// Where a tab opened from `opener` goes in the strip: after the opener and
+/// everything opened from it, so a page's links open in the order they were
+/// clicked
+pub fn insert_index(tabs: &[Tab], opener: u32) -> Option<usize> {
+    let rows = tree(tabs);
+    let start = rows.iter().position(|(t, _)| t.id == opener)?;
+    let depth = rows[start].1;
+    let family = std::iter::once(&rows[start]).chain(rows[start + 1..].iter().take_while(|(_, d)| *d > depth));
+    family
+        .filter_map(|(tab, _)| tabs.iter().position(|t| t.id == tab.id))
+        .max()
+        .map(|i| i + 1)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn tab(id: u32, opener: Option<u32>) -> Tab {
+        Tab { id, opener, ..Tab::default() }
+    }
+
+    fn rows(tabs: &[Tab]) -> Vec<(u32, usize)> {
+        tree(tabs).into_iter().map(|(t, depth)| (t.id, depth)).collect()
+    }
+
+    #[test]
+    fn tabs_hang_under_their_opener() {
+        let tabs = [tab(0, None), tab(1, Some(0)), tab(2, None), tab(3, Some(1)), tab(4, Some(0))];
+        assert_eq!(rows(&tabs), [(0, 0), (1, 1), (3, 2), (4, 1), (2, 0)]);
+    }
+
+    #[test]
+    fn openers_that_dont_count_leave_a_root() {
+        let tabs = [
+            Tab { pinned: true, ..tab(0, None) },
+            // Under a pinned tab
+            tab(1, Some(0)),
+            // Closed
+            tab(2, Some(9)),
+            // Pinned itself
+            Tab { pinned: true, ..tab(3, Some(2)) },
+            // In another group
+            Tab { group: Some(1), ..tab(4, Some(2)) },
+            tab(5, Some(2)),
+        ];
+        assert_eq!(rows(&tabs), [(0, 0), (1, 0), (2, 0), (5, 1), (3, 0), (4, 0)]);
+    }
+
+    #[test]
+    fn openers_newer_than_the_tab_cant_make_a_loop() {
+        let tabs = [tab(1, Some(2)), tab(2, Some(1)), tab(3, Some(3))];
+        assert_eq!(rows(&tabs), [(1, 0), (2, 1), (3, 0)]);
+    }
+
+    #[test]
+    fn links_open_after_the_openers_family() {
+        let tabs = [tab(0, None), tab(1, Some(0)), tab(2, Some(1)), tab(3, None), tab(4, Some(0))];
+        // After 4, the last of 0's descendants in the strip
+        assert_eq!(insert_index(&tabs, 0), Some(5));
+        assert_eq!(insert_index(&tabs, 1), Some(3));
+        assert_eq!(insert_index(&tabs, 3), Some(4));
+        assert_eq!(insert_index(&tabs, 9), None);
+        // Descendants placed further along the strip still count
+        let tabs = [tab(0, None), tab(5, None), tab(6, Some(0))];
+        assert_eq!(insert_index(&tabs, 0), Some(3));
+        assert_eq!(insert_index(&tabs, 5), Some(2));
+    }
+}
@@ -0,0 +1,148 @@
+//! Colour themes, applied as CSS custom properties on the root element.
+
+use std::collections::BTreeMap;
+
+use serde::{Deserialize, Serialize};
+use wasm_bindgen::JsCast;
+use web_sys::HtmlElement;
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub enum Theme {
+    #[default]
+    Light,
+    Dark,
+    /// Follows the operating system's `prefers-color-scheme`
+    System,
+}
+
+impl Theme {
+    pub fn next(&self) -> Self {
+        match self {
+            Theme::Light => Theme::Dark,
+            Theme::Dark => Theme::System,
+            Theme::System => Theme::Light,
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Theme::Light => "Light",
+            Theme::Dark => "Dark",
+            Theme::System => "System",
+        }
+    }
+
+    pub fn icon(&self) -> &'static str {
+        match self {
+            Theme::Light => "icon-light-mode",
+            Theme::Dark => "icon-dark-mode",
+            Theme::System => "icon-brightness-auto",
+        }
+    }
+
+    pub fn is_dark(&self, system_prefers_dark: bool) -> bool {
+        match self {
+            Theme::Light => false,
+            Theme::Dark => true,
+            Theme::System => system_prefers_dark,
+        }
+    }
+}
+
+/// Every themeable colour, as the CSS variable name without its `--` and the
+/// light and dark defaults. `styles.css` falls back to the light values.
+const PALETTE: &[(&str, &str, &str)] = &[
+    ("bg", "#ffffff", "#202124"),
+    ("chrome-bg", "#f5f5f5", "#292a2d"),
+    ("tab-bar-bg", "#f0f0f0", "#202124"),
+    ("tab-bg", "#e0e0e0", "#2d2e31"),
+    ("tab-hover-bg", "#e8e8e8", "#35363a"),
+    ("tab-active-bg", "#ffffff", "#35363a"),
+    ("border", "#e0e0e0", "#3c4043"),
+    ("border-strong", "#d0d0d0", "#5f6368"),
+    ("text", "#333333", "#e8eaed"),
+    ("text-secondary", "#444444", "#bdc1c6"),
+    ("text-muted", "#999999", "#9aa0a6"),
+    ("hover-bg", "#f0f0f0", "#3c4043"),
+    ("input-bg", "#f5f5f5", "#303134"),
+    ("panel-bg", "#ffffff", "#292a2d"),
+    ("item-bg", "#f8f8f8", "#35363a"),
+    ("accent", "#666666", "#8ab4f8"),
+    ("home-title", "#dddddd", "#5f6368"),
+    ("shadow", "rgba(0, 0, 0, 0.15)", "rgba(0, 0, 0, 0.5)"),
+    ("icon-filter", "none", "invert(0.85)"),
+];
+
+/// A user supplied theme, layered over the built-in light or dark palette
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct CustomTheme {
+    pub name: String,
+    /// Overrides used in light mode, keyed by variable name without `--`
+    #[serde(default)]
+    pub colors: BTreeMap<String, String>,
+    /// Overrides used in dark mode; falls back to `colors`
+    #[serde(default)]
+    pub dark_colors: BTreeMap<String, String>,
+}
+
+impl CustomTheme {
+    /// Parses and validates a theme file
+    pub fn from_json(json: &str) -> Result<Self, String> {
+        let theme: CustomTheme = serde_json::from_str(json).map_err(|e| format!("Invalid theme file: {}", e))?;
+        for key in theme.colors.keys().chain(theme.dark_colors.keys()) {
+            if !PALETTE.iter().any(|(name, _, _)| name == key) {
+                return Err(format!("Unknown theme colour \"{}\"", key));
+            }
+        }
+        for value in theme.colors.values().chain(theme.dark_colors.values()) {
+            // Values end up inside a style declaration
+            if value.contains([';', '{', '}']) {
+                return Err(format!("Invalid colour value \"{}\"", value));
+            }
+        }
+        Ok(theme)
+    }
+
+    /// The full built-in palettes, as a starting point for editing
+    pub fn built_in() -> Self {
+        Self {
+            name: String::from("Graphite"),
+            colors: PALETTE.iter().map(|(k, light, _)| (k.to_string(), light.to_string())).collect(),
+            dark_colors: PALETTE.iter().map(|(k, _, dark)| (k.to_string(), dark.to_string())).collect(),
+        }
+    }
+
+    pub fn to_json(&self) -> String {
+        serde_json::to_string_pretty(self).unwrap_or_default()
+    }
+}
+
+/// Writes the effective colours onto `<html>` so every stylesheet rule picks them up
+pub fn apply(theme: Theme, custom: Option<&CustomTheme>, system_prefers_dark: bool) {
+    let Some(root) = web_sys::window()
+        .and_then(|w| w.document())
+        .and_then(|d| d.document_element())
+        .and_then(|e| e.dyn_into::<HtmlElement>().ok())
+    else {
+        return;
+    };
+
+    let dark = theme.is_dark(system_prefers_dark);
+    let style = root.style();
+    for (name, light_value, dark_value) in PALETTE {
+        let custom_value = custom.and_then(|c| {
+            let overrides = if dark { c.dark_colors.get(*name).or(c.colors.get(*name)) } else { c.colors.get(*name) };
+            overrides.map(String::as_str)
+        });
+        let value = custom_value.unwrap_or(if dark { dark_value } else { light_value });
+        let _ = style.set_property(&format!("--{}", name), value);
+    }
+    // Lets native controls such as scrollbars and selects match
+    let _ = style.set_property("color-scheme", if dark { "dark" } else { "light" });
+    let _ = root.set_attribute("data-theme", if dark { "dark" } else { "light" });
+}
+
+/// Current value of the `prefers-color-scheme: dark` media query
+pub fn color_scheme_query() -> Option<web_sys::MediaQueryList> {
+    web_sys::window()?.match_media("(prefers-color-scheme: dark)").ok().flatten()
+}
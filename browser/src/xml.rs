@@ -0,0 +1,340 @@
+//! A small XML reader for the documents the browser fetches itself, such as
+//! OpenSearch descriptions.
+//!
+//! It builds a tree of elements and text and understands comments, CDATA,
+//! processing instructions and the predefined and numeric entities. DTDs are
+//! skipped rather than interpreted. Namespace prefixes are dropped from
+//! element and attribute names, which is enough for formats with a single
+//! vocabulary.
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum Node {
+    Element(Element),
+    Text(String),
+}
+
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Element {
+    /// Local name, without any namespace prefix
+    pub name: String,
+    pub attributes: Vec<(String, String)>,
+    pub children: Vec<Node>,
+}
+
+impl Element {
+    pub fn attribute(&self, name: &str) -> Option<&str> {
+        self.attributes
+            .iter()
+            .find(|(n, _)| n.eq_ignore_ascii_case(name))
+            .map(|(_, v)| v.as_str())
+    }
+
+    /// Child elements with the given local name
+    pub fn children_named<'a>(&'a self, name: &'a str) -> impl Iterator<Item = &'a Element> + 'a {
+        self.children.iter().filter_map(move |node| match node {
+            Node::Element(el) if el.name.eq_ignore_ascii_case(name) => Some(el),
+            _ => None,
+        })
+    }
+
+    pub fn child(&self, name: &str) -> Option<&Element> {
+        self.children.iter().find_map(|node| match node {
+            Node::Element(el) if el.name.eq_ignore_ascii_case(name) => Some(el),
+            _ => None,
+        })
+    }
+
+    /// All text inside the element, trimmed
+    pub fn text(&self) -> String {
+        let mut text = String::new();
+        collect_text(self, &mut text);
+        text.trim().to_string()
+    }
+}
+
+fn collect_text(element: &Element, out: &mut String) {
+    for node in &element.children {
+        match node {
+            Node::Text(text) => out.push_str(text),
+            Node::Element(el) => collect_text(el, out),
+        }
+    }
+}
+
+/// Parses a document and returns its root element
+pub fn parse(input: &str) -> Result<Element, String> {
+    // Elements being filled, outermost first
+    let mut stack: Vec<Element> = Vec::new();
+    let mut root: Option<Element> = None;
+    let mut rest = input.strip_prefix('\u{feff}').unwrap_or(input);
+
+    while !rest.is_empty() {
+        let Some(start) = rest.find('<') else {
+            push_text(&mut stack, rest)?;
+            break;
+        };
+        if start > 0 {
+            push_text(&mut stack, &rest[..start])?;
+            rest = &rest[start..];
+        }
+
+        if let Some(after) = rest.strip_prefix("<!--") {
+            rest = skip_past(after, "-->", "comment")?;
+        } else if let Some(after) = rest.strip_prefix("<![CDATA[") {
+            let end = after.find("]]>").ok_or("Unterminated CDATA section")?;
+            match stack.last_mut() {
+                Some(parent) => parent.children.push(Node::Text(after[..end].to_string())),
+                None => return Err(String::from("Text outside the root element")),
+            }
+            rest = &after[end + 3..];
+        } else if let Some(after) = rest.strip_prefix("<?") {
+            rest = skip_past(after, "?>", "processing instruction")?;
+        } else if rest.starts_with("<!") {
+            rest = skip_declaration(rest)?;
+        } else if let Some(after) = rest.strip_prefix("</") {
+            let end = after.find('>').ok_or("Unterminated closing tag")?;
+            let name = local_name(after[..end].trim());
+            let element = stack.pop().ok_or_else(|| format!("Unexpected </{}>", name))?;
+            if !element.name.eq_ignore_ascii_case(name) {
+                return Err(format!("Expected </{}> but found </{}>", element.name, name));
+            }
+            close(&mut stack, &mut root, element)?;
+            rest = &after[end + 1..];
+        } else {
+            let (element, self_closing, after) = read_start_tag(&rest[1..])?;
+            if self_closing {
+                close(&mut stack, &mut root, element)?;
+            } else {
+                if root.is_some() {
+                    return Err(String::from("More than one root element"));
+                }
+                stack.push(element);
+            }
+            rest = after;
+        }
+    }
+
+    if let Some(open) = stack.last() {
+        return Err(format!("<{}> is never closed", open.name));
+    }
+    root.ok_or_else(|| String::from("No root element"))
+}
+
+/// Attaches a finished element to its parent, or makes it the root
+fn close(stack: &mut [Element], root: &mut Option<Element>, element: Element) -> Result<(), String> {
+    match stack.last_mut() {
+        Some(parent) => parent.children.push(Node::Element(element)),
+        None if root.is_none() => *root = Some(element),
+        None => return Err(String::from("More than one root element")),
+    }
+    Ok(())
+}
+
+fn push_text(stack: &mut [Element], text: &str) -> Result<(), String> {
+    match stack.last_mut() {
+        Some(parent) => parent.children.push(Node::Text(unescape(text))),
+        None if text.trim().is_empty() => {}
+        None => return Err(String::from("Text outside the root element")),
+    }
+    Ok(())
+}
+
+fn skip_past<'a>(text: &'a str, terminator: &str, what: &str) -> Result<&'a str, String> {
+    let end = text.find(terminator).ok_or_else(|| format!("Unterminated {}", what))?;
+    Ok(&text[end + terminator.len()..])
+}
+
+/// Skips `<!DOCTYPE ...>`, including an internal subset in brackets
+fn skip_declaration(text: &str) -> Result<&str, String> {
+    let mut depth = 0usize;
+    for (i, c) in text.char_indices() {
+        match c {
+            '[' => depth += 1,
+            ']' => depth = depth.saturating_sub(1),
+            '>' if depth == 0 => return Ok(&text[i + 1..]),
+            _ => {}
+        }
+    }
+    Err(String::from("Unterminated declaration"))
+}
+
+/// Reads a start tag after its `<`, returning the element, whether it closed
+/// itself, and the text after the tag
+fn read_start_tag(text: &str) -> Result<(Element, bool, &str), String> {
+    let name_end = text
+        .find(|c: char| c.is_whitespace() || c == '>' || c == '/')
+        .ok_or("Unterminated tag")?;
+    let name = &text[..name_end];
+    if name.is_empty() {
+        return Err(String::from("Tag without a name"));
+    }
+    let mut element = Element { name: local_name(name).to_string(), ..Element::default() };
+
+    let mut rest = &text[name_end..];
+    loop {
+        rest = rest.trim_start();
+        if let Some(after) = rest.strip_prefix("/>") {
+            return Ok((element, true, after));
+        }
+        if let Some(after) = rest.strip_prefix('>') {
+            return Ok((element, false, after));
+        }
+        let eq = rest.find('=').ok_or_else(|| format!("Malformed attribute in <{}>", element.name))?;
+        let attr_name = rest[..eq].trim();
+        if attr_name.is_empty() || attr_name.contains(|c: char| c.is_whitespace() || c == '>') {
+            return Err(format!("Malformed attribute in <{}>", element.name));
+        }
+        let value_text = rest[eq + 1..].trim_start();
+        let quote = value_text
+            .chars()
+            .next()
+            .filter(|c| *c == '"' || *c == '\'')
+            .ok_or_else(|| format!("Unquoted attribute {} in <{}>", attr_name, element.name))?;
+        let value_end = value_text[1..].find(quote).ok_or("Unterminated attribute value")?;
+        let value = unescape(&value_text[1..1 + value_end]);
+        // Namespace declarations aren't attributes of the vocabulary
+        if attr_name != "xmlns" && !attr_name.starts_with("xmlns:") {
+            element.attributes.push((local_name(attr_name).to_string(), value));
+        }
+        rest = &value_text[value_end + 2..];
+    }
+}
+
+fn local_name(name: &str) -> &str {
+    name.rsplit_once(':').map_or(name, |(_, local)| local)
+}
+
+/// Decodes entity references, leaving unknown ones as written
+fn unescape(text: &str) -> String {
+    let mut result = String::with_capacity(text.len());
+    let mut rest = text;
+    while let Some(amp) = rest.find('&') {
+        result.push_str(&rest[..amp]);
+        rest = &rest[amp..];
+        let decoded = rest.find(';').filter(|&end| end <= 12).and_then(|end| {
+            let ch = match &rest[1..end] {
+                "amp" => Some('&'),
+                "lt" => Some('<'),
+                "gt" => Some('>'),
+                "quot" => Some('"'),
+                "apos" => Some('\''),
+                entity => {
+                    let code = match entity.strip_prefix("#x").or_else(|| entity.strip_prefix("#X")) {
+                        Some(hex) => u32::from_str_radix(hex, 16).ok(),
+                        None => entity.strip_prefix('#').and_then(|dec| dec.parse().ok()),
+                    };
+                    code.and_then(char::from_u32)
+                }
+            };
+            ch.map(|ch| (ch, end))
+        });
+        match decoded {
+            Some((ch, end)) => {
+                result.push(ch);
+                rest = &rest[end + 1..];
+            }
+            None => {
+                result.push('&');
+                rest = &rest[1..];
+            }
+        }
+    }
+    result.push_str(rest);
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn text(text: &str) -> Node {
+        Node::Text(text.to_string())
+    }
+
+    #[test]
+    fn builds_a_tree_of_elements_and_text() {
+        let root = parse("<?xml version=\"1.0\"?>\n<a x=\"1\" y='two'><b>hi</b> there<c/></a>\n").unwrap();
+        assert_eq!(root.name, "a");
+        assert_eq!(root.attribute("x"), Some("1"));
+        assert_eq!(root.attribute("Y"), Some("two"));
+        assert_eq!(root.attribute("z"), None);
+        assert_eq!(root.children.len(), 3);
+        assert_eq!(root.child("b").map(Element::text), Some(String::from("hi")));
+        assert_eq!(root.children[1], text(" there"));
+        assert_eq!(root.child("c"), Some(&Element { name: String::from("c"), ..Element::default() }));
+        assert_eq!(root.text(), "hi there");
+    }
+
+    #[test]
+    fn decodes_entities() {
+        let root = parse("<a title=\"&quot;x&quot; &amp; &#39;y&#39;\">&lt;b&gt; &#233;&#xE9;&#Xe9; &apos;&nbsp;&#xD800;&#; &amp</a>").unwrap();
+        assert_eq!(root.attribute("title"), Some("\"x\" & 'y'"));
+        // Unknown and invalid references are left as written
+        assert_eq!(root.text(), "<b> ééé '&nbsp;&#xD800;&#; &amp");
+    }
+
+    #[test]
+    fn keeps_cdata_verbatim() {
+        let root = parse("<a>before <![CDATA[<not> &amp; a tag]]> after</a>").unwrap();
+        assert_eq!(root.children, [text("before "), text("<not> &amp; a tag"), text(" after")]);
+        assert!(parse("<a><![CDATA[never closed</a>").is_err());
+    }
+
+    #[test]
+    fn skips_comments_declarations_and_instructions() {
+        let doc = "\u{feff}<?xml version=\"1.0\"?>\n\
+                   <!DOCTYPE a [ <!ENTITY e \"<b>\"> ]>\n\
+                   <!-- <ignored/> -->\n\
+                   <a><?pi <x>?><!-- > --><b/></a>\n\
+                   <!-- trailing -->";
+        let root = parse(doc).unwrap();
+        assert_eq!(root.children, [Node::Element(Element { name: String::from("b"), ..Element::default() })]);
+    }
+
+    #[test]
+    fn drops_namespace_prefixes_and_declarations() {
+        let doc = "<os:Root xmlns:os=\"urn:os\" xmlns=\"urn:default\" moz:flag=\"on\" plain=\"1\">\
+                   <moz:SearchForm>https://example.com/</moz:SearchForm></os:Root>";
+        let root = parse(doc).unwrap();
+        assert_eq!(root.name, "Root");
+        assert_eq!(root.attributes, [(String::from("flag"), String::from("on")), (String::from("plain"), String::from("1"))]);
+        assert_eq!(root.child("SearchForm").map(Element::text), Some(String::from("https://example.com/")));
+    }
+
+    #[test]
+    fn malformed_documents_are_errors() {
+        for doc in [
+            "",
+            "   ",
+            "just text",
+            "<a>",
+            "<a></b>",
+            "</a>",
+            "<a/><b/>",
+            "<a></a><b></b>",
+            "text<a/>",
+            "<a/>text",
+            "<a",
+            "<a x>",
+            "<a x=1>",
+            "<a x=\"1>",
+            "<a =\"1\">",
+            "<>",
+            "<a><!-- never closed</a>",
+            "<a><?pi</a>",
+            "<!DOCTYPE a [",
+            "<a></a",
+        ] {
+            assert!(parse(doc).is_err(), "parsing {:?}", doc);
+        }
+    }
+
+    #[test]
+    fn truncated_documents_never_panic() {
+        let doc = "<?xml version=\"1.0\"?><r a=\"é&amp;\"><!-- c --><![CDATA[ü]]>&#233;<x:y b='1'/>テキスト</r>";
+        assert!(parse(doc).is_ok());
+        for (end, _) in doc.char_indices() {
+            assert!(parse(&doc[..end]).is_err(), "parsing {:?}", &doc[..end]);
+        }
+    }
+}